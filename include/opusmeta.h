@@ -0,0 +1,125 @@
+#ifndef OPUSMETA_H
+#define OPUSMETA_H
+
+/* Generated by cbindgen from src/capi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The result of a call to the C API.
+typedef enum OpusmetaStatus {
+  // The call succeeded.
+  OPUSMETA_STATUS_OK = 0,
+  // A pointer argument was null.
+  OPUSMETA_STATUS_NULL_POINTER = 1,
+  // A string argument wasn't valid UTF-8, or a string to return contained a NUL byte.
+  OPUSMETA_STATUS_INVALID_STRING = 2,
+  // An I/O operation failed.
+  OPUSMETA_STATUS_IO = 3,
+  // The file isn't an opus file (or a file of another supported codec).
+  OPUSMETA_STATUS_NOT_OPUS = 4,
+  // The file or its comment header is malformed.
+  OPUSMETA_STATUS_MALFORMED = 5,
+  // The requested value doesn't exist.
+  OPUSMETA_STATUS_NOT_FOUND = 6,
+  // Any other error, including a panic in the library.
+  OPUSMETA_STATUS_OTHER = 7,
+} OpusmetaStatus;
+
+// Stores Opus comments.
+typedef struct OpusmetaTag OpusmetaTag;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns a description of the last error which occured on the calling thread.
+//
+// Returns null if no error occured yet. The string is owned by the library, and stays valid until
+// the next failing call on the same thread.
+const char *opusmeta_last_error_message(void);
+
+// Releases a string returned by this API. Does nothing if `string` is null.
+// # Safety
+// `string` must be null or a string returned by this API, which wasn't released yet.
+void opusmeta_string_free(char *string);
+
+// Creates an empty tag with the given vendor string. Returns null if `vendor` is null or isn't
+// valid UTF-8.
+// # Safety
+// `vendor` must be null or a NUL-terminated string.
+struct OpusmetaTag *opusmeta_tag_new(const char *vendor);
+
+// Releases a tag. Does nothing if `tag` is null.
+// # Safety
+// `tag` must be null or a tag returned by this API, which wasn't released yet.
+void opusmeta_tag_free(struct OpusmetaTag *tag);
+
+// Reads the tags of the file at `path` into `*out`.
+// # Safety
+// `path` must be null or a NUL-terminated string, and `out` must be null or valid for writes.
+enum OpusmetaStatus opusmeta_tag_read(const char *path, struct OpusmetaTag **out);
+
+// Reads the tags of a file held in memory into `*out`.
+// # Safety
+// `data` must be valid for reads of `len` bytes, and `out` must be null or valid for writes.
+enum OpusmetaStatus opusmeta_tag_read_buffer(const uint8_t *data,
+                                             size_t len,
+                                             struct OpusmetaTag **out);
+
+// Writes a tag to the file at `path`, replacing its tags.
+// # Safety
+// `tag` must be null or a valid tag, and `path` must be null or a NUL-terminated string.
+enum OpusmetaStatus opusmeta_tag_write(const struct OpusmetaTag *tag, const char *path);
+
+// Gets the vendor string of a tag, into `*out`.
+// # Safety
+// `tag` must be null or a valid tag, and `out` must be null or valid for writes.
+enum OpusmetaStatus opusmeta_tag_vendor(const struct OpusmetaTag *tag, char **out);
+
+// Sets the vendor string of a tag.
+// # Safety
+// `tag` must be null or a valid tag, and `vendor` must be null or a NUL-terminated string.
+enum OpusmetaStatus opusmeta_tag_set_vendor(struct OpusmetaTag *tag, const char *vendor);
+
+// Returns the number of values of a key, or 0 if the key has no values or an argument is
+// invalid.
+// # Safety
+// `tag` must be null or a valid tag, and `key` must be null or a NUL-terminated string.
+size_t opusmeta_tag_value_count(const struct OpusmetaTag *tag, const char *key);
+
+// Gets the value of a key at `index` (0 for the first value), into `*out`. Returns
+// [`OpusmetaStatus::NotFound`] if the key has fewer values.
+// # Safety
+// `tag` must be null or a valid tag, `key` must be null or a NUL-terminated string, and `out`
+// must be null or valid for writes.
+enum OpusmetaStatus opusmeta_tag_get(const struct OpusmetaTag *tag,
+                                     const char *key,
+                                     size_t index,
+                                     char **out);
+
+// Adds a value to a key, after its existing values.
+// # Safety
+// `tag` must be null or a valid tag, and `key` and `value` must be null or NUL-terminated
+// strings.
+enum OpusmetaStatus opusmeta_tag_add(struct OpusmetaTag *tag, const char *key, const char *value);
+
+// Replaces all values of a key with a single value.
+// # Safety
+// `tag` must be null or a valid tag, and `key` and `value` must be null or NUL-terminated
+// strings.
+enum OpusmetaStatus opusmeta_tag_set(struct OpusmetaTag *tag, const char *key, const char *value);
+
+// Removes all values of a key. Succeeds if the key has no values.
+// # Safety
+// `tag` must be null or a valid tag, and `key` must be null or a NUL-terminated string.
+enum OpusmetaStatus opusmeta_tag_remove(struct OpusmetaTag *tag, const char *key);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* OPUSMETA_H */
@@ -0,0 +1,96 @@
+//! `AcoustID` fingerprints and identifiers.
+//!
+//! [`AcoustID`](https://acoustid.org) identifies recordings by an acoustic fingerprint of their
+//! audio, which taggers such as Picard store in the `ACOUSTID_FINGERPRINT` comment,
+//! along with the `ACOUSTID_ID` it resolved to. Reading and writing these comments is always
+//! available; computing a fingerprint, which decodes the audio, requires the `chromaprint`
+//! feature.
+
+use crate::Tag;
+#[cfg(feature = "chromaprint")]
+use crate::{chromaprint, Result};
+#[cfg(all(feature = "chromaprint", feature = "fs"))]
+use std::fs::File;
+#[cfg(all(feature = "chromaprint", feature = "fs"))]
+use std::io::BufReader;
+#[cfg(feature = "chromaprint")]
+use std::io::{Read, Seek};
+#[cfg(all(feature = "chromaprint", feature = "fs"))]
+use std::path::Path;
+#[cfg(feature = "chromaprint")]
+use std::time::Duration;
+
+/// The acoustic fingerprint of a file. Requires the `chromaprint` feature.
+#[cfg(feature = "chromaprint")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// The fingerprint, compressed and encoded the way the `AcoustID` web service expects it.
+    pub fingerprint: String,
+    /// The duration of the file, which `AcoustID` lookups require along with the fingerprint.
+    pub duration: Duration,
+}
+
+/// Computes the fingerprint of the first opus stream in a reader, from its first two minutes, as
+/// the `fpcalc` tool does. Streams with more than 2 channels aren't supported.
+/// # Errors
+/// This function will error if the reader doesn't contain an opus stream, or if the audio can't
+/// be decoded.
+#[cfg(feature = "chromaprint")]
+pub fn fingerprint_from<R: Read + Seek>(f_in: R) -> Result<Fingerprint> {
+    let (fingerprint, duration) = chromaprint::fingerprint(f_in)?;
+    Ok(Fingerprint {
+        fingerprint,
+        duration,
+    })
+}
+
+/// Computes the fingerprint of the first opus stream in a file.
+/// # Errors
+/// This function will error if the file cannot be opened, or for the same reasons as
+/// [`fingerprint_from`].
+#[cfg(all(feature = "chromaprint", feature = "fs"))]
+pub fn fingerprint_path<P: AsRef<Path>>(path: P) -> Result<Fingerprint> {
+    let file = File::open(path)?;
+    fingerprint_from(BufReader::new(file))
+}
+
+/// Computes the fingerprint of a file and writes it to its `ACOUSTID_FINGERPRINT` tag. Returns
+/// the fingerprint.
+/// # Errors
+/// This function will error for the same reasons as [`fingerprint_path`], or if writing the tags
+/// fails.
+#[cfg(all(feature = "chromaprint", feature = "fs"))]
+pub fn tag_fingerprint<P: AsRef<Path>>(path: P) -> Result<Fingerprint> {
+    let path = path.as_ref();
+    let fingerprint = fingerprint_path(path)?;
+    let mut tag = Tag::read_from_path(path)?;
+    tag.set_acoustid_fingerprint(fingerprint.fingerprint.clone());
+    tag.write_to_path(path)?;
+    Ok(fingerprint)
+}
+
+impl Tag {
+    /// Gets the `AcoustID` fingerprint stored in the `ACOUSTID_FINGERPRINT` comment.
+    #[must_use]
+    pub fn acoustid_fingerprint(&self) -> Option<&String> {
+        self.get_one("ACOUSTID_FINGERPRINT".to_string())
+    }
+
+    /// Replaces the `AcoustID` fingerprint stored in the `ACOUSTID_FINGERPRINT` comment.
+    pub fn set_acoustid_fingerprint(&mut self, fingerprint: String) {
+        self.remove_entries("ACOUSTID_FINGERPRINT".to_string());
+        self.add_one("ACOUSTID_FINGERPRINT".to_string(), fingerprint);
+    }
+
+    /// Gets the `AcoustID` identifier stored in the `ACOUSTID_ID` comment.
+    #[must_use]
+    pub fn acoustid_id(&self) -> Option<&String> {
+        self.get_one("ACOUSTID_ID".to_string())
+    }
+
+    /// Replaces the `AcoustID` identifier stored in the `ACOUSTID_ID` comment.
+    pub fn set_acoustid_id(&mut self, id: String) {
+        self.remove_entries("ACOUSTID_ID".to_string());
+        self.add_one("ACOUSTID_ID".to_string(), id);
+    }
+}
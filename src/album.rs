@@ -0,0 +1,129 @@
+//! Grouping tracks into albums, and finding what is inconsistent within an album.
+//!
+//! Tracks are grouped by their `ALBUM`, `ALBUMARTIST` and `DISCNUMBER` comments, so each disc of a
+//! multi-disc album is an [`Album`] of its own. The tracks usually come from a directory scan
+//! (see the `scan` module, which requires the `walkdir` feature), with the failed reads left
+//! out.
+
+use crate::picture::{Picture, PictureType};
+use crate::Tag;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The comments which should have the same values on every track of an album.
+pub const ALBUM_KEYS: [&str; 13] = [
+    "album",
+    "albumartist",
+    "albumartistsort",
+    "albumsort",
+    "catalognumber",
+    "compilation",
+    "date",
+    "discnumber",
+    "disctotal",
+    "genre",
+    "label",
+    "organization",
+    "tracktotal",
+];
+
+/// The tracks of one disc of an album.
+#[derive(Debug)]
+pub struct Album {
+    /// The value of `ALBUM` shared by the tracks, or None for tracks without one.
+    pub album: Option<String>,
+    /// The value of `ALBUMARTIST` shared by the tracks, or None for tracks without one.
+    pub album_artist: Option<String>,
+    /// The value of `DISCNUMBER` shared by the tracks, or None for tracks without one.
+    pub disc_number: Option<String>,
+    /// The tracks, sorted by `TRACKNUMBER` (tracks without one come last, in their original
+    /// order).
+    pub tracks: Vec<(PathBuf, Tag)>,
+}
+
+/// A comment which doesn't have the same values on every track of an [`Album`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inconsistency {
+    /// The key of the comment, in lowercase.
+    pub key: String,
+    /// The path of every track with its values of the comment, which are empty if the track
+    /// doesn't have the comment.
+    pub values: Vec<(PathBuf, Vec<String>)>,
+}
+
+impl Album {
+    /// Groups tracks into albums, sorted by album title, then album artist, then disc number.
+    pub fn group<I: IntoIterator<Item = (PathBuf, Tag)>>(tracks: I) -> Vec<Self> {
+        type AlbumKey = (Option<String>, Option<String>, Option<String>);
+        let mut albums: BTreeMap<AlbumKey, Vec<(PathBuf, Tag)>> = BTreeMap::new();
+        for (path, tag) in tracks {
+            let key = (
+                tag.get_one("album".to_string()).cloned(),
+                tag.get_one("albumartist".to_string()).cloned(),
+                tag.get_one("discnumber".to_string()).cloned(),
+            );
+            albums.entry(key).or_default().push((path, tag));
+        }
+        albums
+            .into_iter()
+            .map(|((album, album_artist, disc_number), mut tracks)| {
+                tracks.sort_by_key(|(_, tag)| track_number(tag).unwrap_or(u32::MAX));
+                Self {
+                    album,
+                    album_artist,
+                    disc_number,
+                    tracks,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the comments of [`ALBUM_KEYS`] which don't have the same values on every track,
+    /// sorted by key.
+    #[must_use]
+    pub fn inconsistencies(&self) -> Vec<Inconsistency> {
+        ALBUM_KEYS
+            .iter()
+            .filter_map(|key| {
+                let values: Vec<(PathBuf, Vec<String>)> = self
+                    .tracks
+                    .iter()
+                    .map(|(path, tag)| {
+                        let values = tag.get((*key).to_string()).unwrap_or_default();
+                        (path.clone(), values.to_vec())
+                    })
+                    .collect();
+                let consistent = values.windows(2).all(|pair| pair[0].1 == pair[1].1);
+                (!consistent).then(|| Inconsistency {
+                    key: (*key).to_string(),
+                    values,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the front cover shared by every track, or None if a track has no front cover or
+    /// the covers differ (in their data or MIME type).
+    #[must_use]
+    pub fn shared_cover(&self) -> Option<Picture> {
+        let mut covers = self
+            .tracks
+            .iter()
+            .map(|(_, tag)| tag.get_picture_type(PictureType::CoverFront));
+        let first = covers.next()??;
+        for cover in covers {
+            let cover = cover?;
+            if cover.data != first.data || cover.mime_type != first.mime_type {
+                return None;
+            }
+        }
+        Some(first)
+    }
+}
+
+/// Parses the number of a track from its `TRACKNUMBER` comment, which may also hold the total
+/// (`3/12`).
+fn track_number(tag: &Tag) -> Option<u32> {
+    let value = tag.get_one("tracknumber".to_string())?;
+    value.split('/').next()?.trim().parse().ok()
+}
@@ -0,0 +1,207 @@
+//! Async versions of the reading and writing functions, for the tokio runtime.
+//!
+//! They share the page handling of their blocking counterparts, and only differ in how the data
+//! is read and written.
+
+use crate::page::{Chunk, ChunkBuffer, READ_SIZE};
+use crate::write::{self, PageCopier, Progress, Written, PROGRESS_INTERVAL};
+use crate::{CommentPacket, HeaderPages, ReadOptions, Result, Tag, WriteOptions};
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt, BufWriter};
+
+/// Reads raw pages from an async reader. See [`PageReader`](crate::page::PageReader).
+struct AsyncPageReader<R> {
+    inner: R,
+    buffer: ChunkBuffer,
+}
+
+impl<R: AsyncRead + Unpin> AsyncPageReader<R> {
+    const fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: ChunkBuffer::new(),
+        }
+    }
+
+    /// Reads the next chunk, or returns None at the end of the stream.
+    async fn read_chunk(&mut self) -> std::io::Result<Option<Chunk>> {
+        let mut block = [0; READ_SIZE];
+        loop {
+            match self.buffer.next_chunk() {
+                Ok(chunk) => return Ok(chunk),
+                Err(needed) => {
+                    while self.buffer.needs(needed) {
+                        let read = self.inner.read(&mut block).await?;
+                        self.buffer.extend(&block[..read]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Tag {
+    /// Read a `Tag` from an async reader. See [`read_from`](Self::read_from).
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from).
+    pub async fn read_from_async<R: AsyncRead + AsyncSeek + Unpin>(f_in: R) -> Result<Self> {
+        Self::read_from_async_with(f_in, &ReadOptions::default()).await
+    }
+
+    /// Read a `Tag` from an async reader, using the given [`ReadOptions`]. See
+    /// [`read_from_with`](Self::read_from_with).
+    /// # Errors
+    /// This function will error for the same reasons as
+    /// [`read_from_with`](Self::read_from_with).
+    pub async fn read_from_async_with<R: AsyncRead + AsyncSeek + Unpin>(
+        f_in: R,
+        options: &ReadOptions,
+    ) -> Result<Self> {
+        let mut reader = AsyncPageReader::new(f_in);
+        let mut packet = CommentPacket::default();
+        while let Some(chunk) = reader.read_chunk().await? {
+            if let Some((codec, data)) = packet.push(chunk, options)? {
+                return Self::from_comment_packet(codec, &data, options);
+            }
+        }
+        Err(packet.missing())
+    }
+
+    /// Convenience function for reading comments from a path with [`tokio::fs`].
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from).
+    pub async fn read_from_path_async<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path).await?;
+        Self::read_from_async(file).await
+    }
+
+    /// Writes a retagged copy of the opus stream read from `src` to `dst`. See
+    /// [`write_to_new`](Self::write_to_new).
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to_new`](Self::write_to_new).
+    pub async fn write_to_async<R, W>(&self, src: R, dst: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.write_to_async_with(src, dst, &WriteOptions::default())
+            .await?;
+        Ok(())
+    }
+
+    /// Writes a retagged copy of the opus stream read from `src` to `dst`, using the given
+    /// [`WriteOptions`]. See [`write_to_new_with`](Self::write_to_new_with).
+    ///
+    /// Returns the number of header pages before and after the write.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to_new`](Self::write_to_new).
+    pub async fn write_to_async_with<R, W>(
+        &self,
+        src: R,
+        dst: W,
+        options: &WriteOptions,
+    ) -> Result<HeaderPages>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        // the copy doesn't replace the stream the tags were read from
+        let (header_pages, _) = self.copy_async(src, dst, options).await?;
+        Ok(header_pages)
+    }
+
+    /// Writes a retagged copy of `src` to `dst`. Returns the header page counts, and the header
+    /// written, to be committed if `dst` replaces `src`.
+    async fn copy_async<R, W>(
+        &self,
+        src: R,
+        dst: W,
+        options: &WriteOptions,
+    ) -> Result<(HeaderPages, Written<'_>)>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut reader = AsyncPageReader::new(src);
+        let mut dst = BufWriter::new(dst);
+        let mut progress = Progress::new(options, None);
+        let mut copier = PageCopier::new(write::preferred_stream(self));
+        // pages are written to a buffer by the copier, then to `dst`
+        let mut pages = vec![];
+        let (header_pages, written) = loop {
+            if copier.is_done() {
+                break copier.into_parts();
+            }
+            let Some(chunk) = reader.read_chunk().await? else {
+                break copier.finish()?;
+            };
+            let Chunk::Page { page, .. } = chunk else {
+                continue;
+            };
+            copier.push(page, options, &mut pages)?;
+            dst.write_all(&pages).await?;
+            pages.clear();
+            progress.update(reader.buffer.position());
+        };
+
+        // the rest of the stream is copied verbatim
+        let mut done = reader.buffer.position();
+        let lookahead = reader.buffer.into_lookahead();
+        dst.write_all(&lookahead).await?;
+        done += lookahead.len() as u64;
+        let mut src = reader.inner;
+        loop {
+            // copied in parts to report progress
+            let mut part = (&mut src).take(PROGRESS_INTERVAL);
+            let count = tokio::io::copy(&mut part, &mut dst).await?;
+            if count == 0 {
+                break;
+            }
+            done += count;
+            progress.update(done);
+        }
+        dst.flush().await?;
+        progress.finish(done);
+        Ok((header_pages, written))
+    }
+
+    /// Convenience function for writing to a path with [`tokio::fs`]. The new file is written
+    /// next to the original and renamed over it, like with
+    /// [`WriteStrategy::Atomic`](crate::WriteStrategy::Atomic).
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to), or if the
+    /// temporary file cannot be created or renamed.
+    pub async fn write_to_path_async<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        // the file a symlink points to is replaced, rather than the link
+        let path = &tokio::fs::canonicalize(path).await?;
+        let temp_path = write::temp_path(path);
+        let result = self.replace_async(path, &temp_path).await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    /// Writes a retagged copy of the file at `path` to `temp_path`, then renames it over the
+    /// original.
+    async fn replace_async(&self, path: &Path, temp_path: &Path) -> Result<()> {
+        let mut src = File::open(path).await?;
+        let mut temp = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(temp_path)
+            .await?;
+        let (_, written) = self
+            .copy_async(&mut src, &mut temp, &WriteOptions::default())
+            .await?;
+
+        let src = src.into_std().await;
+        let temp = temp.into_std().await;
+        write::copy_file_metadata(&src, &temp)?;
+        File::from_std(temp).sync_all().await?;
+        tokio::fs::rename(temp_path, path).await?;
+        written.commit();
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! Audiobook metadata: the narrator, the series a book belongs to and its part in it, and the
+//! subtitle.
+//!
+//! There is no standard spelling for these comments, so each is read from the first of several
+//! keys found, and written to the first of them, the one most audiobook managers read:
+//!
+//! - the narrator from `NARRATOR`, `READER` or `NARRATEDBY`,
+//! - the series from `SERIES`,
+//! - the part in the series from `SERIESPART`, `SERIES-PART` or `SERIES_PART`,
+//! - the subtitle from `SUBTITLE`.
+
+use crate::Tag;
+
+/// The keys of the narrator, preferred first.
+const NARRATOR_KEYS: [&str; 3] = ["NARRATOR", "READER", "NARRATEDBY"];
+
+/// The keys of the series.
+const SERIES_KEYS: [&str; 1] = ["SERIES"];
+
+/// The keys of the part in the series, preferred first.
+const SERIES_PART_KEYS: [&str; 3] = ["SERIESPART", "SERIES-PART", "SERIES_PART"];
+
+/// The keys of the subtitle.
+const SUBTITLE_KEYS: [&str; 1] = ["SUBTITLE"];
+
+/// The audiobook metadata of a file. See [`Tag::audiobook_info`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudiobookInfo {
+    pub narrator: Option<String>,
+    pub series: Option<String>,
+    /// The part of the book in the series, such as `1` or `2.5`.
+    pub series_part: Option<String>,
+    pub subtitle: Option<String>,
+}
+
+impl Tag {
+    /// Gets the narrator, from the `NARRATOR`, `READER` or `NARRATEDBY` comment.
+    #[must_use]
+    pub fn narrator(&self) -> Option<&String> {
+        self.get_first_of(&NARRATOR_KEYS)
+    }
+
+    /// Replaces the narrator, stored in the `NARRATOR` comment. The other spellings are removed.
+    pub fn set_narrator(&mut self, narrator: String) {
+        self.set_first_of(&NARRATOR_KEYS, Some(narrator));
+    }
+
+    /// Gets the series the book belongs to, from the `SERIES` comment.
+    #[must_use]
+    pub fn series(&self) -> Option<&String> {
+        self.get_first_of(&SERIES_KEYS)
+    }
+
+    /// Replaces the series the book belongs to, stored in the `SERIES` comment.
+    pub fn set_series(&mut self, series: String) {
+        self.set_first_of(&SERIES_KEYS, Some(series));
+    }
+
+    /// Gets the part of the book in its series, from the `SERIESPART`, `SERIES-PART` or
+    /// `SERIES_PART` comment.
+    #[must_use]
+    pub fn series_part(&self) -> Option<&String> {
+        self.get_first_of(&SERIES_PART_KEYS)
+    }
+
+    /// Replaces the part of the book in its series, stored in the `SERIESPART` comment. The other
+    /// spellings are removed.
+    pub fn set_series_part(&mut self, part: String) {
+        self.set_first_of(&SERIES_PART_KEYS, Some(part));
+    }
+
+    /// Gets the subtitle, from the `SUBTITLE` comment.
+    #[must_use]
+    pub fn subtitle(&self) -> Option<&String> {
+        self.get_first_of(&SUBTITLE_KEYS)
+    }
+
+    /// Replaces the subtitle, stored in the `SUBTITLE` comment.
+    pub fn set_subtitle(&mut self, subtitle: String) {
+        self.set_first_of(&SUBTITLE_KEYS, Some(subtitle));
+    }
+
+    /// Returns all the audiobook metadata of the file.
+    #[must_use]
+    pub fn audiobook_info(&self) -> AudiobookInfo {
+        AudiobookInfo {
+            narrator: self.narrator().cloned(),
+            series: self.series().cloned(),
+            series_part: self.series_part().cloned(),
+            subtitle: self.subtitle().cloned(),
+        }
+    }
+
+    /// Replaces all the audiobook metadata of the file. Fields which are None are removed, under
+    /// every spelling.
+    pub fn set_audiobook_info(&mut self, info: AudiobookInfo) {
+        self.set_first_of(&NARRATOR_KEYS, info.narrator);
+        self.set_first_of(&SERIES_KEYS, info.series);
+        self.set_first_of(&SERIES_PART_KEYS, info.series_part);
+        self.set_first_of(&SUBTITLE_KEYS, info.subtitle);
+    }
+}
@@ -0,0 +1,102 @@
+//! Reading and editing the tags of many files at once.
+
+use crate::{ReadOptions, Result, Tag, TagPatch, WriteOptions};
+use std::path::{Path, PathBuf};
+
+/// Reads the tags of every file in `paths`, returning each path with its result, in the same
+/// order. With the `rayon` feature, the files are read in parallel.
+///
+/// This is the same as calling [`Tag::read_from_path`] on every path, so a file which fails to
+/// read doesn't stop the others from being read.
+pub fn read_many<I, P>(paths: I) -> Vec<(PathBuf, Result<Tag>)>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    read_many_with(paths, &ReadOptions::default())
+}
+
+/// Reads the tags of every file in `paths` using the given [`ReadOptions`]. See [`read_many`].
+///
+/// When scanning a large library, [`ReadOptions::skip_pictures`] keeps the memory used by the
+/// results small.
+pub fn read_many_with<I, P>(paths: I, options: &ReadOptions) -> Vec<(PathBuf, Result<Tag>)>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    for_each_path(paths, |path| Tag::read_from_path_with(path, options))
+}
+
+/// Applies a [`TagPatch`] to every file in `paths`, returning each path with whether the file
+/// changed, in the same order. With the `rayon` feature, the files are edited in parallel.
+///
+/// Files the patch doesn't change are not written. A file which fails to read or write doesn't
+/// stop the others from being edited.
+pub fn apply_to_all<I, P>(paths: I, patch: &TagPatch) -> Vec<(PathBuf, Result<bool>)>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    apply_to_all_with(paths, patch, &WriteOptions::default())
+}
+
+/// Applies a [`TagPatch`] to every file in `paths`, writing them with the given
+/// [`WriteOptions`]. See [`apply_to_all`].
+pub fn apply_to_all_with<I, P>(
+    paths: I,
+    patch: &TagPatch,
+    options: &WriteOptions,
+) -> Vec<(PathBuf, Result<bool>)>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    for_each_path(paths, |path| {
+        let mut tag = Tag::read_from_path(path)?;
+        if !patch.apply(&mut tag) {
+            return Ok(false);
+        }
+        tag.write_to_path_with(path, options)?;
+        Ok(true)
+    })
+}
+
+/// Returns whether a [`TagPatch`] would change each file in `paths`, without writing anything.
+/// See [`apply_to_all`].
+pub fn apply_to_all_dry_run<I, P>(paths: I, patch: &TagPatch) -> Vec<(PathBuf, Result<bool>)>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    for_each_path(paths, |path| {
+        let mut tag = Tag::read_from_path(path)?;
+        Ok(patch.apply(&mut tag))
+    })
+}
+
+/// Runs `f` on every path, in parallel with the `rayon` feature, returning each path with its
+/// result in the same order.
+fn for_each_path<I, P, T, F>(paths: I, f: F) -> Vec<(PathBuf, T)>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+    T: Send,
+    F: Fn(&Path) -> T + Sync,
+{
+    let paths = paths.into_iter().map(|path| path.as_ref().to_path_buf());
+    let run = |path: PathBuf| {
+        let result = f(&path);
+        (path, result)
+    };
+
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        paths.collect::<Vec<_>>().into_par_iter().map(run).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        paths.map(run).collect()
+    }
+}
@@ -0,0 +1,385 @@
+//! A flat C API, for players and programs written in other languages. Requires the `capi` feature.
+//!
+//! Build the shared library with `cargo rustc --release --features capi --crate-type cdylib`,
+//! which leaves the crate an rlib for everyone else; the matching header is `include/opusmeta.h`,
+//! generated from this module by cbindgen.
+//!
+//! Tags are handed out as opaque `OpusmetaTag` pointers, which must be released with
+//! [`opusmeta_tag_free`]. Functions which can fail return an [`OpusmetaStatus`]; when it isn't
+//! [`OpusmetaStatus::Ok`], [`opusmeta_last_error_message`] describes what went wrong. Strings
+//! returned by this API are owned by the caller and must be released with
+//! [`opusmeta_string_free`]. Strings passed in must be NUL-terminated and UTF-8 encoded.
+//!
+//! No panic unwinds into the caller: a call which panics fails with [`OpusmetaStatus::Other`], or
+//! returns null or 0 if it doesn't return a status.
+
+use crate::{Error, Tag};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// The result of a call to the C API.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpusmetaStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// A pointer argument was null.
+    NullPointer = 1,
+    /// A string argument wasn't valid UTF-8, or a string to return contained a NUL byte.
+    InvalidString = 2,
+    /// An I/O operation failed.
+    Io = 3,
+    /// The file isn't an opus file (or a file of another supported codec).
+    NotOpus = 4,
+    /// The file or its comment header is malformed.
+    Malformed = 5,
+    /// The requested value doesn't exist.
+    NotFound = 6,
+    /// Any other error, including a panic in the library.
+    Other = 7,
+}
+
+impl From<&Error> for OpusmetaStatus {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::DataError(_) => Self::Io,
+            Error::NotOpus => Self::NotOpus,
+            Error::ReadError(_)
+            | Error::MissingPacket
+            | Error::MalformedComment(_)
+            | Error::UTFError(_)
+            | Error::CorruptHeader { .. } => Self::Malformed,
+            _ => Self::Other,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Records the message of a failed call, and returns its status.
+fn fail(status: OpusmetaStatus, message: impl Display) -> OpusmetaStatus {
+    // a message with a NUL byte is cut short rather than lost
+    let mut message = message.to_string();
+    message.truncate(message.find('\0').unwrap_or(message.len()));
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    status
+}
+
+fn fail_with(error: &Error) -> OpusmetaStatus {
+    fail(error.into(), error)
+}
+
+/// Borrows a string argument.
+unsafe fn str_arg<'a>(string: *const c_char, name: &str) -> Result<&'a str, OpusmetaStatus> {
+    if string.is_null() {
+        return Err(fail(
+            OpusmetaStatus::NullPointer,
+            format_args!("`{name}` is null"),
+        ));
+    }
+    CStr::from_ptr(string).to_str().map_err(|_| {
+        fail(
+            OpusmetaStatus::InvalidString,
+            format_args!("`{name}` is not valid UTF-8"),
+        )
+    })
+}
+
+/// Borrows a tag argument.
+unsafe fn tag_arg<'a>(tag: *const Tag) -> Result<&'a Tag, OpusmetaStatus> {
+    tag.as_ref()
+        .ok_or_else(|| fail(OpusmetaStatus::NullPointer, "`tag` is null"))
+}
+
+/// Mutably borrows a tag argument.
+unsafe fn tag_arg_mut<'a>(tag: *mut Tag) -> Result<&'a mut Tag, OpusmetaStatus> {
+    tag.as_mut()
+        .ok_or_else(|| fail(OpusmetaStatus::NullPointer, "`tag` is null"))
+}
+
+/// Hands a string over to the caller through an out pointer.
+unsafe fn return_string(string: &str, out: *mut *mut c_char) -> Result<(), OpusmetaStatus> {
+    let string = CString::new(string).map_err(|_| {
+        fail(
+            OpusmetaStatus::InvalidString,
+            "the value contains a NUL byte",
+        )
+    })?;
+    *out = string.into_raw();
+    Ok(())
+}
+
+/// Runs the body of a call, returning `on_panic` if it panics, as unwinding into the caller is
+/// undefined behavior. The panic message becomes the last error.
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        fail(OpusmetaStatus::Other, format_args!("panicked: {message}"));
+        on_panic
+    })
+}
+
+/// Runs the body of a call, and turns its result into a status.
+fn run(body: impl FnOnce() -> Result<(), OpusmetaStatus>) -> OpusmetaStatus {
+    guard(OpusmetaStatus::Other, || {
+        body().err().unwrap_or(OpusmetaStatus::Ok)
+    })
+}
+
+/// Returns a description of the last error which occured on the calling thread.
+///
+/// Returns null if no error occured yet. The string is owned by the library, and stays valid until
+/// the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn opusmeta_last_error_message() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+    })
+}
+
+/// Releases a string returned by this API. Does nothing if `string` is null.
+/// # Safety
+/// `string` must be null or a string returned by this API, which wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_string_free(string: *mut c_char) {
+    guard((), || {
+        if !string.is_null() {
+            drop(CString::from_raw(string));
+        }
+    });
+}
+
+/// Creates an empty tag with the given vendor string. Returns null if `vendor` is null or isn't
+/// valid UTF-8.
+/// # Safety
+/// `vendor` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_new(vendor: *const c_char) -> *mut Tag {
+    guard(ptr::null_mut(), || {
+        str_arg(vendor, "vendor").map_or(ptr::null_mut(), |vendor| {
+            Box::into_raw(Box::new(Tag::new(vendor.to_string(), vec![])))
+        })
+    })
+}
+
+/// Releases a tag. Does nothing if `tag` is null.
+/// # Safety
+/// `tag` must be null or a tag returned by this API, which wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_free(tag: *mut Tag) {
+    guard((), || {
+        if !tag.is_null() {
+            drop(Box::from_raw(tag));
+        }
+    });
+}
+
+/// Reads the tags of the file at `path` into `*out`.
+/// # Safety
+/// `path` must be null or a NUL-terminated string, and `out` must be null or valid for writes.
+#[cfg(feature = "fs")]
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_read(
+    path: *const c_char,
+    out: *mut *mut Tag,
+) -> OpusmetaStatus {
+    run(|| {
+        let path = str_arg(path, "path")?;
+        if out.is_null() {
+            return Err(fail(OpusmetaStatus::NullPointer, "`out` is null"));
+        }
+        let tag = Tag::read_from_path(path).map_err(|error| fail_with(&error))?;
+        *out = Box::into_raw(Box::new(tag));
+        Ok(())
+    })
+}
+
+/// Reads the tags of a file held in memory into `*out`.
+/// # Safety
+/// `data` must be valid for reads of `len` bytes, and `out` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_read_buffer(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut Tag,
+) -> OpusmetaStatus {
+    run(|| {
+        if data.is_null() || out.is_null() {
+            return Err(fail(OpusmetaStatus::NullPointer, "`data` or `out` is null"));
+        }
+        let data = std::slice::from_raw_parts(data, len);
+        let tag = Tag::from_slice(data).map_err(|error| fail_with(&error))?;
+        *out = Box::into_raw(Box::new(tag));
+        Ok(())
+    })
+}
+
+/// Writes a tag to the file at `path`, replacing its tags.
+/// # Safety
+/// `tag` must be null or a valid tag, and `path` must be null or a NUL-terminated string.
+#[cfg(feature = "fs")]
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_write(
+    tag: *const Tag,
+    path: *const c_char,
+) -> OpusmetaStatus {
+    run(|| {
+        let tag = tag_arg(tag)?;
+        let path = str_arg(path, "path")?;
+        tag.write_to_path(path).map_err(|error| fail_with(&error))
+    })
+}
+
+/// Gets the vendor string of a tag, into `*out`.
+/// # Safety
+/// `tag` must be null or a valid tag, and `out` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_vendor(
+    tag: *const Tag,
+    out: *mut *mut c_char,
+) -> OpusmetaStatus {
+    run(|| {
+        let tag = tag_arg(tag)?;
+        if out.is_null() {
+            return Err(fail(OpusmetaStatus::NullPointer, "`out` is null"));
+        }
+        return_string(tag.get_vendor(), out)
+    })
+}
+
+/// Sets the vendor string of a tag.
+/// # Safety
+/// `tag` must be null or a valid tag, and `vendor` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_set_vendor(
+    tag: *mut Tag,
+    vendor: *const c_char,
+) -> OpusmetaStatus {
+    run(|| {
+        let tag = tag_arg_mut(tag)?;
+        let vendor = str_arg(vendor, "vendor")?;
+        tag.set_vendor(vendor.to_string());
+        Ok(())
+    })
+}
+
+/// Returns the number of values of a key, or 0 if the key has no values or an argument is
+/// invalid.
+/// # Safety
+/// `tag` must be null or a valid tag, and `key` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_value_count(tag: *const Tag, key: *const c_char) -> usize {
+    guard(0, || {
+        let (Ok(tag), Ok(key)) = (tag_arg(tag), str_arg(key, "key")) else {
+            return 0;
+        };
+        tag.get(key.to_string()).map_or(0, <[String]>::len)
+    })
+}
+
+/// Gets the value of a key at `index` (0 for the first value), into `*out`. Returns
+/// [`OpusmetaStatus::NotFound`] if the key has fewer values.
+/// # Safety
+/// `tag` must be null or a valid tag, `key` must be null or a NUL-terminated string, and `out`
+/// must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_get(
+    tag: *const Tag,
+    key: *const c_char,
+    index: usize,
+    out: *mut *mut c_char,
+) -> OpusmetaStatus {
+    run(|| {
+        let tag = tag_arg(tag)?;
+        let key = str_arg(key, "key")?;
+        if out.is_null() {
+            return Err(fail(OpusmetaStatus::NullPointer, "`out` is null"));
+        }
+        let value = tag
+            .get(key.to_string())
+            .and_then(|values| values.get(index))
+            .ok_or_else(|| {
+                fail(
+                    OpusmetaStatus::NotFound,
+                    format_args!("`{key}` has no value at index {index}"),
+                )
+            })?;
+        return_string(value, out)
+    })
+}
+
+/// Adds a value to a key, after its existing values.
+/// # Safety
+/// `tag` must be null or a valid tag, and `key` and `value` must be null or NUL-terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_add(
+    tag: *mut Tag,
+    key: *const c_char,
+    value: *const c_char,
+) -> OpusmetaStatus {
+    run(|| {
+        let tag = tag_arg_mut(tag)?;
+        let key = str_arg(key, "key")?;
+        let value = str_arg(value, "value")?;
+        tag.add_one(key.to_string(), value.to_string());
+        Ok(())
+    })
+}
+
+/// Replaces all values of a key with a single value.
+/// # Safety
+/// `tag` must be null or a valid tag, and `key` and `value` must be null or NUL-terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_set(
+    tag: *mut Tag,
+    key: *const c_char,
+    value: *const c_char,
+) -> OpusmetaStatus {
+    run(|| {
+        let tag = tag_arg_mut(tag)?;
+        let key = str_arg(key, "key")?;
+        let value = str_arg(value, "value")?;
+        tag.remove_entries(key.to_string());
+        tag.add_one(key.to_string(), value.to_string());
+        Ok(())
+    })
+}
+
+/// Removes all values of a key. Succeeds if the key has no values.
+/// # Safety
+/// `tag` must be null or a valid tag, and `key` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn opusmeta_tag_remove(tag: *mut Tag, key: *const c_char) -> OpusmetaStatus {
+    run(|| {
+        let tag = tag_arg_mut(tag)?;
+        let key = str_arg(key, "key")?;
+        tag.remove_entries(key.to_string());
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_panic_as_error() {
+        assert_eq!(guard(7, || panic!("boom")), 7);
+        // SAFETY: the message stays valid until the next failing call on this thread
+        let message = unsafe { CStr::from_ptr(opusmeta_last_error_message()) };
+        assert_eq!(message.to_str().unwrap(), "panicked: boom");
+        assert_eq!(run(|| panic!("boom")), OpusmetaStatus::Other);
+    }
+}
@@ -0,0 +1,269 @@
+//! Chapters, stored with the [Ogg chapter extension](https://wiki.xiph.org/Chapter_Extension).
+//!
+//! Every chapter is stored in a set of numbered comments: `CHAPTER001=00:00:00.000` holds the time
+//! the chapter starts at, `CHAPTER001NAME=Intro` its title, and `CHAPTER001URL` a link for it.
+//! `CHAPTER001IMAGE`, which isn't part of the extension but is written by some podcast tools,
+//! holds the URL of an image for the chapter. For images embedded in the file,
+//! `CHAPTER001PICTURE` holds a picture in the same format as `METADATA_BLOCK_PICTURE`.
+
+use crate::picture::Picture;
+use crate::{Result, Tag};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use thiserror::Error;
+
+/// A chapter of a file.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Chapter {
+    /// The time the chapter starts at, from the start of the file.
+    pub start: Duration,
+    /// The title of the chapter, which is empty if it has none.
+    pub title: String,
+    /// A URL with more information about the chapter.
+    pub url: Option<String>,
+    /// The URL of an image for the chapter.
+    pub image: Option<String>,
+    /// An image for the chapter, embedded in the file.
+    pub picture: Option<Picture>,
+}
+
+impl Chapter {
+    /// Creates a chapter with a start time and a title.
+    #[must_use]
+    pub const fn new(start: Duration, title: String) -> Self {
+        Self {
+            start,
+            title,
+            url: None,
+            image: None,
+            picture: None,
+        }
+    }
+}
+
+/// Errors that could be raised while reading or writing [`Chapter`]s.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum ChapterError {
+    /// A chapter start time was not in `HH:MM:SS.mmm` format. The offending value is provided for
+    /// convenience.
+    #[error("Invalid chapter start time: {0}")]
+    InvalidTimestamp(String),
+    /// A chapter starts before the one before it. The index of the chapter is provided for
+    /// convenience.
+    #[error("Chapter {0} starts before the chapter before it")]
+    OutOfOrder(usize),
+    /// There is no chapter with this index. The index is provided for convenience.
+    #[error("There is no chapter {0}")]
+    NoSuchChapter(usize),
+}
+
+/// What a chapter comment holds, from the suffix after its number.
+#[derive(Clone, Copy)]
+enum Field {
+    Start,
+    Name,
+    Url,
+    Image,
+    Picture,
+}
+
+/// Splits a lowercase comment key into a chapter number and the field it holds, or returns None
+/// if it isn't a chapter comment.
+fn parse_key(key: &str) -> Option<(u32, Field)> {
+    let rest = key.strip_prefix("chapter")?;
+    let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 {
+        return None;
+    }
+    let (number, suffix) = rest.split_at(digits);
+    let field = match suffix {
+        "" => Field::Start,
+        "name" => Field::Name,
+        "url" => Field::Url,
+        "image" => Field::Image,
+        "picture" => Field::Picture,
+        _ => return None,
+    };
+    Some((number.parse().ok()?, field))
+}
+
+/// Returns true if a lowercase comment key holds the embedded picture of a chapter.
+pub(crate) fn is_picture_key(key: &str) -> bool {
+    matches!(parse_key(key), Some((_, Field::Picture)))
+}
+
+/// Parses a chapter start time in `HH:MM:SS.mmm` format. The hours and the fraction of a second
+/// may be left out, and the fraction may have any number of digits.
+/// # Errors
+/// This function will error if the value isn't in that format, or if the minutes or seconds are
+/// 60 or more.
+pub fn parse_timestamp(value: &str) -> std::result::Result<Duration, ChapterError> {
+    let invalid = || ChapterError::InvalidTimestamp(value.to_string());
+    let (time, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let parts = time
+        .split(':')
+        .map(|part| {
+            if part.is_empty() || !part.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None;
+            }
+            part.parse::<u64>().ok()
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [minutes, seconds] => (0, minutes, seconds),
+        [hours, minutes, seconds] if minutes < 60 => (hours, minutes, seconds),
+        _ => return Err(invalid()),
+    };
+    if seconds >= 60 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    // only the first 9 digits of the fraction fit in nanoseconds
+    let nanos = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0, |nanos, digit| nanos * 10 + u32::from(digit - b'0'));
+    let seconds = hours
+        .checked_mul(3600)
+        .zip(minutes.checked_mul(60))
+        .and_then(|(hours, minutes)| hours.checked_add(minutes)?.checked_add(seconds))
+        .ok_or_else(invalid)?;
+    Ok(Duration::new(seconds, nanos))
+}
+
+/// Formats a chapter start time in `HH:MM:SS.mmm` format, rounding down to the millisecond.
+#[must_use]
+pub fn format_timestamp(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        time.subsec_millis()
+    )
+}
+
+/// Checks that every chapter starts no earlier than the one before it.
+/// # Errors
+/// This function will return [`ChapterError::OutOfOrder`] with the index of the first chapter
+/// which starts before the one before it.
+pub fn validate(chapters: &[Chapter]) -> std::result::Result<(), ChapterError> {
+    chapters
+        .windows(2)
+        .position(|pair| pair[1].start < pair[0].start)
+        .map_or(Ok(()), |index| Err(ChapterError::OutOfOrder(index + 1)))
+}
+
+impl Tag {
+    /// Returns the chapters of the file, in the order of their numbers. Chapters without a start
+    /// time are skipped.
+    /// # Errors
+    /// This function will error if the start time or the picture of a chapter is invalid.
+    pub fn chapters(&self) -> Result<Vec<Chapter>> {
+        let mut chapters: BTreeMap<u32, (Option<Duration>, Chapter)> = BTreeMap::new();
+        for (key, values) in &self.comments {
+            let (Some((number, field)), Some(value)) = (parse_key(key), values.first()) else {
+                continue;
+            };
+            let (start, chapter) = chapters.entry(number).or_default();
+            match field {
+                Field::Start => *start = Some(parse_timestamp(value)?),
+                Field::Name => chapter.title.clone_from(value),
+                Field::Url => chapter.url = Some(value.clone()),
+                Field::Image => chapter.image = Some(value.clone()),
+                Field::Picture => chapter.picture = Some(Picture::from_base64(value)?),
+            }
+        }
+
+        Ok(chapters
+            .into_values()
+            .filter_map(|(start, chapter)| {
+                Some(Chapter {
+                    start: start?,
+                    ..chapter
+                })
+            })
+            .collect())
+    }
+
+    /// Replaces the chapters of the file. The chapters are numbered from 1 in the order they are
+    /// given, and empty titles are left out.
+    /// # Errors
+    /// This function will error if the chapters aren't in order of their start times (see
+    /// [`validate`]), or if a picture can't be encoded, in which case the tag is left unchanged.
+    pub fn set_chapters(&mut self, chapters: &[Chapter]) -> Result<()> {
+        validate(chapters)?;
+        let pictures = chapters
+            .iter()
+            .map(|chapter| chapter.picture.as_ref().map(Picture::to_base64).transpose())
+            .collect::<Result<Vec<_>>>()?;
+        self.remove_chapters();
+        for ((number, chapter), picture) in (1..).zip(chapters).zip(pictures) {
+            let key = format!("CHAPTER{number:03}");
+            self.add_one(key.clone(), format_timestamp(chapter.start));
+            if !chapter.title.is_empty() {
+                self.add_one(format!("{key}NAME"), chapter.title.clone());
+            }
+            if let Some(url) = &chapter.url {
+                self.add_one(format!("{key}URL"), url.clone());
+            }
+            if let Some(image) = &chapter.image {
+                self.add_one(format!("{key}IMAGE"), image.clone());
+            }
+            if let Some(picture) = picture {
+                self.add_one(format!("{key}PICTURE"), picture);
+            }
+        }
+        Ok(())
+    }
+
+    /// Embeds an image for the chapter at `index` in [`chapters`](Self::chapters), replacing
+    /// any it had. Its URL, if any, is kept. The chapters are renumbered from 1, as by
+    /// [`set_chapters`](Self::set_chapters).
+    /// # Errors
+    /// This function will error with [`ChapterError::NoSuchChapter`] if there is no chapter at
+    /// `index`, or for the same reasons as [`chapters`](Self::chapters) and
+    /// [`set_chapters`](Self::set_chapters).
+    pub fn set_chapter_picture(&mut self, index: usize, picture: Picture) -> Result<()> {
+        let mut chapters = self.chapters()?;
+        let chapter = chapters
+            .get_mut(index)
+            .ok_or(ChapterError::NoSuchChapter(index))?;
+        chapter.picture = Some(picture);
+        self.set_chapters(&chapters)
+    }
+
+    /// Removes the embedded image of the chapter at `index` in [`chapters`](Self::chapters),
+    /// and returns it.
+    /// # Errors
+    /// This function will error for the same reasons as
+    /// [`set_chapter_picture`](Self::set_chapter_picture).
+    pub fn remove_chapter_picture(&mut self, index: usize) -> Result<Option<Picture>> {
+        let mut chapters = self.chapters()?;
+        let chapter = chapters
+            .get_mut(index)
+            .ok_or(ChapterError::NoSuchChapter(index))?;
+        let picture = chapter.picture.take();
+        self.set_chapters(&chapters)?;
+        Ok(picture)
+    }
+
+    /// Removes every chapter comment.
+    pub fn remove_chapters(&mut self) {
+        self.comments_mut()
+            .retain(|key, _| parse_key(key).is_none());
+    }
+
+    /// Sorts the chapters by their start times and numbers them from 1, closing any gaps in the
+    /// numbering.
+    /// # Errors
+    /// This function will error for the same reasons as [`chapters`](Self::chapters).
+    pub fn renumber_chapters(&mut self) -> Result<()> {
+        let mut chapters = self.chapters()?;
+        chapters.sort_by_key(|chapter| chapter.start);
+        self.set_chapters(&chapters)
+    }
+}
@@ -0,0 +1,143 @@
+//! Checksums of the audio data, to prove that retagging a file never altered its audio. Requires
+//! the `checksum` feature.
+//!
+//! The checksum is the SHA-256 hash of the packets of the tagged logical stream (the first opus
+//! stream, or the first stream of another supported codec) which follow its comment header, so
+//! it doesn't depend on the tags, nor on how the packets are laid out in pages (which changes
+//! when the comment header grows or shrinks). It is stored in the `OPUSMETA_AUDIO_SHA256`
+//! comment, in lowercase hexadecimal.
+
+use crate::{Codec, Error, Result, Tag};
+use ogg::PacketReader;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// The comment the audio checksum is stored in.
+pub const AUDIO_CHECKSUM_KEY: &str = "OPUSMETA_AUDIO_SHA256";
+
+/// Computes the checksum of the audio of the first opus stream in a reader, or of the first stream
+/// of another supported [`Codec`] if there is none, in lowercase hexadecimal.
+/// # Errors
+/// This function will error if reading fails, or if the reader doesn't contain a stream of a
+/// supported codec with a comment header.
+pub fn audio_checksum_from<R: Read + Seek>(f_in: R) -> Result<String> {
+    let mut reader = PacketReader::new(f_in);
+    let mut stream: Option<(u32, Codec)> = None;
+    let mut bos_done = false;
+    // packets of the stream seen after its identification header; the first is the comment header
+    let mut packets = 0u64;
+    let mut hasher = Sha256::new();
+
+    while let Some(packet) = reader.read_packet()? {
+        // all BOS pages come before any other page, and an opus stream is preferred to a stream
+        // of another codec found before it
+        bos_done |= !packet.first_in_stream();
+        if !bos_done {
+            let chosen = stream.map(|(_, codec)| codec);
+            if let Some(codec) =
+                Codec::from_first_packet(&packet.data).filter(|codec| codec.is_preferred_to(chosen))
+            {
+                stream = Some((packet.stream_serial(), codec));
+            }
+            continue;
+        }
+        let Some((serial, _)) = stream else {
+            // there are no supported streams
+            break;
+        };
+        if packet.stream_serial() != serial {
+            continue;
+        }
+        packets += 1;
+        if packets > 1 {
+            hasher.update(&packet.data);
+        }
+    }
+
+    if stream.is_none() {
+        return Err(Error::NotOpus);
+    }
+    if packets < 1 {
+        return Err(Error::MissingPacket);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut output, byte| {
+            let _ = write!(output, "{byte:02x}");
+            output
+        }))
+}
+
+/// Computes the checksum of the audio of a file. See [`audio_checksum_from`].
+/// # Errors
+/// This function will error if the file cannot be opened, or for the same reasons as
+/// [`audio_checksum_from`].
+#[cfg(feature = "fs")]
+pub fn audio_checksum_path<P: AsRef<Path>>(path: P) -> Result<String> {
+    let file = File::open(path)?;
+    audio_checksum_from(BufReader::new(file))
+}
+
+/// Computes the checksum of the audio of a file and stores it in the file's
+/// `OPUSMETA_AUDIO_SHA256` comment. Returns the checksum.
+/// # Errors
+/// This function will error for the same reasons as [`audio_checksum_path`], or if writing the
+/// tags fails.
+#[cfg(feature = "fs")]
+pub fn add_audio_checksum<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let checksum = audio_checksum_path(path)?;
+    let mut tag = Tag::read_from_path(path)?;
+    tag.set_audio_checksum(checksum.clone());
+    tag.write_to_path(path)?;
+    Ok(checksum)
+}
+
+/// Checks the audio of the file in a reader against the checksum stored in its tags. Returns
+/// None if no checksum is stored, and whether the audio matches it otherwise.
+/// # Errors
+/// This function will error if the tags can't be read, or for the same reasons as
+/// [`audio_checksum_from`].
+pub fn verify_audio_checksum_from<R: Read + Seek>(mut f_in: R) -> Result<Option<bool>> {
+    let start = f_in.stream_position()?;
+    let tag = Tag::read_from(&mut f_in)?;
+    let Some(stored) = tag.audio_checksum() else {
+        return Ok(None);
+    };
+    f_in.seek(SeekFrom::Start(start))?;
+    let checksum = audio_checksum_from(f_in)?;
+    Ok(Some(stored.eq_ignore_ascii_case(&checksum)))
+}
+
+/// Checks the audio of a file against the checksum stored in its tags. See
+/// [`verify_audio_checksum_from`].
+/// # Errors
+/// This function will error if the file cannot be opened, or for the same reasons as
+/// [`verify_audio_checksum_from`].
+#[cfg(feature = "fs")]
+pub fn verify_audio_checksum<P: AsRef<Path>>(path: P) -> Result<Option<bool>> {
+    let file = File::open(path)?;
+    verify_audio_checksum_from(BufReader::new(file))
+}
+
+impl Tag {
+    /// Gets the audio checksum stored in the `OPUSMETA_AUDIO_SHA256` comment.
+    #[must_use]
+    pub fn audio_checksum(&self) -> Option<&String> {
+        self.get_one(AUDIO_CHECKSUM_KEY.to_string())
+    }
+
+    /// Replaces the audio checksum stored in the `OPUSMETA_AUDIO_SHA256` comment.
+    pub fn set_audio_checksum(&mut self, checksum: String) {
+        self.remove_entries(AUDIO_CHECKSUM_KEY.to_string());
+        self.add_one(AUDIO_CHECKSUM_KEY.to_string(), checksum);
+    }
+}
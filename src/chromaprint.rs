@@ -0,0 +1,125 @@
+//! Computation of Chromaprint fingerprints with the Chromaprint library, so that fingerprints can
+//! be looked up with the `AcoustID` web service.
+//!
+//! The audio is decoded with libopus and fed to libchromaprint, which downmixes, resamples and
+//! fingerprints it with its default algorithm, exactly as the `fpcalc` tool does.
+
+use crate::decode::{OpusDecoder, SAMPLE_RATE};
+use crate::{Error, Result};
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::io::{Read, Seek};
+use std::time::Duration;
+
+/// The length of the audio which is fingerprinted, as done by the `fpcalc` tool.
+const MAX_SECONDS: u64 = 120;
+/// `CHROMAPRINT_ALGORITHM_TEST2`, the default algorithm, used by `fpcalc` and `AcoustID`.
+const ALGORITHM: c_int = 1;
+
+#[repr(C)]
+struct ChromaprintContext {
+    _private: [u8; 0],
+}
+
+#[link(name = "chromaprint")]
+extern "C" {
+    fn chromaprint_new(algorithm: c_int) -> *mut ChromaprintContext;
+    fn chromaprint_free(ctx: *mut ChromaprintContext);
+    fn chromaprint_start(
+        ctx: *mut ChromaprintContext,
+        sample_rate: c_int,
+        num_channels: c_int,
+    ) -> c_int;
+    fn chromaprint_feed(ctx: *mut ChromaprintContext, data: *const i16, size: c_int) -> c_int;
+    fn chromaprint_finish(ctx: *mut ChromaprintContext) -> c_int;
+    fn chromaprint_get_fingerprint(
+        ctx: *mut ChromaprintContext,
+        fingerprint: *mut *mut c_char,
+    ) -> c_int;
+    fn chromaprint_dealloc(ptr: *mut c_void);
+}
+
+/// An owned libchromaprint context, freed on drop.
+struct Context(*mut ChromaprintContext);
+
+impl Context {
+    fn new(channels: usize) -> Result<Self> {
+        // SAFETY: chromaprint_new has no preconditions, and returns null on failure
+        let context = Self(unsafe { chromaprint_new(ALGORITHM) });
+        if context.0.is_null() {
+            return Err(Error::FingerprintError);
+        }
+        let channels = c_int::try_from(channels)?;
+        let rate = c_int::try_from(SAMPLE_RATE)?;
+        // SAFETY: the context is valid until it is dropped
+        check(unsafe { chromaprint_start(context.0, rate, channels) })?;
+        Ok(context)
+    }
+
+    /// Feeds interleaved samples.
+    fn feed(&mut self, samples: &[i16]) -> Result<()> {
+        // SAFETY: the pointer and length describe `samples`, which the library only reads
+        check(unsafe {
+            chromaprint_feed(self.0, samples.as_ptr(), c_int::try_from(samples.len())?)
+        })
+    }
+
+    /// Finishes the audio and returns the fingerprint, compressed and encoded in URL-safe base64.
+    fn finish(self) -> Result<String> {
+        // SAFETY: the context is valid, and the fingerprint returned on success is a nul
+        // terminated string which has to be freed with chromaprint_dealloc
+        unsafe {
+            check(chromaprint_finish(self.0))?;
+            let mut fingerprint = std::ptr::null_mut();
+            check(chromaprint_get_fingerprint(self.0, &raw mut fingerprint))?;
+            if fingerprint.is_null() {
+                return Err(Error::FingerprintError);
+            }
+            let encoded = CStr::from_ptr(fingerprint).to_string_lossy().into_owned();
+            chromaprint_dealloc(fingerprint.cast());
+            Ok(encoded)
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // SAFETY: the context was created by chromaprint_new and is freed only here
+        unsafe { chromaprint_free(self.0) }
+    }
+}
+
+/// Turns the status returned by the library (1 on success) into a result.
+const fn check(status: c_int) -> Result<()> {
+    if status == 1 {
+        Ok(())
+    } else {
+        Err(Error::FingerprintError)
+    }
+}
+
+/// Decodes the first opus stream of a reader and computes its fingerprint, returning it encoded
+/// and the duration of the stream.
+pub fn fingerprint<R: Read + Seek>(f_in: R) -> Result<(String, Duration)> {
+    let mut decoder = OpusDecoder::new(f_in)?;
+    let channels = decoder.channels();
+    let mut context = Context::new(channels)?;
+    let limit = MAX_SECONDS * u64::from(SAMPLE_RATE);
+    let mut length = 0u64;
+    while let Some(samples) = decoder.next_chunk()? {
+        let frames = (samples.len() / channels) as u64;
+        if length < limit {
+            let fed = usize::try_from(frames.min(limit - length))? * channels;
+            // scaled to the 16 bit samples the library works with
+            #[allow(clippy::cast_possible_truncation)]
+            let samples: Vec<i16> = samples[..fed]
+                .iter()
+                .map(|&sample| (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16)
+                .collect();
+            context.feed(&samples)?;
+        }
+        length += frames;
+    }
+
+    let duration = Duration::from_nanos(length.saturating_mul(62_500) / 3);
+    Ok((context.finish()?, duration))
+}
@@ -0,0 +1,196 @@
+//! Classical music metadata: the composer, conductor and opus number, the work a track is part
+//! of, and its movement.
+//!
+//! The movement comments follow `MusicBrainz` Picard, the tagger most classical libraries are
+//! tagged with: `MOVEMENTNAME` holds the name of the movement, `MOVEMENT` its number and
+//! `MOVEMENTTOTAL` the number of movements in the work. Other taggers write the name to
+//! `MOVEMENT` and the number to `MOVEMENTNUMBER`, so a `MOVEMENT` comment is read as a number if
+//! it is one (`3`, or `3/4` with the total), and as the name otherwise. Values are always written
+//! the way Picard writes them, and the other spellings are removed.
+
+use crate::Tag;
+
+/// The keys of the composer.
+const COMPOSER_KEYS: [&str; 1] = ["COMPOSER"];
+
+/// The keys of the conductor.
+const CONDUCTOR_KEYS: [&str; 1] = ["CONDUCTOR"];
+
+/// The keys of the opus number.
+const OPUS_KEYS: [&str; 1] = ["OPUS"];
+
+/// The keys of the work.
+const WORK_KEYS: [&str; 1] = ["WORK"];
+
+/// The keys of the movement total, preferred first.
+const MOVEMENT_TOTAL_KEYS: [&str; 2] = ["MOVEMENTTOTAL", "MOVEMENTCOUNT"];
+
+/// The classical music metadata of a file. See [`Tag::classical_info`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassicalInfo {
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    /// The opus number of the work, such as `Op. 125` or `BWV 1007`.
+    pub opus: Option<String>,
+    /// The work the track is part of, such as `Symphony No. 9 in D minor`.
+    pub work: Option<String>,
+    /// The name of the movement, such as `Adagio molto e cantabile`.
+    pub movement: Option<String>,
+    /// The number of the movement in the work, from 1.
+    pub movement_number: Option<u32>,
+    /// The number of movements in the work.
+    pub movement_total: Option<u32>,
+}
+
+/// Parses a movement number, with the total if there is one, as in `3` or `3/4`.
+fn parse_number(value: &str) -> Option<(u32, Option<u32>)> {
+    let (number, total) = value
+        .split_once('/')
+        .map_or((value, None), |(number, total)| (number, Some(total)));
+    let number = number.trim().parse().ok()?;
+    let total = total.map(|total| total.trim().parse()).transpose().ok()?;
+    Some((number, total))
+}
+
+impl Tag {
+    /// Gets the composer, from the `COMPOSER` comment.
+    #[must_use]
+    pub fn composer(&self) -> Option<&String> {
+        self.get_first_of(&COMPOSER_KEYS)
+    }
+
+    /// Replaces the composer, stored in the `COMPOSER` comment.
+    pub fn set_composer(&mut self, composer: String) {
+        self.set_first_of(&COMPOSER_KEYS, Some(composer));
+    }
+
+    /// Gets the conductor, from the `CONDUCTOR` comment.
+    #[must_use]
+    pub fn conductor(&self) -> Option<&String> {
+        self.get_first_of(&CONDUCTOR_KEYS)
+    }
+
+    /// Replaces the conductor, stored in the `CONDUCTOR` comment.
+    pub fn set_conductor(&mut self, conductor: String) {
+        self.set_first_of(&CONDUCTOR_KEYS, Some(conductor));
+    }
+
+    /// Gets the opus number of the work, from the `OPUS` comment.
+    #[must_use]
+    pub fn opus(&self) -> Option<&String> {
+        self.get_first_of(&OPUS_KEYS)
+    }
+
+    /// Replaces the opus number of the work, stored in the `OPUS` comment.
+    pub fn set_opus(&mut self, opus: String) {
+        self.set_first_of(&OPUS_KEYS, Some(opus));
+    }
+
+    /// Gets the work the track is part of, from the `WORK` comment.
+    #[must_use]
+    pub fn work(&self) -> Option<&String> {
+        self.get_first_of(&WORK_KEYS)
+    }
+
+    /// Replaces the work the track is part of, stored in the `WORK` comment.
+    pub fn set_work(&mut self, work: String) {
+        self.set_first_of(&WORK_KEYS, Some(work));
+    }
+
+    /// Gets the name of the movement, from the `MOVEMENTNAME` comment, or from the `MOVEMENT`
+    /// comment if it isn't a number.
+    #[must_use]
+    pub fn movement(&self) -> Option<&String> {
+        self.get_one("MOVEMENTNAME".to_string()).or_else(|| {
+            self.get_one("MOVEMENT".to_string())
+                .filter(|value| parse_number(value).is_none())
+        })
+    }
+
+    /// Replaces the name of the movement, stored in the `MOVEMENTNAME` comment.
+    pub fn set_movement(&mut self, movement: String) {
+        self.set_movement_fields(Some(movement), self.movement_number());
+    }
+
+    /// Gets the number of the movement in the work, from the `MOVEMENT` comment if it is a
+    /// number, or from the `MOVEMENTNUMBER` comment.
+    #[must_use]
+    pub fn movement_number(&self) -> Option<u32> {
+        self.movement_numbers().map(|(number, _)| number)
+    }
+
+    /// Replaces the number of the movement in the work, stored in the `MOVEMENT` comment.
+    pub fn set_movement_number(&mut self, number: u32) {
+        self.set_movement_fields(self.movement().cloned(), Some(number));
+    }
+
+    /// Gets the number of movements in the work, from the `MOVEMENTTOTAL` or `MOVEMENTCOUNT`
+    /// comment, or from a movement number such as `3/4`.
+    #[must_use]
+    pub fn movement_total(&self) -> Option<u32> {
+        self.get_first_of(&MOVEMENT_TOTAL_KEYS)
+            .and_then(|value| value.trim().parse().ok())
+            .or_else(|| self.movement_numbers().and_then(|(_, total)| total))
+    }
+
+    /// Replaces the number of movements in the work, stored in the `MOVEMENTTOTAL` comment.
+    pub fn set_movement_total(&mut self, total: u32) {
+        self.set_movement_total_field(Some(total));
+    }
+
+    /// Returns all the classical music metadata of the file.
+    #[must_use]
+    pub fn classical_info(&self) -> ClassicalInfo {
+        ClassicalInfo {
+            composer: self.composer().cloned(),
+            conductor: self.conductor().cloned(),
+            opus: self.opus().cloned(),
+            work: self.work().cloned(),
+            movement: self.movement().cloned(),
+            movement_number: self.movement_number(),
+            movement_total: self.movement_total(),
+        }
+    }
+
+    /// Replaces all the classical music metadata of the file. Fields which are None are removed,
+    /// under every spelling.
+    pub fn set_classical_info(&mut self, info: ClassicalInfo) {
+        self.set_first_of(&COMPOSER_KEYS, info.composer);
+        self.set_first_of(&CONDUCTOR_KEYS, info.conductor);
+        self.set_first_of(&OPUS_KEYS, info.opus);
+        self.set_first_of(&WORK_KEYS, info.work);
+        self.set_movement_fields(info.movement, info.movement_number);
+        self.set_movement_total_field(info.movement_total);
+    }
+
+    /// Returns the movement number, and the total if it is written with the number.
+    fn movement_numbers(&self) -> Option<(u32, Option<u32>)> {
+        ["MOVEMENT", "MOVEMENTNUMBER"]
+            .iter()
+            .filter_map(|key| self.get_one((*key).to_string()))
+            .find_map(|value| parse_number(value))
+    }
+
+    /// Replaces the movement name and number, which share the `MOVEMENT` comment.
+    fn set_movement_fields(&mut self, movement: Option<String>, number: Option<u32>) {
+        // a total written with the number would be lost
+        let total = self.movement_total();
+        for key in ["MOVEMENTNAME", "MOVEMENT", "MOVEMENTNUMBER"] {
+            self.remove_entries(key.to_string());
+        }
+        if let Some(movement) = movement {
+            self.add_one("MOVEMENTNAME".to_string(), movement);
+        }
+        if let Some(number) = number {
+            self.add_one("MOVEMENT".to_string(), number.to_string());
+        }
+        if self.get_first_of(&MOVEMENT_TOTAL_KEYS).is_none() {
+            self.set_movement_total_field(total);
+        }
+    }
+
+    /// Replaces the movement total.
+    fn set_movement_total_field(&mut self, total: Option<u32>) {
+        self.set_first_of(&MOVEMENT_TOTAL_KEYS, total.map(|total| total.to_string()));
+    }
+}
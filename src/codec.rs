@@ -0,0 +1,161 @@
+//! The codecs whose comment headers can be read and written.
+
+use crate::{Error, Result};
+
+/// A codec whose Ogg streams carry their metadata in a Vorbis-style comment header: a vendor
+/// string followed by `KEY=VALUE` comments, which is what a [`Tag`](crate::Tag) holds.
+///
+/// The functions which read from or write to an Ogg stream without taking a serial number work
+/// on its first Opus stream, or if it has none, on its first logical stream of any of these
+/// codecs. Other streams can be selected with [`OggFile`](crate::OggFile).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Codec {
+    /// Opus (RFC 7845). The comment header starts with `OpusTags`.
+    Opus,
+    /// Vorbis. The comment header starts with `\x03vorbis`, and ends with a framing bit.
+    Vorbis,
+    /// Speex. The comment header has no magic signature, so a bare Speex comment header packet
+    /// can only be parsed with [`TagRef::from_codec_packet`](crate::TagRef::from_codec_packet).
+    Speex,
+    /// Theora video. The comment header starts with `\x81theora`.
+    ///
+    /// The Theora stream of a video file comes before its audio streams, but an Opus audio stream
+    /// is still the one whose comments are read and written by the functions which don't take a
+    /// serial number.
+    Theora,
+    /// FLAC in Ogg. The comment header is a `VORBIS_COMMENT` metadata block, which starts with a
+    /// 4 byte block header instead of a magic signature. As with Speex, a bare FLAC comment
+    /// header packet can only be parsed with
+    /// [`TagRef::from_codec_packet`](crate::TagRef::from_codec_packet).
+    Flac,
+}
+
+/// Type of the `VORBIS_COMMENT` metadata block of FLAC.
+const FLAC_VORBIS_COMMENT: u8 = 4;
+/// Flag set in a FLAC metadata block header if the block is the last one.
+const FLAC_LAST_BLOCK: u8 = 0x80;
+/// Size of a FLAC metadata block header.
+const FLAC_BLOCK_HEADER_SIZE: usize = 4;
+/// Largest length of a FLAC metadata block, which is stored in 24 bits.
+const FLAC_MAX_BLOCK_LENGTH: usize = (1 << 24) - 1;
+
+impl Codec {
+    /// Identifies the codec of a logical stream from its first packet (the identification
+    /// header), or returns None if the codec isn't supported.
+    #[must_use]
+    pub fn from_first_packet(packet: &[u8]) -> Option<Self> {
+        if packet.starts_with(b"OpusHead") {
+            Some(Self::Opus)
+        } else if packet.starts_with(b"\x01vorbis") {
+            Some(Self::Vorbis)
+        } else if packet.starts_with(b"Speex   ") {
+            Some(Self::Speex)
+        } else if packet.starts_with(b"\x80theora") {
+            Some(Self::Theora)
+        } else if packet.starts_with(b"\x7fFLAC") {
+            Some(Self::Flac)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a stream of this codec is the one to work on rather than the stream of `chosen`
+    /// found before it, for the functions which don't take a serial number: the first Opus
+    /// stream wins over any other, and otherwise the first stream does.
+    pub(crate) fn is_preferred_to(self, chosen: Option<Self>) -> bool {
+        chosen.is_none_or(|chosen| self == Self::Opus && chosen != Self::Opus)
+    }
+
+    /// Identifies the codec of a bare comment header packet from its magic signature. Codecs
+    /// without one are never returned.
+    pub(crate) fn from_comment_header(packet: &[u8]) -> Option<Self> {
+        [Self::Opus, Self::Vorbis, Self::Theora]
+            .into_iter()
+            .find(|codec| packet.starts_with(codec.comment_magic()))
+    }
+
+    /// The magic signature the comment header starts with, which is empty for Speex and FLAC.
+    #[must_use]
+    pub const fn comment_magic(self) -> &'static [u8] {
+        match self {
+            Self::Opus => b"OpusTags",
+            Self::Vorbis => b"\x03vorbis",
+            Self::Theora => b"\x81theora",
+            Self::Speex | Self::Flac => b"",
+        }
+    }
+
+    /// Whether the comment header ends with a framing bit, a byte with its lowest bit set.
+    pub(crate) const fn has_framing_bit(self) -> bool {
+        matches!(self, Self::Vorbis)
+    }
+
+    /// Size of what comes before the vendor string in the comment header.
+    pub(crate) const fn prefix_size(self) -> usize {
+        match self {
+            Self::Flac => FLAC_BLOCK_HEADER_SIZE,
+            _ => self.comment_magic().len(),
+        }
+    }
+
+    /// Checks and skips what comes before the vendor string in a comment header. Returns None if
+    /// the header doesn't start as expected.
+    pub(crate) fn strip_prefix(self, packet: &[u8]) -> Option<&[u8]> {
+        match self {
+            Self::Flac => packet
+                .split_first()
+                .filter(|(&block_type, _)| block_type & !FLAC_LAST_BLOCK == FLAC_VORBIS_COMMENT)
+                .and_then(|(_, rest)| rest.get(FLAC_BLOCK_HEADER_SIZE - 1..)),
+            _ => packet.strip_prefix(self.comment_magic()),
+        }
+    }
+
+    /// Writes what comes before the vendor string in a comment header. For FLAC, the block
+    /// length is filled in by [`finish`](Self::finish).
+    pub(crate) fn write_prefix(self, output: &mut Vec<u8>) {
+        match self {
+            Self::Flac => output.extend_from_slice(&[FLAC_VORBIS_COMMENT, 0, 0, 0]),
+            _ => output.extend_from_slice(self.comment_magic()),
+        }
+    }
+
+    /// Completes a comment header once all of it has been written, filling in the block length
+    /// for FLAC. Fails if the header is too long for the block length.
+    pub(crate) fn finish(self, packet: &mut [u8]) -> Result<()> {
+        if self == Self::Flac {
+            let length = packet.len().saturating_sub(FLAC_BLOCK_HEADER_SIZE);
+            if length > FLAC_MAX_BLOCK_LENGTH {
+                return Err(Error::TooBigError);
+            }
+            // fits in 24 bits
+            #[allow(clippy::cast_possible_truncation)]
+            let length = length as u32;
+            packet[1..FLAC_BLOCK_HEADER_SIZE].copy_from_slice(&length.to_be_bytes()[1..]);
+        }
+        Ok(())
+    }
+
+    /// Pads a comment header with zero bytes up to `length` bytes, unless it is already that
+    /// long, or the codec can't hold a header that long.
+    pub(crate) fn pad(self, packet: &mut Vec<u8>, length: usize) {
+        if length <= packet.len() {
+            return;
+        }
+        let original = packet.len();
+        packet.resize(length, 0);
+        if self.finish(packet).is_err() {
+            packet.truncate(original);
+        }
+    }
+
+    /// Carries over the parts of an existing comment header which aren't comments to a new one:
+    /// the last-block flag of FLAC, which depends on the metadata blocks following the header.
+    pub(crate) fn copy_flags(self, old: &[u8], new: &mut [u8]) {
+        if self == Self::Flac {
+            if let (Some(old), Some(new)) = (old.first(), new.first_mut()) {
+                *new = (*new & !FLAC_LAST_BLOCK) | (old & FLAC_LAST_BLOCK);
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Collapsing keys with several values into one value.
+
+use crate::Tag;
+use smallvec::smallvec;
+
+/// How [`Tag::ensure_single`] collapses the values of a key into one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CollapseStrategy {
+    /// Keeps the first value.
+    #[default]
+    KeepFirst,
+    /// Keeps the last value.
+    KeepLast,
+    /// Joins the values with the separator, such as `"; "`.
+    Join(String),
+}
+
+impl Tag {
+    /// Collapses the values of a key into a single value, for exporting to formats which can't
+    /// represent several values of a key. Returns true if the key had more than one value.
+    pub fn ensure_single(&mut self, mut tag: String, strategy: &CollapseStrategy) -> bool {
+        tag.make_ascii_lowercase();
+        let Some(values) = self.comments_mut().get_mut(tag.as_str()) else {
+            return false;
+        };
+        if values.len() < 2 {
+            return false;
+        }
+        let value = match strategy {
+            CollapseStrategy::KeepFirst => values.swap_remove(0),
+            CollapseStrategy::KeepLast => values.pop().unwrap_or_default(),
+            CollapseStrategy::Join(separator) => values.join(separator),
+        };
+        *values = smallvec![value];
+        true
+    }
+}
@@ -0,0 +1,78 @@
+//! A single comment, with a key validated against the spec.
+
+use crate::diagnostics::is_valid_key;
+use crate::{Error, Result};
+use std::fmt;
+
+/// A comment: a key and a value, as in `ARTIST=Someone`.
+///
+/// The key is checked when the comment is created, so that a [`Tag`](crate::Tag) built from
+/// comments can't hold a key which would be written as a malformed comment: keys must be
+/// non-empty and only contain printable ASCII (0x20 to 0x7D) other than `=`. Values can be any
+/// text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Comment {
+    key: String,
+    value: String,
+}
+
+impl Comment {
+    /// Creates a comment.
+    /// # Errors
+    /// This function will error with [`Error::InvalidKey`] if the key isn't allowed by the spec.
+    pub fn new(key: impl Into<String>, value: impl Into<String>) -> Result<Self> {
+        let key = key.into();
+        if !is_valid_key(&key) {
+            return Err(Error::InvalidKey(key));
+        }
+        Ok(Self {
+            key,
+            value: value.into(),
+        })
+    }
+
+    /// Creates a comment from a key and value stored in a tag, which aren't checked again.
+    pub(crate) const fn new_unchecked(key: String, value: String) -> Self {
+        Self { key, value }
+    }
+
+    /// Returns the key.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the value.
+    #[must_use]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Splits the comment into its key and value.
+    #[must_use]
+    pub fn into_parts(self) -> (String, String) {
+        (self.key, self.value)
+    }
+}
+
+impl TryFrom<(String, String)> for Comment {
+    type Error = Error;
+
+    fn try_from((key, value): (String, String)) -> Result<Self> {
+        Self::new(key, value)
+    }
+}
+
+impl TryFrom<(&str, &str)> for Comment {
+    type Error = Error;
+
+    fn try_from((key, value): (&str, &str)) -> Result<Self> {
+        Self::new(key, value)
+    }
+}
+
+impl fmt::Display for Comment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
@@ -0,0 +1,345 @@
+//! Cue sheets embedded in a `CUESHEET` comment, as done for single-file album rips.
+//!
+//! A cue sheet describes the tracks of an album stored as one file: where each of them starts,
+//! and their titles and performers. See
+//! <https://wiki.hydrogenaud.io/index.php?title=Cue_sheet> for the format. Only the commands
+//! describing the tracks are kept when parsing; the others (`FLAGS`, `PREGAP`, `REM`, ...) are
+//! skipped.
+//!
+//! Players which don't understand cue sheets often understand [chapters](crate::chapters), which
+//! [`Tag::chapters_from_cue_sheet`] makes from the tracks of a cue sheet.
+
+use crate::chapters::Chapter;
+use crate::{Result, Tag};
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Number of frames per second in cue sheet times.
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// A cue sheet.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct CueSheet {
+    /// The performer of the album.
+    pub performer: Option<String>,
+    /// The title of the album.
+    pub title: Option<String>,
+    /// The UPC/EAN code of the album.
+    pub catalog: Option<String>,
+    /// The name of the file the tracks are in. It is written as a `WAVE` file, which is what
+    /// embedded cue sheets conventionally use whatever the actual format.
+    pub file: Option<String>,
+    /// The tracks, in order.
+    pub tracks: Vec<CueTrack>,
+}
+
+/// A track of a [`CueSheet`].
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct CueTrack {
+    /// The number of the track.
+    pub number: u32,
+    /// The title of the track.
+    pub title: Option<String>,
+    /// The performer of the track.
+    pub performer: Option<String>,
+    /// The songwriter of the track.
+    pub songwriter: Option<String>,
+    /// The ISRC code of the track.
+    pub isrc: Option<String>,
+    /// The indices of the track, in order.
+    pub indices: Vec<CueIndex>,
+}
+
+/// An index of a [`CueTrack`]: a position in the file. Index 1 is the start of the track, and
+/// index 0, if any, the start of its pregap.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CueIndex {
+    /// The number of the index.
+    pub number: u32,
+    /// The position of the index, from the start of the file. Cue sheets store positions in
+    /// frames of 1/75 of a second, so positions are rounded to the nearest frame when written.
+    pub position: Duration,
+}
+
+/// Errors that could be raised while parsing a [`CueSheet`].
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum CueSheetError {
+    /// A `TRACK` or `INDEX` command was malformed. The offending line is provided for
+    /// convenience.
+    #[error("Malformed cue sheet line: {0}")]
+    MalformedLine(String),
+    /// An `INDEX` command came before the first `TRACK` command. The offending line is provided
+    /// for convenience.
+    #[error("Cue sheet index outside of a track: {0}")]
+    IndexOutsideTrack(String),
+}
+
+/// Splits a cue sheet line into its command and the rest of the line.
+fn split_command(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    line.split_once(char::is_whitespace)
+        .map_or((line, ""), |(command, rest)| (command, rest.trim_start()))
+}
+
+/// Reads a value which may be surrounded by quotes, returning it and the rest of the line.
+fn split_value(rest: &str) -> (String, &str) {
+    if let Some(quoted) = rest.strip_prefix('"') {
+        if let Some((value, after)) = quoted.split_once('"') {
+            return (value.to_string(), after.trim_start());
+        }
+        return (quoted.to_string(), "");
+    }
+    let (value, after) = split_command(rest);
+    (value.to_string(), after)
+}
+
+/// Parses a time in `MM:SS:FF` format, where FF is a number of frames.
+fn parse_time(value: &str) -> Option<Duration> {
+    let mut parts = value.split(':').map(|part| part.parse::<u64>().ok());
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if seconds >= 60 || frames >= FRAMES_PER_SECOND {
+        return None;
+    }
+    let frames = minutes
+        .checked_mul(60)?
+        .checked_add(seconds)?
+        .checked_mul(FRAMES_PER_SECOND)?
+        .checked_add(frames)?;
+    Some(Duration::from_nanos(
+        (u128::from(frames) * 1_000_000_000 / u128::from(FRAMES_PER_SECOND))
+            .try_into()
+            .ok()?,
+    ))
+}
+
+/// Formats a time in `MM:SS:FF` format, rounded to the nearest frame.
+fn format_time(time: Duration) -> String {
+    let frames = (time.as_nanos() * u128::from(FRAMES_PER_SECOND) + 500_000_000) / 1_000_000_000;
+    let per_minute = u128::from(FRAMES_PER_SECOND) * 60;
+    format!(
+        "{:02}:{:02}:{:02}",
+        frames / per_minute,
+        frames % per_minute / u128::from(FRAMES_PER_SECOND),
+        frames % u128::from(FRAMES_PER_SECOND)
+    )
+}
+
+impl CueSheet {
+    /// Parses a cue sheet.
+    /// # Errors
+    /// This function will error if a `TRACK` or `INDEX` command is malformed, or if an index
+    /// comes before the first track.
+    pub fn parse(text: &str) -> std::result::Result<Self, CueSheetError> {
+        let mut sheet = Self::default();
+        for line in text.lines() {
+            let malformed = || CueSheetError::MalformedLine(line.to_string());
+            let (command, rest) = split_command(line);
+            let (value, after) = split_value(rest);
+            let track = sheet.tracks.last_mut();
+            match command.to_ascii_uppercase().as_str() {
+                "TRACK" => {
+                    let number = value.parse().map_err(|_| malformed())?;
+                    sheet.tracks.push(CueTrack {
+                        number,
+                        ..CueTrack::default()
+                    });
+                }
+                "INDEX" => {
+                    let track =
+                        track.ok_or_else(|| CueSheetError::IndexOutsideTrack(line.to_string()))?;
+                    let number = value.parse().map_err(|_| malformed())?;
+                    let position = parse_time(after.trim()).ok_or_else(malformed)?;
+                    track.indices.push(CueIndex { number, position });
+                }
+                "TITLE" => match track {
+                    Some(track) => track.title = Some(value),
+                    None => sheet.title = Some(value),
+                },
+                "PERFORMER" => match track {
+                    Some(track) => track.performer = Some(value),
+                    None => sheet.performer = Some(value),
+                },
+                "SONGWRITER" => {
+                    if let Some(track) = track {
+                        track.songwriter = Some(value);
+                    }
+                }
+                "ISRC" => {
+                    if let Some(track) = track {
+                        track.isrc = Some(value);
+                    }
+                }
+                "CATALOG" => sheet.catalog = Some(value),
+                "FILE" => {
+                    sheet.file.get_or_insert(value);
+                }
+                _ => {}
+            }
+        }
+        Ok(sheet)
+    }
+
+    /// Creates a cue sheet with a track for every chapter, numbered from 1 in the order the
+    /// chapters are given. Each track has the title of its chapter and a single index at its
+    /// start.
+    #[must_use]
+    pub fn from_chapters(chapters: &[Chapter]) -> Self {
+        let tracks = (1..)
+            .zip(chapters)
+            .map(|(number, chapter)| CueTrack {
+                number,
+                title: Some(chapter.title.clone()).filter(|title| !title.is_empty()),
+                indices: vec![CueIndex {
+                    number: 1,
+                    position: chapter.start,
+                }],
+                ..CueTrack::default()
+            })
+            .collect();
+        Self {
+            tracks,
+            ..Self::default()
+        }
+    }
+
+    /// Returns a chapter for every track with an index, titled after the track, in order of
+    /// their start times.
+    #[must_use]
+    pub fn to_chapters(&self) -> Vec<Chapter> {
+        let mut chapters: Vec<Chapter> = self
+            .tracks
+            .iter()
+            .filter_map(|track| {
+                let title = track.title.clone().unwrap_or_default();
+                Some(Chapter::new(track.start()?, title))
+            })
+            .collect();
+        chapters.sort_by_key(|chapter| chapter.start);
+        chapters
+    }
+
+    /// Returns the track playing at the given time: the last one starting at or before it.
+    #[must_use]
+    pub fn track_at(&self, time: Duration) -> Option<&CueTrack> {
+        self.tracks
+            .iter()
+            .rev()
+            .find(|track| track.start().is_some_and(|start| start <= time))
+    }
+}
+
+impl CueTrack {
+    /// Returns the position the track starts at: that of index 1, or of its first index if it
+    /// has no index 1.
+    #[must_use]
+    pub fn start(&self) -> Option<Duration> {
+        self.indices
+            .iter()
+            .find(|index| index.number == 1)
+            .or_else(|| self.indices.first())
+            .map(|index| index.position)
+    }
+}
+
+/// Writes the cue sheet in the usual layout, with a `WAVE` file. Every value is quoted.
+impl fmt::Display for CueSheet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(catalog) = &self.catalog {
+            writeln!(f, "CATALOG {catalog}")?;
+        }
+        if let Some(performer) = &self.performer {
+            writeln!(f, "PERFORMER \"{performer}\"")?;
+        }
+        if let Some(title) = &self.title {
+            writeln!(f, "TITLE \"{title}\"")?;
+        }
+        if let Some(file) = &self.file {
+            writeln!(f, "FILE \"{file}\" WAVE")?;
+        }
+        for track in &self.tracks {
+            writeln!(f, "  TRACK {:02} AUDIO", track.number)?;
+            if let Some(title) = &track.title {
+                writeln!(f, "    TITLE \"{title}\"")?;
+            }
+            if let Some(performer) = &track.performer {
+                writeln!(f, "    PERFORMER \"{performer}\"")?;
+            }
+            if let Some(songwriter) = &track.songwriter {
+                writeln!(f, "    SONGWRITER \"{songwriter}\"")?;
+            }
+            if let Some(isrc) = &track.isrc {
+                writeln!(f, "    ISRC {isrc}")?;
+            }
+            for index in &track.indices {
+                let position = format_time(index.position);
+                writeln!(f, "    INDEX {:02} {position}", index.number)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Tag {
+    /// Returns the cue sheet stored in the `CUESHEET` comment, or None if there is none.
+    /// # Errors
+    /// This function will error if the cue sheet is malformed (see [`CueSheet::parse`]).
+    pub fn cue_sheet(&self) -> Result<Option<CueSheet>> {
+        let Some(value) = self
+            .comments
+            .get("cuesheet")
+            .and_then(|values| values.first())
+        else {
+            return Ok(None);
+        };
+        Ok(Some(CueSheet::parse(value)?))
+    }
+
+    /// Replaces the cue sheet stored in the `CUESHEET` comment.
+    pub fn set_cue_sheet(&mut self, sheet: &CueSheet) {
+        self.remove_cue_sheet();
+        self.add_one("CUESHEET".to_string(), sheet.to_string());
+    }
+
+    /// Replaces the chapters with those of the cue sheet (see [`CueSheet::to_chapters`]), so that
+    /// players which only understand chapters can navigate the tracks. Returns false, leaving the
+    /// chapters unchanged, if there is no cue sheet.
+    /// # Errors
+    /// This function will error if the cue sheet is malformed.
+    pub fn chapters_from_cue_sheet(&mut self) -> Result<bool> {
+        let Some(sheet) = self.cue_sheet()? else {
+            return Ok(false);
+        };
+        self.set_chapters(&sheet.to_chapters())?;
+        Ok(true)
+    }
+
+    /// Replaces the cue sheet with one made from the chapters (see [`CueSheet::from_chapters`]),
+    /// with the album performer and title taken from `ALBUMARTIST` (or `ARTIST`) and `ALBUM`.
+    /// Returns false, leaving the cue sheet unchanged, if there are no chapters.
+    /// # Errors
+    /// This function will error if the start time of a chapter is invalid.
+    pub fn cue_sheet_from_chapters(&mut self) -> Result<bool> {
+        let chapters = self.chapters()?;
+        if chapters.is_empty() {
+            return Ok(false);
+        }
+        let mut sheet = CueSheet::from_chapters(&chapters);
+        sheet.performer = self
+            .get_one("albumartist".to_string())
+            .or_else(|| self.get_one("artist".to_string()))
+            .cloned();
+        sheet.title = self.get_one("album".to_string()).cloned();
+        self.set_cue_sheet(&sheet);
+        Ok(true)
+    }
+
+    /// Removes the `CUESHEET` comment.
+    pub fn remove_cue_sheet(&mut self) {
+        self.comments_mut().remove("cuesheet");
+    }
+}
@@ -0,0 +1,141 @@
+//! Decoding of the audio of an opus stream, for the features which analyze it.
+
+use crate::{Error, Result};
+use audiopus::coder::Decoder;
+use audiopus::{packet::Packet, Channels, MutSignals, SampleRate};
+use ogg::PacketReader;
+use std::io::{Read, Seek};
+
+/// The sample rate opus streams are decoded at.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// The largest number of samples per channel in an opus packet: 120 ms at 48 kHz.
+const MAX_PACKET_SAMPLES: usize = 5760;
+
+/// Decodes the first opus stream of a reader into interleaved samples at 48 kHz, with the output
+/// gain of the `OpusHead` applied and the pre-skip and end padding removed.
+pub struct OpusDecoder<R: Read + Seek> {
+    reader: PacketReader<R>,
+    decoder: Decoder,
+    serial: u32,
+    channels: usize,
+    /// The output gain, as a factor.
+    gain: f32,
+    /// Samples per channel still to be dropped at the start of the stream.
+    to_skip: usize,
+    pre_skip: u64,
+    /// Samples per channel returned so far, after the pre-skip.
+    returned: u64,
+    /// The granule position of the last page of the stream seen so far.
+    granule: Option<u64>,
+    /// The samples of the last decoded packet, held back until it is known whether the packet is
+    /// the last one, which may have to be trimmed.
+    pending: Vec<f32>,
+    buffer: Vec<f32>,
+}
+
+impl<R: Read + Seek> OpusDecoder<R> {
+    /// Reads the `OpusHead` and `OpusTags` packets of the first opus stream and sets up a
+    /// decoder for it.
+    /// # Errors
+    /// This function will error if there is no opus stream, or if the decoder doesn't support its
+    /// channel count (streams with more than 2 channels aren't supported).
+    pub fn new(f_in: R) -> Result<Self> {
+        let mut reader = PacketReader::new(f_in);
+        let head = loop {
+            let packet = reader.read_packet()?.ok_or(Error::NotOpus)?;
+            if crate::is_opus_head(&packet) && packet.data.len() >= 19 {
+                break packet;
+            }
+            if !packet.first_in_stream() {
+                return Err(Error::NotOpus);
+            }
+        };
+        let serial = head.stream_serial();
+        let channels = Channels::try_from(i32::from(head.data[9]))?;
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]);
+        let gain = i16::from_le_bytes([head.data[16], head.data[17]]);
+
+        // skip the comment header
+        loop {
+            let packet = reader.read_packet()?.ok_or(Error::MissingPacket)?;
+            if packet.stream_serial() == serial {
+                break;
+            }
+        }
+
+        Ok(Self {
+            reader,
+            decoder: Decoder::new(SampleRate::Hz48000, channels)?,
+            serial,
+            channels: channels as usize,
+            // the output gain is in Q7.8 dB
+            gain: 10f32.powf(f32::from(gain) / (256.0 * 20.0)),
+            to_skip: pre_skip.into(),
+            pre_skip: pre_skip.into(),
+            returned: 0,
+            granule: None,
+            pending: vec![],
+            buffer: vec![0.0; MAX_PACKET_SAMPLES * channels as usize],
+        })
+    }
+
+    /// Returns the number of channels.
+    pub const fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Decodes the next packet, returning its interleaved samples, or None at the end of the
+    /// stream.
+    /// # Errors
+    /// This function will error if reading a packet fails, or if a packet can't be decoded.
+    pub fn next_chunk(&mut self) -> Result<Option<Vec<f32>>> {
+        loop {
+            let Some(packet) = self.reader.read_packet()? else {
+                return Ok(self.finish());
+            };
+            if packet.stream_serial() != self.serial {
+                continue;
+            }
+            if packet.last_in_page() {
+                self.granule = Some(packet.absgp_page());
+            }
+
+            let samples = self.decoder.decode_float(
+                Some(Packet::try_from(&packet.data[..])?),
+                MutSignals::try_from(&mut self.buffer[..])?,
+                false,
+            )?;
+            let skipped = samples.min(self.to_skip);
+            self.to_skip -= skipped;
+            let decoded = self.buffer[skipped * self.channels..samples * self.channels]
+                .iter()
+                .map(|sample| sample * self.gain)
+                .collect();
+
+            let chunk = std::mem::replace(&mut self.pending, decoded);
+            if packet.last_in_stream() {
+                self.pending.splice(0..0, chunk);
+                return Ok(self.finish());
+            }
+            if !chunk.is_empty() {
+                self.returned += (chunk.len() / self.channels) as u64;
+                return Ok(Some(chunk));
+            }
+        }
+    }
+
+    /// Returns the held back samples, trimmed to the length given by the last granule position.
+    fn finish(&mut self) -> Option<Vec<f32>> {
+        let mut chunk = std::mem::take(&mut self.pending);
+        if let Some(granule) = self.granule {
+            let remaining = granule
+                .saturating_sub(self.pre_skip)
+                .saturating_sub(self.returned);
+            let remaining = usize::try_from(remaining).unwrap_or(usize::MAX);
+            chunk.truncate(remaining.saturating_mul(self.channels));
+        }
+        self.returned += (chunk.len() / self.channels) as u64;
+        Some(chunk).filter(|chunk| !chunk.is_empty())
+    }
+}
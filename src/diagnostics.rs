@@ -0,0 +1,138 @@
+//! Collecting the non-fatal issues noticed while reading and writing tags.
+
+use crate::picture::PictureType;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A non-fatal issue noticed while reading or writing tags. Unlike an [`Error`](enum@crate::Error),
+/// it doesn't stop the operation, but it may explain why a file doesn't round-trip exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// Bytes which aren't part of any Ogg page were found at `offset` and skipped. When writing,
+    /// they are dropped from the new file.
+    Garbage { offset: u64, length: u64 },
+    /// The stream ends with a page which was cut short, at `offset`.
+    TruncatedPage { offset: u64, length: u64 },
+    /// `count` pictures were left out because of
+    /// [`ReadOptions::skip_pictures`](crate::ReadOptions::skip_pictures).
+    PicturesSkipped { count: usize },
+    /// A comment key contains characters the spec doesn't allow (anything but printable ASCII
+    /// other than `=`), or is empty. Many programs will ignore the comment.
+    SuspiciousKey(String),
+    /// The comment header has `length` bytes after the last comment, which is padding (or, for
+    /// opus, possibly binary data).
+    Padding { length: usize },
+    /// The header pages of the stream with this serial number didn't follow RFC 7845 (a header
+    /// shared a page with another packet, or the identification header page had a non-zero
+    /// granule position), and were fixed in the new file.
+    PaginationFixed { serial: u32 },
+    /// The new comment header of the stream with this serial number takes up a different number
+    /// of pages than the old one, so every following page was renumbered.
+    PagesRenumbered { serial: u32, old: u32, new: u32 },
+    /// A picture with `size` bytes of image data was left out of the written file to stay within
+    /// the [`DeviceProfile`](crate::DeviceProfile).
+    PictureDropped {
+        picture_type: PictureType,
+        size: usize,
+    },
+    /// A value of this key, `length` bytes long, was truncated in the written file to stay
+    /// within the [`DeviceProfile`](crate::DeviceProfile).
+    ValueTruncated { key: String, length: usize },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Garbage { offset, length } => {
+                write!(
+                    f,
+                    "skipped {length} bytes outside of any page at offset {offset}"
+                )
+            }
+            Self::TruncatedPage { offset, length } => {
+                write!(
+                    f,
+                    "the page at offset {offset} is cut short after {length} bytes"
+                )
+            }
+            Self::PicturesSkipped { count } => write!(f, "skipped {count} pictures"),
+            Self::SuspiciousKey(key) => write!(f, "the key {key:?} isn't allowed by the spec"),
+            Self::Padding { length } => {
+                write!(f, "the comment header has {length} bytes of padding")
+            }
+            Self::PaginationFixed { serial } => {
+                write!(f, "fixed the pagination of the headers of stream {serial}")
+            }
+            Self::PagesRenumbered { serial, old, new } => write!(
+                f,
+                "the headers of stream {serial} went from {old} to {new} pages, so the pages \
+                 after them were renumbered"
+            ),
+            Self::PictureDropped { picture_type, size } => write!(
+                f,
+                "dropped a {picture_type:?} picture of {size} bytes to fit the device profile"
+            ),
+            Self::ValueTruncated { key, length } => write!(
+                f,
+                "truncated a value of {key:?} of {length} bytes to fit the device profile"
+            ),
+        }
+    }
+}
+
+/// A sink for the [`Diagnostic`]s of read and write operations.
+///
+/// It is set with [`ReadOptions::diagnostics`](crate::ReadOptions::diagnostics) and
+/// [`WriteOptions::diagnostics`](crate::WriteOptions::diagnostics). Clones share the same list, so the caller keeps a clone and inspects it once the operation
+/// is done.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics(Arc<Mutex<Vec<Diagnostic>>>);
+
+impl Diagnostics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a diagnostic.
+    pub fn push(&self, diagnostic: Diagnostic) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(diagnostic);
+    }
+
+    /// Removes and returns the diagnostics collected so far.
+    #[must_use]
+    pub fn take(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Returns a copy of the diagnostics collected so far.
+    #[must_use]
+    pub fn to_vec(&self) -> Vec<Diagnostic> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Returns true if no diagnostics were collected.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+}
+
+/// Returns true if a comment key only contains the characters the spec allows: printable ASCII
+/// (0x20 to 0x7D) other than `=`.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|byte| (0x20..=0x7D).contains(&byte) && byte != b'=')
+}
@@ -0,0 +1,482 @@
+//! Reading and writing tags of native FLAC (`.flac`) files.
+//!
+//! A FLAC file starts with a list of metadata blocks instead of Ogg pages. The comments are stored
+//! in its `VORBIS_COMMENT` block, which uses the same format as the comment header of an Ogg
+//! stream, and the pictures in `PICTURE` blocks, which use the format of
+//! [`Picture::to_bytes`](crate::picture::Picture::to_bytes). The functions of this module read
+//! them into a [`Tag`] holding the pictures as `METADATA_BLOCK_PICTURE` comments, like the tags
+//! of an opus file, and write them back into those blocks.
+//!
+//! When writing, the other metadata blocks keep their order, and padding blocks are merged into
+//! one at the end of the metadata. If the new blocks fit in the space taken up by the old ones
+//! (including their padding), they are written over them, with the space left over as padding, so
+//! that the audio data doesn't have to be moved. An `ID3v2` tag in front of the file is left as-is.
+
+use crate::write::{self, Written};
+use crate::{Codec, Error, ReadOptions, Result, Tag, WriteOptions};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// The signature at the start of a FLAC file.
+const MAGIC: &[u8] = b"fLaC";
+/// Size of an `ID3v2` tag header.
+const ID3_HEADER_SIZE: usize = 10;
+/// Metadata block types.
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+const INVALID: u8 = 127;
+/// Flag set in a metadata block header if the block is the last one.
+const LAST_BLOCK: u8 = 0x80;
+/// Size of a metadata block header.
+const BLOCK_HEADER_SIZE: usize = 4;
+/// Largest length of a metadata block, which is stored in 24 bits.
+const MAX_BLOCK_LENGTH: usize = (1 << 24) - 1;
+
+/// Read a `Tag` from a FLAC file.
+/// # Errors
+/// This function will error if the reader is not a FLAC file ([`Error::NotOpus`]), if the
+/// metadata blocks are shorter than their headers say, or for the same reasons as
+/// [`Tag::read_from`] when parsing the `VORBIS_COMMENT` block.
+pub fn read_from<R: Read>(f_in: R) -> Result<Tag> {
+    read_from_with(f_in, &ReadOptions::default())
+}
+
+/// Read a `Tag` from a FLAC file, using the given [`ReadOptions`]. The
+/// [size limit](ReadOptions::max_header_size) applies to the comment and picture blocks together.
+/// # Errors
+/// This function will error for the same reasons as [`read_from`].
+pub fn read_from_with<R: Read>(f_in: R, options: &ReadOptions) -> Result<Tag> {
+    let metadata = Metadata::read(f_in, !options.skip_pictures, options.max_header_size)?;
+    metadata.tag(options)
+}
+
+/// Convenience function for reading the tags of a FLAC file from a path.
+/// # Errors
+/// This function will error for the same reasons as [`read_from`].
+#[cfg(feature = "fs")]
+pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<Tag> {
+    let file = File::open(path)?;
+    read_from(BufReader::new(file))
+}
+
+/// Writes tags to a FLAC file.
+/// # Errors
+/// This function will error if the file is not a FLAC file, if a comment or picture is too big for
+/// its metadata block (longer than 16 MiB), or if reading, writing or seeking fails.
+pub fn write_to<F: Read + Write + Seek>(tag: &Tag, f_in: F) -> Result<()> {
+    write_to_with(tag, f_in, &WriteOptions::default())
+}
+
+/// Writes tags to a FLAC file, using the given [`WriteOptions`]. If the audio data has to be
+/// moved, a padding block of [`WriteOptions::padding`] bytes is added, so that later edits can be
+/// made in place.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`].
+pub fn write_to_with<F: Read + Write + Seek>(
+    tag: &Tag,
+    f_in: F,
+    options: &WriteOptions,
+) -> Result<()> {
+    let (_, written) = write_in_place(tag, f_in, options)?;
+    written.commit();
+    Ok(())
+}
+
+/// Writes a retagged copy of the FLAC file read from `src` to `dst`, leaving `src` unchanged.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`].
+pub fn write_to_new<R: Read, W: Write>(tag: &Tag, src: R, dst: W) -> Result<()> {
+    write_to_new_with(tag, src, dst, &WriteOptions::default())
+}
+
+/// Writes a retagged copy of the FLAC file read from `src` to `dst`, using the given
+/// [`WriteOptions`]. The copy gets a padding block of [`WriteOptions::padding`] bytes.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`].
+pub fn write_to_new_with<R: Read, W: Write>(
+    tag: &Tag,
+    src: R,
+    dst: W,
+    options: &WriteOptions,
+) -> Result<()> {
+    // the copy doesn't replace the file the tags were read from
+    let _ = copy(tag, src, dst, options)?;
+    Ok(())
+}
+
+/// Writes a retagged copy of `src` to `dst`. Returns the comment block written, to be committed
+/// if `dst` replaces `src`.
+fn copy<'a, R: Read, W: Write>(
+    tag: &'a Tag,
+    mut src: R,
+    dst: W,
+    options: &WriteOptions,
+) -> Result<Written<'a>> {
+    let metadata = Metadata::read(&mut src, true, None)?;
+    let (blocks, written) = metadata.blocks_for(tag, options)?;
+    let mut dst = BufWriter::new(dst);
+    dst.write_all(&encode(
+        &metadata.prefix,
+        blocks,
+        requested_padding(options),
+    )?)?;
+    std::io::copy(&mut src, &mut dst)?;
+    dst.flush()?;
+    Ok(written)
+}
+
+/// Convenience function for writing the tags of a FLAC file to a path.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`].
+#[cfg(feature = "fs")]
+pub fn write_to_path<P: AsRef<Path>>(tag: &Tag, path: P) -> Result<()> {
+    write_to_path_with(tag, path, &WriteOptions::default())
+}
+
+/// Convenience function for writing the tags of a FLAC file to a path, using the given
+/// [`WriteOptions`]. The file is written according to the
+/// [`WriteStrategy`](crate::WriteStrategy) of the options.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`], or as
+/// [`Tag::write_to_path_with`] for the file operations.
+#[cfg(feature = "fs")]
+pub fn write_to_path_with<P: AsRef<Path>>(
+    tag: &Tag,
+    path: P,
+    options: &WriteOptions,
+) -> Result<()> {
+    let written = write::write_path(
+        path.as_ref(),
+        options,
+        |file| write_in_place(tag, file, options),
+        |src, dst| copy(tag, src, dst, options),
+    )?;
+    written.commit();
+    Ok(())
+}
+
+/// Rewrites the metadata of the FLAC file in `f_in` with these tags. Returns the new length of
+/// the file, and the comment block written.
+fn write_in_place<'a, F: Read + Write + Seek>(
+    tag: &'a Tag,
+    mut f_in: F,
+    options: &WriteOptions,
+) -> Result<(u64, Written<'a>)> {
+    f_in.seek(SeekFrom::Start(0))?;
+    let metadata = Metadata::read(&mut f_in, true, None)?;
+    let (blocks, written) = metadata.blocks_for(tag, options)?;
+    let size = metadata.prefix.len() + blocks.iter().map(Vec::len).sum::<usize>();
+    let space = usize::try_from(metadata.length)?;
+
+    // the space left over has to be filled by a padding block, header included
+    let padding = match space.checked_sub(size) {
+        Some(0) => None,
+        Some(left)
+            if (BLOCK_HEADER_SIZE..=BLOCK_HEADER_SIZE + MAX_BLOCK_LENGTH).contains(&left) =>
+        {
+            Some(left - BLOCK_HEADER_SIZE)
+        }
+        _ => {
+            // the new blocks don't fit, so the audio data has to be moved
+            let head = encode(&metadata.prefix, blocks, requested_padding(options))?;
+            let length = write::splice_head(f_in, &head, metadata.length)?;
+            return Ok((length, written));
+        }
+    };
+    let head = encode(&metadata.prefix, blocks, padding)?;
+    f_in.seek(SeekFrom::Start(0))?;
+    f_in.write_all(&head)?;
+    f_in.flush()?;
+    Ok((f_in.seek(SeekFrom::End(0))?, written))
+}
+
+/// The metadata at the start of a FLAC file.
+struct Metadata {
+    /// The `ID3v2` tag in front of the file, if any, followed by the `fLaC` signature.
+    prefix: Vec<u8>,
+    /// The metadata blocks which were read, headers included.
+    blocks: Vec<Vec<u8>>,
+    /// Length of the metadata, including the prefix and the blocks which were skipped.
+    length: u64,
+}
+
+impl Metadata {
+    /// Reads the metadata up to the first audio frame. Padding blocks are skipped, and so are
+    /// picture blocks unless `pictures` is set. Fails if the blocks which are read are bigger than
+    /// `max_size`.
+    fn read<R: Read>(mut f_in: R, pictures: bool, max_size: Option<usize>) -> Result<Self> {
+        let mut prefix = read_vec(&mut f_in, vec![], MAGIC.len())?;
+        if prefix.starts_with(b"ID3") {
+            prefix = read_vec(&mut f_in, prefix, ID3_HEADER_SIZE - MAGIC.len())?;
+            // the size is stored in 4 bytes of 7 bits each, and doesn't include the header, or
+            // the footer (a copy of the header) if the flag for it is set
+            let size = prefix[6..ID3_HEADER_SIZE]
+                .iter()
+                .fold(0, |size, byte| size << 7 | usize::from(byte & 0x7f));
+            let footer = if prefix[5] & 0x10 == 0 {
+                0
+            } else {
+                ID3_HEADER_SIZE
+            };
+            prefix = read_vec(&mut f_in, prefix, size + footer + MAGIC.len())?;
+        }
+        if !prefix.ends_with(MAGIC) {
+            return Err(Error::NotOpus);
+        }
+
+        let mut length = prefix.len() as u64;
+        let mut blocks = vec![];
+        let mut read = 0;
+        loop {
+            let header = read_vec(&mut f_in, vec![], BLOCK_HEADER_SIZE)?;
+            let size = usize::try_from(u32::from_be_bytes([0, header[1], header[2], header[3]]))?;
+            length += (BLOCK_HEADER_SIZE + size) as u64;
+            let block_type = header[0] & !LAST_BLOCK;
+            let last = header[0] & LAST_BLOCK != 0;
+            if block_type == INVALID {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "invalid metadata block type",
+                )
+                .into());
+            }
+
+            if block_type == PADDING || (block_type == PICTURE && !pictures) {
+                let skipped =
+                    std::io::copy(&mut (&mut f_in).take(size as u64), &mut std::io::sink())?;
+                if skipped < size as u64 {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+            } else {
+                read += BLOCK_HEADER_SIZE + size;
+                if let Some(limit) = max_size.filter(|&limit| read > limit) {
+                    return Err(Error::HeaderTooLarge(limit));
+                }
+                blocks.push(read_vec(&mut f_in, header, size)?);
+            }
+
+            if last {
+                break;
+            }
+        }
+
+        Ok(Self {
+            prefix,
+            blocks,
+            length,
+        })
+    }
+
+    /// Returns the first block of the given type.
+    fn block(&self, block_type: u8) -> Option<&[u8]> {
+        self.blocks
+            .iter()
+            .find(|block| kind(block) == block_type)
+            .map(Vec::as_slice)
+    }
+
+    /// Parses the comment and picture blocks into a `Tag`. A file without a comment block gives
+    /// an empty tag.
+    fn tag(&self, options: &ReadOptions) -> Result<Tag> {
+        let mut tag = match self.block(VORBIS_COMMENT) {
+            Some(block) => Tag::from_comment_packet(Codec::Flac, block, options)?,
+            None => Tag::default(),
+        };
+        for block in self.blocks.iter().filter(|block| kind(block) == PICTURE) {
+            let data = BASE64_STANDARD.encode(&block[BLOCK_HEADER_SIZE..]);
+            tag.add_one("METADATA_BLOCK_PICTURE".to_string(), data);
+        }
+        Ok(tag)
+    }
+
+    /// Returns the metadata blocks with the comments and pictures of `tag` in place of the
+    /// existing ones, without any padding, and the comment block. A new comment block goes right
+    /// after the `STREAMINFO` block, and new pictures at the end.
+    fn blocks_for<'a>(
+        &self,
+        tag: &'a Tag,
+        options: &WriteOptions,
+    ) -> Result<(Vec<Vec<u8>>, Written<'a>)> {
+        let (comments, pictures) = tag.split_pictures();
+        let old = self.block(VORBIS_COMMENT).unwrap_or_default();
+        let comments = write::header_packet(&comments, Codec::Flac, old, options)?;
+        let written = Written::new(tag, &comments);
+        let pictures = pictures
+            .into_iter()
+            .map(|data| block(PICTURE, data))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut comments = Some(comments);
+        let mut pictures = Some(pictures);
+        let mut output = vec![];
+        for block in &self.blocks {
+            match kind(block) {
+                VORBIS_COMMENT => output.extend(comments.take()),
+                PICTURE => output.extend(pictures.take().into_iter().flatten()),
+                _ => output.push(block.clone()),
+            }
+        }
+        let position = output.len().min(1);
+        output.splice(position..position, comments);
+        output.extend(pictures.into_iter().flatten());
+        Ok((output, written))
+    }
+}
+
+/// Returns the type of a metadata block.
+fn kind(block: &[u8]) -> u8 {
+    block[0] & !LAST_BLOCK
+}
+
+/// Prepends a metadata block header to `data`.
+fn block(block_type: u8, mut data: Vec<u8>) -> Result<Vec<u8>> {
+    if data.len() > MAX_BLOCK_LENGTH {
+        return Err(Error::TooBigError);
+    }
+    // fits in 24 bits
+    #[allow(clippy::cast_possible_truncation)]
+    let length = (data.len() as u32).to_be_bytes();
+    data.splice(0..0, [block_type, length[1], length[2], length[3]]);
+    Ok(data)
+}
+
+/// Encodes the prefix and the metadata blocks, followed by a padding block of `padding` bytes if
+/// there is one, and sets the last-block flag of the last block.
+fn encode(prefix: &[u8], mut blocks: Vec<Vec<u8>>, padding: Option<usize>) -> Result<Vec<u8>> {
+    if let Some(padding) = padding {
+        blocks.push(block(PADDING, vec![0; padding])?);
+    }
+    let count = blocks.len();
+    let mut output = prefix.to_vec();
+    for (index, mut block) in blocks.into_iter().enumerate() {
+        block[0] &= !LAST_BLOCK;
+        if index + 1 == count {
+            block[0] |= LAST_BLOCK;
+        }
+        output.extend(block);
+    }
+    Ok(output)
+}
+
+/// The padding block to add when the audio data is moved anyway.
+const fn requested_padding(options: &WriteOptions) -> Option<usize> {
+    if options.padding > 0 {
+        Some(options.padding)
+    } else {
+        None
+    }
+}
+
+/// Reads `count` more bytes to the end of `output`.
+fn read_vec<R: Read>(mut f_in: R, mut output: Vec<u8>, count: usize) -> Result<Vec<u8>> {
+    let start = output.len();
+    output.resize(start + count, 0);
+    f_in.read_exact(&mut output[start..])?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::picture::Picture;
+    use std::io::Cursor;
+
+    /// Stands in for the audio frames after the metadata.
+    const AUDIO: &[u8] = b"\xFF\xF8 audio frames";
+
+    /// A FLAC file with a `STREAMINFO` and an `APPLICATION` block, no tags, and a padding block of
+    /// `padding` bytes if there is one.
+    fn flac(padding: Option<usize>) -> Vec<u8> {
+        let blocks = vec![
+            block(0, vec![0; 34]).unwrap(),
+            block(2, b"test".to_vec()).unwrap(),
+        ];
+        let mut output = encode(MAGIC, blocks, padding).unwrap();
+        output.extend_from_slice(AUDIO);
+        output
+    }
+
+    /// Returns the type and length of every metadata block, checking that only the last one has
+    /// the last-block flag and that the audio follows it.
+    fn blocks(data: &[u8]) -> Vec<(u8, usize)> {
+        let mut blocks = vec![];
+        let mut position = MAGIC.len();
+        loop {
+            let header = &data[position..position + BLOCK_HEADER_SIZE];
+            let length = usize::try_from(u32::from_be_bytes([0, header[1], header[2], header[3]]));
+            blocks.push((kind(header), length.unwrap()));
+            position += BLOCK_HEADER_SIZE + blocks.last().unwrap().1;
+            if header[0] & LAST_BLOCK != 0 {
+                break;
+            }
+        }
+        assert_eq!(&data[position..], AUDIO);
+        blocks
+    }
+
+    fn tag() -> Tag {
+        let mut tag = Tag::new("vendor".to_string(), vec![]);
+        tag.add_one("TITLE".to_string(), "title".to_string());
+        let picture = Picture {
+            data: vec![1; 100],
+            ..Picture::new()
+        };
+        tag.add_picture(&picture).unwrap();
+        tag
+    }
+
+    #[test]
+    fn writes_over_padding_in_place() {
+        let data = flac(Some(1000));
+        let tag = tag();
+        let mut file = Cursor::new(data.clone());
+        write_to(&tag, &mut file).unwrap();
+        let output = file.into_inner();
+        assert_eq!(output.len(), data.len());
+
+        let kinds: Vec<u8> = blocks(&output).into_iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [0, VORBIS_COMMENT, 2, PICTURE, PADDING]);
+        let read = read_from(&output[..]).unwrap();
+        assert_eq!(read.get_one("TITLE".to_string()).unwrap(), "title");
+        assert_eq!(read.pictures(), tag.pictures());
+    }
+
+    #[test]
+    fn moves_audio_when_blocks_dont_fit() {
+        let data = flac(None);
+        let tag = tag();
+        let mut file = Cursor::new(data.clone());
+        let options = WriteOptions::new().padding(100);
+        write_to_with(&tag, &mut file, &options).unwrap();
+        let output = file.into_inner();
+        assert!(output.len() > data.len());
+        assert_eq!(blocks(&output).last(), Some(&(PADDING, 100)));
+
+        // a smaller tag now fits, and the space it frees up goes to the padding
+        let mut smaller = tag;
+        smaller.remove_entries("METADATA_BLOCK_PICTURE".to_string());
+        let mut file = Cursor::new(output.clone());
+        write_to(&smaller, &mut file).unwrap();
+        let rewritten = file.into_inner();
+        assert_eq!(rewritten.len(), output.len());
+        let kinds: Vec<u8> = blocks(&rewritten)
+            .into_iter()
+            .map(|(kind, _)| kind)
+            .collect();
+        assert_eq!(kinds, [0, VORBIS_COMMENT, 2, PADDING]);
+        assert!(read_from(&rewritten[..]).unwrap().pictures().is_empty());
+    }
+
+    #[test]
+    fn rejects_file_which_isnt_flac() {
+        let result = read_from(&b"OggS not flac"[..]);
+        assert!(matches!(result, Err(Error::NotOpus)));
+    }
+}
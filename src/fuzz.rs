@@ -0,0 +1,34 @@
+//! [`Arbitrary`] implementations, for fuzzing and property testing. Requires the `arbitrary`
+//! feature.
+
+use crate::picture::Picture;
+use crate::Tag;
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// Generates a key the spec allows: 1 to 32 characters of printable ASCII other than `=`, so that
+/// the tag survives a round trip through a file.
+fn arbitrary_key(u: &mut Unstructured<'_>) -> Result<String> {
+    let length = u.int_in_range(1..=32)?;
+    (0..length)
+        .map(|_| {
+            let byte = u.int_in_range(0x20..=0x7Du8)?;
+            Ok(char::from(if byte == b'=' { b'_' } else { byte }))
+        })
+        .collect()
+}
+
+impl<'a> Arbitrary<'a> for Tag {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut tag = Self::new(String::arbitrary(u)?, vec![]);
+        for _ in 0..u.arbitrary_len::<(u8, String)>()? {
+            tag.add_one(arbitrary_key(u)?, String::arbitrary(u)?);
+        }
+        for picture in u.arbitrary_iter::<Picture>()? {
+            let data = picture?
+                .to_base64()
+                .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+            tag.add_one("METADATA_BLOCK_PICTURE".to_string(), data);
+        }
+        Ok(tag)
+    }
+}
@@ -0,0 +1,216 @@
+//! Setting the R128 gain tags, and folding them into the output gain of the `OpusHead`.
+
+#[cfg(feature = "fs")]
+use crate::page::{Chunk, PageReader};
+use crate::Tag;
+#[cfg(feature = "fs")]
+use crate::{write, Error, Result, WriteOptions};
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::{BufReader, Seek, SeekFrom};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// Which R128 gain [`apply_r128_gain`] folds into the output gain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GainMode {
+    /// The `R128_TRACK_GAIN` tag, so every track plays at the reference loudness.
+    #[default]
+    Track,
+    /// The `R128_ALBUM_GAIN` tag, so the tracks of an album keep their relative loudness.
+    Album,
+}
+
+#[cfg(feature = "fs")]
+impl GainMode {
+    /// The tag holding the gain which is applied.
+    const fn key(self) -> &'static str {
+        match self {
+            Self::Track => "R128_TRACK_GAIN",
+            Self::Album => "R128_ALBUM_GAIN",
+        }
+    }
+
+    /// The tag holding the other gain, which stays a tag.
+    const fn other_key(self) -> &'static str {
+        match self {
+            Self::Track => "R128_ALBUM_GAIN",
+            Self::Album => "R128_TRACK_GAIN",
+        }
+    }
+}
+
+impl Tag {
+    /// Sets the `R128_TRACK_GAIN` tag to a gain in dB, relative to the output gain of the file.
+    /// See [`set_album_gain_db`](Self::set_album_gain_db).
+    pub fn set_track_gain_db(&mut self, gain: f32) {
+        self.set_r128_gain("R128_TRACK_GAIN", gain);
+    }
+
+    /// Sets the `R128_ALBUM_GAIN` tag to a gain in dB, relative to the output gain of the file.
+    ///
+    /// The gain is stored in the Q7.8 fixed point format the tag requires (1/256 dB), so it is
+    /// rounded, and clamped to the representable range of -128 to about +128 dB. A NaN gain is
+    /// stored as 0. RFC 7845 says the `REPLAYGAIN_*` tags should not appear in opus files: with
+    /// the `tracing` feature, a warning is emitted if the tag has any.
+    pub fn set_album_gain_db(&mut self, gain: f32) {
+        self.set_r128_gain("R128_ALBUM_GAIN", gain);
+    }
+
+    fn set_r128_gain(&mut self, key: &str, gain: f32) {
+        let gain = (f64::from(gain) * 256.0).round();
+        // clamped to the range of i16, so the cast can't truncate
+        #[allow(clippy::cast_possible_truncation)]
+        let gain = gain.clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16;
+        self.remove_entries(key.to_string());
+        self.add_one(key.to_string(), gain.to_string());
+        if self
+            .comments
+            .keys()
+            .any(|key| key.starts_with("replaygain_"))
+        {
+            warn!(
+                key,
+                "the tag has REPLAYGAIN tags, which RFC 7845 says should not appear in opus files"
+            );
+        }
+    }
+}
+
+/// Adds the R128 gain selected by `mode` to the output gain of the `OpusHead` of a file, so that
+/// it plays at normalized volume even in players which ignore the R128 tags.
+///
+/// Returns the gain which was applied, in Q7.8 dB, or None (leaving the file untouched) if the
+/// file doesn't have the tag.
+///
+/// R128 gains are relative to the output gain, so the applied tag is removed, and the other one
+/// is reduced by the same amount to keep its meaning. The tags and the `OpusHead` are those of the
+/// first opus stream, and both are changed in a single copy of the file which replaces it, so the
+/// gain is never applied without its tag being removed.
+/// # Errors
+/// This function will error if the file can't be read or written, or with [`Error::InvalidGain`]
+/// if a gain tag isn't a Q7.8 number or the new gains don't fit in the Q7.8 format.
+#[cfg(feature = "fs")]
+pub fn apply_r128_gain<P: AsRef<Path>>(path: P, mode: GainMode) -> Result<Option<i16>> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let mut streams = Tag::read_streams_from(BufReader::new(&file))?;
+    let (_, head) = find_head_page(&file, None)?;
+    let serial = head.serial;
+    let mut tag = streams.remove(&serial).ok_or(Error::MissingPacket)?;
+    drop(file);
+
+    let Some(gain) = r128_gain(&tag, mode.key())? else {
+        return Ok(None);
+    };
+    let other = r128_gain(&tag, mode.other_key())?
+        .map(|other| {
+            other
+                .checked_sub(gain)
+                .ok_or_else(|| Error::InvalidGain(format!("{} is out of range", mode.other_key())))
+        })
+        .transpose()?;
+    let output_gain = i16::from_le_bytes([head.body[16], head.body[17]]);
+    let new_gain = output_gain.checked_add(gain).ok_or_else(|| {
+        Error::InvalidGain(format!(
+            "output gain {output_gain} + {gain} is out of range"
+        ))
+    })?;
+
+    tag.remove_entries(mode.key().to_string());
+    if let Some(other) = other {
+        tag.remove_entries(mode.other_key().to_string());
+        tag.add_one(mode.other_key().to_string(), other.to_string());
+    }
+    write::replace_atomically(path, |src, dst| {
+        // the tag is a copy, which doesn't need to be marked clean
+        let _ = write::copy_file(src, dst, &WriteOptions::default(), |stream, _| {
+            (stream == serial).then_some(&tag)
+        })?;
+        // the OpusHead page is copied as-is, other than its pagination
+        let (offset, mut page) = find_head_page(dst, Some(serial))?;
+        page.body[16..18].copy_from_slice(&new_gain.to_le_bytes());
+        page.update_checksum();
+        let mut dst: &File = dst;
+        dst.seek(SeekFrom::Start(offset))?;
+        page.write_to(&mut dst)?;
+        Ok(())
+    })?;
+    Ok(Some(gain))
+}
+
+/// Parses an R128 gain tag, in Q7.8 dB.
+#[cfg(feature = "fs")]
+fn r128_gain(tag: &Tag, key: &str) -> Result<Option<i16>> {
+    let Some(value) = tag.get_one(key.to_string()) else {
+        return Ok(None);
+    };
+    value
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|_| Error::InvalidGain(format!("{key}={value}")))
+}
+
+/// Finds the page holding the `OpusHead` of the first opus stream, or of the stream with the
+/// given serial number, with its offset.
+#[cfg(feature = "fs")]
+fn find_head_page(mut file: &File, serial: Option<u32>) -> Result<(u64, crate::page::Page)> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = PageReader::new(BufReader::new(file));
+    while let Some(chunk) = reader.read_chunk()? {
+        let Chunk::Page { offset, page } = chunk else {
+            continue;
+        };
+        if !page.is_bos() {
+            break;
+        }
+        let wanted = serial.is_none_or(|serial| serial == page.serial);
+        if wanted && page.body.starts_with(b"OpusHead") && page.body.len() >= 19 {
+            return Ok((offset, page));
+        }
+    }
+    Err(Error::NotOpus)
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+    use crate::testing::OpusStream;
+
+    #[test]
+    fn applies_gain_to_head_and_tags_of_first_stream() {
+        let first = OpusStream::new()
+            .serial(7)
+            .output_gain(100)
+            .comment("R128_TRACK_GAIN", "-256")
+            .comment("R128_ALBUM_GAIN", "-512")
+            .build()
+            .unwrap();
+        // a chained stream, which mustn't be touched
+        let second = OpusStream::new()
+            .serial(8)
+            .comment("R128_TRACK_GAIN", "1000")
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("opusmeta-gain-{}.opus", std::process::id()));
+        std::fs::write(&path, [first, second.clone()].concat()).unwrap();
+
+        let applied = apply_r128_gain(&path, GainMode::Track);
+        let head = find_head_page(&File::open(&path).unwrap(), Some(7));
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(applied.unwrap(), Some(-256));
+
+        let (_, head) = head.unwrap();
+        assert_eq!(i16::from_le_bytes([head.body[16], head.body[17]]), -156);
+        let streams = Tag::read_streams_from(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(streams[&7].get_one("R128_TRACK_GAIN".into()), None);
+        assert_eq!(
+            streams[&7].get_one("R128_ALBUM_GAIN".into()).unwrap(),
+            "-256"
+        );
+        assert!(data.ends_with(&second));
+    }
+}
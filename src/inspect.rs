@@ -0,0 +1,118 @@
+//! Low-level inspection of the Ogg pages in a file.
+//!
+//! This gives a debugging view of a file's page structure, similar to what `ogginfo` or
+//! `opusinfo` print, without decoding any packets. Data between pages is skipped; use
+//! [`verify_from`](crate::verify_from) to find out about it.
+
+use crate::page::{Chunk, PageReader, FLAG_BOS, FLAG_CONTINUED, FLAG_EOS, HEADER_SIZE, NO_GRANULE};
+use crate::Result;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::Read;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// A summary of a single Ogg page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageSummary {
+    /// Byte offset of the page in the stream.
+    pub offset: u64,
+    /// Serial number of the logical stream the page belongs to.
+    pub serial: u32,
+    pub sequence_number: u32,
+    /// Granule position of the page, or None if no packet ends on this page.
+    pub granule_position: Option<u64>,
+    /// Number of packets which end on this page.
+    pub packets: usize,
+    /// Size of the page header, including the segment table, in bytes.
+    pub header_size: usize,
+    /// Size of the page body in bytes.
+    pub body_size: usize,
+    /// The raw header type flags of the page. See also [`is_continued`](Self::is_continued),
+    /// [`is_first_in_stream`](Self::is_first_in_stream) and
+    /// [`is_last_in_stream`](Self::is_last_in_stream).
+    pub flags: u8,
+    /// The last packet on this page continues onto the next page.
+    pub ends_with_continued: bool,
+    /// The checksum stored in the page header matches the page contents.
+    pub checksum_valid: bool,
+}
+
+impl PageSummary {
+    /// Total size of the page in bytes.
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        self.header_size + self.body_size
+    }
+
+    /// Returns true if the page starts with the continuation of a packet from the previous page.
+    #[must_use]
+    pub const fn is_continued(&self) -> bool {
+        self.flags & FLAG_CONTINUED != 0
+    }
+
+    /// Returns true if the page is the first page of its logical stream.
+    #[must_use]
+    pub const fn is_first_in_stream(&self) -> bool {
+        self.flags & FLAG_BOS != 0
+    }
+
+    /// Returns true if the page is the last page of its logical stream.
+    #[must_use]
+    pub const fn is_last_in_stream(&self) -> bool {
+        self.flags & FLAG_EOS != 0
+    }
+}
+
+/// Iterator over the pages of a stream. See [`pages`].
+pub struct Pages<R> {
+    reader: PageReader<R>,
+}
+
+impl<R: Read> Iterator for Pages<R> {
+    type Item = Result<PageSummary>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (offset, page) = match self.reader.read_chunk() {
+                Ok(Some(Chunk::Page { offset, page })) => (offset, page),
+                Ok(Some(Chunk::Garbage { .. })) => continue,
+                Ok(Some(Chunk::Truncated { .. }) | None) => return None,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            return Some(Ok(PageSummary {
+                offset,
+                serial: page.serial,
+                sequence_number: page.sequence_number,
+                granule_position: Some(page.granule_position).filter(|&g| g != NO_GRANULE),
+                packets: page.packets_completed(),
+                header_size: HEADER_SIZE + page.segments.len(),
+                body_size: page.body.len(),
+                flags: page.flags,
+                ends_with_continued: page.ends_with_continued(),
+                checksum_valid: page.compute_checksum() == page.checksum,
+            }));
+        }
+    }
+}
+
+/// Returns an iterator over summaries of the pages in a reader.
+#[must_use]
+pub const fn pages<R: Read>(f_in: R) -> Pages<R> {
+    Pages {
+        reader: PageReader::new(f_in),
+    }
+}
+
+/// Convenience function for inspecting the pages of a file at a path. See [`pages`].
+/// # Errors
+/// This function will error if the file cannot be opened. Errors while reading are returned by
+/// the iterator.
+#[cfg(feature = "fs")]
+pub fn pages_from_path<P: AsRef<Path>>(path: P) -> Result<Pages<BufReader<File>>> {
+    let file = File::open(path)?;
+    Ok(pages(BufReader::new(file)))
+}
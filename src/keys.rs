@@ -0,0 +1,109 @@
+//! Interning of comment keys.
+//!
+//! The same few keys (`title`, `artist`, `album`, ...) appear in nearly every file, so instead of
+//! allocating a new string for each of them in every [`Tag`](crate::Tag), keys found in a static
+//! table of common keys are stored as borrowed `&'static str`s. Other keys are stored as owned
+//! strings.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// A lowercase comment key.
+pub type Key = Cow<'static, str>;
+
+/// Common comment keys, in lowercase and sorted for binary search.
+const KNOWN_KEYS: [&str; 62] = [
+    "album",
+    "albumartist",
+    "albumartistsort",
+    "albumsort",
+    "arranger",
+    "artist",
+    "artistsort",
+    "author",
+    "bpm",
+    "catalognumber",
+    "comment",
+    "compilation",
+    "composer",
+    "composersort",
+    "conductor",
+    "contact",
+    "copyright",
+    "date",
+    "description",
+    "discnumber",
+    "disctotal",
+    "encoded-by",
+    "encodedby",
+    "encoder",
+    "encoder_options",
+    "genre",
+    "grouping",
+    "isrc",
+    "label",
+    "language",
+    "license",
+    "location",
+    "lyricist",
+    "lyrics",
+    "metadata_block_picture",
+    "musicbrainz_albumartistid",
+    "musicbrainz_albumid",
+    "musicbrainz_artistid",
+    "musicbrainz_releasegroupid",
+    "musicbrainz_trackid",
+    "organization",
+    "originaldate",
+    "performer",
+    "publisher",
+    "r128_album_gain",
+    "r128_track_gain",
+    "rating",
+    "releasecountry",
+    "replaygain_album_gain",
+    "replaygain_album_peak",
+    "replaygain_track_gain",
+    "replaygain_track_peak",
+    "script",
+    "subtitle",
+    "title",
+    "titlesort",
+    "totaldiscs",
+    "totaltracks",
+    "tracknumber",
+    "tracktotal",
+    "version",
+    "year",
+];
+
+/// Finds `key` in the table of known keys, ignoring ASCII case.
+fn find(key: &str) -> Option<&'static str> {
+    KNOWN_KEYS
+        .binary_search_by(|known| compare_lowercase(known, key))
+        .ok()
+        .map(|index| KNOWN_KEYS[index])
+}
+
+/// Compares a lowercase string with another string converted to lowercase, without allocating.
+fn compare_lowercase(lowercase: &str, other: &str) -> Ordering {
+    lowercase
+        .bytes()
+        .cmp(other.bytes().map(|byte| byte.to_ascii_lowercase()))
+}
+
+/// Returns the key for `key`, only allocating if it isn't a known key.
+pub fn intern(key: &str) -> Key {
+    find(key).map_or_else(|| Cow::Owned(key.to_ascii_lowercase()), Cow::Borrowed)
+}
+
+/// Returns the key for `key`, reusing its allocation if it isn't a known key.
+pub fn intern_owned(mut key: String) -> Key {
+    find(&key).map_or_else(
+        || {
+            key.make_ascii_lowercase();
+            Cow::Owned(key)
+        },
+        Cow::Borrowed,
+    )
+}
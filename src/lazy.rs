@@ -0,0 +1,196 @@
+//! A comment header which is only parsed when it is first accessed.
+
+use crate::picture::Picture;
+use crate::{Codec, Error, ReadOptions, Result, Tag, TagRef};
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{Read, Seek};
+use std::ops::Range;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Stores the raw comment header of an opus file, and parses it on first access.
+///
+/// Reading a `LazyTag` only reads the comment header packet from the file. The first lookup then
+/// finds where every comment is in the packet, without copying any of them, and later lookups
+/// reuse that. Pictures are only decoded by [`pictures`](Self::pictures). This makes a
+/// `LazyTag` the cheapest way to check a few fields of a file; use [`into_tag`](Self::into_tag)
+/// to get a [`Tag`] for editing.
+///
+/// Since parsing is deferred, errors in the comment header are reported by the accessors rather
+/// than when reading.
+#[derive(Debug, Clone)]
+pub struct LazyTag {
+    codec: Codec,
+    packet: Vec<u8>,
+    index: OnceLock<Index>,
+}
+
+/// Where the vendor string and every comment are in the packet.
+#[derive(Debug, Clone, Default)]
+struct Index {
+    vendor: Range<usize>,
+    comments: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl LazyTag {
+    /// Wraps a bare comment header packet, such as an `OpusTags` packet, without parsing it. The
+    /// codec is detected from the magic signature at the start of the packet; packets without
+    /// one are taken to be opus packets.
+    #[must_use]
+    pub fn from_packet(packet: Vec<u8>) -> Self {
+        let codec = Codec::from_comment_header(&packet).unwrap_or(Codec::Opus);
+        Self::from_codec_packet(codec, packet)
+    }
+
+    /// Wraps the bare comment header packet of a stream of the given codec, without parsing it.
+    #[must_use]
+    pub const fn from_codec_packet(codec: Codec, packet: Vec<u8>) -> Self {
+        Self {
+            codec,
+            packet,
+            index: OnceLock::new(),
+        }
+    }
+
+    /// Read the comment header from a reader, without parsing it.
+    /// # Errors
+    /// This function will error for the same reasons as [`Tag::read_from`], except for those
+    /// related to parsing the comment header itself.
+    pub fn read_from<R: Read + Seek>(f_in: R) -> Result<Self> {
+        Self::read_from_with(f_in, &ReadOptions::default())
+    }
+
+    /// Read the comment header from a reader, using the given [`ReadOptions`].
+    /// [`ReadOptions::skip_pictures`] has no effect, since pictures are never decoded unless
+    /// asked for.
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from).
+    pub fn read_from_with<R: Read + Seek>(f_in: R, options: &ReadOptions) -> Result<Self> {
+        let (codec, packet) = crate::read_comment_packet(f_in, options)?;
+        Ok(Self::from_codec_packet(codec, packet))
+    }
+
+    /// Convenience function for reading the comment header from a path.
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from).
+    #[cfg(feature = "fs")]
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        Self::read_from(BufReader::new(file))
+    }
+
+    /// Returns the codec of the stream the comment header belongs to.
+    #[must_use]
+    pub const fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns the raw comment header packet.
+    #[must_use]
+    pub fn packet(&self) -> &[u8] {
+        &self.packet
+    }
+
+    /// Gets the vendor string.
+    /// # Errors
+    /// This function will error for the same reasons as [`TagRef::from_packet`].
+    pub fn get_vendor(&self) -> Result<&str> {
+        let index = self.index()?;
+        Ok(self.str_at(&index.vendor))
+    }
+
+    /// Get all entries for a particular key, in the order they appear in the file. Keys are
+    /// case-insensitive.
+    /// # Errors
+    /// This function will error for the same reasons as [`TagRef::from_packet`].
+    pub fn get<'s>(&'s self, tag: &'s str) -> Result<impl Iterator<Item = &'s str> + 's> {
+        Ok(self
+            .comments()?
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(tag))
+            .map(|(_, value)| value))
+    }
+
+    /// Gets the first entry for a particular key, or None if no occurences of the key exist.
+    /// # Errors
+    /// This function will error for the same reasons as [`TagRef::from_packet`].
+    pub fn get_one(&self, tag: &str) -> Result<Option<&str>> {
+        Ok(self
+            .comments()?
+            .find(|(key, _)| key.eq_ignore_ascii_case(tag))
+            .map(|(_, value)| value))
+    }
+
+    /// Returns an iterator over every (key, value) pair, in the order they appear in the file.
+    /// # Errors
+    /// This function will error for the same reasons as [`TagRef::from_packet`].
+    pub fn comments(&self) -> Result<impl Iterator<Item = (&str, &str)> + '_> {
+        let index = self.index()?;
+        Ok(index
+            .comments
+            .iter()
+            .map(|(key, value)| (self.str_at(key), self.str_at(value))))
+    }
+
+    /// Returns a Vec of all encoded pictures. This function will skip pictures that are encoded
+    /// improperly.
+    /// # Errors
+    /// This function will error for the same reasons as [`TagRef::from_packet`].
+    pub fn pictures(&self) -> Result<Vec<Picture>> {
+        Ok(self
+            .get("METADATA_BLOCK_PICTURE")?
+            .filter_map(|data| Picture::from_base64(data).ok())
+            .collect())
+    }
+
+    /// Parses the whole comment header into a [`Tag`].
+    /// # Errors
+    /// This function will error for the same reasons as [`TagRef::from_packet`].
+    pub fn into_tag(self) -> Result<Tag> {
+        Tag::from_comment_packet(self.codec, &self.packet, &ReadOptions::default())
+    }
+
+    /// Returns the index of the packet, building it on first use.
+    fn index(&self) -> Result<&Index> {
+        if let Some(index) = self.index.get() {
+            return Ok(index);
+        }
+        let index = Index::build(self.codec, &self.packet)?;
+        Ok(self.index.get_or_init(|| index))
+    }
+
+    /// The string at a range of the packet. Only called with ranges from the index, which are
+    /// known to be valid UTF-8.
+    fn str_at(&self, range: &Range<usize>) -> &str {
+        std::str::from_utf8(&self.packet[range.clone()]).unwrap_or_default()
+    }
+}
+
+impl Index {
+    fn build(codec: Codec, packet: &[u8]) -> Result<Self> {
+        let tag = TagRef::from_codec_packet(codec, packet)?;
+        // every string of a TagRef is a slice of the packet
+        let range = |s: &str| {
+            let start = s.as_ptr() as usize - packet.as_ptr() as usize;
+            start..start + s.len()
+        };
+        Ok(Self {
+            vendor: range(tag.get_vendor()),
+            comments: tag
+                .comments()
+                .map(|(key, value)| (range(key), range(value)))
+                .collect(),
+        })
+    }
+}
+
+impl TryFrom<LazyTag> for Tag {
+    type Error = Error;
+
+    fn try_from(tag: LazyTag) -> Result<Self> {
+        tag.into_tag()
+    }
+}
@@ -1,6 +1,9 @@
 #![allow(clippy::module_name_repetitions)]
 
 //! opusmeta is a Rust crate for reading and writing metadata from opus files.
+//! Ogg Vorbis, FLAC, Speex and Theora files, which use the same comment format, are supported as
+//! well; see [`Codec`]. [`OggFile`] reads the tags of a file without having to know its codec.
+//! Native FLAC files are supported by the [`flac`] module.
 //!
 //! See the `read_tags` example file for basic usage.
 //!
@@ -10,19 +13,138 @@
 //!
 //! For reading and writing picture data, opusmeta uses the
 //! [METADATA_BLOCK_PICTURE](https://wiki.xiph.org/VorbisComment#Cover_art) proposal, which is supported by common players like ffmpeg and vlc.
+//!
+//! The comment header can span any number of Ogg pages, which happens when large pictures are
+//! embedded. When reading, it is assembled page by page and held in memory while it is parsed;
+//! [`ReadOptions::max_header_size`] can put a limit on its size. When writing, it is split over as
+//! many pages as needed (up to 65025 bytes each), with every page after the first flagged as
+//! continuing it. The only hard limits are those of the spec: the vendor string and every comment
+//! must be shorter than 4 GiB, and there can be at most [`u32::MAX`] comments.
+
+// declared first, so that its macros are available in every other module
+#[macro_use]
+mod trace;
 
+pub mod acoustid;
+pub mod album;
+#[cfg(feature = "tokio")]
+mod async_io;
+pub mod audiobook;
+#[cfg(feature = "fs")]
+mod batch;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod chapters;
+#[cfg(feature = "checksum")]
+pub mod checksum;
+#[cfg(feature = "chromaprint")]
+mod chromaprint;
+pub mod classical;
+mod codec;
+mod collapse;
+mod comment;
+pub mod cuesheet;
+#[cfg(any(feature = "ebur128", feature = "chromaprint"))]
+mod decode;
+mod diagnostics;
+pub mod flac;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+mod gain;
+pub mod inspect;
+mod keys;
+mod lazy;
+#[cfg(feature = "ebur128")]
+pub mod loudness;
+pub mod lyrics;
+#[cfg(feature = "matroska")]
+pub mod matroska;
+mod ogg_file;
+mod options;
+mod opus_file;
+mod page;
+mod patch;
 pub mod picture;
+mod profile;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "http")]
+pub mod remote;
+#[cfg(feature = "fs")]
+pub mod rename;
+pub mod repair;
+mod roundtrip;
+mod sanitize;
+#[cfg(feature = "walkdir")]
+pub mod scan;
+mod search;
+pub mod storage;
+mod tag_ref;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod values;
+mod vendor;
+pub mod verify;
+mod write;
 
-use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use chapters::ChapterError;
+use cuesheet::CueSheetError;
+use keys::Key;
+use ogg::{OggReadError, PacketReader};
+use page::{Chunk, PageReader};
 use picture::{Picture, PictureError, PictureType};
-use std::collections::HashMap;
+use smallvec::{smallvec, SmallVec};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "fs")]
 use std::fs::File;
+#[cfg(feature = "fs")]
 use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "fs")]
+use std::io::BufReader;
 use std::io::Cursor;
-use std::io::{Read, Seek, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "fs")]
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicU64};
 use thiserror::Error;
 
+#[cfg(feature = "fs")]
+pub use batch::{apply_to_all, apply_to_all_dry_run, apply_to_all_with, read_many, read_many_with};
+pub use codec::Codec;
+pub use collapse::CollapseStrategy;
+pub use comment::Comment;
+pub use diagnostics::{Diagnostic, Diagnostics};
+#[cfg(feature = "fs")]
+pub use gain::apply_r128_gain;
+pub use gain::GainMode;
+pub use lazy::LazyTag;
+pub use ogg_file::{OggFile, StreamTags};
+pub use options::{
+    CommentOrder, KeyCase, Pagination, ReadOptions, VendorPolicy, WriteOptions, WriteStrategy,
+    DEFAULT_VENDOR,
+};
+pub use opus_file::{OpusFile, OpusHead};
+pub use patch::{PatchOperation, TagPatch};
+pub use profile::DeviceProfile;
+pub use repair::repair_from;
+#[cfg(feature = "fs")]
+pub use repair::repair_path;
+pub use roundtrip::verify_roundtrip;
+pub use sanitize::SanitizeOptions;
+pub use search::SearchPattern;
+pub use tag_ref::TagRef;
+pub use values::ValuesMut;
+pub use vendor::VendorInfo;
+pub use verify::verify_from;
+#[cfg(feature = "fs")]
+pub use verify::verify_path;
+pub use write::{HeaderPages, WritePlan};
+
 /// Error type.
 ///
 /// Encapsulates every error that could occur in the usage of this crate.
@@ -32,7 +154,8 @@ pub enum Error {
     /// Failed to read an ogg packet, or the file is not an ogg file
     #[error("{0}")]
     ReadError(#[from] ogg::OggReadError),
-    /// The selected file is an ogg file, but not an opus file.
+    /// The selected file is an ogg file, but not an opus file (or a file of another supported
+    /// [`Codec`]).
     #[error("The selected file is not an opus file")]
     NotOpus,
     /// Expected a packet (for example, the comment header packet), but the stream ended early
@@ -63,59 +186,186 @@ pub enum Error {
     /// the opus spec uses u32 for lengths, but Rust uses usize instead.
     #[error("This crate expects `usize` to be at least 32 bits in size.")]
     PlatformError(#[from] std::num::TryFromIntError),
+    /// The checksum of a page containing an opus header did not match its contents, meaning the
+    /// page is corrupt. Checksum verification can be turned off with
+    /// [`ReadOptions::verify_checksums`].
+    #[error(
+        "A header page is corrupt (stored checksum {stored:#010x}, computed {computed:#010x})"
+    )]
+    CorruptHeader { stored: u32, computed: u32 },
+    /// The comment header in the file is not the one the tag was read from, meaning another
+    /// program modified the file in the meantime. Only raised when
+    /// [`WriteOptions::check_unmodified`] is set.
+    #[error("The comment header was modified since the tags were read")]
+    ModifiedSinceRead,
+    /// The comment header is bigger than the limit set with [`ReadOptions::max_header_size`],
+    /// which is provided for convenience.
+    #[error("The comment header is bigger than the limit of {0} bytes")]
+    HeaderTooLarge(usize),
+    /// The tag would change if written and read back. Contains a description of the first
+    /// difference. Raised by [`verify_roundtrip`].
+    #[error("The tag would not survive a round trip: {0}")]
+    RoundTripMismatch(String),
+    /// A placeholder of a renaming pattern is malformed. The pattern is provided for convenience.
+    /// See [`rename::rename_by_pattern`].
+    #[error("Malformed renaming pattern: {0}")]
+    MalformedPattern(String),
+    /// Renaming would move the files in `from` to the same path `to`, or move a file to a path
+    /// which already exists. See [`rename::rename_by_pattern`].
+    #[error("Renaming would move {from:?} to {to:?}, which is already taken")]
+    RenameCollision { from: Vec<PathBuf>, to: PathBuf },
+    /// An R128 gain tag isn't a Q7.8 number, or applying it would overflow the output gain.
+    /// Contains a description of the offending gain. See [`apply_r128_gain`].
+    #[error("Invalid R128 gain: {0}")]
+    InvalidGain(String),
+    /// A comment key is empty, or contains characters the spec doesn't allow (anything but
+    /// printable ASCII other than `=`). The key is provided for convenience. See [`Comment`].
+    #[error("Invalid comment key: {0:?}")]
+    InvalidKey(String),
+    /// The comment header is `size` bytes even without any picture, more than the `limit` of the
+    /// [`DeviceProfile`] set with [`WriteOptions::device_profile`].
+    #[error("The comment header is {size} bytes without pictures, more than the limit of {limit} bytes of the device profile")]
+    ExceedsDeviceProfile { size: usize, limit: usize },
+    /// An error occured while reading or writing chapters. See [`ChapterError`] for more info.
+    #[error("An error occured while reading or writing chapters: {0}")]
+    ChapterError(#[from] ChapterError),
+    /// An error occured while parsing a cue sheet. See [`CueSheetError`] for more info.
+    #[error("An error occured while parsing a cue sheet: {0}")]
+    CueSheetError(#[from] CueSheetError),
+    /// Failed to decode the audio of an opus stream. Requires the `ebur128` or `chromaprint`
+    /// feature.
+    #[cfg(any(feature = "ebur128", feature = "chromaprint"))]
+    #[error("Failed to decode the audio: {0}")]
+    DecodeError(#[from] audiopus::Error),
+    /// libchromaprint failed to compute a fingerprint. Requires the `chromaprint` feature.
+    #[cfg(feature = "chromaprint")]
+    #[error("libchromaprint failed to compute the fingerprint")]
+    FingerprintError,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The values of a key. Most keys have a single value, which is stored inline.
+type Values = SmallVec<[String; 1]>;
+
 /// Stores Opus comments.
 #[derive(Debug, Default)]
 pub struct Tag {
     vendor: String,
-    comments: HashMap<String, Vec<String>>,
+    comments: HashMap<Key, Values>,
+    /// Hash of the comment header this tag was last read from or written to, or 0 if it wasn't
+    /// read from a file. See [`WriteOptions::check_unmodified`].
+    source_header: AtomicU64,
+    /// Number of calls to methods which can modify the tag. See [`is_dirty`](Self::is_dirty).
+    generation: u64,
+    /// One more than the `generation` when the tag was last read or written, or 0 if it wasn't
+    /// read from a file.
+    clean_generation: AtomicU64,
 }
 
 impl Tag {
-    /// Create a new tag from a vendor string and a list of comments.
+    /// Create a new tag from a vendor string and a list of comments, such as a `Vec`, an array or
+    /// an iterator of [`Comment`]s.
     #[must_use]
-    pub fn new(vendor: String, comments: Vec<(String, String)>) -> Self {
-        let mut comments_map = HashMap::new();
-        for (mut key, value) in comments {
-            key.make_ascii_lowercase();
-            comments_map
-                .entry(key)
-                .and_modify(|v: &mut Vec<String>| v.push(value.clone()))
-                .or_insert_with(|| vec![value]);
+    pub fn new(vendor: String, comments: impl IntoIterator<Item = Comment>) -> Self {
+        let mut tag = Self {
+            vendor,
+            comments: HashMap::new(),
+            source_header: AtomicU64::new(0),
+            generation: 0,
+            clean_generation: AtomicU64::new(0),
+        };
+        for comment in comments {
+            tag.add_comment(comment);
         }
+        tag
+    }
 
-        Self {
-            vendor,
-            comments: comments_map,
+    /// Add one entry. The key isn't checked; see [`add_comment`](Self::add_comment).
+    pub fn add_one(&mut self, tag: String, value: String) {
+        self.add_value(keys::intern_owned(tag), value);
+    }
+
+    /// Add a comment, whose key was checked when it was created.
+    pub fn add_comment(&mut self, comment: Comment) {
+        let (key, value) = comment.into_parts();
+        self.add_one(key, value);
+    }
+
+    /// Returns all comments, sorted by key (in lowercase). Values of the same key keep the order
+    /// they were added in. Keys read from a file are returned as they are, even if the spec
+    /// doesn't allow them (see [`Diagnostic::SuspiciousKey`]).
+    pub fn comments(&self) -> impl Iterator<Item = Comment> + '_ {
+        let mut comments: Vec<(&Key, &Values)> = self.comments.iter().collect();
+        comments.sort_by_key(|(key, _)| *key);
+        comments.into_iter().flat_map(|(key, values)| {
+            values
+                .iter()
+                .map(|value| Comment::new_unchecked(key.to_string(), value.clone()))
+        })
+    }
+
+    /// Add one entry, unless the key already has exactly this value, so that tagging a file
+    /// repeatedly doesn't pile up duplicates. Returns true if the value was added.
+    pub fn add_one_unique(&mut self, tag: String, value: String) -> bool {
+        let key = keys::intern_owned(tag);
+        if self
+            .comments
+            .get(&key)
+            .is_some_and(|values| values.contains(&value))
+        {
+            return false;
         }
+        self.add_value(key, value);
+        true
     }
 
-    /// Add one entry.
-    pub fn add_one(&mut self, mut tag: String, value: String) {
-        tag.make_ascii_lowercase();
-        self.comments
-            .entry(tag)
-            .and_modify(|v: &mut Vec<String>| v.push(value.clone()))
-            .or_insert_with(|| vec![value]);
+    /// Add multiple entries, from a `Vec`, an array or an iterator of values.
+    pub fn add_many(&mut self, tag: String, values: impl IntoIterator<Item = String>) {
+        match self.comments_mut().entry(keys::intern_owned(tag)) {
+            Entry::Occupied(mut entry) => entry.get_mut().extend(values),
+            Entry::Vacant(entry) => {
+                entry.insert(values.into_iter().collect());
+            }
+        }
     }
 
-    /// Add multiple entries.
-    pub fn add_many(&mut self, mut tag: String, mut values: Vec<String>) {
+    /// Get all entries for a particular key, or None if no occurrences of the key exist.
+    #[must_use]
+    pub fn get(&self, mut tag: String) -> Option<&[String]> {
         tag.make_ascii_lowercase();
-        self.comments
-            .entry(tag)
-            .and_modify(|v: &mut Vec<String>| v.append(&mut values))
-            .or_insert(values);
+        self.comments.get(tag.as_str()).map(|values| &values[..])
     }
 
-    /// Get all entries for a particular key, or None if no occurrences of the key exist.
+    /// Get all entries for a particular key for editing in place, or None if no occurrences of
+    /// the key exist. Values can be changed, added and removed through the returned
+    /// [`ValuesMut`]; the key is removed once it has no values left.
     #[must_use]
-    pub fn get(&self, mut tag: String) -> Option<&Vec<String>> {
+    pub fn get_mut(&mut self, mut tag: String) -> Option<ValuesMut<'_>> {
+        tag.make_ascii_lowercase();
+        match self.comments_mut().entry(Cow::Owned(tag)) {
+            Entry::Occupied(entry) => Some(ValuesMut::new(entry)),
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    /// Returns the values of a particular key for editing in place, such as trimming whitespace
+    /// or fixing their case. Yields nothing if no occurrences of the key exist.
+    pub fn values_mut(&mut self, mut tag: String) -> impl Iterator<Item = &mut String> {
         tag.make_ascii_lowercase();
-        self.comments.get(&tag)
+        self.comments_mut()
+            .get_mut(tag.as_str())
+            .into_iter()
+            .flatten()
+    }
+
+    /// Returns every comment as its lowercase key and its value for editing in place, in no
+    /// particular order. Pictures are included, as base64 values of the `metadata_block_picture`
+    /// key.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut String)> {
+        self.comments_mut()
+            .iter_mut()
+            .flat_map(|(key, values)| values.iter_mut().map(move |value| (key.as_ref(), value)))
     }
 
     /// Gets the first entry for a particular key, or None if no occurences of the key exist.
@@ -127,7 +377,29 @@ impl Tag {
     /// Remove all entries for a particular key. Optionally returns the removed values, if any.
     pub fn remove_entries(&mut self, mut tag: String) -> Option<Vec<String>> {
         tag.make_ascii_lowercase();
-        self.comments.remove(&tag)
+        self.comments_mut()
+            .remove(tag.as_str())
+            .map(Values::into_vec)
+    }
+
+    /// Returns the comments as a map from lowercase keys to their values, for bulk operations
+    /// with the standard map API. The map borrows from the tag.
+    #[must_use]
+    pub fn as_map(&self) -> HashMap<&str, &[String]> {
+        self.comments
+            .iter()
+            .map(|(key, values)| (key.as_ref(), &values[..]))
+            .collect()
+    }
+
+    /// Consumes the tag and returns its comments as a map from lowercase keys to their values.
+    /// The vendor string is dropped.
+    #[must_use]
+    pub fn into_comments(self) -> HashMap<String, Vec<String>> {
+        self.comments
+            .into_iter()
+            .map(|(key, values)| (key.into_owned(), values.into_vec()))
+            .collect()
     }
 
     /// Gets the vendor string
@@ -138,6 +410,7 @@ impl Tag {
 
     /// Sets the vendor string.
     pub fn set_vendor(&mut self, new_vendor: String) {
+        self.generation += 1;
         self.vendor = new_vendor;
     }
 
@@ -152,12 +425,26 @@ impl Tag {
         Ok(())
     }
 
+    /// Add a picture like [`add_picture`](Self::add_picture), after checking it with
+    /// [`Picture::validate`].
+    /// # Errors
+    /// This function will error with [`PictureError::Invalid`] if the picture has any problem, in
+    /// which case the tag is left unchanged, or for the same reasons as
+    /// [`add_picture`](Self::add_picture).
+    pub fn add_picture_strict(&mut self, picture: &Picture) -> Result<()> {
+        let problems = picture.validate();
+        if !problems.is_empty() {
+            return Err(PictureError::Invalid(problems).into());
+        }
+        self.add_picture(picture)
+    }
+
     /// Removes a picture with the given picture type. Returns the removed picture for convenience.
     /// # Errors
     /// Although rare, this function can error if a picture with the given type is not found AND
     /// the first picture in the set is not decoded properly.
     pub fn remove_picture_type(&mut self, picture_type: PictureType) -> Result<Option<Picture>> {
-        let Some(pictures) = self.comments.get_mut("metadata_block_picture") else {
+        let Some(pictures) = self.comments_mut().get_mut("metadata_block_picture") else {
             return Ok(None);
         };
         let mut index_to_remove = 0;
@@ -188,6 +475,70 @@ impl Tag {
         None
     }
 
+    /// Gets the first picture whose description is `description`, or None if there are no pictures
+    /// with that description. Useful to tell apart pictures of the same type, such as the pages of
+    /// a booklet stored as [`Other`](PictureType::Other) pictures.
+    #[must_use]
+    pub fn get_picture_by_description(&self, description: &str) -> Option<Picture> {
+        self.find_picture(|picture| picture.description == description)
+    }
+
+    /// Gets the first picture for which `predicate` returns true, or None if there is none.
+    /// Pictures which are encoded improperly are skipped.
+    #[must_use]
+    pub fn find_picture(&self, mut predicate: impl FnMut(&Picture) -> bool) -> Option<Picture> {
+        self.comments
+            .get("metadata_block_picture")?
+            .iter()
+            .filter_map(|picture| Picture::from_base64(picture).ok())
+            .find(|picture| predicate(picture))
+    }
+
+    /// Picks the picture to show as the cover of the file: the front cover if there is one, then
+    /// a picture of type [`Other`](PictureType::Other), then the first picture. Returns None if
+    /// there are no pictures which can be decoded.
+    #[must_use]
+    pub fn best_cover(&self) -> Option<Picture> {
+        let mut pictures = self.pictures();
+        let index = [PictureType::CoverFront, PictureType::Other]
+            .iter()
+            .find_map(|picture_type| {
+                pictures
+                    .iter()
+                    .position(|picture| picture.picture_type == *picture_type)
+            })
+            .unwrap_or(0);
+        (index < pictures.len()).then(|| pictures.swap_remove(index))
+    }
+
+    /// Returns the [best cover](Self::best_cover) of the tag, or if it has no pictures, the
+    /// [folder art](picture::folder_art) next to the file at `path`.
+    /// # Errors
+    /// This function will error for the same reasons as [`picture::folder_art`].
+    #[cfg(feature = "fs")]
+    pub fn cover_or_folder_art<P: AsRef<Path>>(&self, path: P) -> Result<Option<Picture>> {
+        self.best_cover()
+            .map_or_else(|| picture::folder_art(path), |cover| Ok(Some(cover)))
+    }
+
+    /// Embeds the [folder art](picture::folder_art) next to the file at `path` as the front
+    /// cover, if the tag has no pictures. Returns the embedded picture, or None if the tag
+    /// already has pictures or there is no folder art.
+    /// # Errors
+    /// This function will error for the same reasons as [`picture::folder_art`], or if the
+    /// picture can't be encoded.
+    #[cfg(feature = "fs")]
+    pub fn embed_folder_art<P: AsRef<Path>>(&mut self, path: P) -> Result<Option<Picture>> {
+        if self.comments.contains_key("metadata_block_picture") {
+            return Ok(None);
+        }
+        let Some(picture) = picture::folder_art(path)? else {
+            return Ok(None);
+        };
+        self.add_picture(&picture)?;
+        Ok(Some(picture))
+    }
+
     /// Returns a Vec of all encoded pictures. This function will skip pictures that are encoded
     /// improperly.
     #[must_use]
@@ -207,11 +558,13 @@ impl Tag {
 }
 
 impl Tag {
-    /// Read a `Tag` from a reader.
+    /// Read a `Tag` from a reader. The tags are read from the first logical stream of a supported
+    /// [`Codec`], which is the opus stream of an Ogg Opus file, or the vorbis stream of an Ogg
+    /// Vorbis file.
     /// # Errors
     /// This function can error if:
     /// - The ogg stream is shorter than expected (e.g. doesn't include the first or second
-    ///     packets)
+    ///   packets)
     /// - The given reader is not an opus stream
     /// - The comment header does not include the magic signature
     /// - The comment header is shorter than mandated by the spec
@@ -219,158 +572,1207 @@ impl Tag {
     /// - The spec mandates UTF-8, but the data is invalid unicode
     /// - A comment line is not in TAG=VALUE format.
     pub fn read_from<R: Read + Seek>(f_in: R) -> Result<Self> {
-        let mut reader = PacketReader::new(f_in);
-        let first_packet = reader.read_packet()?.ok_or(Error::MissingPacket)?;
-        if !first_packet.data.starts_with(b"OpusHead") {
+        Self::read_from_with(f_in, &ReadOptions::default())
+    }
+
+    /// Read a `Tag` from a reader, using the given [`ReadOptions`].
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from), or with
+    /// [`Error::CorruptHeader`] if checksum verification is enabled and a header page is corrupt.
+    pub fn read_from_with<R: Read + Seek>(f_in: R, options: &ReadOptions) -> Result<Self> {
+        let (codec, header_packet) = read_comment_packet(f_in, options)?;
+        Self::from_comment_packet(codec, &header_packet, options)
+    }
+
+    /// Read a `Tag` from an ogg stream starting `offset` bytes into a reader, such as a stream
+    /// embedded in an archive or following a junk prefix. Nothing before the offset is read.
+    /// # Errors
+    /// This function will error if seeking to the offset fails, or for the same reasons as
+    /// [`read_from`](Self::read_from).
+    pub fn read_from_offset<R: Read + Seek>(f_in: R, offset: u64) -> Result<Self> {
+        Self::read_from_offset_with(f_in, offset, &ReadOptions::default())
+    }
+
+    /// Read a `Tag` from an ogg stream starting `offset` bytes into a reader, using the given
+    /// [`ReadOptions`]. See [`read_from_offset`](Self::read_from_offset).
+    /// # Errors
+    /// This function will error for the same reasons as
+    /// [`read_from_offset`](Self::read_from_offset).
+    pub fn read_from_offset_with<R: Read + Seek>(
+        mut f_in: R,
+        offset: u64,
+        options: &ReadOptions,
+    ) -> Result<Self> {
+        f_in.seek(SeekFrom::Start(offset))?;
+        Self::read_from_with(f_in, options)
+    }
+
+    /// Read a `Tag` from an in-memory buffer. The buffer can either contain a whole Ogg Opus file
+    /// (or a file of another supported [`Codec`]), or just a bare comment header packet, such as
+    /// an `OpusTags` packet. The packets and comments are copied out of the buffer, so the
+    /// returned tag doesn't borrow it.
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from), or with
+    /// [`Error::NotOpus`] if the buffer is neither an ogg stream nor a comment header packet.
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        if Codec::from_comment_header(data).is_some() {
+            Self::from_packet_data(data)
+        } else if data.starts_with(b"OggS") {
+            Self::read_from(Cursor::new(data))
+        } else {
+            Err(Error::NotOpus)
+        }
+    }
+
+    /// Read a `Tag` from a path by memory-mapping the file, so that it is parsed straight from the
+    /// page cache instead of being copied through read calls. Requires the `mmap` feature.
+    /// # Errors
+    /// This function will error if the file cannot be opened or mapped, or for the same reasons
+    /// as [`from_slice`](Self::from_slice).
+    #[cfg(feature = "mmap")]
+    pub fn read_from_mmap<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the map is only read from for the duration of this function. If the file is
+        // modified by another process at the same time, parsing may fail, but the parser never
+        // assumes the data stays unchanged.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::from_slice(&map)
+    }
+
+    /// Read the tags of every opus stream in a reader, keyed by stream serial number. This is
+    /// useful for files containing several opus streams, such as multi-language audio, where each
+    /// track has its own metadata. Streams of other codecs are left out; [`OggFile`] reads those
+    /// too, along with their codec.
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from), for any of
+    /// the opus streams in the reader, or with [`Error::NotOpus`] if it has no opus stream.
+    pub fn read_streams_from<R: Read + Seek>(f_in: R) -> Result<HashMap<u32, Self>> {
+        let streams: HashMap<u32, Self> = read_stream_packets(f_in)?
+            .into_iter()
+            .filter(|(_, codec, _)| *codec == Codec::Opus)
+            .map(|(serial, codec, data)| {
+                let tag = Self::from_comment_packet(codec, &data, &ReadOptions::default())?;
+                Ok((serial, tag))
+            })
+            .collect::<Result<_>>()?;
+        if streams.is_empty() {
             return Err(Error::NotOpus);
         }
-        let header_packet = reader.read_packet()?.ok_or(Error::MissingPacket)?;
-        let mut cursor = Cursor::new(header_packet.data);
-        cursor.seek_relative(8)?; // length of string "OpusTags"
-        let mut buffer = [0; 4];
-        cursor.read_exact(&mut buffer)?;
-        // only panics on platforms where usize < 32 bits
-        let vendor_length: usize = u32::from_le_bytes(buffer).try_into()?;
-        let mut buffer = vec![0; vendor_length];
-        cursor.read_exact(&mut buffer)?;
-        let vendor = String::from_utf8(buffer)?;
-        let mut buffer = [0; 4];
-        cursor.read_exact(&mut buffer)?;
-        let comment_count = u32::from_le_bytes(buffer);
-        let mut comments: Vec<(String, String)> = Vec::new();
-        for _ in 0..comment_count {
-            let mut buffer = [0; 4];
-            cursor.read_exact(&mut buffer)?;
-            // only panics on platforms where usize < 32 bits
-            let comment_length: usize = u32::from_le_bytes(buffer).try_into()?;
-            let mut buffer = vec![0; comment_length];
-            cursor.read_exact(&mut buffer)?;
-            let comment = String::from_utf8(buffer.clone())?;
-            let pair = comment
-                .split_once('=')
-                .map(|(tag, value)| (tag.to_string(), value.to_string()))
-                .ok_or_else(|| Error::MalformedComment(comment))?;
-            comments.push(pair);
-        }
-        Ok(Self::new(vendor, comments))
+        Ok(streams)
+    }
+
+    /// Convenience function for reading the tags of every opus stream from a path.
+    /// # Errors
+    /// This function will error for the same reasons as [`read_streams_from`](Self::read_streams_from)
+    #[cfg(feature = "fs")]
+    pub fn read_streams_from_path<P: AsRef<Path>>(path: P) -> Result<HashMap<u32, Self>> {
+        let file = File::open(path)?;
+        Self::read_streams_from(BufReader::new(file))
+    }
+
+    /// Returns the first value of the first of `keys` the tag has, for comments which have
+    /// several spellings.
+    fn get_first_of(&self, keys: &[&str]) -> Option<&String> {
+        keys.iter().find_map(|key| self.get_one((*key).to_string()))
+    }
+
+    /// Replaces the values of all `keys` with a single value under the first of them, or removes
+    /// them all if `value` is None.
+    fn set_first_of(&mut self, keys: &[&str], value: Option<String>) {
+        for key in keys {
+            self.remove_entries((*key).to_string());
+        }
+        if let Some(value) = value {
+            self.add_one(keys[0].to_string(), value);
+        }
+    }
+
+    /// Adds a value to an interned key. The value is moved into place, never copied.
+    fn add_value(&mut self, key: Key, value: String) {
+        match self.comments_mut().entry(key) {
+            Entry::Occupied(mut entry) => entry.get_mut().push(value),
+            Entry::Vacant(entry) => {
+                entry.insert(smallvec![value]);
+            }
+        }
+    }
+
+    /// Parses a comment header packet read from a file with the given [`ReadOptions`].
+    fn from_comment_packet(codec: Codec, data: &[u8], options: &ReadOptions) -> Result<Self> {
+        let (mut tag_ref, trailing) = TagRef::parse(codec, data)?;
+        if options.diagnostics.is_some() {
+            if trailing > usize::from(codec.has_framing_bit()) {
+                options.report(|| Diagnostic::Padding { length: trailing });
+            }
+            let invalid: BTreeSet<&str> = tag_ref
+                .comments()
+                .map(|(key, _)| key)
+                .filter(|key| !diagnostics::is_valid_key(key))
+                .collect();
+            for key in invalid {
+                options.report(|| Diagnostic::SuspiciousKey(key.to_string()));
+            }
+        }
+        if options.skip_pictures {
+            let count = tag_ref.remove_pictures();
+            if count > 0 {
+                options.report(|| Diagnostic::PicturesSkipped { count });
+            }
+        }
+        let tag = tag_ref.into_owned();
+        tag.set_source_header(data);
+        Ok(tag)
+    }
+
+    /// Parses a bare comment header packet of any codec with a magic signature.
+    fn from_packet_data(data: &[u8]) -> Result<Self> {
+        let codec = Codec::from_comment_header(data).ok_or(Error::NotOpus)?;
+        Self::from_comment_packet(codec, data, &ReadOptions::default())
+    }
+
+    /// Remembers `data` as the comment header this tag corresponds to in its file, and the
+    /// current content of the tag as clean.
+    fn set_source_header(&self, data: &[u8]) {
+        self.set_source_hash(header_hash(data));
+    }
+
+    /// Like [`set_source_header`](Self::set_source_header), with the [`header_hash`] of the
+    /// header.
+    fn set_source_hash(&self, data_hash: u64) {
+        self.source_header
+            .store(data_hash, atomic::Ordering::Relaxed);
+        self.clean_generation
+            .store(self.generation + 1, atomic::Ordering::Relaxed);
+    }
+
+    /// Returns the comments for modifying them, counting the modification for
+    /// [`is_dirty`](Self::is_dirty).
+    const fn comments_mut(&mut self) -> &mut HashMap<Key, Values> {
+        self.generation += 1;
+        &mut self.comments
+    }
+
+    /// Returns true if the tag may have been modified since it was last read from or written to a
+    /// file, or if it wasn't read from a file at all.
+    ///
+    /// This is cheap to call: any call to a method which can modify the tag, including
+    /// [`get_mut`](Self::get_mut), [`values_mut`](Self::values_mut) and
+    /// [`iter_mut`](Self::iter_mut), marks it dirty, even if nothing ends up changing.
+    #[must_use]
+    pub fn is_dirty(&self) -> bool {
+        self.clean_generation.load(atomic::Ordering::Relaxed) != self.generation + 1
+    }
+
+    /// Fails if `data` is not the comment header this tag was read from, when
+    /// [`WriteOptions::check_unmodified`] is set.
+    fn check_source_header(&self, data: &[u8], options: &WriteOptions) -> Result<()> {
+        let expected = self.source_header.load(atomic::Ordering::Relaxed);
+        if options.check_unmodified && expected != 0 && header_hash(data) != expected {
+            return Err(Error::ModifiedSinceRead);
+        }
+        Ok(())
     }
 
     /// Convenience function for reading comments from a path.
     /// # Errors
     /// This function will error for the same reasons as [`read_from`](Self::read_from)
+    #[cfg(feature = "fs")]
     pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::open(path)?;
-        Self::read_from(file)
+        Self::read_from(BufReader::new(file))
+    }
+
+    /// Convenience function for reading comments from a path, using the given [`ReadOptions`].
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from_with`](Self::read_from_with)
+    #[cfg(feature = "fs")]
+    pub fn read_from_path_with<P: AsRef<Path>>(path: P, options: &ReadOptions) -> Result<Self> {
+        let file = File::open(path)?;
+        Self::read_from_with(BufReader::new(file), options)
     }
 
     /// Writes tags to a writer. This function expects the writer to already contain an existing
-    /// opus stream. This function reads the existing stream, copies it **into memory**, replaces the
-    /// comment header, and dumps the whole stream back into the file.
+    /// opus stream, or a stream of another supported [`Codec`]; the comment header of the first
+    /// one is replaced, in the format of its codec. The stream is rewritten onto itself page by
+    /// page, replacing the comment header and shifting the pages after it. Only the difference in
+    /// size between the old and new comment headers is held in memory.
+    ///
+    /// If the new stream is shorter than the old one, the data left over at the end of the writer
+    /// is not removed; use [`write_to_path`](Self::write_to_path) to also truncate the file. Since
+    /// the stream is rewritten as it is read, an error part way through can leave the writer
+    /// corrupted.
+    ///
+    /// If the file contains other logical streams multiplexed with the opus stream (for example a
+    /// video or subtitle track), their packets are copied through unmodified.
     /// # Errors
     /// This function will error if:
     /// - No opus stream exists in the target
     /// - The ogg stream is shorter than expected (e.g. doesn't include the first or second
-    ///     packets)
+    ///   packets)
     /// - A comment in this Tag object is too big for the opus spec (some string is longer than [`u32::MAX`] bytes,
-    ///     or the object contains more than [`u32::MAX`] comments)
+    ///   or the object contains more than [`u32::MAX`] comments)
     /// - An unspecified error occurs while reading ogg packets from the target
     /// - An error occurs while writing an ogg packet to the target
     /// - An error occurs while seeking through the target
-    /// - An error occurs while copying the finished ogg stream from memory back to the target
-    pub fn write_to<W: Read + Write + Seek>(&self, mut f_in: W) -> Result<()> {
-        let f_out_raw: Vec<u8> = vec![];
-        let mut cursor = Cursor::new(f_out_raw);
-
-        let mut reader = PacketReader::new(&mut f_in);
-        let mut writer = PacketWriter::new(&mut cursor);
+    pub fn write_to<W: Read + Write + Seek>(&self, f_in: W) -> Result<()> {
+        self.write_to_with(f_in, &WriteOptions::default())?;
+        Ok(())
+    }
 
-        // first packet
-        {
-            let first_packet = reader.read_packet()?.ok_or(Error::MissingPacket)?;
-            writer.write_packet(
-                first_packet.data.clone(),
-                first_packet.stream_serial(),
-                get_end_info(&first_packet),
-                first_packet.absgp_page(),
-            )?;
-        }
+    /// Writes tags to a writer, using the given [`WriteOptions`].
+    ///
+    /// If [padding](WriteOptions::padding) is requested and the new comment header fits in the
+    /// space taken up by the existing one, only the header pages are overwritten, without reading
+    /// or copying the rest of the stream.
+    ///
+    /// Returns the number of header pages before and after the write.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to).
+    pub fn write_to_with<W: Read + Write + Seek>(
+        &self,
+        f_in: W,
+        options: &WriteOptions,
+    ) -> Result<HeaderPages> {
+        let (_, header_pages, written) = self.write_in_place(f_in, options)?;
+        written.commit();
+        Ok(header_pages)
+    }
 
-        // second packet, which is the comment header
-        {
-            let comment_header_packet = reader.read_packet()?.ok_or(Error::MissingPacket)?;
-            let new_pack_data = self.to_packet_data()?;
-            writer.write_packet(
-                new_pack_data,
-                comment_header_packet.stream_serial(),
-                PacketWriteEndInfo::EndPage,
-                comment_header_packet.absgp_page(),
-            )?;
+    /// Rewrites the stream in `f_in` with these tags. Returns the new length of the stream, the
+    /// header page counts, and the header written, to be committed once the write is complete.
+    fn write_in_place<W: Read + Write + Seek>(
+        &self,
+        mut f_in: W,
+        options: &WriteOptions,
+    ) -> Result<(u64, HeaderPages, write::Written<'_>)> {
+        if options.padding > 0 {
+            if let Some((header_pages, written)) = write::patch_in_place(&mut f_in, self, options)?
+            {
+                let length = f_in.seek(std::io::SeekFrom::End(0))?;
+                return Ok((length, header_pages, written));
+            }
         }
+        write::splice_streams(f_in, options, write::preferred_stream(self))
+    }
 
-        while let Some(packet) = reader.read_packet()? {
-            let stream_serial = packet.stream_serial();
-            let end_info = get_end_info(&packet);
-            let absgp_page = packet.absgp_page();
-            writer.write_packet(packet.data, stream_serial, end_info, absgp_page)?;
-        }
-        // stream ended
+    /// Works out what [`write_to`](Self::write_to) would do to `f_in`, without writing anything:
+    /// the size of the new comment header, whether it fits in the existing one, how many bytes
+    /// would be moved and how the length of the stream would change. Batch tools can use this to
+    /// report on a change before making it.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to), except for
+    /// those related to writing.
+    pub fn plan_write<R: Read + Seek>(&self, f_in: R) -> Result<WritePlan> {
+        self.plan_write_with(f_in, &WriteOptions::default())
+    }
 
-        drop(reader);
-        cursor.seek(std::io::SeekFrom::Start(0))?;
-        f_in.seek(std::io::SeekFrom::Start(0))?;
-        std::io::copy(&mut cursor, &mut f_in)?;
+    /// Works out what [`write_to_with`](Self::write_to_with) would do to `f_in` with the given
+    /// [`WriteOptions`], without writing anything. See [`plan_write`](Self::plan_write).
+    /// # Errors
+    /// This function will error for the same reasons as [`plan_write`](Self::plan_write).
+    pub fn plan_write_with<R: Read + Seek>(
+        &self,
+        f_in: R,
+        options: &WriteOptions,
+    ) -> Result<WritePlan> {
+        write::plan_write(f_in, self, options)
+    }
 
+    /// Writes a retagged copy of the opus stream read from `src` to `dst`, leaving `src`
+    /// untouched. Neither needs to be seekable, so `dst` can for example be a temporary file, a
+    /// pipe or stdout. The stream is copied page by page, without holding it in memory.
+    ///
+    /// Only the comment header pages are rewritten; every other page is copied unchanged, apart
+    /// from its sequence number if the new comment header takes up a different number of pages.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to). Since the
+    /// copy is streamed, part of it may already have been written to `dst` when an error occurs.
+    pub fn write_to_new<R: Read, W: Write>(&self, src: R, dst: W) -> Result<()> {
+        self.write_to_new_with(src, dst, &WriteOptions::default())?;
         Ok(())
     }
 
-    /// Convenience function for writing to a path.
+    /// Writes a retagged copy of the opus stream read from `src` to `dst`, using the given
+    /// [`WriteOptions`]. See [`write_to_new`](Self::write_to_new).
+    ///
+    /// Returns the number of header pages before and after the write.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to_new`](Self::write_to_new).
+    pub fn write_to_new_with<R: Read, W: Write>(
+        &self,
+        src: R,
+        dst: W,
+        options: &WriteOptions,
+    ) -> Result<HeaderPages> {
+        write::copy_streams(src, dst, options, write::preferred_stream(self))
+    }
+
+    /// Returns a retagged copy of the opus stream read from `src`, without touching the disk. See
+    /// [`write_to_new`](Self::write_to_new).
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to_new`](Self::write_to_new).
+    pub fn write_to_vec<R: Read>(&self, src: R) -> Result<Vec<u8>> {
+        self.write_to_vec_with(src, &WriteOptions::default())
+    }
+
+    /// Returns a retagged copy of the opus stream read from `src`, using the given
+    /// [`WriteOptions`]. See [`write_to_vec`](Self::write_to_vec).
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to_new`](Self::write_to_new).
+    pub fn write_to_vec_with<R: Read>(&self, src: R, options: &WriteOptions) -> Result<Vec<u8>> {
+        let mut output = vec![];
+        self.write_to_new_with(src, &mut output, options)?;
+        Ok(output)
+    }
+
+    /// Convenience function for writing to a path. The file is truncated if the new stream is
+    /// shorter than the old one.
     /// # Errors
     /// This function will error for the same reasons as [`write_to`](Self::write_to)
+    #[cfg(feature = "fs")]
     pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let file = OpenOptions::new().read(true).write(true).open(path)?;
-        self.write_to(file)
+        self.write_to_path_with(path, &WriteOptions::default())?;
+        Ok(())
     }
 
-    fn to_packet_data(&self) -> Result<Vec<u8>> {
-        let mut output = vec![];
+    /// Convenience function for writing to a path, using the given [`WriteOptions`]. The file is
+    /// written according to the [`WriteStrategy`] of the options.
+    ///
+    /// Returns the number of header pages before and after the write.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to), or if the
+    /// temporary file of [`WriteStrategy::Atomic`] cannot be created or renamed, or if the
+    /// [backup](WriteOptions::backup) cannot be written.
+    #[cfg(feature = "fs")]
+    pub fn write_to_path_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &WriteOptions,
+    ) -> Result<HeaderPages> {
+        let (header_pages, written) = write::write_path(
+            path.as_ref(),
+            options,
+            |file| {
+                let (length, header_pages, written) = self.write_in_place(file, options)?;
+                Ok((length, (header_pages, written)))
+            },
+            |src, dst| write::copy_file(src, dst, options, write::preferred_stream(self)),
+        )?;
+        written.commit();
+        Ok(header_pages)
+    }
+
+    /// Writes to a path like [`write_to_path`](Self::write_to_path), unless the tag isn't
+    /// [dirty](Self::is_dirty), in which case the file isn't touched at all. Returns true if the
+    /// file was written.
+    ///
+    /// This is meant for writing a tag back to the file it was read from: a tag which is clean
+    /// is assumed to match the file.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to_path`](Self::write_to_path).
+    #[cfg(feature = "fs")]
+    pub fn write_if_changed<P: AsRef<Path>>(&self, path: P) -> Result<bool> {
+        self.write_if_changed_with(path, &WriteOptions::default())
+    }
+
+    /// Writes to a path like [`write_to_path_with`](Self::write_to_path_with), unless the tag
+    /// isn't [dirty](Self::is_dirty). See [`write_if_changed`](Self::write_if_changed).
+    /// # Errors
+    /// This function will error for the same reasons as
+    /// [`write_to_path_with`](Self::write_to_path_with).
+    #[cfg(feature = "fs")]
+    pub fn write_if_changed_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &WriteOptions,
+    ) -> Result<bool> {
+        if !self.is_dirty() && options.gain_adjustment == 0 {
+            debug!("the tag is unchanged, skipped writing");
+            return Ok(false);
+        }
+        self.write_to_path_with(path, options)?;
+        Ok(true)
+    }
+
+    /// Writes per-stream tags to a writer, keyed by stream serial number (see
+    /// [`read_streams_from`](Self::read_streams_from)). Opus streams without an entry in `tags`
+    /// keep their existing comment header, and packets of non-opus streams are copied through
+    /// unmodified.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to).
+    pub fn write_streams_to<W: Read + Write + Seek>(
+        tags: &HashMap<u32, Self>,
+        f_in: W,
+    ) -> Result<()> {
+        let (_, _, written) =
+            write::splice_streams(f_in, &WriteOptions::default(), opus_stream_tags(tags))?;
+        written.commit();
+        Ok(())
+    }
+
+    /// Convenience function for writing per-stream tags to a path.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to)
+    #[cfg(feature = "fs")]
+    pub fn write_streams_to_path<P: AsRef<Path>>(tags: &HashMap<u32, Self>, path: P) -> Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let (length, _, written) =
+            write::splice_streams(&mut file, &WriteOptions::default(), opus_stream_tags(tags))?;
+        file.set_len(length)?;
+        written.commit();
+        Ok(())
+    }
+
+    /// Encodes this tag as a bare `OpusTags` comment header packet, as written by
+    /// [`write_to`](Self::write_to) (without padding). This is the inverse of
+    /// [`from_slice`](Self::from_slice).
+    /// # Errors
+    /// This function will error if the tag is too big for the opus spec (some string is longer
+    /// than [`u32::MAX`] bytes, or the tag contains more than [`u32::MAX`] comments).
+    pub fn to_packet_data(&self) -> Result<Vec<u8>> {
+        self.to_packet_data_for(Codec::Opus)
+    }
+
+    /// Encodes this tag as the bare comment header packet of a stream of the given codec, like
+    /// [`to_packet_data`](Self::to_packet_data).
+    /// # Errors
+    /// This function will error for the same reasons as [`to_packet_data`](Self::to_packet_data).
+    pub fn to_packet_data_for(&self, codec: Codec) -> Result<Vec<u8>> {
+        self.to_packet_data_with(&self.vendor, codec, &WriteOptions::default())
+    }
+
+    /// Writes this tag as the comment header of a stream being muxed with an
+    /// [`ogg::PacketWriter`], so that metadata can be embedded while encoding instead of
+    /// rewriting the file afterwards.
+    ///
+    /// Call this right after writing the `OpusHead` packet of the stream with `serial`, which must
+    /// end its page ([`PacketWriteEndInfo::EndPage`](ogg::PacketWriteEndInfo::EndPage)), and
+    /// before the first audio packet. The comment header is written with a granule position of 0
+    /// and ends its last page, so the audio data starts on a new page as required by RFC 7845.
+    /// # Errors
+    /// This function will error if the tag is too big for the opus spec, or if writing to the
+    /// underlying writer fails.
+    pub fn write_packet_to<W: Write>(
+        &self,
+        writer: &mut ogg::PacketWriter<'_, W>,
+        serial: u32,
+    ) -> Result<()> {
+        writer.write_packet(
+            self.to_packet_data()?,
+            serial,
+            ogg::PacketWriteEndInfo::EndPage,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// Computes the size in bytes of the comment header packet that [`write_to`](Self::write_to)
+    /// would write for this tag, without encoding it. Padding is not included. This can be used
+    /// to enforce size limits, for example by downscaling the cover art before writing.
+    /// # Errors
+    /// This function will error if the tag is too big for the opus spec (some string is longer
+    /// than [`u32::MAX`] bytes, or the tag contains more than [`u32::MAX`] comments).
+    pub fn packet_size(&self) -> Result<usize> {
+        self.packet_size_with(&self.vendor, Codec::Opus)
+    }
+
+    /// Computes the size of the comment header packet with the given vendor string.
+    fn packet_size_with(&self, vendor: &str, codec: Codec) -> Result<usize> {
+        // magic signature, vendor length, vendor, comment count and framing bit
+        let mut size = codec.prefix_size()
+            + 4
+            + encoded_length(vendor.len())? as usize
+            + 4
+            + usize::from(codec.has_framing_bit());
+        let mut count: usize = 0;
+        for (key, values) in &self.comments {
+            for value in values {
+                // length, then KEY=VALUE
+                size += 4 + encoded_length(key.len() + 1 + value.len())? as usize;
+            }
+            count += values.len();
+        }
+        encoded_length(count)?;
+        Ok(size)
+    }
+
+    /// Encodes the comment header with the given vendor string, using the key case and comment
+    /// order of `options`. The packet is written straight into a buffer of the right size.
+    fn to_packet_data_with(
+        &self,
+        vendor: &str,
+        codec: Codec,
+        options: &WriteOptions,
+    ) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(self.packet_size_with(vendor, codec)?);
         // magic signature
-        output.extend_from_slice(b"OpusTags");
+        codec.write_prefix(&mut output);
 
         // encode vendor
-        let vendor = &self.vendor;
-        let vendor_length: u32 = vendor.len().try_into().map_err(|_| Error::TooBigError)?;
-        output.extend_from_slice(&vendor_length.to_le_bytes());
+        output.extend_from_slice(&encoded_length(vendor.len())?.to_le_bytes());
         output.extend_from_slice(vendor.as_bytes());
 
-        let mut formatted_tags = vec![];
-        for (tag, values) in &self.comments {
+        let count = self.comments.values().map(SmallVec::len).sum();
+        output.extend_from_slice(&encoded_length(count)?.to_le_bytes());
+
+        for (key, values) in self.sorted_comments(options) {
             for value in values {
-                formatted_tags.push(format!("{tag}={value}"));
+                let length = encoded_length(key.len() + 1 + value.len())?;
+                output.extend_from_slice(&length.to_le_bytes());
+                output.extend(key.bytes().map(|byte| options.key_case.apply(byte)));
+                output.push(b'=');
+                output.extend_from_slice(value.as_bytes());
             }
         }
+        if codec.has_framing_bit() {
+            output.push(1);
+        }
+        codec.finish(&mut output)?;
 
-        let num_comments: u32 = formatted_tags
-            .len()
-            .try_into()
-            .map_err(|_| Error::TooBigError)?;
-        output.extend_from_slice(&num_comments.to_le_bytes());
+        Ok(output)
+    }
+
+    /// Returns the comments in the order they are written in, following
+    /// [`WriteOptions::comment_order`].
+    fn sorted_comments(&self, options: &WriteOptions) -> Vec<(&Key, &Values)> {
+        let mut comments: Vec<(&Key, &Values)> = self.comments.iter().collect();
+        comments.sort_by_key(|(key, _)| options.comment_order.rank(key));
+        comments
+    }
 
-        for tag in formatted_tags {
-            let tag_length: u32 = tag.len().try_into().map_err(|_| Error::TooBigError)?;
-            output.extend_from_slice(&tag_length.to_le_bytes());
-            output.extend_from_slice(tag.as_bytes());
+    /// Splits off the pictures, decoded from base64, for formats which store them outside of the
+    /// comment header. Returns a copy of this tag without them; pictures which aren't valid
+    /// base64 are kept in it as comments.
+    fn split_pictures(&self) -> (Self, Vec<Vec<u8>>) {
+        let mut pictures = vec![];
+        let mut comments = HashMap::new();
+        for (key, values) in &self.comments {
+            let mut values = values.clone();
+            if key == "metadata_block_picture" {
+                values.retain(|value| {
+                    BASE64_STANDARD
+                        .decode(value)
+                        .map(|data| pictures.push(data))
+                        .is_err()
+                });
+            }
+            if !values.is_empty() {
+                comments.insert(key.clone(), values);
+            }
         }
+        let tag = Self {
+            vendor: self.vendor.clone(),
+            comments,
+            source_header: AtomicU64::new(self.source_header.load(atomic::Ordering::Relaxed)),
+            generation: self.generation,
+            clean_generation: AtomicU64::new(self.clean_generation.load(atomic::Ordering::Relaxed)),
+        };
+        (tag, pictures)
+    }
+}
 
-        Ok(output)
+/// Returns a `tag_for` for the functions of [`write`] which gives every opus stream its entry in
+/// `tags`, if it has one.
+fn opus_stream_tags<'a>(tags: &'a HashMap<u32, Tag>) -> impl FnMut(u32, Codec) -> Option<&'a Tag> {
+    |serial, codec| tags.get(&serial).filter(|_| codec == Codec::Opus)
+}
+
+/// Converts a length to the u32 used in the comment header.
+fn encoded_length(length: usize) -> Result<u32> {
+    length.try_into().map_err(|_| Error::TooBigError)
+}
+
+/// Hashes a comment header packet for [`Tag::check_source_header`]. Never returns 0.
+fn header_hash(data: &[u8]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish().max(1)
+}
+
+/// Returns true if the packet is the first packet of an opus stream.
+fn is_opus_head(packet: &ogg::Packet) -> bool {
+    packet.first_in_stream() && packet.data.starts_with(b"OpusHead")
+}
+
+/// Reads the comment header packet of every stream of a supported [`Codec`], with the stream
+/// serial number and codec, in the order they appear in the file.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+)]
+fn read_stream_packets<R: Read + Seek>(f_in: R) -> Result<Vec<(u32, Codec, Vec<u8>)>> {
+    let mut reader = PacketReader::new(f_in);
+    let mut output = vec![];
+    // streams whose comment header hasn't been read yet, with their codec
+    let mut pending = HashMap::new();
+
+    while let Some(packet) = reader.read_packet().map_err(header_error)? {
+        let stream_serial = packet.stream_serial();
+        trace!(
+            serial = stream_serial,
+            size = packet.data.len(),
+            "read packet"
+        );
+        let codec = Codec::from_first_packet(&packet.data);
+        if let Some(codec) = codec.filter(|_| packet.first_in_stream()) {
+            pending.insert(stream_serial, codec);
+        } else if let Some(codec) = pending.remove(&stream_serial) {
+            output.push((stream_serial, codec, packet.data));
+        } else if !packet.first_in_stream() && pending.is_empty() {
+            // all BOS pages come before any other page, so there are no more supported streams
+            break;
+        }
+    }
+
+    if !pending.is_empty() {
+        return Err(Error::MissingPacket);
+    }
+    if output.is_empty() {
+        return Err(Error::NotOpus);
+    }
+
+    Ok(output)
+}
+
+/// Reads the comment header packet of the first opus stream, or of the first stream of another
+/// supported [`Codec`] if there is none (see [`Codec::is_preferred_to`]), assembling it page by
+/// page so that it can be checked against [`ReadOptions::max_header_size`] as it grows.
+/// Pages belonging to other logical streams are skipped.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+)]
+fn read_comment_packet<R: Read>(f_in: R, options: &ReadOptions) -> Result<(Codec, Vec<u8>)> {
+    let mut reader = PageReader::new(f_in);
+    let mut packet = CommentPacket::default();
+    while let Some(chunk) = reader.read_chunk()? {
+        if let Some(data) = packet.push(chunk, options)? {
+            debug!(codec = ?data.0, size = data.1.len(), "read comment header");
+            return Ok(data);
+        }
+    }
+    Err(packet.missing())
+}
+
+/// The comment header packet of the stream picked by [`read_comment_packet`], as it is assembled
+/// from the chunks of a stream.
+#[derive(Default)]
+struct CommentPacket {
+    /// Serial number and codec of the stream.
+    stream: Option<(u32, Codec)>,
+    /// Whether all BOS pages (which come before any other page) have been read, so that the
+    /// stream is settled.
+    bos_done: bool,
+    /// Number of packets of the stream completed so far.
+    packets: usize,
+    data: Vec<u8>,
+}
+
+impl CommentPacket {
+    /// Adds the next chunk of the stream. Returns the packet once it is complete.
+    fn push(&mut self, chunk: Chunk, options: &ReadOptions) -> Result<Option<(Codec, Vec<u8>)>> {
+        let page = match chunk {
+            Chunk::Page { page, .. } => page,
+            Chunk::Garbage { offset: 0, .. } => {
+                return Err(OggReadError::NoCapturePatternFound.into())
+            }
+            Chunk::Garbage { offset, length } => {
+                options.report(|| Diagnostic::Garbage { offset, length });
+                return Ok(None);
+            }
+            Chunk::Truncated { offset, length } => {
+                options.report(|| Diagnostic::TruncatedPage { offset, length });
+                return Ok(None);
+            }
+        };
+        trace!(
+            serial = page.serial,
+            sequence = page.sequence_number,
+            size = page.body.len(),
+            "read page"
+        );
+        if options.verify_checksums {
+            let computed = page.compute_checksum();
+            if computed != page.checksum {
+                return Err(Error::CorruptHeader {
+                    stored: page.checksum,
+                    computed,
+                });
+            }
+        }
+        self.bos_done |= !page.is_bos();
+        let codec = match self.stream {
+            // an opus stream is preferred to a stream of another codec found before it
+            _ if !self.bos_done => {
+                let chosen = self.stream.map(|(_, codec)| codec);
+                match Codec::from_first_packet(&page.body)
+                    .filter(|codec| codec.is_preferred_to(chosen))
+                {
+                    Some(codec) => {
+                        self.stream = Some((page.serial, codec));
+                        self.packets = 0;
+                        self.data.clear();
+                        codec
+                    }
+                    None => return Ok(None),
+                }
+            }
+            // all BOS pages come before any other page, so if we're past them there is no
+            // supported stream in this file
+            None => return Err(Error::NotOpus),
+            Some((serial, codec)) if page.serial == serial => codec,
+            Some(_) => return Ok(None),
+        };
+
+        // the comment header is the second packet, and can span any number of pages
+        for (fragment, complete) in page.fragments() {
+            if self.packets == 1 {
+                if let Some(limit) = options.max_header_size {
+                    if self.data.len() + fragment.len() > limit {
+                        return Err(Error::HeaderTooLarge(limit));
+                    }
+                }
+                self.data.extend_from_slice(fragment);
+            }
+            if complete {
+                self.packets += 1;
+                if self.packets == 2 {
+                    return Ok(Some((codec, std::mem::take(&mut self.data))));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The error to report if the stream ends before the packet is complete.
+    const fn missing(&self) -> Error {
+        match self.stream {
+            Some(_) => Error::MissingPacket,
+            None => Error::NotOpus,
+        }
+    }
+}
+
+/// Converts an error that occured while reading header pages, reporting checksum mismatches as
+/// [`Error::CorruptHeader`].
+fn header_error(err: OggReadError) -> Error {
+    match err {
+        OggReadError::HashMismatch(stored, computed) => Error::CorruptHeader { stored, computed },
+        err => err.into(),
     }
 }
 
-fn get_end_info(packet: &ogg::Packet) -> PacketWriteEndInfo {
-    if packet.last_in_stream() {
-        PacketWriteEndInfo::EndStream
-    } else if packet.last_in_page() {
-        PacketWriteEndInfo::EndPage
-    } else {
-        PacketWriteEndInfo::NormalPacket
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::{Page, FLAG_CONTINUED};
+    use crate::testing::{Corruption, OpusStream};
+
+    /// Splits a stream into its pages.
+    fn pages(data: &[u8]) -> Vec<Page> {
+        let mut reader = PageReader::new(data);
+        let mut pages = vec![];
+        while let Some(chunk) = reader.read_chunk().unwrap() {
+            if let Chunk::Page { page, .. } = chunk {
+                pages.push(page);
+            }
+        }
+        pages
+    }
+
+    /// A picture with `size` bytes of data which doesn't compress, like a real scan.
+    fn scan(size: usize) -> Picture {
+        let mut state = 0x1234_5678_u32;
+        let data = std::iter::repeat_with(|| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state.to_le_bytes()[0]
+        })
+        .take(size)
+        .collect();
+        Picture {
+            data,
+            ..Picture::new()
+        }
+    }
+
+    #[test]
+    fn edits_values_through_get_mut() {
+        let mut tag = Tag::new(String::new(), vec![]);
+        tag.add_many("ARTIST".into(), ["a".into(), "b".into(), "c".into()]);
+        let mut values = tag.get_mut("artist".into()).unwrap();
+        values.push("d".into());
+        assert_eq!(values.remove(0), "a");
+        values.retain(|value| value != "c");
+        values[0].make_ascii_uppercase();
+        drop(values);
+        assert_eq!(tag.get("ARTIST".into()).unwrap(), ["B", "d"]);
+
+        tag.get_mut("artist".into()).unwrap().clear();
+        assert!(tag.get("artist".into()).is_none());
+        assert!(tag.as_map().is_empty());
+        assert!(tag.get_mut("artist".into()).is_none());
+    }
+
+    #[test]
+    fn accepts_mime_type_aliases() {
+        let jpeg = Picture {
+            mime_type: "image/jpg".into(),
+            data: b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00".to_vec(),
+            ..Picture::new()
+        };
+        assert!(jpeg.validate().is_empty());
+
+        let png = Picture {
+            mime_type: "image/jpg".into(),
+            data: b"\x89PNG\r\n\x1A\n\x00\x00\x00\rIHDR".to_vec(),
+            ..Picture::new()
+        };
+        assert!(matches!(
+            png.validate()[..],
+            [picture::PictureProblem::MimeTypeMismatch { .. }]
+        ));
+    }
+
+    #[test]
+    fn prefers_opus_stream_to_theora_stream_before_it() {
+        let theora = OpusStream::new()
+            .codec(Codec::Theora)
+            .serial(1)
+            .comment("TITLE", "video")
+            .build()
+            .unwrap();
+        let opus = OpusStream::new()
+            .serial(2)
+            .comment("TITLE", "audio")
+            .padding(1024)
+            .build()
+            .unwrap();
+        let data = crate::testing::multiplex(&[&theora, &opus]).unwrap();
+        let mut tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        assert_eq!(tag.get_one("TITLE".into()).unwrap(), "audio");
+
+        tag.add_one("ARTIST".into(), "someone".into());
+        let copied = tag.write_to_vec(&data[..]).unwrap();
+        let mut spliced = Cursor::new(data.clone());
+        tag.write_to(&mut spliced).unwrap();
+        // patched in place, since the new header fits in the padding
+        let mut patched = Cursor::new(data);
+        tag.write_to_with(&mut patched, &WriteOptions::new().padding(16))
+            .unwrap();
+        for output in [copied, spliced.into_inner(), patched.into_inner()] {
+            let file = OggFile::read_from(Cursor::new(&output)).unwrap();
+            assert_eq!(file.codec(), Codec::Opus);
+            let video = file.stream(1).unwrap().tag();
+            assert_eq!(video.get_one("TITLE".into()).unwrap(), "video");
+            assert!(video.get("ARTIST".into()).is_none());
+            let audio = file.stream(2).unwrap().tag();
+            assert_eq!(audio.get_one("ARTIST".into()).unwrap(), "someone");
+        }
+
+        // without an opus stream, the first stream is used
+        let vorbis = OpusStream::new()
+            .codec(Codec::Vorbis)
+            .serial(2)
+            .comment("TITLE", "audio")
+            .build()
+            .unwrap();
+        let data = crate::testing::multiplex(&[&theora, &vorbis]).unwrap();
+        let tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        assert_eq!(tag.get_one("TITLE".into()).unwrap(), "video");
+    }
+
+    #[test]
+    fn reads_and_writes_tags_per_opus_stream() {
+        let english = OpusStream::new()
+            .serial(1)
+            .comment("LANGUAGE", "en")
+            .build()
+            .unwrap();
+        let french = OpusStream::new()
+            .serial(2)
+            .comment("LANGUAGE", "fr")
+            .build()
+            .unwrap();
+        let vorbis = OpusStream::new()
+            .codec(Codec::Vorbis)
+            .serial(3)
+            .comment("LANGUAGE", "de")
+            .build()
+            .unwrap();
+        let data = crate::testing::multiplex(&[&english, &french, &vorbis]).unwrap();
+        let mut tags = Tag::read_streams_from(Cursor::new(&data)).unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[&1].get_one("LANGUAGE".into()).unwrap(), "en");
+        assert_eq!(tags[&2].get_one("LANGUAGE".into()).unwrap(), "fr");
+
+        tags.remove(&1);
+        tags.get_mut(&2)
+            .unwrap()
+            .add_one("TITLE".into(), "titre".into());
+        // the vorbis stream isn't an opus stream, so its entry is ignored
+        tags.insert(3, Tag::new(String::new(), vec![]));
+        let mut output = Cursor::new(data);
+        Tag::write_streams_to(&tags, &mut output).unwrap();
+        let file = OggFile::read_from(Cursor::new(output.into_inner())).unwrap();
+        let titles: Vec<_> = file
+            .streams()
+            .iter()
+            .map(|stream| stream.tag().get_one("TITLE".into()))
+            .collect();
+        assert_eq!(titles, [None, Some(&"titre".to_string()), None]);
+        let german = file.stream(3).unwrap().tag();
+        assert_eq!(german.get_one("LANGUAGE".into()).unwrap(), "de");
+
+        assert!(matches!(
+            Tag::read_streams_from(Cursor::new(&vorbis)),
+            Err(Error::NotOpus)
+        ));
+    }
+
+    #[test]
+    fn marks_tag_clean_only_after_writing_to_source() {
+        let data = OpusStream::new().comment("TITLE", "old").build().unwrap();
+        let mut tag = Tag::read_from(std::io::Cursor::new(&data)).unwrap();
+        tag.add_one("TITLE".into(), "new".into());
+        assert!(tag.is_dirty());
+
+        // a copy doesn't replace the source
+        let copy = tag.write_to_vec(&data[..]).unwrap();
+        assert!(tag.is_dirty());
+        assert!(!Tag::read_from(std::io::Cursor::new(&copy))
+            .unwrap()
+            .is_dirty());
+
+        let mut source = std::io::Cursor::new(data);
+        tag.write_to(&mut source).unwrap();
+        assert!(!tag.is_dirty());
+    }
+
+    #[test]
+    fn marks_tag_dirty_when_borrowed_for_editing() {
+        let data = OpusStream::new().comment("TITLE", "old").build().unwrap();
+        let mut tag = Tag::read_from(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(tag.get_one("TITLE".into()).unwrap(), "old");
+        assert!(tag.comments().count() == 1 && !tag.is_dirty());
+
+        assert!(tag.values_mut("ARTIST".into()).next().is_none());
+        assert!(tag.is_dirty());
+    }
+
+    #[test]
+    fn reads_header_spread_over_many_pages() {
+        // one lacing value, so 255 bytes, per page
+        let stream = OpusStream::new()
+            .comment("TITLE", "x".repeat(100_000))
+            .page_size(255);
+        let data = stream.build().unwrap();
+        assert!(pages(&data).len() > 390);
+        let tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        assert!(tag.comments().eq(stream.tag().unwrap().comments()));
+    }
+
+    #[test]
+    fn reads_header_interleaved_with_other_stream() {
+        let opus = pages(
+            &OpusStream::new()
+                .comment("TITLE", "x".repeat(200_000))
+                .page_size(4096)
+                .build()
+                .unwrap(),
+        );
+        let other = pages(
+            &OpusStream::new()
+                .serial(2)
+                .comment("TITLE", "other")
+                .audio_packets(100)
+                .build()
+                .unwrap(),
+        );
+        // both BOS pages first, then the pages of both streams alternating
+        let mut data = vec![];
+        opus[0].write_to(&mut data).unwrap();
+        other[0].write_to(&mut data).unwrap();
+        for index in 1..opus.len().max(other.len()) {
+            for page in [opus.get(index), other.get(index)].into_iter().flatten() {
+                page.write_to(&mut data).unwrap();
+            }
+        }
+        let tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        assert_eq!(tag.get_one("title".to_string()).unwrap().len(), 200_000);
+    }
+
+    #[test]
+    fn enforces_max_header_size() {
+        let stream = OpusStream::new()
+            .comment("TITLE", "x".repeat(100_000))
+            .page_size(4096);
+        let data = stream.build().unwrap();
+        let size = stream.tag().unwrap().packet_size().unwrap();
+
+        let options = ReadOptions::new().max_header_size(Some(size));
+        assert!(Tag::read_from_with(Cursor::new(&data), &options).is_ok());
+        let options = ReadOptions::new().max_header_size(Some(size - 1));
+        let result = Tag::read_from_with(Cursor::new(&data), &options);
+        assert!(matches!(result, Err(Error::HeaderTooLarge(limit)) if limit == size - 1));
+
+        // the limit is hit before the end of the header is needed
+        let truncated = &data[..data.len() / 2];
+        let options = ReadOptions::new().max_header_size(Some(1000));
+        let result = Tag::read_from_with(Cursor::new(truncated), &options);
+        assert!(matches!(result, Err(Error::HeaderTooLarge(1000))));
+        let result = Tag::read_from(Cursor::new(truncated));
+        assert!(matches!(result, Err(Error::MissingPacket)));
+    }
+
+    #[test]
+    fn roundtrips_header_of_tens_of_megabytes() {
+        let data = OpusStream::new().build().unwrap();
+        let mut tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        for size in [8_000_000, 8_000_001] {
+            let picture = scan(size).to_base64().unwrap();
+            tag.add_one("METADATA_BLOCK_PICTURE".to_string(), picture);
+        }
+        let size = tag.packet_size().unwrap();
+        assert!(size > 20_000_000);
+
+        let mut file = Cursor::new(data);
+        tag.write_to(&mut file).unwrap();
+        let output = file.into_inner();
+
+        // the header takes more than 255 pages, which all continue the packet but the first
+        let header: Vec<Page> = pages(&output)
+            .into_iter()
+            .skip(1)
+            .take_while(|page| page.granule_position != 960 + 312)
+            .collect();
+        assert_eq!(header.len(), size / 65025 + 1);
+        assert!(!header[0].is_continued());
+        assert!(header[1..]
+            .iter()
+            .all(|page| page.flags & FLAG_CONTINUED != 0));
+        for (sequence, page) in (1..).zip(&header) {
+            assert_eq!(page.sequence_number, sequence);
+        }
+
+        let read = Tag::read_from(Cursor::new(&output)).unwrap();
+        assert_eq!(read.pictures(), tag.pictures());
+    }
+
+    #[test]
+    fn roundtrips_vorbis_comment_header_with_framing_bit() {
+        let data = OpusStream::new()
+            .codec(Codec::Vorbis)
+            .comment("TITLE", "old")
+            .build()
+            .unwrap();
+        let mut tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        assert_eq!(tag.get_one("TITLE".to_string()).unwrap(), "old");
+        tag.remove_entries("TITLE".to_string());
+        tag.add_one("TITLE".to_string(), "a longer title".to_string());
+
+        let mut file = Cursor::new(data);
+        tag.write_to(&mut file).unwrap();
+        let output = file.into_inner();
+        let pages = pages(&output);
+        let header = &pages[1].body;
+        assert!(header.starts_with(b"\x03vorbis"));
+        assert_eq!(header.last(), Some(&1));
+        let size = tag.packet_size_with(tag.get_vendor(), Codec::Vorbis);
+        assert_eq!(header.len(), size.unwrap());
+        // the setup header follows, untouched
+        assert_eq!(pages[2].body, b"\x05vorbis\x00");
+
+        let read = Tag::read_from(Cursor::new(&output)).unwrap();
+        assert_eq!(read.get_one("TITLE".to_string()).unwrap(), "a longer title");
+        assert_eq!(read.get_vendor(), "opusmeta testing");
+    }
+
+    #[test]
+    fn reads_and_writes_speex_comment_header() {
+        let data = OpusStream::new()
+            .codec(Codec::Speex)
+            .comment("TITLE", "a long title")
+            .build()
+            .unwrap();
+        let header = pages(&data)[1].body.clone();
+        // without a magic signature, the codec has to be given
+        assert!(TagRef::from_packet(&header).is_err());
+        let tag_ref = TagRef::from_codec_packet(Codec::Speex, &header).unwrap();
+        assert_eq!(tag_ref.get_vendor(), "opusmeta testing");
+
+        let mut tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        assert_eq!(tag.get_one("TITLE".to_string()).unwrap(), "a long title");
+        tag.remove_entries("TITLE".to_string());
+        tag.add_one("TITLE".to_string(), "short".to_string());
+
+        // a shorter header is padded to replace the old one in place
+        let mut file = Cursor::new(data.clone());
+        tag.write_to_with(&mut file, &WriteOptions::new().padding(1))
+            .unwrap();
+        let output = file.into_inner();
+        assert_eq!(output.len(), data.len());
+        let header = &pages(&output)[1].body;
+        assert_eq!(header.len(), pages(&data)[1].body.len());
+        assert!(header.starts_with(&16u32.to_le_bytes()));
+
+        let read = Tag::read_from(Cursor::new(&output)).unwrap();
+        assert_eq!(read.get_one("TITLE".to_string()).unwrap(), "short");
+    }
+
+    #[test]
+    fn reads_and_writes_theora_comment_header() {
+        let data = OpusStream::new()
+            .codec(Codec::Theora)
+            .comment("TITLE", "old")
+            .build()
+            .unwrap();
+        let mut tag = Tag::read_from(Cursor::new(&data)).unwrap();
+        assert_eq!(tag.get_one("TITLE".to_string()).unwrap(), "old");
+        tag.add_one("ARTIST".to_string(), "artist".to_string());
+
+        let mut file = Cursor::new(data);
+        tag.write_to(&mut file).unwrap();
+        let output = file.into_inner();
+        let pages = pages(&output);
+        assert!(pages[0].body.starts_with(b"\x80theora"));
+        assert!(pages[1].body.starts_with(b"\x81theora"));
+        assert_eq!(pages[2].body, b"\x82theora\x00");
+
+        let read = Tag::read_from(Cursor::new(&output)).unwrap();
+        assert_eq!(read.get_one("TITLE".to_string()).unwrap(), "old");
+        assert_eq!(read.get_one("ARTIST".to_string()).unwrap(), "artist");
+        let header = TagRef::from_packet(&pages[1].body).unwrap();
+        assert_eq!(header.len(), 2);
+    }
+
+    #[test]
+    fn verifies_header_page_checksums_unless_disabled() {
+        let data = OpusStream::new()
+            .corrupt(Corruption::HeaderChecksum)
+            .build()
+            .unwrap();
+        let result = Tag::read_from(Cursor::new(&data));
+        assert!(matches!(result, Err(Error::CorruptHeader { .. })));
+
+        // a header page whose stored checksum is wrong, but whose content is intact
+        let data = OpusStream::new().comment("TITLE", "title").build().unwrap();
+        let mut damaged = vec![];
+        for (index, mut page) in pages(&data).into_iter().enumerate() {
+            if index == 1 {
+                page.checksum ^= 1;
+            }
+            page.write_to(&mut damaged).unwrap();
+        }
+        let stored = pages(&damaged)[1].checksum;
+        let result = Tag::read_from(Cursor::new(&damaged));
+        assert!(matches!(
+            result,
+            Err(Error::CorruptHeader { stored: s, computed }) if s == stored && computed == stored ^ 1
+        ));
+        let options = ReadOptions::new().verify_checksums(false);
+        let tag = Tag::read_from_with(Cursor::new(&damaged), &options).unwrap();
+        assert_eq!(tag.get_one("TITLE".to_string()).unwrap(), "title");
     }
 }
@@ -0,0 +1,489 @@
+//! Loudness scanning, to write the `R128_TRACK_GAIN` and `R128_ALBUM_GAIN` tags. Requires the
+//! `ebur128` feature.
+//!
+//! The audio is decoded and measured as described in EBU R 128 (ITU-R BS.1770-4): the integrated
+//! loudness, with K-weighting and gating, and the true peak, with 4x oversampling. RFC 7845
+//! defines the R128 gains as the gain which brings a file to the R 128 reference level of
+//! -23 LUFS, on top of the output gain of its `OpusHead`, which is why the output gain is applied
+//! before measuring.
+
+use crate::decode::{OpusDecoder, SAMPLE_RATE};
+use crate::{Result, Tag};
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{Read, Seek};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// The loudness R128 gains bring a file to, in LUFS.
+pub const REFERENCE_LOUDNESS: f64 = -23.0;
+
+/// Length of a gating block: 400 ms.
+const BLOCK_SIZE: usize = SAMPLE_RATE as usize * 4 / 10;
+/// Distance between gating blocks, which overlap by 75%: 100 ms.
+const BLOCK_STEP: usize = BLOCK_SIZE / 4;
+/// Blocks quieter than this, in LUFS, are left out.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks more than this many LU quieter than the loudness of the blocks above the absolute gate
+/// are left out.
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Coefficients of the two K-weighting filters at 48 kHz, as `(b, a)` with `a[0] == 1`: a high
+/// shelf modeling the head, and a high-pass filter.
+const K_WEIGHTING: [([f64; 3], [f64; 3]); 2] = [
+    (
+        [
+            1.535_124_859_586_97,
+            -2.691_696_189_406_38,
+            1.198_392_810_852_85,
+        ],
+        [1.0, -1.690_659_293_182_41, 0.732_480_774_215_85],
+    ),
+    (
+        [1.0, -2.0, 1.0],
+        [1.0, -1.990_047_454_833_98, 0.990_072_250_366_21],
+    ),
+];
+
+/// The polyphase FIR filter for 4x oversampling from BS.1770-4 annex 2, one phase per row.
+const OVERSAMPLING: [[f64; 12]; 4] = [
+    [
+        0.001_708_984_375_0,
+        0.010_986_328_125_0,
+        -0.019_653_320_312_5,
+        0.033_203_125_000_0,
+        -0.059_448_242_187_5,
+        0.137_329_101_562_5,
+        0.972_167_968_750_0,
+        -0.102_294_921_875_0,
+        0.047_607_421_875_0,
+        -0.026_611_328_125_0,
+        0.014_892_578_125_0,
+        -0.008_300_781_250_0,
+    ],
+    [
+        -0.029_174_804_687_5,
+        0.029_296_875_000_0,
+        -0.051_757_812_500_0,
+        0.089_111_328_125_0,
+        -0.166_503_906_250_0,
+        0.465_087_890_625_0,
+        0.779_785_156_250_0,
+        -0.200_317_382_812_5,
+        0.101_562_500_000_0,
+        -0.058_227_539_062_5,
+        0.033_081_054_687_5,
+        -0.018_920_898_437_5,
+    ],
+    [
+        -0.018_920_898_437_5,
+        0.033_081_054_687_5,
+        -0.058_227_539_062_5,
+        0.101_562_500_000_0,
+        -0.200_317_382_812_5,
+        0.779_785_156_250_0,
+        0.465_087_890_625_0,
+        -0.166_503_906_250_0,
+        0.089_111_328_125_0,
+        -0.051_757_812_500_0,
+        0.029_296_875_000_0,
+        -0.029_174_804_687_5,
+    ],
+    [
+        -0.008_300_781_250_0,
+        0.014_892_578_125_0,
+        -0.026_611_328_125_0,
+        0.047_607_421_875_0,
+        -0.102_294_921_875_0,
+        0.972_167_968_750_0,
+        0.137_329_101_562_5,
+        -0.059_448_242_187_5,
+        0.033_203_125_000_0,
+        -0.019_653_320_312_5,
+        0.010_986_328_125_0,
+        0.001_708_984_375_0,
+    ],
+];
+
+/// The loudness of a file, or of an album (see [`album_loudness`]).
+#[derive(Debug, Clone, Default)]
+pub struct Loudness {
+    /// The integrated loudness, in LUFS. Negative infinity if the audio is silent.
+    pub integrated: f64,
+    /// The true peak, in dBTP. Negative infinity if the audio is silent.
+    pub true_peak: f64,
+    /// The mean square of every block above the absolute gate, for computing the loudness of an
+    /// album.
+    blocks: Vec<f64>,
+}
+
+impl Loudness {
+    /// Computes the loudness from the mean squares of the blocks above the absolute gate.
+    fn from_blocks(blocks: Vec<f64>, peak: f64) -> Self {
+        let relative_gate = block_loudness(mean(&blocks)) + RELATIVE_GATE;
+        let gated: Vec<f64> = blocks
+            .iter()
+            .copied()
+            .filter(|&power| block_loudness(power) > relative_gate)
+            .collect();
+        Self {
+            integrated: block_loudness(mean(&gated)),
+            true_peak: 20.0 * peak.log10(),
+            blocks,
+        }
+    }
+
+    /// Returns the gain which brings the audio to the [reference loudness](REFERENCE_LOUDNESS),
+    /// in the Q7.8 fixed point format of the R128 tags (1/256 dB), or None if the audio is
+    /// silent.
+    #[must_use]
+    pub fn r128_gain(&self) -> Option<i16> {
+        if !self.integrated.is_finite() {
+            return None;
+        }
+        let gain = ((REFERENCE_LOUDNESS - self.integrated) * 256.0).round();
+        // clamped to the range of i16, so the cast can't truncate
+        #[allow(clippy::cast_possible_truncation)]
+        Some(gain.clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16)
+    }
+}
+
+/// Converts the mean square of a block to LUFS.
+fn block_loudness(power: f64) -> f64 {
+    10.0f64.mul_add(power.log10(), -0.691)
+}
+
+/// The mean of a list of block powers, or 0 if it is empty.
+fn mean(powers: &[f64]) -> f64 {
+    if powers.is_empty() {
+        return 0.0;
+    }
+    // precision is lost only for more blocks than there are in a few million years of audio
+    #[allow(clippy::cast_precision_loss)]
+    let count = powers.len() as f64;
+    powers.iter().sum::<f64>() / count
+}
+
+/// A biquad filter in direct form 1.
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    inputs: [f64; 2],
+    outputs: [f64; 2],
+}
+
+impl Biquad {
+    // written out as the difference equation, which is easier to check than nested mul_adds
+    #[allow(clippy::suboptimal_flops)]
+    fn process(&mut self, (b, a): &([f64; 3], [f64; 3]), input: f64) -> f64 {
+        let output = b[0] * input + b[1] * self.inputs[0] + b[2] * self.inputs[1]
+            - a[1] * self.outputs[0]
+            - a[2] * self.outputs[1];
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+/// The state of one channel of a [`Meter`].
+#[derive(Clone, Default)]
+struct Channel {
+    filters: [Biquad; 2],
+    /// The last 12 input samples, most recent first, for oversampling.
+    history: [f64; 12],
+}
+
+/// Measures the loudness of interleaved samples at 48 kHz.
+struct Meter {
+    channels: Vec<Channel>,
+    /// Sum of the squared K-weighted samples of every channel, per 100 ms step; the last 4 make
+    /// up the current block.
+    steps: Vec<f64>,
+    /// Sum of the squared K-weighted samples of the current step.
+    step: f64,
+    /// Samples per channel in the current step.
+    step_length: usize,
+    /// Mean squares of the blocks above the absolute gate.
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl Meter {
+    fn new(channels: usize) -> Self {
+        Self {
+            channels: vec![Channel::default(); channels],
+            steps: vec![],
+            step: 0.0,
+            step_length: 0,
+            blocks: vec![],
+            peak: 0.0,
+        }
+    }
+
+    /// Measures interleaved samples.
+    fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels.len()) {
+            for (channel, &sample) in self.channels.iter_mut().zip(frame) {
+                let sample = f64::from(sample);
+                let weighted = channel
+                    .filters
+                    .iter_mut()
+                    .zip(&K_WEIGHTING)
+                    .fold(sample, |value, (filter, coefficients)| {
+                        filter.process(coefficients, value)
+                    });
+                // both channels of mono and stereo audio are weighted equally
+                self.step += weighted * weighted;
+
+                channel.history.rotate_right(1);
+                channel.history[0] = sample;
+                for phase in &OVERSAMPLING {
+                    let value: f64 = phase
+                        .iter()
+                        .zip(&channel.history)
+                        .map(|(coefficient, sample)| coefficient * sample)
+                        .sum();
+                    self.peak = self.peak.max(value.abs());
+                }
+                self.peak = self.peak.max(sample.abs());
+            }
+
+            self.step_length += 1;
+            if self.step_length == BLOCK_STEP {
+                self.end_step();
+            }
+        }
+    }
+
+    /// Ends a 100 ms step, recording the block ending with it.
+    fn end_step(&mut self) {
+        self.steps.push(self.step);
+        self.step = 0.0;
+        self.step_length = 0;
+        if let Some(block) = self.steps.last_chunk::<4>() {
+            // the block size is a small integer, so the conversion is exact
+            #[allow(clippy::cast_precision_loss)]
+            let power = block.iter().sum::<f64>() / BLOCK_SIZE as f64;
+            if block_loudness(power) > ABSOLUTE_GATE {
+                self.blocks.push(power);
+            }
+        }
+    }
+
+    fn finish(self) -> Loudness {
+        Loudness::from_blocks(self.blocks, self.peak)
+    }
+}
+
+/// Measures the loudness of the first opus stream in a reader. Streams with more than 2 channels
+/// aren't supported.
+/// # Errors
+/// This function will error if the reader doesn't contain an opus stream, or if the audio can't
+/// be decoded.
+pub fn analyze_from<R: Read + Seek>(f_in: R) -> Result<Loudness> {
+    let mut decoder = OpusDecoder::new(f_in)?;
+    let mut meter = Meter::new(decoder.channels());
+    while let Some(samples) = decoder.next_chunk()? {
+        meter.add(&samples);
+    }
+    Ok(meter.finish())
+}
+
+/// Measures the loudness of the first opus stream in a file.
+/// # Errors
+/// This function will error if the file cannot be opened, or for the same reasons as
+/// [`analyze_from`].
+#[cfg(feature = "fs")]
+pub fn analyze_path<P: AsRef<Path>>(path: P) -> Result<Loudness> {
+    let file = File::open(path)?;
+    analyze_from(BufReader::new(file))
+}
+
+/// Computes the loudness of an album from the loudness of its tracks, as if they were played one
+/// after the other.
+#[must_use]
+pub fn album_loudness(tracks: &[Loudness]) -> Loudness {
+    let blocks = tracks
+        .iter()
+        .flat_map(|track| &track.blocks)
+        .copied()
+        .collect();
+    let true_peak = tracks
+        .iter()
+        .map(|track| track.true_peak)
+        .fold(f64::NEG_INFINITY, f64::max);
+    Loudness::from_blocks(blocks, 10f64.powf(true_peak / 20.0))
+}
+
+/// Measures the loudness of a file and writes its `R128_TRACK_GAIN` tag. Returns the loudness.
+/// # Errors
+/// This function will error for the same reasons as [`analyze_path`], or if writing the tags
+/// fails.
+#[cfg(feature = "fs")]
+pub fn tag_track<P: AsRef<Path>>(path: P) -> Result<Loudness> {
+    let path = path.as_ref();
+    let loudness = analyze_path(path)?;
+    let mut tag = Tag::read_from_path(path)?;
+    tag.set_r128_gains(&loudness, None);
+    tag.write_to_path(path)?;
+    Ok(loudness)
+}
+
+/// Measures the loudness of the files of an album and writes their `R128_TRACK_GAIN` and
+/// `R128_ALBUM_GAIN` tags. Returns the loudness of the album.
+///
+/// Every file is measured before any of them is written, so nothing is written if one of them
+/// can't be measured.
+/// # Errors
+/// This function will error for the same reasons as [`tag_track`].
+#[cfg(feature = "fs")]
+pub fn tag_album<P: AsRef<Path>>(paths: &[P]) -> Result<Loudness> {
+    let tracks = paths.iter().map(analyze_path).collect::<Result<Vec<_>>>()?;
+    let album = album_loudness(&tracks);
+    for (path, track) in paths.iter().zip(&tracks) {
+        let mut tag = Tag::read_from_path(path)?;
+        tag.set_r128_gains(track, Some(&album));
+        tag.write_to_path(path)?;
+    }
+    Ok(album)
+}
+
+impl Tag {
+    /// Sets the `R128_TRACK_GAIN` tag from the loudness of the file and, if given, the
+    /// `R128_ALBUM_GAIN` tag from the loudness of its album. The tags of silent audio are
+    /// removed, since no gain can make it reach the reference loudness.
+    pub fn set_r128_gains(&mut self, track: &Loudness, album: Option<&Loudness>) {
+        let gains = [("R128_TRACK_GAIN", Some(track)), ("R128_ALBUM_GAIN", album)];
+        for (key, loudness) in gains {
+            let Some(loudness) = loudness else {
+                continue;
+            };
+            self.remove_entries(key.to_string());
+            if let Some(gain) = loudness.r128_gain() {
+                self.add_one(key.to_string(), gain.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// A stereo sine, both channels in phase, of the given frequency in Hz, level in dBFS and
+    /// starting phase in degrees.
+    fn sine(frequency: f64, level: f64, phase: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(level / 20.0);
+        let step = 2.0 * PI * frequency / f64::from(SAMPLE_RATE);
+        let phase = phase.to_radians();
+        // a few minutes of samples at most
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let length = (seconds * f64::from(SAMPLE_RATE)).round() as usize;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+        (0..length)
+            .flat_map(|index| {
+                let sample = (amplitude * step.mul_add(index as f64, phase).sin()) as f32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    /// A 1 kHz sine of the given level in dBFS, for the given number of seconds.
+    fn tone(level: f64, seconds: f64) -> Vec<f32> {
+        sine(1000.0, level, 0.0, seconds)
+    }
+
+    /// Measures stereo signals played one after the other.
+    fn measure(signals: &[Vec<f32>]) -> Loudness {
+        let mut meter = Meter::new(2);
+        for samples in signals {
+            meter.add(samples);
+        }
+        meter.finish()
+    }
+
+    fn assert_close(measured: f64, expected: f64, below: f64, above: f64) {
+        assert!(
+            (expected - below..=expected + above).contains(&measured),
+            "measured {measured}, expected {expected} -{below}/+{above}",
+        );
+    }
+
+    // the cases of EBU Tech 3341 for the integrated loudness, which allow +-0.1 LU
+
+    #[test]
+    fn tech_3341_case_1() {
+        assert_close(measure(&[tone(-23.0, 20.0)]).integrated, -23.0, 0.1, 0.1);
+    }
+
+    #[test]
+    fn tech_3341_case_2() {
+        assert_close(measure(&[tone(-33.0, 20.0)]).integrated, -33.0, 0.1, 0.1);
+    }
+
+    #[test]
+    fn tech_3341_case_3() {
+        let loudness = measure(&[tone(-36.0, 10.0), tone(-23.0, 60.0), tone(-36.0, 10.0)]);
+        assert_close(loudness.integrated, -23.0, 0.1, 0.1);
+    }
+
+    #[test]
+    fn tech_3341_case_4() {
+        let loudness = measure(&[
+            tone(-72.0, 10.0),
+            tone(-36.0, 10.0),
+            tone(-23.0, 60.0),
+            tone(-36.0, 10.0),
+            tone(-72.0, 10.0),
+        ]);
+        assert_close(loudness.integrated, -23.0, 0.1, 0.1);
+    }
+
+    #[test]
+    fn tech_3341_case_5() {
+        let loudness = measure(&[tone(-26.0, 20.0), tone(-20.0, 20.1), tone(-26.0, 20.0)]);
+        assert_close(loudness.integrated, -23.0, 0.1, 0.1);
+    }
+
+    // the true peak cases of EBU Tech 3341: sines of -6 dBFS whose peaks fall between samples,
+    // which allow +0.2/-0.4 dB
+
+    #[test]
+    fn tech_3341_true_peak_cases() {
+        let rate = f64::from(SAMPLE_RATE);
+        for (frequency, phase) in [
+            (rate / 4.0, 0.0),
+            (rate / 4.0, 45.0),
+            (rate / 6.0, 60.0),
+            (rate / 8.0, 67.5),
+        ] {
+            let mut samples = sine(frequency, -6.0, phase, 1.0);
+            // faded in over 10 ms, as the oversampling filter rings at a sudden start
+            let fade = SAMPLE_RATE as usize / 100;
+            for (index, frame) in samples.chunks_exact_mut(2).take(fade).enumerate() {
+                #[allow(clippy::cast_precision_loss)]
+                let gain = index as f32 / fade as f32;
+                frame[0] *= gain;
+                frame[1] *= gain;
+            }
+            let loudness = measure(&[samples]);
+            assert_close(loudness.true_peak, -6.0, 0.4, 0.2);
+        }
+    }
+
+    #[test]
+    fn silence_has_no_gain() {
+        let loudness = measure(&[tone(f64::NEG_INFINITY, 5.0)]);
+        assert!(loudness.integrated.is_infinite() && loudness.integrated < 0.0);
+        assert_eq!(loudness.r128_gain(), None);
+    }
+
+    #[test]
+    fn gain_brings_audio_to_reference() {
+        let loudness = measure(&[tone(-18.0, 20.0)]);
+        let gain = f64::from(loudness.r128_gain().unwrap()) / 256.0;
+        assert_close(gain, -5.0, 0.1, 0.1);
+    }
+}
@@ -0,0 +1,258 @@
+//! Lyrics, either plain or synchronized in the
+//! [LRC](https://en.wikipedia.org/wiki/LRC_(file_format)) format.
+//!
+//! There is no standard comment for synchronized lyrics. Most taggers write them to
+//! `SYNCEDLYRICS`, while some store them in `LYRICS` in place of plain lyrics; both are read.
+//! Each line of lyrics is a start time, from the start of the file, and its text.
+//!
+//! Plain (unsynchronized) lyrics are stored in `LYRICS`, or `UNSYNCEDLYRICS` by some taggers.
+//! Lyrics in a specific language are stored with the ISO 639-2 code of the language as a suffix,
+//! as in `LYRICS:eng`.
+
+use crate::Tag;
+use std::time::Duration;
+
+/// A line of synchronized lyrics: the time it starts at and its text.
+pub type SyncedLine = (Duration, String);
+
+/// The precision of the timestamps written by [`to_lrc`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimestampPrecision {
+    /// `[mm:ss.xx]`, in hundredths of a second, which every player understands.
+    #[default]
+    Hundredths,
+    /// `[mm:ss.xxx]`, in milliseconds, the extended format understood by most modern players.
+    Milliseconds,
+}
+
+/// Converts `\r\n` and `\r` line endings to `\n`.
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Whether a lyrics value holds plain lyrics rather than lyrics in LRC format.
+fn is_unsynced(value: &str) -> bool {
+    parse_lrc(value).is_empty()
+}
+
+/// Parses a timestamp in `mm:ss`, `mm:ss.xx` or `mm:ss.xxx` format, without the brackets.
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let (minutes, seconds) = value.split_once(':')?;
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    let is_number = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+    if !is_number(minutes) || !is_number(seconds) || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let seconds: u64 = seconds.parse().ok()?;
+    if seconds >= 60 {
+        return None;
+    }
+
+    // only the first 3 digits of the fraction are kept
+    let millis = fraction
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(3)
+        .fold(0, |millis, digit| millis * 10 + u64::from(digit - b'0'));
+    let seconds = minutes
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(60)?
+        .checked_add(seconds)?;
+    Some(Duration::from_secs(seconds) + Duration::from_millis(millis))
+}
+
+/// Parses lyrics in LRC format, returning the lines in order of their start times.
+///
+/// Lines with several timestamps are repeated at each of them. The `[offset:...]` tag is applied;
+/// other ID tags (such as `[ar:...]`) and lines without a timestamp are skipped.
+#[must_use]
+pub fn parse_lrc(text: &str) -> Vec<SyncedLine> {
+    let mut lines = vec![];
+    // in milliseconds; a positive offset makes the lyrics come earlier
+    let mut offset = 0i64;
+    for line in text.lines() {
+        let mut rest = line.trim_start();
+        let mut times = vec![];
+        while let Some((tag, after)) = rest
+            .strip_prefix('[')
+            .and_then(|inner| inner.split_once(']'))
+        {
+            if let Some(time) = parse_timestamp(tag.trim()) {
+                times.push(time);
+            } else if let Some(value) = tag.strip_prefix("offset:") {
+                offset = value.trim().parse().unwrap_or(offset);
+            } else {
+                break;
+            }
+            rest = after;
+        }
+        lines.extend(
+            times
+                .into_iter()
+                .map(|time| (time, rest.trim().to_string())),
+        );
+    }
+
+    if offset != 0 {
+        let shift = Duration::from_millis(offset.unsigned_abs());
+        for (time, _) in &mut lines {
+            *time = if offset > 0 {
+                time.saturating_sub(shift)
+            } else {
+                *time + shift
+            };
+        }
+    }
+    lines.sort_by_key(|(time, _)| *time);
+    lines
+}
+
+/// Generates lyrics in LRC format, with one line per entry and timestamps of the given precision
+/// (rounded down).
+#[must_use]
+pub fn to_lrc(lines: &[SyncedLine], precision: TimestampPrecision) -> String {
+    lines
+        .iter()
+        .map(|(time, text)| {
+            let seconds = time.as_secs();
+            let fraction = match precision {
+                TimestampPrecision::Hundredths => format!("{:02}", time.subsec_millis() / 10),
+                TimestampPrecision::Milliseconds => format!("{:03}", time.subsec_millis()),
+            };
+            format!("[{:02}:{:02}.{fraction}]{text}", seconds / 60, seconds % 60)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Tag {
+    /// Returns the synchronized lyrics, from the first `SYNCEDLYRICS` or `LYRICS` value in LRC
+    /// format, or None if there is none.
+    #[must_use]
+    pub fn synced_lyrics(&self) -> Option<Vec<SyncedLine>> {
+        ["syncedlyrics", "lyrics"]
+            .into_iter()
+            .filter_map(|key| self.comments.get(key))
+            .flatten()
+            .map(|value| parse_lrc(value))
+            .find(|lines| !lines.is_empty())
+    }
+
+    /// Replaces the synchronized lyrics, writing them to `SYNCEDLYRICS` with timestamps in
+    /// hundredths of a second. `LYRICS` values in LRC format are removed, while plain lyrics are
+    /// kept.
+    pub fn set_synced_lyrics(&mut self, lines: &[SyncedLine]) {
+        self.set_synced_lyrics_with(lines, TimestampPrecision::default());
+    }
+
+    /// Replaces the synchronized lyrics like [`set_synced_lyrics`](Self::set_synced_lyrics),
+    /// with timestamps of the given precision.
+    pub fn set_synced_lyrics_with(&mut self, lines: &[SyncedLine], precision: TimestampPrecision) {
+        self.remove_synced_lyrics();
+        self.add_one("SYNCEDLYRICS".to_string(), to_lrc(lines, precision));
+    }
+
+    /// Removes the synchronized lyrics: every `SYNCEDLYRICS` value and the `LYRICS` values in LRC
+    /// format.
+    pub fn remove_synced_lyrics(&mut self) {
+        self.comments_mut().remove("syncedlyrics");
+        if let Some(values) = self.comments_mut().get_mut("lyrics") {
+            values.retain(|value| is_unsynced(value));
+            if values.is_empty() {
+                self.comments_mut().remove("lyrics");
+            }
+        }
+    }
+
+    /// Returns the plain lyrics, from the first `UNSYNCEDLYRICS` or `LYRICS` value which isn't in
+    /// LRC format, with `\n` line endings. Falls back to the lyrics of any language if there are
+    /// none without one.
+    #[must_use]
+    pub fn unsynced_lyrics(&self) -> Option<String> {
+        self.find_unsynced_lyrics(&["unsyncedlyrics", "lyrics"])
+            .or_else(|| {
+                let mut keys: Vec<&str> = self
+                    .comments
+                    .keys()
+                    .filter(|key| lyrics_language(key).is_some())
+                    .map(AsRef::as_ref)
+                    .collect();
+                keys.sort_unstable();
+                self.find_unsynced_lyrics(&keys)
+            })
+    }
+
+    /// Returns the plain lyrics in the given language, from `LYRICS:<language>` or
+    /// `UNSYNCEDLYRICS:<language>`, with `\n` line endings. The language is compared
+    /// case-insensitively.
+    #[must_use]
+    pub fn unsynced_lyrics_for(&self, language: &str) -> Option<String> {
+        let language = language.to_ascii_lowercase();
+        self.find_unsynced_lyrics(&[
+            &format!("unsyncedlyrics:{language}"),
+            &format!("lyrics:{language}"),
+        ])
+    }
+
+    /// Returns the languages with plain lyrics, in lowercase and sorted.
+    #[must_use]
+    pub fn lyrics_languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = self
+            .comments
+            .keys()
+            .filter_map(|key| lyrics_language(key))
+            .map(str::to_string)
+            .collect();
+        languages.sort_unstable();
+        languages.dedup();
+        languages
+    }
+
+    /// Replaces the plain lyrics, writing them to `LYRICS` with `\n` line endings. `UNSYNCEDLYRICS`
+    /// is removed, while synchronized lyrics and the lyrics of specific languages are kept.
+    pub fn set_unsynced_lyrics(&mut self, lyrics: &str) {
+        self.remove_unsynced_lyrics();
+        self.add_one("LYRICS".to_string(), normalize_newlines(lyrics));
+    }
+
+    /// Replaces the plain lyrics in the given language, writing them to `LYRICS:<language>` with
+    /// `\n` line endings.
+    pub fn set_unsynced_lyrics_for(&mut self, language: &str, lyrics: &str) {
+        let language = language.to_ascii_lowercase();
+        self.comments_mut()
+            .remove(format!("unsyncedlyrics:{language}").as_str());
+        let key = format!("lyrics:{language}");
+        self.comments_mut().remove(key.as_str());
+        self.add_one(key, normalize_newlines(lyrics));
+    }
+
+    /// Removes the plain lyrics: every `UNSYNCEDLYRICS` value and the `LYRICS` values which
+    /// aren't in LRC format. The lyrics of specific languages are kept.
+    pub fn remove_unsynced_lyrics(&mut self) {
+        self.comments_mut().remove("unsyncedlyrics");
+        if let Some(values) = self.comments_mut().get_mut("lyrics") {
+            values.retain(|value| !is_unsynced(value));
+            if values.is_empty() {
+                self.comments_mut().remove("lyrics");
+            }
+        }
+    }
+
+    /// Returns the first value of the given keys which holds plain lyrics.
+    fn find_unsynced_lyrics(&self, keys: &[&str]) -> Option<String> {
+        keys.iter()
+            .filter_map(|key| self.comments.get(*key))
+            .flatten()
+            .find(|value| is_unsynced(value))
+            .map(|value| normalize_newlines(value))
+    }
+}
+
+/// Returns the language of a lowercase `LYRICS:<language>` or `UNSYNCEDLYRICS:<language>` key.
+fn lyrics_language(key: &str) -> Option<&str> {
+    key.strip_prefix("lyrics:")
+        .or_else(|| key.strip_prefix("unsyncedlyrics:"))
+        .filter(|language| !language.is_empty())
+}
@@ -0,0 +1,770 @@
+//! Reading and writing the tags of the opus track of Matroska (`.mkv`, `.mka`) and webm files.
+//! Requires the `matroska` feature.
+//!
+//! The comments are mapped to the `SimpleTag`s of the `Tags` element which apply to the whole file
+//! (which is how ffmpeg writes them) or to the opus track. The vendor string is the `WritingApp`
+//! of the segment, and image attachments (such as `cover.jpg`) are read as pictures.
+//!
+//! Writing replaces these tags with a single `Tag` element applying to the whole file, while tags
+//! targeting other tracks, chapters or attachments are kept. Attachments and the `WritingApp` are
+//! left unchanged. The new `Tags` element is written over the old one if it fits in its space
+//! (including a `Void` element following it); otherwise the old one is turned into a `Void`
+//! element and the new one is appended to the end of the segment, which then has to be the end of
+//! the file. Either way, the audio data is never moved.
+
+use crate::picture::{Picture, PictureType};
+#[cfg(feature = "fs")]
+use crate::write;
+use crate::{Error, ReadOptions, Result, Tag, WriteOptions};
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// Element IDs, including their marker bits.
+const EBML: u32 = 0x1A45_DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_UID: u32 = 0x73C5;
+const CODEC_ID: u32 = 0x86;
+const CLUSTER: u32 = 0x1F43_B675;
+const ATTACHMENTS: u32 = 0x1941_A469;
+const FILE_DESCRIPTION: u32 = 0x467E;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const TAGS: u32 = 0x1254_C367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63C0;
+const TAG_TRACK_UID: u32 = 0x63C5;
+const TAG_EDITION_UID: u32 = 0x63C9;
+const TAG_CHAPTER_UID: u32 = 0x63C4;
+const TAG_ATTACHMENT_UID: u32 = 0x63C6;
+const SIMPLE_TAG: u32 = 0x67C8;
+const TAG_NAME: u32 = 0x45A3;
+const TAG_STRING: u32 = 0x4487;
+const VOID: u32 = 0xEC;
+
+/// The codec ID of opus tracks.
+const OPUS_CODEC_ID: &[u8] = b"A_OPUS";
+/// Length of the smallest `Void` element: a 1 byte ID and a 1 byte size.
+const MIN_VOID_LENGTH: u64 = 2;
+
+/// Read the tags of the opus track of a Matroska or webm file.
+/// # Errors
+/// This function will error with [`Error::NotOpus`] if the reader is not a Matroska file or has no
+/// opus track, if the elements holding the tags are malformed or cut short, or if a tag is not
+/// valid UTF-8.
+pub fn read_from<R: Read + Seek>(f_in: R) -> Result<Tag> {
+    read_from_with(f_in, &ReadOptions::default())
+}
+
+/// Read the tags of the opus track of a Matroska or webm file, using the given [`ReadOptions`].
+///
+/// [Skipping pictures](ReadOptions::skip_pictures) skips the attachments, and the
+/// [size limit](ReadOptions::max_header_size) applies to the `Tags` and `Attachments` elements.
+/// # Errors
+/// This function will error for the same reasons as [`read_from`].
+pub fn read_from_with<R: Read + Seek>(f_in: R, options: &ReadOptions) -> Result<Tag> {
+    let segment = Segment::read(f_in, !options.skip_pictures, options.max_header_size)?;
+    let track = segment.opus_track()?;
+
+    let vendor = segment
+        .info
+        .as_deref()
+        .and_then(|info| child(info, WRITING_APP))
+        .map(|app| String::from_utf8(app.to_vec()))
+        .transpose()?
+        .unwrap_or_default();
+    let mut tag = Tag::new(vendor, vec![]);
+    if let Some(tags) = &segment.tags {
+        for simple_tag in simple_tags(&tags.data, track) {
+            let (Some(name), Some(value)) =
+                (child(simple_tag, TAG_NAME), child(simple_tag, TAG_STRING))
+            else {
+                continue;
+            };
+            let name = String::from_utf8(name.to_vec())?;
+            tag.add_one(name, String::from_utf8(value.to_vec())?);
+        }
+        tag.set_source_header(&tags.data);
+    }
+
+    for attachments in &segment.attachments {
+        for file in elements(attachments).filter_map(std::result::Result::ok) {
+            if let Some(picture) = attached_picture(file.data)? {
+                tag.add_one("METADATA_BLOCK_PICTURE".to_string(), picture.to_base64()?);
+            }
+        }
+    }
+    Ok(tag)
+}
+
+/// Convenience function for reading the tags of a Matroska or webm file from a path.
+/// # Errors
+/// This function will error for the same reasons as [`read_from`].
+#[cfg(feature = "fs")]
+pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<Tag> {
+    let file = File::open(path)?;
+    read_from(BufReader::new(file))
+}
+
+/// Writes tags to a Matroska or webm file.
+/// # Errors
+/// This function will error for the same reasons as [`read_from`], if the `Tags` element has to
+/// be moved to the end of the segment but the segment isn't at the end of the file, or if reading,
+/// writing or seeking fails.
+pub fn write_to<F: Read + Write + Seek>(tag: &Tag, f_in: F) -> Result<()> {
+    write_to_with(tag, f_in, &WriteOptions::default())
+}
+
+/// Writes tags to a Matroska or webm file, using the given [`WriteOptions`].
+///
+/// If the `Tags` element has to be moved to the end of the segment, it is followed by a `Void` element of
+/// [`WriteOptions::padding`] bytes, so that later edits can be made in place.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`].
+pub fn write_to_with<F: Read + Write + Seek>(
+    tag: &Tag,
+    f_in: F,
+    options: &WriteOptions,
+) -> Result<()> {
+    let data = write_tags(tag, f_in, options)?;
+    tag.set_source_header(&data);
+    Ok(())
+}
+
+/// Writes the tags to `f_in`. Returns the data of the new `Tags` element, to be committed once
+/// the file it was written to replaces the source.
+fn write_tags<F: Read + Write + Seek>(
+    tag: &Tag,
+    mut f_in: F,
+    options: &WriteOptions,
+) -> Result<Vec<u8>> {
+    f_in.seek(SeekFrom::Start(0))?;
+    let segment = Segment::read(&mut f_in, false, None)?;
+    let track = segment.opus_track()?;
+    let old = segment.tags.as_ref().map_or(&[][..], |tags| &tags.data);
+    tag.check_source_header(old, options)?;
+    let data = tags_data(tag, old, track, options);
+    let new = element(TAGS, &data);
+
+    // the new element is written over the old one if it fits, followed by a void element taking
+    // up the space left over
+    if let Some(tags) = &segment.tags {
+        let left = (tags.length + tags.void).checked_sub(new.len() as u64);
+        if let Some(left) = left.filter(|&left| left == 0 || left >= MIN_VOID_LENGTH) {
+            f_in.seek(SeekFrom::Start(tags.offset))?;
+            f_in.write_all(&new)?;
+            f_in.write_all(&void_header(left))?;
+            f_in.flush()?;
+            return Ok(data);
+        }
+    }
+
+    let end = f_in.seek(SeekFrom::End(0))?;
+    if segment.end.is_some_and(|segment_end| segment_end != end) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "the tags have to be moved to the end of the segment, which isn't the end of the file",
+        )
+        .into());
+    }
+    let padding = match options.padding as u64 {
+        0 => 0,
+        padding => padding.max(MIN_VOID_LENGTH),
+    };
+    let mut appended = new;
+    let header = void_header(padding);
+    appended.resize(appended.len() + usize::try_from(padding)?, 0);
+    let start = appended.len() - usize::try_from(padding)?;
+    appended[start..start + header.len()].copy_from_slice(&header);
+    // checked before anything is written, so that a segment whose size field is too narrow for
+    // its new size is left untouched
+    let size_field = segment
+        .size_field
+        .map(|(offset, width)| {
+            let size = end + appended.len() as u64 - segment.data_start;
+            encode_vint(size, width)
+                .map(|size| (offset, size))
+                .ok_or(Error::TooBigError)
+        })
+        .transpose()?;
+    f_in.write_all(&appended)?;
+
+    if let Some(tags) = &segment.tags {
+        f_in.seek(SeekFrom::Start(tags.offset))?;
+        f_in.write_all(&void_header(tags.length))?;
+    }
+    if let Some((offset, size)) = size_field {
+        f_in.seek(SeekFrom::Start(offset))?;
+        f_in.write_all(&size)?;
+    }
+    // the seek head entries are pointed at the new element, or removed if the new position
+    // doesn't fit
+    let position = end - segment.data_start;
+    for seek in &segment.tags_seeks {
+        let value = seek
+            .position
+            .and_then(|(offset, width)| Some((offset, encode_uint(position, width)?)));
+        if let Some((offset, value)) = value {
+            f_in.seek(SeekFrom::Start(offset))?;
+            f_in.write_all(&value)?;
+        } else {
+            f_in.seek(SeekFrom::Start(seek.offset))?;
+            f_in.write_all(&void_header(seek.length))?;
+        }
+    }
+    f_in.flush()?;
+    Ok(data)
+}
+
+/// Convenience function for writing the tags of a Matroska or webm file to a path.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`].
+#[cfg(feature = "fs")]
+pub fn write_to_path<P: AsRef<Path>>(tag: &Tag, path: P) -> Result<()> {
+    write_to_path_with(tag, path, &WriteOptions::default())
+}
+
+/// Convenience function for writing the tags of a Matroska or webm file to a path, using the
+/// given [`WriteOptions`].
+///
+/// The file is written according to the [`WriteStrategy`](crate::WriteStrategy) of the options.
+/// # Errors
+/// This function will error for the same reasons as [`write_to`], or as
+/// [`Tag::write_to_path_with`] for the file operations.
+#[cfg(feature = "fs")]
+pub fn write_to_path_with<P: AsRef<Path>>(
+    tag: &Tag,
+    path: P,
+    options: &WriteOptions,
+) -> Result<()> {
+    let data = write::write_path(
+        path.as_ref(),
+        options,
+        |file| {
+            let data = write_tags(tag, &mut *file, options)?;
+            Ok((file.seek(SeekFrom::End(0))?, data))
+        },
+        |mut src, dst| {
+            std::io::copy(&mut src, dst)?;
+            write_tags(tag, dst, options)
+        },
+    )?;
+    tag.set_source_header(&data);
+    Ok(())
+}
+
+/// Encodes the data of the new `Tags` element: the `Tag` elements of `old` which don't apply to
+/// the opus track, followed by one holding the comments of `tag`.
+fn tags_data(tag: &Tag, old: &[u8], track: u64, options: &WriteOptions) -> Vec<u8> {
+    let mut output = vec![];
+    for old_tag in elements(old).filter_map(std::result::Result::ok) {
+        if old_tag.id == TAG && !applies_to(old_tag.data, track) {
+            output.extend_from_slice(old_tag.raw);
+        }
+    }
+
+    // the pictures stay in the attachments
+    let (comments, _) = tag.split_pictures();
+    let mut data = element(TARGETS, &[]);
+    let mut empty = true;
+    for (key, values) in comments.sorted_comments(options) {
+        for value in values {
+            let mut simple_tag = element(TAG_NAME, key.to_ascii_uppercase().as_bytes());
+            simple_tag.extend(element(TAG_STRING, value.as_bytes()));
+            data.extend(element(SIMPLE_TAG, &simple_tag));
+            empty = false;
+        }
+    }
+    if !empty {
+        output.extend(element(TAG, &data));
+    }
+    output
+}
+
+/// Returns the `SimpleTag` elements of the `Tag` elements which apply to the opus track.
+fn simple_tags(tags: &[u8], track: u64) -> impl Iterator<Item = &[u8]> {
+    elements(tags)
+        .filter_map(std::result::Result::ok)
+        .filter(move |tag| tag.id == TAG && applies_to(tag.data, track))
+        .flat_map(|tag| elements(tag.data).filter_map(std::result::Result::ok))
+        .filter(|simple_tag| simple_tag.id == SIMPLE_TAG)
+        .map(|simple_tag| simple_tag.data)
+}
+
+/// Returns true if a `Tag` element applies to the given track: it targets the whole file, or
+/// that track.
+fn applies_to(tag: &[u8], track: u64) -> bool {
+    let Some(targets) = child(tag, TARGETS) else {
+        return true;
+    };
+    let mut tracks = vec![];
+    for target in elements(targets).filter_map(std::result::Result::ok) {
+        match target.id {
+            TAG_TRACK_UID => tracks.push(decode_uint(target.data)),
+            TAG_EDITION_UID | TAG_CHAPTER_UID | TAG_ATTACHMENT_UID
+                if decode_uint(target.data) != 0 =>
+            {
+                return false;
+            }
+            _ => {}
+        }
+    }
+    tracks.is_empty() || tracks.iter().any(|&uid| uid == 0 || uid == track)
+}
+
+/// Decodes an `AttachedFile` element into a picture, or returns None if it isn't an image.
+/// Attachments named `cover` (of any kind, such as `small_cover_land.png`) are front covers.
+fn attached_picture(file: &[u8]) -> Result<Option<Picture>> {
+    let (Some(mime_type), Some(data)) = (child(file, FILE_MIME_TYPE), child(file, FILE_DATA))
+    else {
+        return Ok(None);
+    };
+    if !mime_type.starts_with(b"image/") {
+        return Ok(None);
+    }
+    let name = child(file, FILE_NAME).unwrap_or_default();
+    let is_cover = name
+        .windows(5)
+        .any(|part| part.eq_ignore_ascii_case(b"cover"));
+    Ok(Some(Picture {
+        picture_type: if is_cover {
+            PictureType::CoverFront
+        } else {
+            PictureType::Other
+        },
+        mime_type: String::from_utf8(mime_type.to_vec())?,
+        description: String::from_utf8(child(file, FILE_DESCRIPTION).unwrap_or_default().to_vec())?,
+        data: data.to_vec(),
+    }))
+}
+
+/// The `Tags` element, as found when reading the segment.
+struct TagsElement {
+    /// Position of the element in the file.
+    offset: u64,
+    /// Length of the element, header included.
+    length: u64,
+    /// Length of the `Void` element directly following it, or 0.
+    void: u64,
+    data: Vec<u8>,
+}
+
+/// A seek head entry pointing at the `Tags` element.
+struct TagsSeek {
+    /// Position of the entry in the file.
+    offset: u64,
+    /// Length of the entry, header included.
+    length: u64,
+    /// Position and width of the value of its `SeekPosition`.
+    position: Option<(u64, usize)>,
+}
+
+/// The parts of the first segment of a Matroska file which hold metadata.
+#[derive(Default)]
+struct Segment {
+    /// Position of the segment data in the file, which the seek head positions are relative to.
+    data_start: u64,
+    /// Position and width of the size of the segment, unless it is unknown.
+    size_field: Option<(u64, usize)>,
+    /// Position of the end of the segment, unless its size is unknown.
+    end: Option<u64>,
+    info: Option<Vec<u8>>,
+    tracks: Option<Vec<u8>>,
+    tags: Option<TagsElement>,
+    attachments: Vec<Vec<u8>>,
+    tags_seeks: Vec<TagsSeek>,
+}
+
+impl Segment {
+    /// Reads the metadata elements of the first segment, skipping the clusters. Attachments are
+    /// only read if `attachments` is set. Fails if the `Tags` or an `Attachments` element is
+    /// bigger than `max_size`.
+    fn read<R: Read + Seek>(
+        mut f_in: R,
+        attachments: bool,
+        max_size: Option<usize>,
+    ) -> Result<Self> {
+        let Some(header) = read_header(&mut f_in)?.filter(|header| header.id == EBML) else {
+            return Err(Error::NotOpus);
+        };
+        let ebml = read_data(&mut f_in, header.size, None)?;
+        if !matches!(child(&ebml, DOC_TYPE), Some(b"matroska" | b"webm")) {
+            return Err(Error::NotOpus);
+        }
+
+        let header = loop {
+            let header = read_header(&mut f_in)?.ok_or(Error::NotOpus)?;
+            if header.id == SEGMENT {
+                break header;
+            }
+            skip(&mut f_in, header.size)?;
+        };
+        let data_start = f_in.stream_position()?;
+        let mut segment = Self {
+            data_start,
+            size_field: header
+                .size
+                .map(|_| (data_start - header.size_width as u64, header.size_width)),
+            end: header.size.map(|size| data_start + size),
+            ..Self::default()
+        };
+
+        // whether the previous element is the Tags element
+        let mut after_tags = false;
+        loop {
+            let offset = f_in.stream_position()?;
+            if segment.end.is_some_and(|end| offset >= end) {
+                break;
+            }
+            let Some(header) = read_header(&mut f_in)? else {
+                break;
+            };
+            // the children of a cluster of unknown size are read as if they were children of the
+            // segment, which skips them as well
+            if header.id == CLUSTER && header.size.is_none() {
+                continue;
+            }
+            after_tags = match header.id {
+                INFO => {
+                    segment.info = Some(read_data(&mut f_in, header.size, None)?);
+                    false
+                }
+                TRACKS => {
+                    segment.tracks = Some(read_data(&mut f_in, header.size, None)?);
+                    false
+                }
+                TAGS if segment.tags.is_none() => {
+                    let data = read_data(&mut f_in, header.size, max_size)?;
+                    segment.tags = Some(TagsElement {
+                        offset,
+                        length: header.length + data.len() as u64,
+                        void: 0,
+                        data,
+                    });
+                    true
+                }
+                ATTACHMENTS if attachments => {
+                    let data = read_data(&mut f_in, header.size, max_size)?;
+                    segment.attachments.push(data);
+                    false
+                }
+                SEEK_HEAD => {
+                    let data = read_data(&mut f_in, header.size, None)?;
+                    segment.read_seek_head(offset + header.length, &data);
+                    false
+                }
+                VOID if after_tags => {
+                    let size = header.size.ok_or_else(malformed)?;
+                    if let Some(tags) = &mut segment.tags {
+                        tags.void = header.length + size;
+                    }
+                    skip(&mut f_in, header.size)?;
+                    false
+                }
+                _ => {
+                    skip(&mut f_in, header.size)?;
+                    false
+                }
+            };
+        }
+        Ok(segment)
+    }
+
+    /// Finds the seek head entries pointing at the `Tags` element, given the position of the seek
+    /// head data in the file.
+    fn read_seek_head(&mut self, start: u64, data: &[u8]) {
+        for seek in elements(data).filter_map(std::result::Result::ok) {
+            if seek.id != SEEK || child(seek.data, SEEK_ID) != Some(&TAGS.to_be_bytes()[..]) {
+                continue;
+            }
+            let seek_start = start + seek.data_offset as u64;
+            let position = elements(seek.data)
+                .filter_map(std::result::Result::ok)
+                .find(|position| position.id == SEEK_POSITION)
+                .map(|position| {
+                    (
+                        seek_start + position.data_offset as u64,
+                        position.data.len(),
+                    )
+                });
+            self.tags_seeks.push(TagsSeek {
+                offset: start + seek.offset as u64,
+                length: seek.raw.len() as u64,
+                position,
+            });
+        }
+    }
+
+    /// Returns the UID of the first opus track.
+    fn opus_track(&self) -> Result<u64> {
+        let tracks = self.tracks.as_deref().ok_or(Error::NotOpus)?;
+        elements(tracks)
+            .filter_map(std::result::Result::ok)
+            .filter(|entry| child(entry.data, CODEC_ID) == Some(OPUS_CODEC_ID))
+            .find_map(|entry| child(entry.data, TRACK_UID).map(decode_uint))
+            .ok_or(Error::NotOpus)
+    }
+}
+
+/// The header of an element read from a file.
+struct Header {
+    id: u32,
+    /// Size of the data, or None if it is unknown.
+    size: Option<u64>,
+    /// Length of the header.
+    length: u64,
+    /// Width of the size in the header.
+    size_width: usize,
+}
+
+/// Reads the header of an element, or returns None at the end of the file.
+fn read_header<R: Read>(mut f_in: R) -> Result<Option<Header>> {
+    let mut first = [0];
+    if f_in.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    let id_width = vint_width(first[0])
+        .filter(|&width| width <= 4)
+        .ok_or_else(malformed)?;
+    let mut id = [0; 4];
+    id[4 - id_width] = first[0];
+    f_in.read_exact(&mut id[5 - id_width..])?;
+
+    f_in.read_exact(&mut first)?;
+    let size_width = vint_width(first[0]).ok_or_else(malformed)?;
+    let mut size = [0; 8];
+    size[8 - size_width] = first[0];
+    f_in.read_exact(&mut size[9 - size_width..])?;
+    Ok(Some(Header {
+        id: u32::from_be_bytes(id),
+        size: decode_vint(&size[8 - size_width..]),
+        length: (id_width + size_width) as u64,
+        size_width,
+    }))
+}
+
+/// Reads the data of an element, which must have a known size.
+fn read_data<R: Read>(f_in: R, size: Option<u64>, max_size: Option<usize>) -> Result<Vec<u8>> {
+    let size = size.ok_or_else(malformed)?;
+    if let Some(limit) = max_size.filter(|&limit| size > limit as u64) {
+        return Err(Error::HeaderTooLarge(limit));
+    }
+    let mut data = vec![];
+    f_in.take(size).read_to_end(&mut data)?;
+    if (data.len() as u64) < size {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(data)
+}
+
+/// Skips the data of an element, which must have a known size.
+fn skip<R: Seek>(mut f_in: R, size: Option<u64>) -> Result<()> {
+    let size = size.ok_or_else(malformed)?;
+    f_in.seek(SeekFrom::Current(i64::try_from(size)?))?;
+    Ok(())
+}
+
+/// An element in a buffer.
+struct Element<'a> {
+    id: u32,
+    data: &'a [u8],
+    /// The whole element, header included.
+    raw: &'a [u8],
+    /// Position of the element in the buffer.
+    offset: usize,
+    /// Position of the data in the buffer.
+    data_offset: usize,
+}
+
+/// Returns an iterator over the elements in a buffer, such as the children of an element.
+/// Iteration stops after the first malformed element.
+fn elements(data: &[u8]) -> impl Iterator<Item = std::io::Result<Element<'_>>> {
+    let mut position = 0;
+    std::iter::from_fn(move || {
+        if position >= data.len() {
+            return None;
+        }
+        let element = parse_element(data, position);
+        position = element.as_ref().map_or(data.len(), |element| {
+            element.data_offset + element.data.len()
+        });
+        Some(element)
+    })
+}
+
+/// Parses the element at `offset` in a buffer.
+fn parse_element(data: &[u8], offset: usize) -> std::io::Result<Element<'_>> {
+    let rest = &data[offset..];
+    let id_width = rest
+        .first()
+        .and_then(|&first| vint_width(first))
+        .filter(|&width| width <= 4 && width <= rest.len())
+        .ok_or_else(malformed)?;
+    let id = rest[..id_width]
+        .iter()
+        .fold(0, |id, &byte| id << 8 | u32::from(byte));
+    let size_width = rest
+        .get(id_width)
+        .and_then(|&first| vint_width(first))
+        .filter(|&width| id_width + width <= rest.len())
+        .ok_or_else(malformed)?;
+    let size = decode_vint(&rest[id_width..id_width + size_width]).ok_or_else(malformed)?;
+    let start = id_width + size_width;
+    let end = usize::try_from(size)
+        .ok()
+        .and_then(|size| start.checked_add(size))
+        .filter(|&end| end <= rest.len())
+        .ok_or_else(malformed)?;
+    Ok(Element {
+        id,
+        data: &rest[start..end],
+        raw: &rest[..end],
+        offset,
+        data_offset: offset + start,
+    })
+}
+
+/// Returns the data of the first child of an element with the given ID.
+fn child(data: &[u8], id: u32) -> Option<&[u8]> {
+    elements(data)
+        .map_while(std::result::Result::ok)
+        .find(|element| element.id == id)
+        .map(|element| element.data)
+}
+
+/// Encodes an element.
+fn element(id: u32, data: &[u8]) -> Vec<u8> {
+    let id = id.to_be_bytes();
+    let id_start = id.iter().position(|&byte| byte != 0).unwrap_or(3);
+    let mut output = id[id_start..].to_vec();
+    let size = data.len() as u64;
+    let width = (1..=8)
+        .find(|&width| encode_vint(size, width).is_some())
+        .unwrap_or(8);
+    output.extend(encode_vint(size, width).unwrap_or_default());
+    output.extend_from_slice(data);
+    output
+}
+
+/// Encodes the header of a `Void` element taking up `length` bytes in total, or nothing if
+/// `length` is 0. `length` must not be 1.
+fn void_header(length: u64) -> Vec<u8> {
+    if length == 0 {
+        return vec![];
+    }
+    // the size is written with as many bytes as it takes for the data to fill the rest
+    (1..=8)
+        .find_map(|width| {
+            let size = length.checked_sub(1 + width as u64)?;
+            let mut header = vec![0xEC];
+            header.extend(encode_vint(size, width)?);
+            Some(header)
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the width of a variable size integer from its first byte, or None if it is invalid.
+fn vint_width(first: u8) -> Option<usize> {
+    let width = first.leading_zeros() as usize + 1;
+    (width <= 8).then_some(width)
+}
+
+/// Decodes a variable size integer, or returns None if it has all of its bits set, which means
+/// an unknown size.
+fn decode_vint(data: &[u8]) -> Option<u64> {
+    let value = data
+        .iter()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte));
+    // remove the marker bit
+    let value = value & !(1 << (7 * data.len()));
+    let unknown = (1 << (7 * data.len())) - 1;
+    (value != unknown).then_some(value)
+}
+
+/// Encodes a variable size integer of the given width, or returns None if it doesn't fit.
+fn encode_vint(value: u64, width: usize) -> Option<Vec<u8>> {
+    // all bits set means an unknown size
+    if width == 0 || width > 8 || value >= (1 << (7 * width)) - 1 {
+        return None;
+    }
+    let value = value | 1 << (7 * width);
+    Some(value.to_be_bytes()[8 - width..].to_vec())
+}
+
+/// Decodes an unsigned integer element.
+fn decode_uint(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+/// Encodes an unsigned integer in the given number of bytes, or returns None if it doesn't fit.
+fn encode_uint(value: u64, width: usize) -> Option<Vec<u8>> {
+    let bytes = value.to_be_bytes();
+    let start = 8usize.checked_sub(width)?;
+    bytes[..start]
+        .iter()
+        .all(|&byte| byte == 0)
+        .then(|| bytes[start..].to_vec())
+}
+
+fn malformed() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "malformed Matroska element",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A file with an opus track and no tags, whose segment has a 1 byte size field.
+    fn file_with_narrow_segment_size() -> Vec<u8> {
+        let mut track = element(CODEC_ID, OPUS_CODEC_ID);
+        track.extend(element(TRACK_UID, &[1]));
+        // a TrackEntry
+        let tracks = element(TRACKS, &element(0xAE, &track));
+        let mut file = element(EBML, &element(DOC_TYPE, b"matroska"));
+        file.extend_from_slice(&SEGMENT.to_be_bytes());
+        file.extend(encode_vint(tracks.len() as u64, 1).unwrap());
+        file.extend(tracks);
+        file
+    }
+
+    #[test]
+    fn writes_tags_to_end_of_segment() {
+        let mut file = Cursor::new(file_with_narrow_segment_size());
+        let mut tag = Tag::new(String::new(), vec![]);
+        tag.add_one("TITLE".to_string(), "title".to_string());
+        write_to_with(&tag, &mut file, &WriteOptions::new().padding(0)).unwrap();
+        file.set_position(0);
+        let read = read_from(&mut file).unwrap();
+        assert_eq!(read.get_one("title".to_string()).unwrap(), "title");
+    }
+
+    #[test]
+    fn leaves_file_untouched_if_segment_size_does_not_fit() {
+        let original = file_with_narrow_segment_size();
+        let mut file = Cursor::new(original.clone());
+        let mut tag = Tag::new(String::new(), vec![]);
+        // more than the 126 bytes a 1 byte size can hold
+        tag.add_one("TITLE".to_string(), "x".repeat(200));
+        let result = write_to_with(&tag, &mut file, &WriteOptions::new().padding(0));
+        assert!(matches!(result, Err(Error::TooBigError)));
+        assert_eq!(file.into_inner(), original);
+    }
+}
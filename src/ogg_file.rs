@@ -0,0 +1,312 @@
+//! A file whose streams are tagged without knowing their codec in advance.
+
+use crate::write::{self, HeaderPages, Written};
+use crate::{Codec, ReadOptions, Result, Tag, WriteOptions};
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// The tags of one logical stream of an [`OggFile`].
+#[derive(Debug)]
+pub struct StreamTags {
+    serial: u32,
+    codec: Codec,
+    tag: Tag,
+}
+
+impl StreamTags {
+    /// Returns the serial number of the stream.
+    #[must_use]
+    pub const fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    /// Returns the codec of the stream.
+    #[must_use]
+    pub const fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns the tags of the stream.
+    #[must_use]
+    pub const fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    /// Returns the tags of the stream for editing.
+    pub const fn tag_mut(&mut self) -> &mut Tag {
+        &mut self.tag
+    }
+
+    /// Replaces the tags of the stream.
+    pub fn set_tag(&mut self, tag: Tag) {
+        self.tag = tag;
+    }
+}
+
+/// An ogg file with the tags of every logical stream of a supported [`Codec`].
+///
+/// The codec of each stream is detected from its identification header, so the same code works
+/// for opus, Vorbis, FLAC, Speex and Theora files. [`tags`](Self::tags) and
+/// [`set_tags`](Self::set_tags) work on the main stream, which is the one the functions of
+/// [`Tag`] read and write: the first opus stream, or the first stream if there is none. The
+/// others, such as the Theora stream of a video, are reached through
+/// [`streams`](Self::streams).
+#[derive(Debug)]
+pub struct OggFile {
+    path: Option<PathBuf>,
+    streams: Vec<StreamTags>,
+}
+
+impl OggFile {
+    /// Reads the tags of every supported stream from a reader.
+    /// # Errors
+    /// This function will error for the same reasons as [`Tag::read_from`], for any of the
+    /// supported streams in the reader.
+    pub fn read_from<R: Read + Seek>(f_in: R) -> Result<Self> {
+        let streams = crate::read_stream_packets(f_in)?
+            .into_iter()
+            .map(|(serial, codec, data)| {
+                let tag = Tag::from_comment_packet(codec, &data, &ReadOptions::default())?;
+                Ok(StreamTags { serial, codec, tag })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            path: None,
+            streams,
+        })
+    }
+
+    /// Reads the tags of every supported stream from a path, which is remembered for
+    /// [`save`](Self::save).
+    /// # Errors
+    /// This function will error if the file cannot be opened, or for the same reasons as
+    /// [`read_from`](Self::read_from).
+    #[cfg(feature = "fs")]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let mut output = Self::read_from(BufReader::new(file))?;
+        output.path = Some(path.to_path_buf());
+        Ok(output)
+    }
+
+    /// Returns the path the file was opened from, if any.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the codec of the main stream.
+    #[must_use]
+    pub fn codec(&self) -> Codec {
+        self.streams[self.main()].codec
+    }
+
+    /// Returns the tags of the main stream.
+    #[must_use]
+    pub fn tags(&self) -> &Tag {
+        &self.streams[self.main()].tag
+    }
+
+    /// Returns the tags of the main stream for editing.
+    pub fn tags_mut(&mut self) -> &mut Tag {
+        let main = self.main();
+        &mut self.streams[main].tag
+    }
+
+    /// Replaces the tags of the main stream.
+    pub fn set_tags(&mut self, tag: Tag) {
+        let main = self.main();
+        self.streams[main].tag = tag;
+    }
+
+    /// Returns every supported stream, in the order they appear in the file.
+    #[must_use]
+    pub fn streams(&self) -> &[StreamTags] {
+        &self.streams
+    }
+
+    /// Returns every supported stream for editing.
+    pub fn streams_mut(&mut self) -> &mut [StreamTags] {
+        &mut self.streams
+    }
+
+    /// Returns the stream with the given serial number, or None if there is no supported stream
+    /// with that serial number.
+    #[must_use]
+    pub fn stream(&self, serial: u32) -> Option<&StreamTags> {
+        self.streams.iter().find(|stream| stream.serial == serial)
+    }
+
+    /// Returns the stream with the given serial number for editing.
+    pub fn stream_mut(&mut self, serial: u32) -> Option<&mut StreamTags> {
+        self.streams
+            .iter_mut()
+            .find(|stream| stream.serial == serial)
+    }
+
+    /// Writes the tags of every stream back to the file they were read from.
+    /// # Errors
+    /// This function will error if the file wasn't read with [`open`](Self::open), or for the
+    /// same reasons as [`Tag::write_to_path`].
+    #[cfg(feature = "fs")]
+    pub fn save(&self) -> Result<()> {
+        self.save_with(&WriteOptions::default())?;
+        Ok(())
+    }
+
+    /// Writes the tags of every stream back to the file they were read from, using the given
+    /// [`WriteOptions`].
+    ///
+    /// Returns the number of header pages before and after the write.
+    /// # Errors
+    /// This function will error for the same reasons as [`save`](Self::save).
+    #[cfg(feature = "fs")]
+    pub fn save_with(&self, options: &WriteOptions) -> Result<HeaderPages> {
+        let path = self.path.as_deref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the file wasn't opened from a path",
+            )
+        })?;
+        let (header_pages, written) = write::write_path(
+            path,
+            options,
+            |file| {
+                let (length, header_pages, written) = self.write_in_place(file, options)?;
+                Ok((length, (header_pages, written)))
+            },
+            |src, dst| write::copy_file(src, dst, options, |serial, _| self.tag_for(serial)),
+        )?;
+        written.commit();
+        Ok(header_pages)
+    }
+
+    /// Writes the tags of every stream to a writer holding the same file.
+    /// # Errors
+    /// This function will error for the same reasons as [`Tag::write_to`].
+    pub fn write_to<W: Read + Write + Seek>(&self, f_in: W) -> Result<()> {
+        let (_, _, written) = self.write_in_place(f_in, &WriteOptions::default())?;
+        written.commit();
+        Ok(())
+    }
+
+    /// Rewrites the file in `f_in` with these tags. Returns the new length of the file, the header
+    /// page counts, and the headers written.
+    fn write_in_place<W: Read + Write + Seek>(
+        &self,
+        f_in: W,
+        options: &WriteOptions,
+    ) -> Result<(u64, HeaderPages, Written<'_>)> {
+        // a single stream can take the shortcut of patching its header in place
+        if let [stream] = &self.streams[..] {
+            return stream.tag.write_in_place(f_in, options);
+        }
+        write::splice_streams(f_in, options, |serial, _| self.tag_for(serial))
+    }
+
+    fn tag_for(&self, serial: u32) -> Option<&Tag> {
+        self.stream(serial).map(StreamTags::tag)
+    }
+
+    /// The index of the main stream: the first opus stream, or the first stream. Reading fails
+    /// if there are no supported streams, so there always is one.
+    fn main(&self) -> usize {
+        self.streams
+            .iter()
+            .position(|stream| stream.codec == Codec::Opus)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{multiplex, OpusStream};
+    use std::io::Cursor;
+
+    /// A video file: a Theora stream, followed by a Vorbis and a FLAC stream.
+    fn video() -> Vec<u8> {
+        let streams: Vec<Vec<u8>> = [Codec::Theora, Codec::Vorbis, Codec::Flac]
+            .into_iter()
+            .zip(1..)
+            .map(|(codec, serial)| {
+                let title = format!("{codec:?}");
+                let stream = OpusStream::new().codec(codec).serial(serial);
+                stream.comment("TITLE", title).build().unwrap()
+            })
+            .collect();
+        let streams: Vec<&[u8]> = streams.iter().map(Vec::as_slice).collect();
+        multiplex(&streams).unwrap()
+    }
+
+    fn title(tag: &Tag) -> &str {
+        tag.get_one("TITLE".to_string()).unwrap()
+    }
+
+    #[test]
+    fn detects_codec_of_every_stream() {
+        let file = OggFile::read_from(Cursor::new(video())).unwrap();
+        let codecs: Vec<(u32, Codec)> = file
+            .streams()
+            .iter()
+            .map(|stream| (stream.serial(), stream.codec()))
+            .collect();
+        assert_eq!(
+            codecs,
+            [(1, Codec::Theora), (2, Codec::Vorbis), (3, Codec::Flac)]
+        );
+        for stream in file.streams() {
+            assert_eq!(title(stream.tag()), format!("{:?}", stream.codec()));
+        }
+        // without an opus stream, the first one is the main stream
+        assert_eq!(file.codec(), Codec::Theora);
+        assert_eq!(title(file.tags()), "Theora");
+    }
+
+    #[test]
+    fn writes_tags_of_every_stream() {
+        let data = video();
+        let mut file = OggFile::read_from(Cursor::new(&data)).unwrap();
+        file.set_tags(Tag::new("video".to_string(), vec![]));
+        let vorbis = file.stream_mut(2).unwrap().tag_mut();
+        vorbis.remove_entries("TITLE".to_string());
+        vorbis.add_one("TITLE".to_string(), "a longer title".to_string());
+
+        let mut output = Cursor::new(data);
+        file.write_to(&mut output).unwrap();
+        let read = OggFile::read_from(Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(read.tags().get_vendor(), "video");
+        assert!(read.tags().get("TITLE".to_string()).is_none());
+        assert_eq!(title(read.stream(2).unwrap().tag()), "a longer title");
+        assert_eq!(title(read.stream(3).unwrap().tag()), "Flac");
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn saves_to_path_it_was_opened_from() {
+        let path =
+            std::env::temp_dir().join(format!("opusmeta-ogg-file-{}.ogg", std::process::id()));
+        std::fs::write(&path, video()).unwrap();
+        let mut file = OggFile::open(&path).unwrap();
+        assert_eq!(file.path(), Some(path.as_path()));
+        file.tags_mut()
+            .add_one("ARTIST".to_string(), "artist".to_string());
+        let saved = file.save();
+        let read = OggFile::read_from(Cursor::new(std::fs::read(&path).unwrap()));
+        std::fs::remove_file(&path).unwrap();
+
+        saved.unwrap();
+        let read = read.unwrap();
+        assert_eq!(read.tags().get_one("ARTIST".to_string()).unwrap(), "artist");
+        assert_eq!(read.streams().len(), 3);
+
+        // a file read from a reader has nowhere to be saved to
+        let file = OggFile::read_from(Cursor::new(video())).unwrap();
+        assert!(file.save().is_err());
+    }
+}
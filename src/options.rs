@@ -0,0 +1,378 @@
+//! Option types for configuring how tags are read and written.
+
+use crate::{DeviceProfile, Diagnostic, Diagnostics, SanitizeOptions};
+use std::fmt;
+use std::sync::Arc;
+
+/// Options for reading tags. See [`Tag::read_from_with`](crate::Tag::read_from_with).
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    pub(crate) verify_checksums: bool,
+    pub(crate) max_header_size: Option<usize>,
+    pub(crate) skip_pictures: bool,
+    pub(crate) diagnostics: Option<Diagnostics>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            verify_checksums: true,
+            max_header_size: None,
+            skip_pictures: false,
+            diagnostics: None,
+        }
+    }
+}
+
+impl ReadOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to verify the checksums of the header pages while reading. If a header page is
+    /// corrupt, reading fails with [`Error::CorruptHeader`](crate::Error::CorruptHeader).
+    ///
+    /// Enabled by default. Disabling it skips the checksum computation, which is slightly faster,
+    /// and allows reading tags from files with damaged (but otherwise readable) header pages.
+    #[must_use]
+    pub const fn verify_checksums(mut self, verify: bool) -> Self {
+        self.verify_checksums = verify;
+        self
+    }
+
+    /// The largest comment header, in bytes, which is read. Reading a bigger one fails with
+    /// [`Error::HeaderTooLarge`](crate::Error::HeaderTooLarge) as soon as the limit is exceeded,
+    /// without reading the rest of it. Defaults to None, meaning no limit.
+    ///
+    /// The comment header is held in memory while it is parsed, so setting a limit guards against
+    /// running out of memory on huge (or maliciously crafted) files.
+    #[must_use]
+    pub const fn max_header_size(mut self, bytes: Option<usize>) -> Self {
+        self.max_header_size = bytes;
+        self
+    }
+
+    /// Whether to leave out the embedded pictures, which are usually by far the biggest part of
+    /// the comment header. Disabled by default. Enabling it keeps the memory used by tags read in
+    /// bulk small, since the pictures are never copied out of the header.
+    #[must_use]
+    pub const fn skip_pictures(mut self, skip: bool) -> Self {
+        self.skip_pictures = skip;
+        self
+    }
+
+    /// Set a sink for the non-fatal issues noticed while reading, such as data outside of any
+    /// page, padding in the comment header or keys the spec doesn't allow. Defaults to None.
+    #[must_use]
+    pub fn diagnostics(mut self, sink: Diagnostics) -> Self {
+        self.diagnostics = Some(sink);
+        self
+    }
+
+    /// Adds a diagnostic to the sink, if there is one.
+    pub(crate) fn report(&self, diagnostic: impl FnOnce() -> Diagnostic) {
+        if let Some(sink) = &self.diagnostics {
+            sink.push(diagnostic());
+        }
+    }
+}
+
+/// The vendor string naming this crate, for [`WriteOptions::default_vendor`].
+pub const DEFAULT_VENDOR: &str = concat!("opusmeta ", env!("CARGO_PKG_VERSION"));
+
+/// Options for writing tags. See [`Tag::write_to_with`](crate::Tag::write_to_with).
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    pub(crate) padding: usize,
+    pub(crate) key_case: KeyCase,
+    pub(crate) comment_order: CommentOrder,
+    pub(crate) vendor: VendorPolicy,
+    pub(crate) strategy: WriteStrategy,
+    pub(crate) backup: bool,
+    pub(crate) preserve_modified: bool,
+    pub(crate) progress: Option<ProgressCallback>,
+    pub(crate) check_unmodified: bool,
+    pub(crate) pagination: Pagination,
+    pub(crate) default_vendor: Option<String>,
+    pub(crate) diagnostics: Option<Diagnostics>,
+    pub(crate) device_profile: Option<DeviceProfile>,
+    pub(crate) sanitize: Option<SanitizeOptions>,
+    pub(crate) gain_adjustment: i16,
+}
+
+impl WriteOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserve `bytes` bytes of padding after the comments in the comment header. Defaults to 0.
+    ///
+    /// Padding lets later edits be written without moving the audio data: when padding is
+    /// requested and the new comment header fits in the space taken up by the existing one, only
+    /// the header pages are overwritten, and whatever space is left over is kept as padding.
+    /// Otherwise the whole stream is rewritten, with `bytes` bytes of padding.
+    ///
+    /// The padding consists of zero bytes, which RFC 7845 allows readers and editors to discard.
+    #[must_use]
+    pub const fn padding(mut self, bytes: usize) -> Self {
+        self.padding = bytes;
+        self
+    }
+
+    /// The case comment keys are written in. Defaults to [`KeyCase::Lower`].
+    #[must_use]
+    pub const fn key_case(mut self, key_case: KeyCase) -> Self {
+        self.key_case = key_case;
+        self
+    }
+
+    /// The order comments are written in. Defaults to [`CommentOrder::Sorted`].
+    #[must_use]
+    pub fn comment_order(mut self, order: CommentOrder) -> Self {
+        self.comment_order = order;
+        self
+    }
+
+    /// Which vendor string is written. Defaults to [`VendorPolicy::Tag`].
+    #[must_use]
+    pub fn vendor(mut self, policy: VendorPolicy) -> Self {
+        self.vendor = policy;
+        self
+    }
+
+    /// The vendor string written when the tag's vendor string is empty (with
+    /// [`VendorPolicy::Tag`] or [`VendorPolicy::Keep`]) and the existing comment header has none
+    /// either, such as [`DEFAULT_VENDOR`]. With a default vendor string, an empty vendor string of
+    /// the tag also falls back to the existing one. An empty vendor string is allowed by the spec,
+    /// but confuses some tools. Defaults to None, which writes the vendor string of the tag even
+    /// if it is empty.
+    #[must_use]
+    pub fn default_vendor(mut self, vendor: Option<String>) -> Self {
+        self.default_vendor = vendor;
+        self
+    }
+
+    /// How files are written by [`Tag::write_to_path_with`](crate::Tag::write_to_path_with).
+    /// Defaults to [`WriteStrategy::InPlace`]. Has no effect on functions writing to a writer.
+    #[must_use]
+    pub const fn strategy(mut self, strategy: WriteStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Whether [`Tag::write_to_path_with`](crate::Tag::write_to_path_with) saves a copy of the
+    /// original file as `<name>.bak` (for example `song.opus.bak`) before modifying it. An existing
+    /// backup is overwritten. Disabled by default.
+    #[must_use]
+    pub const fn backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Whether [`Tag::write_to_path_with`](crate::Tag::write_to_path_with) restores the file's
+    /// original modification time after writing. Sync tools and library scanners often treat a
+    /// changed modification time as a changed file, which is rarely wanted after a metadata-only
+    /// edit. Disabled by default.
+    #[must_use]
+    pub const fn preserve_modified(mut self, preserve: bool) -> Self {
+        self.preserve_modified = preserve;
+        self
+    }
+
+    /// Whether to check that the comment header being replaced is still the one the tag was read
+    /// from, and fail with [`Error::ModifiedSinceRead`](crate::Error::ModifiedSinceRead) if
+    /// another program modified it in the meantime. This prevents lost updates when several
+    /// programs edit the same files. Tags which weren't read from a file are never checked.
+    ///
+    /// Writing a tag updates the header it is checked against, so the same tag can be written
+    /// several times. Disabled by default.
+    #[must_use]
+    pub const fn check_unmodified(mut self, check: bool) -> Self {
+        self.check_unmodified = check;
+        self
+    }
+
+    /// How the new comment header is laid out over pages. Defaults to
+    /// [`Pagination::PreserveAudio`].
+    #[must_use]
+    pub const fn pagination(mut self, pagination: Pagination) -> Self {
+        self.pagination = pagination;
+        self
+    }
+
+    /// Set a callback which is regularly called with the progress of a write, as
+    /// `(bytes_done, bytes_total)`. Bytes are counted in the original stream, and the total is
+    /// None if the length of the stream isn't known (when writing from a reader which can't seek).
+    /// The last call always has `bytes_done` equal to the total.
+    ///
+    /// This is mostly useful to show a progress bar while very large files are rewritten.
+    #[must_use]
+    pub fn progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(u64, Option<u64>) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Limits the written comment header to what a hardware player can handle, reporting
+    /// every picture dropped and value truncated to the [diagnostics](Self::diagnostics) sink.
+    /// See [`DeviceProfile`]. Defaults to None.
+    #[must_use]
+    pub const fn device_profile(mut self, profile: DeviceProfile) -> Self {
+        self.device_profile = Some(profile);
+        self
+    }
+
+    /// Sanitizes the values of the written comments, leaving the tag itself untouched. See
+    /// [`SanitizeOptions`]. Defaults to None.
+    #[must_use]
+    pub const fn sanitize(mut self, options: SanitizeOptions) -> Self {
+        self.sanitize = Some(options);
+        self
+    }
+
+    /// A gain in Q7.8 dB (1/256 dB) added to the output gain of the `OpusHead` of every opus
+    /// stream whose tags are written, making it play louder or quieter in every player. Writing
+    /// fails with [`Error::InvalidGain`](crate::Error::InvalidGain) if the output gain would
+    /// overflow. Defaults to 0, which leaves the output gain alone.
+    ///
+    /// The R128 gain tags, which are relative to the output gain, are left as they are; see
+    /// [`apply_r128_gain`](crate::apply_r128_gain) to fold them into the output gain instead.
+    /// Streams of other codecs, and Matroska and FLAC files, have no output gain to adjust.
+    #[must_use]
+    pub const fn gain_adjustment(mut self, gain: i16) -> Self {
+        self.gain_adjustment = gain;
+        self
+    }
+
+    /// Set a sink for the non-fatal issues noticed while writing, such as data outside of any
+    /// page being dropped, or header pages being repaginated. Defaults to None.
+    #[must_use]
+    pub fn diagnostics(mut self, sink: Diagnostics) -> Self {
+        self.diagnostics = Some(sink);
+        self
+    }
+
+    /// Adds a diagnostic to the sink, if there is one.
+    pub(crate) fn report(&self, diagnostic: impl FnOnce() -> Diagnostic) {
+        if let Some(sink) = &self.diagnostics {
+            sink.push(diagnostic());
+        }
+    }
+}
+
+/// How a new comment header is laid out over Ogg pages. Either way, the header starts and ends
+/// on a page boundary, as required by RFC 7845.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Pagination {
+    /// Spread the new header over as many pages as the old one took up, padding it if it became
+    /// too short for that. The pages after the header then keep their sequence numbers, so they
+    /// are copied byte for byte, which matters for archives that keep checksums of their files.
+    /// Falls back to [`Compact`](Self::Compact) if the new header is too long, or if it would
+    /// take more than 4 KiB of padding, as when the pictures are removed from a header spread over
+    /// dozens of pages.
+    #[default]
+    PreserveAudio,
+    /// Use as few pages as possible for the new header. The pages after it are renumbered if the
+    /// number of header pages changes.
+    Compact,
+}
+
+/// A callback set with [`WriteOptions::progress`].
+#[derive(Clone)]
+pub struct ProgressCallback(pub(crate) Arc<dyn Fn(u64, Option<u64>) + Send + Sync>);
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// How a file is written to by the functions which take a path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WriteStrategy {
+    /// Rewrite the file in place. This is the fastest strategy, and keeps the file itself (along
+    /// with its permissions, hard links, and so on), but a crash or power loss part way through
+    /// can leave a half-written file.
+    #[default]
+    InPlace,
+    /// Write the new file to a temporary file in the same directory, then rename it over the
+    /// original. The original file is left untouched until the rename, which is atomic on most
+    /// filesystems, so it is never seen half-written.
+    ///
+    /// The new file gets the permissions of the original, its ownership where the process is
+    /// allowed to change it, and (with the `xattr` feature) its extended attributes. Hard links
+    /// to the original keep pointing to the old file.
+    Atomic,
+}
+
+/// The case comment keys are written in. Keys are case-insensitive, so this only affects how the
+/// file looks to other tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyCase {
+    /// `artist=...`
+    #[default]
+    Lower,
+    /// `ARTIST=...`, the conventional form used by most encoders.
+    Upper,
+}
+
+impl KeyCase {
+    /// Converts one byte of a key to this case.
+    pub(crate) const fn apply(self, byte: u8) -> u8 {
+        match self {
+            Self::Lower => byte.to_ascii_lowercase(),
+            Self::Upper => byte.to_ascii_uppercase(),
+        }
+    }
+}
+
+/// The order comments are written in. Values of the same key always keep the order they were
+/// added in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CommentOrder {
+    /// Keys are sorted alphabetically.
+    #[default]
+    Sorted,
+    /// The given keys come first, in the given order, followed by every other key sorted
+    /// alphabetically. Keys are compared case-insensitively.
+    KeysFirst(Vec<String>),
+}
+
+impl CommentOrder {
+    /// Sort key for a comment key: its position in the priority list, then the key itself.
+    pub(crate) fn rank<'a>(&self, key: &'a str) -> (usize, &'a str) {
+        let position = match self {
+            Self::Sorted => 0,
+            Self::KeysFirst(keys) => keys
+                .iter()
+                .position(|k| k.eq_ignore_ascii_case(key))
+                .unwrap_or(keys.len()),
+        };
+        (position, key)
+    }
+}
+
+/// Which vendor string is written to the comment header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VendorPolicy {
+    /// Write the vendor string of the [`Tag`](crate::Tag). If it is empty and a
+    /// [default](WriteOptions::default_vendor) vendor string is set, the existing vendor string
+    /// is kept, or the default one is written.
+    #[default]
+    Tag,
+    /// Keep the vendor string already in the file, which usually names the encoder that created
+    /// it. Falls back to the vendor string of the tag if the existing header has none or can't be
+    /// parsed.
+    Keep,
+    /// Write the given vendor string.
+    Replace(String),
+}
@@ -0,0 +1,285 @@
+//! An opus file with its identification header, tags and duration.
+
+use crate::page::{Chunk, PageReader, NO_GRANULE};
+use crate::picture::Picture;
+use crate::write::HeaderPages;
+use crate::{Error, ReadOptions, Result, Tag, WriteOptions};
+use ogg::PacketReader;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Size of the window at the end of the file searched for the last page, which grows until a
+/// page with a granule position is found.
+const TAIL_WINDOW: u64 = 64 * 1024;
+
+/// The identification header of an opus stream, as specified by RFC 7845 section 5.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusHead {
+    /// The version of the header format. Readers must accept any version from 0 to 15.
+    pub version: u8,
+    pub channels: u8,
+    /// The number of samples (at 48 kHz) to discard from the start of the decoded audio.
+    pub pre_skip: u16,
+    /// The sample rate of the original input, in Hz, or 0 if it is unknown. This is only
+    /// informational: opus always decodes at 48 kHz.
+    pub input_sample_rate: u32,
+    /// The gain to apply when decoding, in Q7.8 dB.
+    pub output_gain: i16,
+    pub mapping_family: u8,
+}
+
+impl OpusHead {
+    /// Parses an `OpusHead` packet.
+    /// # Errors
+    /// This function will error with [`Error::NotOpus`] if the packet isn't an `OpusHead`
+    /// packet, or if it is shorter than the spec requires.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 19 || !data.starts_with(b"OpusHead") {
+            return Err(Error::NotOpus);
+        }
+        Ok(Self {
+            version: data[8],
+            channels: data[9],
+            pre_skip: u16::from_le_bytes([data[10], data[11]]),
+            input_sample_rate: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
+            output_gain: i16::from_le_bytes([data[16], data[17]]),
+            mapping_family: data[18],
+        })
+    }
+
+    /// Returns the output gain in dB.
+    #[must_use]
+    pub fn output_gain_db(&self) -> f64 {
+        f64::from(self.output_gain) / 256.0
+    }
+}
+
+/// An opus file: the identification header, the tags and the duration of its first opus stream.
+///
+/// This keeps the path, tags and [`WriteOptions`] together, so that the tags are edited through
+/// the file and written back with [`save`](Self::save). The file isn't kept open between reading
+/// and saving, so that saving can replace it (see
+/// [`WriteStrategy::Atomic`](crate::WriteStrategy::Atomic)).
+#[derive(Debug)]
+pub struct OpusFile {
+    path: Option<PathBuf>,
+    head: OpusHead,
+    tag: Tag,
+    duration: Option<Duration>,
+    options: WriteOptions,
+}
+
+impl OpusFile {
+    /// Reads the first opus stream of a reader.
+    /// # Errors
+    /// This function will error if the reader doesn't contain an opus stream, or for the same
+    /// reasons as [`Tag::read_from`].
+    pub fn read_from<R: Read + Seek>(f_in: R) -> Result<Self> {
+        Self::read_from_with(f_in, &ReadOptions::default())
+    }
+
+    /// Reads the first opus stream of a reader, reading the tags with the given
+    /// [`ReadOptions`].
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from).
+    pub fn read_from_with<R: Read + Seek>(mut f_in: R, options: &ReadOptions) -> Result<Self> {
+        let start = f_in.stream_position()?;
+        let (serial, head) = read_opus_head(&mut f_in)?;
+        f_in.seek(SeekFrom::Start(start))?;
+        let tag = Tag::read_from_with(&mut f_in, options)?;
+        let duration = read_duration(f_in, serial, head.pre_skip)?;
+        Ok(Self {
+            path: None,
+            head,
+            tag,
+            duration,
+            options: WriteOptions::default(),
+        })
+    }
+
+    /// Reads an opus file from a path, which is remembered for [`save`](Self::save).
+    /// # Errors
+    /// This function will error if the file cannot be opened, or for the same reasons as
+    /// [`read_from`](Self::read_from).
+    #[cfg(feature = "fs")]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path, &ReadOptions::default())
+    }
+
+    /// Reads an opus file from a path, reading the tags with the given [`ReadOptions`].
+    /// # Errors
+    /// This function will error for the same reasons as [`open`](Self::open).
+    #[cfg(feature = "fs")]
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &ReadOptions) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let mut output = Self::read_from_with(BufReader::new(file), options)?;
+        output.path = Some(path.to_path_buf());
+        Ok(output)
+    }
+
+    /// Returns the path the file was opened from, if any.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the identification header.
+    #[must_use]
+    pub const fn head(&self) -> &OpusHead {
+        &self.head
+    }
+
+    /// Returns the duration of the audio, without the pre-skip, or None if the file has no page
+    /// with a granule position.
+    #[must_use]
+    pub const fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// Returns the tags.
+    #[must_use]
+    pub const fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    /// Returns the tags for editing.
+    pub const fn tag_mut(&mut self) -> &mut Tag {
+        &mut self.tag
+    }
+
+    /// Replaces the tags.
+    pub fn set_tag(&mut self, tag: Tag) {
+        self.tag = tag;
+    }
+
+    /// Returns the first value of a key.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.tag.get_one(key.to_string()).map(String::as_str)
+    }
+
+    /// Replaces all values of a key with a single value.
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        self.tag.remove_entries(key.to_string());
+        self.tag.add_one(key.to_string(), value.into());
+    }
+
+    /// Adds a value to a key, after its existing values.
+    pub fn add(&mut self, key: &str, value: impl Into<String>) {
+        self.tag.add_one(key.to_string(), value.into());
+    }
+
+    /// Removes all values of a key, and returns them.
+    pub fn remove(&mut self, key: &str) -> Option<Vec<String>> {
+        self.tag.remove_entries(key.to_string())
+    }
+
+    /// Adds a picture, replacing any picture of the same type.
+    /// # Errors
+    /// This function will error for the same reasons as [`Tag::add_picture`].
+    pub fn add_picture(&mut self, picture: &Picture) -> Result<()> {
+        self.tag.add_picture(picture)
+    }
+
+    /// Returns true if the tags were modified since the file was read or saved. See
+    /// [`Tag::is_dirty`].
+    #[must_use]
+    pub fn is_dirty(&self) -> bool {
+        self.tag.is_dirty()
+    }
+
+    /// Returns the options [`save`](Self::save) writes with.
+    #[must_use]
+    pub const fn write_options(&self) -> &WriteOptions {
+        &self.options
+    }
+
+    /// Sets the options [`save`](Self::save) writes with. Defaults to
+    /// [`WriteOptions::default`].
+    pub fn set_write_options(&mut self, options: WriteOptions) {
+        self.options = options;
+    }
+
+    /// Writes the tags back to the file they were read from, using the
+    /// [write options](Self::set_write_options) of this file.
+    ///
+    /// Returns the number of header pages before and after the write.
+    /// # Errors
+    /// This function will error if the file wasn't read with [`open`](Self::open), or for the
+    /// same reasons as [`Tag::write_to_path_with`].
+    #[cfg(feature = "fs")]
+    pub fn save(&self) -> Result<HeaderPages> {
+        let path = self.path.as_deref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the file wasn't opened from a path",
+            )
+        })?;
+        self.tag.write_to_path_with(path, &self.options)
+    }
+
+    /// Writes the tags to a writer holding the same file, using the
+    /// [write options](Self::set_write_options) of this file.
+    /// # Errors
+    /// This function will error for the same reasons as [`Tag::write_to_with`].
+    pub fn write_to<W: Read + Write + Seek>(&self, f_in: W) -> Result<HeaderPages> {
+        self.tag.write_to_with(f_in, &self.options)
+    }
+}
+
+/// Reads the `OpusHead` of the first opus stream in a reader, with the serial number of the
+/// stream.
+pub fn read_opus_head<R: Read + Seek>(f_in: R) -> Result<(u32, OpusHead)> {
+    let mut reader = PacketReader::new(f_in);
+    loop {
+        let packet = reader.read_packet()?.ok_or(Error::NotOpus)?;
+        if crate::is_opus_head(&packet) {
+            return Ok((packet.stream_serial(), OpusHead::parse(&packet.data)?));
+        }
+        if !packet.first_in_stream() {
+            return Err(Error::NotOpus);
+        }
+    }
+}
+
+/// Reads the duration of the opus stream with this serial number, by looking at the granule
+/// position of its last page.
+pub fn read_duration<R: Read + Seek>(
+    mut f_in: R,
+    serial: u32,
+    pre_skip: u16,
+) -> Result<Option<Duration>> {
+    let length = f_in.seek(SeekFrom::End(0))?;
+    let mut window = TAIL_WINDOW;
+    loop {
+        let start = length.saturating_sub(window);
+        f_in.seek(SeekFrom::Start(start))?;
+        let mut reader = PageReader::new((&mut f_in).take(window));
+        let mut granule = None;
+        while let Some(chunk) = reader.read_chunk()? {
+            if let Chunk::Page { page, .. } = chunk {
+                if page.serial == serial && page.granule_position != NO_GRANULE {
+                    granule = Some(page.granule_position);
+                }
+            }
+        }
+
+        if let Some(granule) = granule {
+            // opus granule positions always count samples at 48 kHz
+            let samples = granule.saturating_sub(u64::from(pre_skip));
+            return Ok(Some(Duration::from_nanos(
+                samples.saturating_mul(62_500) / 3,
+            )));
+        }
+        if start == 0 {
+            return Ok(None);
+        }
+        window *= 4;
+    }
+}
@@ -0,0 +1,522 @@
+//! Low-level reading and writing of raw Ogg pages.
+//!
+//! The `ogg` crate only exposes packets, which hides page boundaries, sequence numbers and
+//! checksums. The integrity checking functions in this crate need to look at those directly, so
+//! this module implements just enough of the Ogg page format to do so.
+//!
+//! See <https://xiph.org/ogg/doc/framing.html> for the page layout.
+
+use std::io::{Cursor, Read, Write};
+
+/// Magic bytes at the start of every Ogg page.
+pub const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+
+/// Size of the fixed part of a page header, before the segment table.
+pub const HEADER_SIZE: usize = 27;
+
+/// The page contains a continuation of a packet from the previous page.
+pub const FLAG_CONTINUED: u8 = 0x01;
+/// The page is the first page of a logical stream.
+pub const FLAG_BOS: u8 = 0x02;
+/// The page is the last page of a logical stream.
+pub const FLAG_EOS: u8 = 0x04;
+
+/// Granule position written on pages in which no packet ends.
+pub const NO_GRANULE: u64 = u64::MAX;
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut value = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 0x8000_0000 == 0 {
+                value << 1
+            } else {
+                (value << 1) ^ 0x04c1_1db7
+            };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+};
+
+/// Updates an Ogg CRC-32 checksum with more data.
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc = (crc << 8) ^ CRC_TABLE[usize::from(byte ^ crc.to_be_bytes()[0])];
+    }
+    crc
+}
+
+/// A single Ogg page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub version: u8,
+    pub flags: u8,
+    pub granule_position: u64,
+    pub serial: u32,
+    pub sequence_number: u32,
+    pub checksum: u32,
+    pub segments: Vec<u8>,
+    pub body: Vec<u8>,
+}
+
+impl Page {
+    /// Parses a page from a buffer which contains exactly one complete page.
+    fn parse(data: &[u8]) -> Self {
+        let segment_count = usize::from(data[26]);
+        let mut u64_buf = [0; 8];
+        u64_buf.copy_from_slice(&data[6..14]);
+        let mut u32_buf = [0; 4];
+        u32_buf.copy_from_slice(&data[14..18]);
+        let serial = u32::from_le_bytes(u32_buf);
+        u32_buf.copy_from_slice(&data[18..22]);
+        let sequence_number = u32::from_le_bytes(u32_buf);
+        u32_buf.copy_from_slice(&data[22..26]);
+        let checksum = u32::from_le_bytes(u32_buf);
+
+        Self {
+            version: data[4],
+            flags: data[5],
+            granule_position: u64::from_le_bytes(u64_buf),
+            serial,
+            sequence_number,
+            checksum,
+            segments: data[HEADER_SIZE..HEADER_SIZE + segment_count].to_vec(),
+            body: data[HEADER_SIZE + segment_count..].to_vec(),
+        }
+    }
+
+    pub const fn is_continued(&self) -> bool {
+        self.flags & FLAG_CONTINUED != 0
+    }
+
+    pub const fn is_bos(&self) -> bool {
+        self.flags & FLAG_BOS != 0
+    }
+
+    pub const fn is_eos(&self) -> bool {
+        self.flags & FLAG_EOS != 0
+    }
+
+    /// Number of packets which end on this page.
+    pub fn packets_completed(&self) -> usize {
+        self.segments.iter().filter(|&&s| s < 255).count()
+    }
+
+    /// Returns true if the last packet on this page continues onto the next page.
+    pub fn ends_with_continued(&self) -> bool {
+        self.segments.last() == Some(&255)
+    }
+
+    /// Removes the continuation of a packet from the previous page from the start of this page.
+    pub fn trim_leading_continuation(&mut self) {
+        let count = self
+            .segments
+            .iter()
+            .position(|&s| s < 255)
+            .map_or(self.segments.len(), |i| i + 1);
+        let length: usize = self.segments.drain(..count).map(usize::from).sum();
+        self.body.drain(..length);
+        self.flags &= !FLAG_CONTINUED;
+    }
+
+    /// Removes a packet which continues onto the next page from the end of this page.
+    pub fn trim_trailing_partial(&mut self) {
+        let keep = self
+            .segments
+            .iter()
+            .rposition(|&s| s < 255)
+            .map_or(0, |i| i + 1);
+        let length: usize = self.segments.drain(keep..).map(usize::from).sum();
+        self.body.truncate(self.body.len() - length);
+    }
+
+    /// Number of segments up to and including the end of the first packet which ends on this
+    /// page, or None if no packet ends on this page.
+    pub fn first_packet_end(&self) -> Option<usize> {
+        self.segments.iter().position(|&s| s < 255).map(|i| i + 1)
+    }
+
+    /// Splits the page after the first `count` segments, which must end a packet. The returned
+    /// page holds the remaining segments, and takes over the end-of-stream flag. Neither page's
+    /// checksum is updated.
+    pub fn split_off(&mut self, count: usize) -> Self {
+        let length: usize = self.segments[..count].iter().map(|&s| usize::from(s)).sum();
+        let rest = Self {
+            flags: self.flags & FLAG_EOS,
+            segments: self.segments.split_off(count),
+            body: self.body.split_off(length),
+            ..*self
+        };
+        self.flags &= !FLAG_EOS;
+        rest
+    }
+
+    /// Lays out a single packet over as few pages as possible, starting with sequence number
+    /// `sequence_number`. The last page gets `granule_position`, the others [`NO_GRANULE`].
+    pub fn from_packet(
+        serial: u32,
+        sequence_number: u32,
+        granule_position: u64,
+        data: Vec<u8>,
+    ) -> Vec<Self> {
+        let segments = lacing_values(data.len());
+        Self::paginate(
+            serial,
+            sequence_number,
+            granule_position,
+            data,
+            segments.chunks(255),
+        )
+    }
+
+    /// Like [`from_packet`](Self::from_packet), but spreads the packet over exactly `count`
+    /// pages. Gives the packet back if it is too short or too long for that many pages.
+    pub fn from_packet_over(
+        serial: u32,
+        sequence_number: u32,
+        granule_position: u64,
+        data: Vec<u8>,
+        count: usize,
+    ) -> std::result::Result<Vec<Self>, Vec<u8>> {
+        let segments = lacing_values(data.len());
+        if count == 0 || segments.len() < count || segments.len() > count.saturating_mul(255) {
+            return Err(data);
+        }
+        // fill the first pages, leaving at least one segment for each of the others
+        let mut chunks = Vec::with_capacity(count);
+        let mut remaining = &segments[..];
+        for index in 0..count {
+            let length = remaining.len() - (count - index - 1);
+            let (chunk, rest) = remaining.split_at(length.min(255));
+            chunks.push(chunk);
+            remaining = rest;
+        }
+        Ok(Self::paginate(
+            serial,
+            sequence_number,
+            granule_position,
+            data,
+            chunks,
+        ))
+    }
+
+    /// Builds pages holding a single packet, like [`from_packet`](Self::from_packet), with at
+    /// most `segments_per_page` lacing values (and so at most `segments_per_page * 255` bytes) on
+    /// each page.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn from_packet_in_pages(
+        serial: u32,
+        sequence_number: u32,
+        granule_position: u64,
+        data: Vec<u8>,
+        segments_per_page: usize,
+    ) -> Vec<Self> {
+        let segments = lacing_values(data.len());
+        Self::paginate(
+            serial,
+            sequence_number,
+            granule_position,
+            data,
+            segments.chunks(segments_per_page.clamp(1, 255)),
+        )
+    }
+
+    /// Builds pages holding a single packet, with one page per chunk of lacing values. A packet
+    /// which fits on one page is moved into it rather than copied.
+    fn paginate<'a>(
+        serial: u32,
+        sequence_number: u32,
+        granule_position: u64,
+        mut data: Vec<u8>,
+        chunks: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Vec<Self> {
+        let chunks: Vec<&[u8]> = chunks.into_iter().collect();
+        let single = chunks.len() == 1;
+        let mut pages = vec![];
+        let mut start = 0;
+        for (index, segments) in (0..).zip(chunks) {
+            let length: usize = segments.iter().map(|&s| usize::from(s)).sum();
+            let body = if single {
+                std::mem::take(&mut data)
+            } else {
+                data[start..start + length].to_vec()
+            };
+            let page = Self {
+                version: 0,
+                flags: if index == 0 { 0 } else { FLAG_CONTINUED },
+                granule_position: NO_GRANULE,
+                serial,
+                sequence_number: sequence_number.wrapping_add(index),
+                checksum: 0,
+                segments: segments.to_vec(),
+                body,
+            };
+            start += length;
+            pages.push(page);
+        }
+        if let Some(last) = pages.last_mut() {
+            last.granule_position = granule_position;
+        }
+        for page in &mut pages {
+            page.update_checksum();
+        }
+        pages
+    }
+
+    /// Encodes the header with the checksum field set to `checksum`.
+    fn header_bytes(&self, checksum: u32) -> Vec<u8> {
+        let mut output = Vec::with_capacity(HEADER_SIZE + self.segments.len());
+        output.extend_from_slice(CAPTURE_PATTERN);
+        output.push(self.version);
+        output.push(self.flags);
+        output.extend_from_slice(&self.granule_position.to_le_bytes());
+        output.extend_from_slice(&self.serial.to_le_bytes());
+        output.extend_from_slice(&self.sequence_number.to_le_bytes());
+        output.extend_from_slice(&checksum.to_le_bytes());
+        // the segment table never has more than 255 entries
+        #[allow(clippy::cast_possible_truncation)]
+        output.push(self.segments.len() as u8);
+        output.extend_from_slice(&self.segments);
+        output
+    }
+
+    /// Computes the checksum this page should have.
+    pub fn compute_checksum(&self) -> u32 {
+        let crc = crc32_update(0, &self.header_bytes(0));
+        crc32_update(crc, &self.body)
+    }
+
+    /// Recomputes and stores the checksum of this page.
+    pub fn update_checksum(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Writes this page, including the stored checksum, to a writer.
+    pub fn write_to<W: Write>(&self, mut f_out: W) -> std::io::Result<()> {
+        f_out.write_all(&self.header_bytes(self.checksum))?;
+        f_out.write_all(&self.body)
+    }
+
+    /// Splits the page body into packet fragments. The boolean is true if the fragment completes
+    /// a packet (i.e. the packet does not continue onto the next page).
+    pub fn fragments(&self) -> Vec<(&[u8], bool)> {
+        let mut output = vec![];
+        let mut start = 0;
+        let mut end = 0;
+        for &segment in &self.segments {
+            end += usize::from(segment);
+            if segment < 255 {
+                output.push((&self.body[start..end], true));
+                start = end;
+            }
+        }
+        if start < end {
+            output.push((&self.body[start..end], false));
+        }
+        output
+    }
+}
+
+/// Returns the lacing values (segment sizes) of a packet of `length` bytes.
+fn lacing_values(length: usize) -> Vec<u8> {
+    let mut segments = vec![255; length / 255];
+    // the remainder is always smaller than 255
+    #[allow(clippy::cast_possible_truncation)]
+    segments.push((length % 255) as u8);
+    segments
+}
+
+/// An item read from a raw Ogg stream by [`PageReader`].
+#[derive(Debug)]
+pub enum Chunk {
+    /// A complete page, starting at `offset`.
+    Page { offset: u64, page: Page },
+    /// Bytes which are not part of any page.
+    Garbage { offset: u64, length: u64 },
+    /// A page which was cut short by the end of the stream.
+    Truncated { offset: u64, length: u64 },
+}
+
+/// Reads raw pages from a reader, resynchronizing on the capture pattern if it finds data which
+/// is not part of a page.
+pub struct PageReader<R> {
+    inner: R,
+    buffer: ChunkBuffer,
+}
+
+impl<R: Read> PageReader<R> {
+    pub const fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: ChunkBuffer::new(),
+        }
+    }
+
+    /// Ensures at least `n` bytes are buffered, unless the stream ends first.
+    fn fill(&mut self, n: usize) -> std::io::Result<()> {
+        let mut block = [0; READ_SIZE];
+        while self.buffer.needs(n) {
+            let read = self.inner.read(&mut block)?;
+            self.buffer.extend(&block[..read]);
+        }
+        Ok(())
+    }
+
+    /// Offset in the stream of the next chunk.
+    pub const fn position(&self) -> u64 {
+        self.buffer.position()
+    }
+
+    /// Returns a reader over the rest of the stream, starting at the next chunk.
+    pub fn into_rest(self) -> impl Read {
+        Cursor::new(self.buffer.into_lookahead()).chain(self.inner)
+    }
+
+    /// Reads the next chunk, or returns None at the end of the stream.
+    pub fn read_chunk(&mut self) -> std::io::Result<Option<Chunk>> {
+        loop {
+            match self.buffer.next_chunk() {
+                Ok(chunk) => return Ok(chunk),
+                Err(needed) => self.fill(needed)?,
+            }
+        }
+    }
+}
+
+/// Number of bytes a [`PageReader`] asks for from its reader at a time.
+pub const READ_SIZE: usize = 8192;
+
+/// Splits buffered data into chunks. This is the part of [`PageReader`] which doesn't do any I/O,
+/// so that it can be shared with readers which read in other ways.
+pub struct ChunkBuffer {
+    /// Bytes which have been read but not consumed yet.
+    lookahead: Vec<u8>,
+    /// Offset in the stream of the first byte of `lookahead`.
+    position: u64,
+    eof: bool,
+    /// Offset and length of the garbage skipped so far while looking for the next page.
+    garbage: Option<(u64, u64)>,
+}
+
+impl ChunkBuffer {
+    pub const fn new() -> Self {
+        Self {
+            lookahead: vec![],
+            position: 0,
+            eof: false,
+            garbage: None,
+        }
+    }
+
+    /// Returns true if fewer than `n` bytes are buffered and the stream hasn't ended.
+    pub const fn needs(&self, n: usize) -> bool {
+        self.lookahead.len() < n && !self.eof
+    }
+
+    /// Adds data read from the stream. Empty data marks the end of the stream.
+    pub fn extend(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            self.eof = true;
+        }
+        self.lookahead.extend_from_slice(data);
+    }
+
+    /// Offset in the stream of the next chunk.
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the buffered bytes which haven't been consumed yet.
+    pub fn into_lookahead(self) -> Vec<u8> {
+        self.lookahead
+    }
+
+    /// Drops `n` bytes from the front of the buffer. Returns the offset of the first dropped byte.
+    fn consume(&mut self, n: usize) -> u64 {
+        let offset = self.position;
+        self.lookahead.drain(..n);
+        self.position += n as u64;
+        offset
+    }
+
+    /// Returns the next chunk, None at the end of the stream, or the number of bytes which need
+    /// to be buffered before the next chunk can be found.
+    pub fn next_chunk(&mut self) -> std::result::Result<Option<Chunk>, usize> {
+        if self.needs(HEADER_SIZE) {
+            return Err(HEADER_SIZE);
+        }
+        if self.lookahead.is_empty() {
+            return Ok(None);
+        }
+
+        if self.garbage.is_some() || !self.lookahead.starts_with(CAPTURE_PATTERN) {
+            let (offset, mut length) = self.garbage.take().unwrap_or((self.position, 0));
+            let found = self
+                .lookahead
+                .windows(CAPTURE_PATTERN.len())
+                .position(|w| w == CAPTURE_PATTERN);
+            if let Some(index) = found {
+                self.consume(index);
+                length += index as u64;
+            } else if self.eof {
+                length += self.lookahead.len() as u64;
+                self.consume(self.lookahead.len());
+            } else {
+                // keep the tail, it could be the start of a capture pattern
+                let keep = (CAPTURE_PATTERN.len() - 1).min(self.lookahead.len());
+                let dropped = self.lookahead.len() - keep;
+                self.consume(dropped);
+                length += dropped as u64;
+                self.garbage = Some((offset, length));
+                return Err(self.lookahead.len() + 1);
+            }
+            return Ok(Some(Chunk::Garbage { offset, length }));
+        }
+
+        if self.lookahead.len() < HEADER_SIZE {
+            return Ok(Some(self.truncated()));
+        }
+        let header_length = HEADER_SIZE + usize::from(self.lookahead[26]);
+        if self.lookahead.len() < header_length {
+            return self.wait_for(header_length);
+        }
+        let body_length: usize = self.lookahead[HEADER_SIZE..header_length]
+            .iter()
+            .map(|&s| usize::from(s))
+            .sum();
+        let page_length = header_length + body_length;
+        if self.lookahead.len() < page_length {
+            return self.wait_for(page_length);
+        }
+
+        let page = Page::parse(&self.lookahead[..page_length]);
+        let offset = self.consume(page_length);
+        Ok(Some(Chunk::Page { offset, page }))
+    }
+
+    /// Asks for `n` bytes to be buffered, or returns the rest of the stream as a truncated page
+    /// if it has ended.
+    fn wait_for(&mut self, n: usize) -> std::result::Result<Option<Chunk>, usize> {
+        if self.eof {
+            Ok(Some(self.truncated()))
+        } else {
+            Err(n)
+        }
+    }
+
+    fn truncated(&mut self) -> Chunk {
+        let length = self.lookahead.len();
+        let offset = self.consume(length);
+        Chunk::Truncated {
+            offset,
+            length: length as u64,
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Structured edits of tags, which can be applied to many files at once.
+
+use crate::Tag;
+
+/// One edit of a [`TagPatch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PatchOperation {
+    /// Replaces all values of a key. An empty list of values removes the key.
+    Set { key: String, values: Vec<String> },
+    /// Adds a value to a key, after its existing values.
+    Add { key: String, value: String },
+    /// Removes all values of a key.
+    Remove { key: String },
+}
+
+/// A list of edits, applied in order, such as "set the album artist, remove the comment".
+///
+/// A patch is built once and applied to any number of tags with [`apply`](Self::apply), or to any
+/// number of files with [`apply_to_all`](crate::apply_to_all).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagPatch {
+    operations: Vec<PatchOperation>,
+}
+
+impl TagPatch {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces all values of `key` with `value`.
+    #[must_use]
+    pub fn set(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.set_many(key, vec![value.into()])
+    }
+
+    /// Replaces all values of `key` with `values`.
+    #[must_use]
+    pub fn set_many(mut self, key: impl Into<String>, values: Vec<String>) -> Self {
+        self.operations.push(PatchOperation::Set {
+            key: key.into(),
+            values,
+        });
+        self
+    }
+
+    /// Adds `value` to `key`, after its existing values.
+    #[must_use]
+    pub fn add(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.operations.push(PatchOperation::Add {
+            key: key.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Removes all values of `key`.
+    #[must_use]
+    pub fn remove(mut self, key: impl Into<String>) -> Self {
+        self.operations
+            .push(PatchOperation::Remove { key: key.into() });
+        self
+    }
+
+    /// Returns the edits, in the order they are applied.
+    #[must_use]
+    pub fn operations(&self) -> &[PatchOperation] {
+        &self.operations
+    }
+
+    /// Returns true if the patch has no edits.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Applies the edits to a tag, in order. Returns true if the tag changed: setting a key to
+    /// the values it already has, or removing a key it doesn't have, is not a change.
+    pub fn apply(&self, tag: &mut Tag) -> bool {
+        let mut changed = false;
+        for operation in &self.operations {
+            match operation {
+                PatchOperation::Set { key, values } => {
+                    if tag.get(key.clone()).unwrap_or_default() == values.as_slice() {
+                        continue;
+                    }
+                    tag.remove_entries(key.clone());
+                    if !values.is_empty() {
+                        tag.add_many(key.clone(), values.clone());
+                    }
+                    changed = true;
+                }
+                PatchOperation::Add { key, value } => {
+                    tag.add_one(key.clone(), value.clone());
+                    changed = true;
+                }
+                PatchOperation::Remove { key } => {
+                    changed |= tag.remove_entries(key.clone()).is_some();
+                }
+            }
+        }
+        changed
+    }
+}
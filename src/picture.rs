@@ -6,8 +6,11 @@
 use crate::Result;
 use base64::prelude::{Engine as _, BASE64_STANDARD};
 use mime_sniffer::MimeTypeSniffer;
+use std::fmt;
+#[cfg(feature = "fs")]
 use std::fs::OpenOptions;
 use std::io::{Cursor, Read, Seek};
+#[cfg(feature = "fs")]
 use std::path::Path;
 use thiserror::Error;
 
@@ -16,6 +19,7 @@ use thiserror::Error;
 /// See <https://xiph.org/flac/format.html#metadata_block_picture> for more information.
 #[allow(dead_code)] // todo: change this to expect
 #[derive(Default, Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u32)]
 pub enum PictureType {
     #[default]
@@ -77,6 +81,42 @@ pub enum PictureError {
     /// Failed to sniff a mime type from a file.
     #[error("Failed to sniff mime type from file")]
     NoMimeType,
+    /// The picture has problems found by [`Picture::validate`].
+    #[error("The picture is invalid: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    Invalid(Vec<PictureProblem>),
+}
+
+/// A problem with a picture, found by [`Picture::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PictureProblem {
+    /// The picture has no image data.
+    EmptyData,
+    /// The picture has no MIME type.
+    MissingMimeType,
+    /// The MIME type isn't of the `image/<subtype>` form (or `-->`, for a link to the image).
+    MalformedMimeType(String),
+    /// The MIME type doesn't match the image data, which looks like a `detected` image.
+    MimeTypeMismatch { declared: String, detected: String },
+    /// The encoded picture would be longer than a comment can be ([`u32::MAX`] bytes).
+    TooLong,
+}
+
+impl fmt::Display for PictureProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyData => write!(f, "the picture has no image data"),
+            Self::MissingMimeType => write!(f, "the picture has no MIME type"),
+            Self::MalformedMimeType(mime_type) => {
+                write!(f, "{mime_type:?} isn't an image MIME type")
+            }
+            Self::MimeTypeMismatch { declared, detected } => write!(
+                f,
+                "the MIME type is {declared:?}, but the data looks like {detected:?}"
+            ),
+            Self::TooLong => write!(f, "the picture is too long to fit in a comment"),
+        }
+    }
 }
 
 /// Stores picture data.
@@ -84,7 +124,8 @@ pub enum PictureError {
 /// The `width`. `height`, `depth`, and `num_colors` fields should be left as
 /// 0 if possible.
 #[allow(dead_code)]
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Picture {
     pub picture_type: PictureType,
     pub mime_type: String,
@@ -103,6 +144,10 @@ impl Picture {
     /// # Errors
     /// This function can error if the slice is shorter than expected, or if the system platform's
     /// usize is not big enough (See [`Error::PlatformError`](crate::Error::PlatformError) for more information).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(size = data.len()), err(level = "debug"))
+    )]
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
         let mut cursor = Cursor::new(data);
 
@@ -236,8 +281,238 @@ impl Picture {
     /// function attempts to guess the mime type based on the input data.
     /// # Errors
     /// This function can error for the same reasons as [`Picture::read_from`]
+    #[cfg(feature = "fs")]
     pub fn read_from_path<P: AsRef<Path>>(path: P, mime_type: Option<String>) -> Result<Self> {
         let file = OpenOptions::new().read(true).open(path)?;
         Self::read_from(file, mime_type)
     }
 }
+
+impl Picture {
+    /// Returns the width and height of the image in pixels, read from the header of the image
+    /// data, or None if it isn't a PNG, JPEG, GIF or WebP image, or if its header is truncated.
+    #[must_use]
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let data = &self.data[..];
+        let be16 = |at: usize| {
+            Some(u32::from(u16::from_be_bytes(
+                data.get(at..at + 2)?.try_into().ok()?,
+            )))
+        };
+        let le16 = |at: usize| {
+            Some(u32::from(u16::from_le_bytes(
+                data.get(at..at + 2)?.try_into().ok()?,
+            )))
+        };
+        let le24 = |at: usize| {
+            let bytes = data.get(at..at + 3)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+        };
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+            let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+            return Some((width, height));
+        }
+        if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            return Some((le16(6)?, le16(8)?));
+        }
+        if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+            return match data.get(12..16)? {
+                b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+                b"VP8L" => {
+                    let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                    Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+                }
+                b"VP8 " => Some((le16(26)? & 0x3FFF, le16(28)? & 0x3FFF)),
+                _ => None,
+            };
+        }
+        if data.starts_with(&[0xFF, 0xD8]) {
+            // walk the segments up to the start of frame, which holds the dimensions
+            let mut at = 2;
+            loop {
+                while data.get(at) == Some(&0xFF) && data.get(at + 1) == Some(&0xFF) {
+                    at += 1;
+                }
+                if *data.get(at)? != 0xFF {
+                    return None;
+                }
+                let marker = *data.get(at + 1)?;
+                let is_frame =
+                    (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+                if is_frame {
+                    return Some((be16(at + 7)?, be16(at + 5)?));
+                }
+                at += 2 + usize::try_from(be16(at + 2)?).ok()?;
+            }
+        }
+        None
+    }
+
+    /// Checks that the picture can be embedded and shown by players: it has image data, an image
+    /// MIME type (or `-->`, meaning the data is a link to the image) which matches the data, and
+    /// it fits in a comment. Returns every problem found, or an empty list if there is none.
+    /// The description is text, so it is always valid UTF-8.
+    #[must_use]
+    pub fn validate(&self) -> Vec<PictureProblem> {
+        let mut problems = vec![];
+        if self.data.is_empty() {
+            problems.push(PictureProblem::EmptyData);
+        }
+        let is_link = self.mime_type == "-->";
+        if self.mime_type.is_empty() {
+            problems.push(PictureProblem::MissingMimeType);
+        } else if !is_link && !is_image_mime_type(&self.mime_type) {
+            problems.push(PictureProblem::MalformedMimeType(self.mime_type.clone()));
+        } else if let Some(detected) = self.data.sniff_mime_type() {
+            let detected = detected.to_string();
+            if !is_link
+                && detected.starts_with("image/")
+                && canonical_mime_type(&detected) != canonical_mime_type(&self.mime_type)
+            {
+                problems.push(PictureProblem::MimeTypeMismatch {
+                    declared: self.mime_type.clone(),
+                    detected,
+                });
+            }
+        }
+        // 32 bytes of fields, then the strings and data, encoded to base64 after the key
+        let length = 32 + self.mime_type.len() + self.description.len() + self.data.len();
+        let encoded = length.div_ceil(3).saturating_mul(4);
+        let limit = u32::MAX as usize - "METADATA_BLOCK_PICTURE=".len();
+        if encoded > limit {
+            problems.push(PictureProblem::TooLong);
+        }
+        problems
+    }
+
+    /// Returns a description of the picture over several lines, with its type, MIME type,
+    /// description, dimensions and size, for listing the pictures of a file. The image data
+    /// itself is never included.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let dimensions = self.dimensions().map_or_else(
+            || "unknown".to_string(),
+            |(width, height)| format!("{width}x{height}"),
+        );
+        format!(
+            "Type: {:?}\nMIME type: {}\nDescription: {}\nDimensions: {dimensions}\nSize: {} bytes ({})",
+            self.picture_type,
+            self.mime_type,
+            self.description,
+            self.data.len(),
+            FormatSize(self.data.len()),
+        )
+    }
+}
+
+/// Returns true if a MIME type is of the `image/<subtype>` form.
+fn is_image_mime_type(mime_type: &str) -> bool {
+    mime_type.split_once('/').is_some_and(|(kind, subtype)| {
+        kind.eq_ignore_ascii_case("image")
+            && !subtype.is_empty()
+            && subtype
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() && byte != b'/')
+    })
+}
+
+/// Lowercases an image MIME type and replaces common aliases with the type detected for the
+/// same data, so that for example `image/jpg` matches `image/jpeg`.
+fn canonical_mime_type(mime_type: &str) -> String {
+    let mime_type = mime_type.to_ascii_lowercase();
+    match mime_type.as_str() {
+        "image/jpg" | "image/pjpeg" => "image/jpeg".to_string(),
+        "image/x-png" => "image/png".to_string(),
+        "image/x-bmp" | "image/x-ms-bmp" => "image/bmp".to_string(),
+        "image/vnd.microsoft.icon" | "image/ico" => "image/x-icon".to_string(),
+        _ => mime_type,
+    }
+}
+
+/// Shows the picture on one line, as in `CoverFront (image/jpeg, 600x600, 52.3 KiB) "Front"`.
+/// The description is left out if it is empty, and the dimensions if they are unknown. The image
+/// data itself is never shown.
+impl fmt::Display for Picture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({}", self.picture_type, self.mime_type)?;
+        if let Some((width, height)) = self.dimensions() {
+            write!(f, ", {width}x{height}")?;
+        }
+        write!(f, ", {})", FormatSize(self.data.len()))?;
+        if !self.description.is_empty() {
+            write!(f, " {:?}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a number of bytes in B, KiB or MiB.
+struct FormatSize(usize);
+
+impl fmt::Display for FormatSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // only used for display, so the loss of precision doesn't matter
+        #[allow(clippy::cast_precision_loss)]
+        let size = self.0 as f64;
+        if self.0 < 1024 {
+            write!(f, "{} B", self.0)
+        } else if self.0 < 1024 * 1024 {
+            write!(f, "{:.1} KiB", size / 1024.0)
+        } else {
+            write!(f, "{:.1} MiB", size / (1024.0 * 1024.0))
+        }
+    }
+}
+
+/// Names of image files which hold the cover of the audio files next to them, most preferred
+/// first. See [`folder_art`].
+#[cfg(feature = "fs")]
+const FOLDER_ART_NAMES: [&str; 3] = ["cover", "folder", "front"];
+
+/// Extensions of the image files looked for by [`folder_art`], most preferred first.
+#[cfg(feature = "fs")]
+const FOLDER_ART_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+
+/// Looks for an image file holding the cover next to an audio file, as most players do, and
+/// returns it as a front cover. Returns None if there is none.
+///
+/// The file is named `cover`, `folder` or `front` (preferred in that order, and ignoring case),
+/// with a `jpg`, `jpeg`, `png`, `webp` or `gif` extension. Its MIME type is sniffed from its
+/// content.
+/// # Errors
+/// This function will error if the directory of the file can't be listed, or if the image can't
+/// be read.
+#[cfg(feature = "fs")]
+pub fn folder_art<P: AsRef<Path>>(path: P) -> Result<Option<Picture>> {
+    let directory = match path.as_ref().parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut best = None;
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+        let Some((stem, extension)) = name.rsplit_once('.') else {
+            continue;
+        };
+        let (Some(stem_rank), Some(extension_rank)) = (
+            FOLDER_ART_NAMES.iter().position(|name| *name == stem),
+            FOLDER_ART_EXTENSIONS
+                .iter()
+                .position(|name| *name == extension),
+        ) else {
+            continue;
+        };
+        let rank = (stem_rank, extension_rank);
+        if best.as_ref().is_none_or(|(best_rank, _)| rank < *best_rank) && entry.path().is_file() {
+            best = Some((rank, entry.path()));
+        }
+    }
+    let Some((_, image)) = best else {
+        return Ok(None);
+    };
+    let mut picture = Picture::read_from_path(image, None)?;
+    picture.picture_type = PictureType::CoverFront;
+    Ok(Some(picture))
+}
@@ -0,0 +1,158 @@
+//! Limits enforced when writing, for hardware players which fail on large comment headers.
+
+use crate::chapters;
+use crate::picture::{Picture, PictureType};
+use crate::{Codec, Diagnostic, Error, Result, Tag};
+use std::sync::atomic::AtomicU64;
+
+/// Returns true if a key holds pictures, whose values are measured by the size of the image
+/// they hold: the pictures of the file, and those of its [chapters](crate::chapters).
+fn is_picture_key(key: &str) -> bool {
+    key == "metadata_block_picture" || chapters::is_picture_key(key)
+}
+
+/// Limits known to break hardware players, enforced when writing with
+/// [`WriteOptions::device_profile`](crate::WriteOptions::device_profile).
+///
+/// Rather than writing a file the device can't play, the comments are brought within the limits
+/// and every change is reported as a [`Diagnostic`]: pictures which are too big are dropped, long
+/// values are truncated, and if the comment header is still too big, the biggest pictures are
+/// dropped until it fits. The tag itself is left untouched. No limit is set by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceProfile {
+    pub(crate) header_size: Option<usize>,
+    pub(crate) picture_size: Option<usize>,
+    pub(crate) value_length: Option<usize>,
+}
+
+impl DeviceProfile {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The largest comment header packet, in bytes, not counting
+    /// [padding](crate::WriteOptions::padding). Pictures are dropped, biggest first, until the
+    /// header fits. If it doesn't fit without any picture, writing fails with
+    /// [`Error::ExceedsDeviceProfile`].
+    #[must_use]
+    pub const fn max_header_size(mut self, bytes: Option<usize>) -> Self {
+        self.header_size = bytes;
+        self
+    }
+
+    /// The largest picture, in bytes of image data. Bigger pictures are dropped.
+    #[must_use]
+    pub const fn max_picture_size(mut self, bytes: Option<usize>) -> Self {
+        self.picture_size = bytes;
+        self
+    }
+
+    /// The longest value of a comment, in bytes. Longer values are truncated, without splitting
+    /// a character. Pictures aren't affected.
+    #[must_use]
+    pub const fn max_value_length(mut self, bytes: Option<usize>) -> Self {
+        self.value_length = bytes;
+        self
+    }
+
+    /// Returns a copy of `tag` brought within the limits, with the changes made, or None if the
+    /// tag is already within them.
+    pub(crate) fn apply(
+        &self,
+        tag: &Tag,
+        vendor: &str,
+        codec: Codec,
+    ) -> Result<(Option<Tag>, Vec<Diagnostic>)> {
+        let mut comments = tag.comments.clone();
+        let mut changes = vec![];
+
+        if let Some(limit) = self.picture_size {
+            for (_, pictures) in comments.iter_mut().filter(|(key, _)| is_picture_key(key)) {
+                pictures.retain(|value| {
+                    let (picture_type, size) = measure_picture(value);
+                    let keep = size <= limit;
+                    if !keep {
+                        changes.push(Diagnostic::PictureDropped { picture_type, size });
+                    }
+                    keep
+                });
+            }
+        }
+
+        if let Some(limit) = self.value_length {
+            for (key, values) in &mut comments {
+                if is_picture_key(key) {
+                    continue;
+                }
+                for value in values.iter_mut().filter(|value| value.len() > limit) {
+                    changes.push(Diagnostic::ValueTruncated {
+                        key: key.to_string(),
+                        length: value.len(),
+                    });
+                    let mut end = limit;
+                    while !value.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    value.truncate(end);
+                }
+            }
+        }
+
+        if changes.is_empty() && self.header_size.is_none() {
+            return Ok((None, changes));
+        }
+        let mut constrained = Tag {
+            vendor: tag.vendor.clone(),
+            comments,
+            source_header: AtomicU64::new(0),
+            generation: 0,
+            clean_generation: AtomicU64::new(0),
+        };
+
+        if let Some(limit) = self.header_size {
+            let mut size = constrained.packet_size_with(vendor, codec)?;
+            while size > limit {
+                let biggest = constrained
+                    .comments
+                    .iter()
+                    .filter(|(key, _)| is_picture_key(key))
+                    .flat_map(|(key, pictures)| {
+                        let lengths = pictures.iter().map(String::len).enumerate();
+                        lengths.map(move |(index, length)| (key, length, index))
+                    })
+                    .max_by_key(|(_, length, _)| *length)
+                    .map(|(key, _, index)| (key.clone(), index));
+                let Some((key, index)) = biggest else {
+                    return Err(Error::ExceedsDeviceProfile { size, limit });
+                };
+                let value = constrained
+                    .comments
+                    .get_mut(&key)
+                    .map(|pictures| pictures.remove(index))
+                    .unwrap_or_default();
+                // length, then KEY=VALUE
+                size -= 4 + key.len() + 1 + value.len();
+                let (picture_type, picture_size) = measure_picture(&value);
+                changes.push(Diagnostic::PictureDropped {
+                    picture_type,
+                    size: picture_size,
+                });
+            }
+        }
+
+        if changes.is_empty() {
+            return Ok((None, changes));
+        }
+        constrained.comments.retain(|_, values| !values.is_empty());
+        Ok((Some(constrained), changes))
+    }
+}
+
+/// Returns the type of a picture comment and the size of its image. A picture which can't be
+/// decoded is measured by the size of the comment.
+fn measure_picture(value: &str) -> (PictureType, usize) {
+    Picture::from_base64(value).map_or((PictureType::Other, value.len()), |picture| {
+        (picture.picture_type, picture.data.len())
+    })
+}
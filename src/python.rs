@@ -0,0 +1,272 @@
+//! Python bindings, built with [PyO3](https://pyo3.rs). Requires the `python` feature.
+//!
+//! The extension module is named `opusmeta`, and is built with maturin (see `pyproject.toml`). It
+//! exposes [`Tag`] with dict-like access, similar to the `tags` of a mutagen file:
+//!
+//! ```python
+//! import opusmeta
+//!
+//! tag = opusmeta.Tag.read("song.opus")
+//! print(tag["title"])           # a list of values
+//! tag["artist"] = "Someone"     # a string or a list of strings
+//! del tag["comment"]
+//! tag.save("song.opus")
+//! ```
+//!
+//! Keys are case-insensitive, and are returned in lowercase. Errors are raised as
+//! `opusmeta.OpusmetaError`.
+
+use crate::picture::{Picture, PictureType};
+use crate::{Error, Tag};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::path::PathBuf;
+
+create_exception!(
+    opusmeta,
+    OpusmetaError,
+    PyException,
+    "An error raised by opusmeta."
+);
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> Self {
+        OpusmetaError::new_err(error.to_string())
+    }
+}
+
+/// A value assigned to a key: a single string, or a list of them.
+#[derive(FromPyObject)]
+enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The comments of a file.
+#[pyclass(name = "Tag", module = "opusmeta")]
+struct PyTag(Tag);
+
+#[pymethods]
+impl PyTag {
+    #[new]
+    #[pyo3(signature = (vendor = String::new()))]
+    fn new(vendor: String) -> Self {
+        Self(Tag::new(vendor, vec![]))
+    }
+
+    /// Reads the tags of a file.
+    #[staticmethod]
+    fn read(path: PathBuf) -> PyResult<Self> {
+        Ok(Self(Tag::read_from_path(path)?))
+    }
+
+    /// Reads the tags of a file held in memory.
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        Ok(Self(Tag::from_slice(data)?))
+    }
+
+    /// Writes the tags to a file, replacing its tags.
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.0.write_to_path(path)?)
+    }
+
+    #[getter]
+    fn vendor(&self) -> &str {
+        self.0.get_vendor()
+    }
+
+    #[setter]
+    fn set_vendor(&mut self, vendor: String) {
+        self.0.set_vendor(vendor);
+    }
+
+    fn __getitem__(&self, key: String) -> PyResult<Vec<String>> {
+        self.0
+            .get(key.clone())
+            .map(<[String]>::to_vec)
+            .ok_or_else(|| PyKeyError::new_err(key))
+    }
+
+    fn __setitem__(&mut self, key: String, values: Values) {
+        self.0.remove_entries(key.clone());
+        match values {
+            Values::One(value) => self.0.add_one(key, value),
+            Values::Many(values) => self.0.add_many(key, values),
+        }
+    }
+
+    fn __delitem__(&mut self, key: String) -> PyResult<()> {
+        self.0
+            .remove_entries(key.clone())
+            .map(drop)
+            .ok_or_else(|| PyKeyError::new_err(key))
+    }
+
+    fn __contains__(&self, key: String) -> bool {
+        self.0.get(key).is_some()
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.comments.len()
+    }
+
+    fn __iter__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        Ok(self
+            .keys()
+            .into_pyobject(py)?
+            .try_iter()?
+            .into_any()
+            .unbind())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<opusmeta.Tag vendor={:?} keys={:?}>",
+            self.vendor(),
+            self.keys()
+        )
+    }
+
+    /// Returns the keys, sorted.
+    fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.0.comments.keys().map(ToString::to_string).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Returns (key, values) pairs, sorted by key.
+    fn items(&self) -> Vec<(String, Vec<String>)> {
+        let mut items: Vec<_> = self
+            .0
+            .comments
+            .iter()
+            .map(|(key, values)| (key.to_string(), values.to_vec()))
+            .collect();
+        items.sort_unstable();
+        items
+    }
+
+    /// Returns the values of a key, or `default` if it has none.
+    #[pyo3(signature = (key, default = None))]
+    fn get(&self, key: String, default: Option<Vec<String>>) -> Option<Vec<String>> {
+        self.0.get(key).map(<[String]>::to_vec).or(default)
+    }
+
+    /// Adds a value to a key, after its existing values.
+    fn add(&mut self, key: String, value: String) {
+        self.0.add_one(key, value);
+    }
+
+    /// Returns the pictures which can be decoded.
+    fn pictures(&self) -> Vec<PyPicture> {
+        self.0.pictures().into_iter().map(PyPicture).collect()
+    }
+
+    /// Adds a picture, replacing any picture of the same type.
+    fn add_picture(&mut self, picture: &PyPicture) -> PyResult<()> {
+        Ok(self.0.add_picture(&picture.0)?)
+    }
+
+    /// Removes the picture of a type, and returns it, or None if there is no such picture.
+    fn remove_picture(&mut self, picture_type: u32) -> PyResult<Option<PyPicture>> {
+        let picture_type = picture_type_arg(picture_type)?;
+        Ok(self.0.remove_picture_type(picture_type)?.map(PyPicture))
+    }
+}
+
+fn picture_type_arg(picture_type: u32) -> PyResult<PictureType> {
+    PictureType::from_u32(picture_type).map_err(|error| PyValueError::new_err(error.to_string()))
+}
+
+/// A picture, such as a cover. `picture_type` is the APIC picture type, as a number (3 for the
+/// front cover).
+#[pyclass(name = "Picture", module = "opusmeta")]
+#[derive(Clone)]
+struct PyPicture(Picture);
+
+#[pymethods]
+impl PyPicture {
+    #[new]
+    #[pyo3(signature = (data, mime_type = String::new(), description = String::new(), picture_type = 3))]
+    fn new(
+        data: Vec<u8>,
+        mime_type: String,
+        description: String,
+        picture_type: u32,
+    ) -> PyResult<Self> {
+        Ok(Self(Picture {
+            picture_type: picture_type_arg(picture_type)?,
+            mime_type,
+            description,
+            data,
+        }))
+    }
+
+    /// Reads a picture from an image file, sniffing its MIME type if none is given.
+    #[staticmethod]
+    #[pyo3(signature = (path, mime_type = None))]
+    fn from_file(path: PathBuf, mime_type: Option<String>) -> PyResult<Self> {
+        Ok(Self(Picture::read_from_path(path, mime_type)?))
+    }
+
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.data)
+    }
+
+    #[setter]
+    fn set_data(&mut self, data: Vec<u8>) {
+        self.0.data = data;
+    }
+
+    #[getter]
+    fn mime_type(&self) -> &str {
+        &self.0.mime_type
+    }
+
+    #[setter]
+    fn set_mime_type(&mut self, mime_type: String) {
+        self.0.mime_type = mime_type;
+    }
+
+    #[getter]
+    fn description(&self) -> &str {
+        &self.0.description
+    }
+
+    #[setter]
+    fn set_description(&mut self, description: String) {
+        self.0.description = description;
+    }
+
+    #[getter]
+    const fn picture_type(&self) -> u32 {
+        self.0.picture_type as u32
+    }
+
+    #[setter]
+    fn set_picture_type(&mut self, picture_type: u32) -> PyResult<()> {
+        self.0.picture_type = picture_type_arg(picture_type)?;
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<opusmeta.Picture type={} mime_type={:?} {} bytes>",
+            self.picture_type(),
+            self.0.mime_type,
+            self.0.data.len()
+        )
+    }
+}
+
+#[pymodule]
+fn opusmeta(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyTag>()?;
+    module.add_class::<PyPicture>()?;
+    module.add("OpusmetaError", module.py().get_type::<OpusmetaError>())?;
+    Ok(())
+}
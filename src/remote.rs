@@ -0,0 +1,146 @@
+//! Reading tags from a URL using HTTP range requests. Requires the `http` feature.
+//!
+//! Only the parts of the file which are actually needed are downloaded: the first pages for the
+//! tags, and the last page for the duration. The server must support range requests.
+
+use crate::{opus_file, Result, Tag};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+/// Size of the blocks fetched by a [`RangeReader`].
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// A reader over a remote file, which fetches blocks of the file on demand using HTTP range
+/// requests. Fetched blocks are cached, so seeking back and forth does not refetch them.
+///
+/// Since this implements [`Read`] and [`Seek`], it can be passed to any of this crate's reading
+/// functions, such as [`Tag::read_from`] or [`Tag::read_streams_from`].
+pub struct RangeReader {
+    agent: ureq::Agent,
+    url: String,
+    position: u64,
+    /// Total length of the file, if known.
+    length: Option<u64>,
+    blocks: HashMap<u64, Vec<u8>>,
+}
+
+impl RangeReader {
+    /// Creates a reader for a URL. No request is made until the reader is used.
+    #[must_use]
+    pub fn new(url: &str) -> Self {
+        Self {
+            agent: ureq::Agent::new(),
+            url: url.to_string(),
+            position: 0,
+            length: None,
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Fetches a block of the file, or returns the cached copy.
+    fn block(&mut self, index: u64) -> std::io::Result<&[u8]> {
+        if !self.blocks.contains_key(&index) {
+            let start = index * BLOCK_SIZE;
+            let end = start + BLOCK_SIZE - 1;
+            let response = self
+                .agent
+                .get(&self.url)
+                .set("Range", &format!("bytes={start}-{end}"))
+                .call();
+            let data = match response {
+                Ok(response) if response.status() == 206 => {
+                    if let Some(length) = response
+                        .header("Content-Range")
+                        .and_then(|range| range.rsplit_once('/'))
+                        .and_then(|(_, length)| length.parse().ok())
+                    {
+                        self.length = Some(length);
+                    }
+                    let mut data = vec![];
+                    response
+                        .into_reader()
+                        .take(BLOCK_SIZE)
+                        .read_to_end(&mut data)?;
+                    data
+                }
+                Ok(_) => {
+                    return Err(std::io::Error::other(
+                        "the server does not support range requests",
+                    ))
+                }
+                // range starts past the end of the file
+                Err(ureq::Error::Status(416, _)) => vec![],
+                Err(err) => return Err(std::io::Error::other(err)),
+            };
+            self.blocks.insert(index, data);
+        }
+        Ok(&self.blocks[&index])
+    }
+
+    /// Returns the total length of the file, fetching the first block if it isn't known yet.
+    fn length(&mut self) -> std::io::Result<u64> {
+        if self.length.is_none() {
+            self.block(0)?;
+        }
+        self.length.ok_or_else(|| {
+            std::io::Error::other("the server did not report the length of the file")
+        })
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let index = self.position / BLOCK_SIZE;
+        // the offset is always smaller than BLOCK_SIZE
+        #[allow(clippy::cast_possible_truncation)]
+        let offset = (self.position % BLOCK_SIZE) as usize;
+        let block = self.block(index)?;
+        if offset >= block.len() {
+            return Ok(0);
+        }
+        let count = buf.len().min(block.len() - offset);
+        buf[..count].copy_from_slice(&block[offset..offset + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for RangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.length()?.checked_add_signed(offset),
+        };
+        self.position = new_position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl Tag {
+    /// Read a `Tag` from a URL, fetching only the header pages with HTTP range requests.
+    /// Requires the `http` feature.
+    /// # Errors
+    /// This function will error if a request fails (see [`RangeReader`]), or for the same reasons
+    /// as [`read_from`](Self::read_from).
+    pub fn read_from_url(url: &str) -> Result<Self> {
+        Self::read_from(RangeReader::new(url))
+    }
+}
+
+/// Gets the duration of the first opus stream in a remote file, fetching only the first and last
+/// pages with HTTP range requests. Returns None if the duration could not be determined.
+/// # Errors
+/// This function will error if a request fails (see [`RangeReader`]), or if the file is not an
+/// opus file.
+pub fn duration_from_url(url: &str) -> Result<Option<Duration>> {
+    let mut reader = RangeReader::new(url);
+    let (serial, head) = opus_file::read_opus_head(&mut reader)?;
+    opus_file::read_duration(reader, serial, head.pre_skip)
+}
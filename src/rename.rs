@@ -0,0 +1,247 @@
+//! Renaming files after their tags, such as `{albumartist}/{album}/{tracknumber:2} {title}`.
+//!
+//! A pattern is a path (relative to the directory of each file, or to
+//! [`RenameOptions::destination`]) in which `{key}` is replaced with the first value of the
+//! comment `key`, and `{key:N}` pads a numeric value with zeroes to `N` digits (the total of
+//! values such as `3/12` is dropped). `{{` and `}}` stand for literal braces. Characters which
+//! aren't allowed in file names are replaced with `_` in the values, and the extension of the
+//! original file is kept.
+
+use crate::{Error, Result, Tag};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Options for [`rename_by_pattern`].
+#[derive(Debug, Clone)]
+pub struct RenameOptions {
+    pub(crate) destination: Option<PathBuf>,
+    pub(crate) create_dirs: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) missing: String,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            destination: None,
+            create_dirs: true,
+            dry_run: false,
+            missing: "Unknown".to_string(),
+        }
+    }
+}
+
+impl RenameOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The directory the pattern is relative to. Defaults to None, meaning the directory of each
+    /// file. A pattern with directories in it should usually have a destination, or renaming the
+    /// files again would nest the directories once more.
+    #[must_use]
+    pub fn destination(mut self, directory: Option<PathBuf>) -> Self {
+        self.destination = directory;
+        self
+    }
+
+    /// Whether to create the directories of the new paths which don't exist yet. Enabled by
+    /// default. When disabled, moving a file into a missing directory fails.
+    #[must_use]
+    pub const fn create_dirs(mut self, create: bool) -> Self {
+        self.create_dirs = create;
+        self
+    }
+
+    /// Whether to only plan the moves, without touching any file. Disabled by default.
+    #[must_use]
+    pub const fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// The text used for keys a file doesn't have. Defaults to `Unknown`.
+    #[must_use]
+    pub fn missing(mut self, text: impl Into<String>) -> Self {
+        self.missing = text.into();
+        self
+    }
+}
+
+/// A file moved (or, in a dry run, to be moved) by [`rename_by_pattern`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Moves every file in `paths` to the path made from its tags and `pattern`, and returns the
+/// moves, in the same order. Files which are already at their new path are left out.
+///
+/// Every destination is computed before any file is moved, so nothing is moved if a file can't
+/// be read, if two files would be moved to the same path, or if a file would replace an existing
+/// one (even one which is being moved too). Files are moved with [`std::fs::rename`], which
+/// fails across filesystems.
+/// # Errors
+/// This function will error if the pattern is malformed, if the tags of a file can't be read,
+/// with [`Error::RenameCollision`] if two files would end up at the same path, or if creating a
+/// directory or moving a file fails (in which case the files before it were already moved).
+pub fn rename_by_pattern<I, P>(
+    paths: I,
+    pattern: &str,
+    options: &RenameOptions,
+) -> Result<Vec<Rename>>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let pattern = parse_pattern(pattern)?;
+    let mut renames = vec![];
+    let mut sources: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for path in paths {
+        let from = path.as_ref().to_path_buf();
+        let tag = Tag::read_from_path(&from)?;
+        let to = destination(&from, &tag, &pattern, options);
+        if let Some(other) = sources.insert(to.clone(), from.clone()) {
+            return Err(Error::RenameCollision {
+                from: vec![other, from],
+                to,
+            });
+        }
+        if to != from {
+            renames.push(Rename { from, to });
+        }
+    }
+    for rename in &renames {
+        if rename.to.exists() {
+            return Err(Error::RenameCollision {
+                from: vec![rename.from.clone()],
+                to: rename.to.clone(),
+            });
+        }
+    }
+
+    if !options.dry_run {
+        for rename in &renames {
+            if let Some(parent) = rename.to.parent() {
+                if options.create_dirs && !parent.as_os_str().is_empty() {
+                    fs::create_dir_all(parent)?;
+                }
+            }
+            fs::rename(&rename.from, &rename.to)?;
+        }
+    }
+    Ok(renames)
+}
+
+/// A piece of a pattern.
+enum Segment {
+    Text(String),
+    Key { key: String, width: Option<usize> },
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment>> {
+    let malformed = || Error::MalformedPattern(pattern.to_string());
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(malformed()),
+                    }
+                }
+                let (key, width) = match placeholder.split_once(':') {
+                    Some((key, width)) => (key, Some(width.parse().map_err(|_| malformed())?)),
+                    None => (placeholder.as_str(), None),
+                };
+                if key.is_empty() {
+                    return Err(malformed());
+                }
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+                segments.push(Segment::Key {
+                    key: key.to_string(),
+                    width,
+                });
+            }
+            '}' => return Err(malformed()),
+            c => text.push(c),
+        }
+    }
+    segments.push(Segment::Text(text));
+    Ok(segments)
+}
+
+/// Computes the new path of a file.
+fn destination(from: &Path, tag: &Tag, pattern: &[Segment], options: &RenameOptions) -> PathBuf {
+    let mut name = String::new();
+    for segment in pattern {
+        match segment {
+            Segment::Text(text) => name.push_str(text),
+            Segment::Key { key, width } => {
+                let value = tag
+                    .get_one(key.clone())
+                    .map_or(options.missing.as_str(), String::as_str);
+                let value =
+                    width.map_or_else(|| value.to_string(), |width| pad_number(value, width));
+                name.push_str(&sanitize(&value));
+            }
+        }
+    }
+    let mut to = options
+        .destination
+        .clone()
+        .or_else(|| from.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+    to.push(name);
+    if let Some(extension) = from.extension() {
+        let mut file_name = to.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(extension);
+        to.set_file_name(file_name);
+    }
+    to
+}
+
+/// Pads the number at the start of a value such as `3` or `3/12` to `width` digits. Other values
+/// are returned unchanged.
+fn pad_number(value: &str, width: usize) -> String {
+    let number = value.split('/').next().unwrap_or_default().trim();
+    number
+        .parse::<u64>()
+        .map_or_else(|_| value.to_string(), |number| format!("{number:0width$}"))
+}
+
+/// Replaces the characters which aren't allowed in file names on common filesystems, and strips
+/// the dots and spaces Windows doesn't allow at the end of a name.
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = value.trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
@@ -0,0 +1,359 @@
+//! Best-effort repair of damaged Ogg structure.
+//!
+//! [`repair_path`] rewrites a file, fixing the kinds of damage reported by
+//! [`verify_path`](crate::verify_path): bad page checksums, discontinuous page sequence numbers,
+//! missing stream flags, data outside of pages, and truncated final pages. The packets themselves
+//! (and so the tags and audio data) are kept as-is wherever possible; only packets which were cut
+//! in half by the damage are dropped.
+
+use crate::page::{Chunk, Page, PageReader, FLAG_BOS, FLAG_EOS, NO_GRANULE};
+use crate::Result;
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{BufWriter, Read, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// A summary of what was changed by a repair. See [`repair_path`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Number of pages written to the output.
+    pub pages: u64,
+    /// Number of pages whose stored checksum did not match their contents.
+    pub checksums_fixed: u64,
+    /// Number of pages which were renumbered to make the page sequence continuous.
+    pub sequence_numbers_fixed: u64,
+    /// Number of pages whose beginning-of-stream or end-of-stream flag, or granule position, was
+    /// corrected.
+    pub flags_fixed: u64,
+    /// Number of incomplete packets which were dropped because part of them was lost.
+    pub partial_packets_removed: u64,
+    /// Number of bytes of data found outside of any page, which were dropped.
+    pub garbage_bytes_removed: u64,
+    /// Number of bytes of truncated pages at the end of the file, which were dropped.
+    pub truncated_bytes_removed: u64,
+}
+
+impl RepairReport {
+    /// Returns true if the input needed no repairs.
+    #[must_use]
+    pub const fn is_unchanged(&self) -> bool {
+        self.checksums_fixed == 0
+            && self.sequence_numbers_fixed == 0
+            && self.flags_fixed == 0
+            && self.partial_packets_removed == 0
+            && self.garbage_bytes_removed == 0
+            && self.truncated_bytes_removed == 0
+    }
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// The last page kept for this stream, with its index in the input, held back until it is
+    /// known whether it ends the stream and whether its last packet is complete.
+    held: Option<(u64, Page)>,
+    /// Whether the last page kept ends in the middle of a packet.
+    partial: bool,
+    /// Sequence number of the next page written, or None if no page has been written yet.
+    next_sequence: Option<u32>,
+}
+
+/// The state of [`repair_from`], which is fed one page at a time.
+struct Repair<W> {
+    f_out: W,
+    report: RepairReport,
+    streams: HashMap<u32, StreamState>,
+    /// Whether a page other than the first page of its stream has been read in the current link
+    /// of the stream, so that a new stream starts a new link.
+    bos_done: bool,
+    /// Index of the next page read.
+    index: u64,
+}
+
+impl<W: Write> Repair<W> {
+    fn push(&mut self, mut page: Page) -> Result<()> {
+        let index = self.index;
+        self.index += 1;
+        let serial = page.serial;
+        let new_stream = !self.streams.contains_key(&serial);
+        if new_stream && self.bos_done {
+            // the streams of a link of a chained stream end before the next link starts
+            self.flush(|_| Some(true))?;
+            self.streams.clear();
+            self.bos_done = false;
+        }
+        let state = self.streams.entry(serial).or_default();
+        let had_segments = !page.segments.is_empty();
+
+        // drop fragments of packets whose other half was lost
+        if page.is_continued() && !state.partial {
+            page.trim_leading_continuation();
+            self.report.partial_packets_removed += 1;
+        } else if !page.is_continued() && state.partial {
+            // the start of a packet on a page which was already written can't be dropped anymore
+            if let Some((_, held)) = &mut state.held {
+                held.trim_trailing_partial();
+                self.report.partial_packets_removed += 1;
+                if held.segments.is_empty() {
+                    state.held = None;
+                }
+            }
+        }
+        if had_segments && page.segments.is_empty() {
+            state.partial = false;
+            return Ok(());
+        }
+        state.partial = page.ends_with_continued();
+
+        if !new_stream && !self.bos_done {
+            // all BOS pages come before any other page, so the first pages of the other streams
+            // are written now, assuming that their streams go on
+            self.bos_done = true;
+            self.flush(|stream| (stream == serial).then_some(false))?;
+        }
+        let state = self.streams.entry(serial).or_default();
+        if let Some((_, held)) = state.held.replace((index, page)) {
+            self.write(held, Some(false))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pages held back, in the order of the input. `last` tells whether the page of a
+    /// stream ends it, or None to keep its end-of-stream flag. A page which ends its stream loses
+    /// its trailing partial packet.
+    fn flush(&mut self, last: impl Fn(u32) -> Option<bool>) -> Result<()> {
+        let mut held = vec![];
+        for (&serial, state) in &mut self.streams {
+            let Some((index, mut page)) = state.held.take() else {
+                continue;
+            };
+            if last(serial) == Some(true) && std::mem::take(&mut state.partial) {
+                page.trim_trailing_partial();
+                self.report.partial_packets_removed += 1;
+                if page.segments.is_empty() {
+                    continue;
+                }
+            }
+            held.push((index, page));
+        }
+        held.sort_unstable_by_key(|(index, _)| *index);
+        for (_, page) in held {
+            let last = last(page.serial);
+            self.write(page, last)?;
+        }
+        Ok(())
+    }
+
+    /// Fixes up the flags, sequence number, granule position and checksum of a page, and writes
+    /// it. `last` is whether the page ends its stream, or None to keep its end-of-stream flag.
+    fn write(&mut self, mut page: Page, last: Option<bool>) -> Result<()> {
+        let original_flags = page.flags;
+        let original_granule = page.granule_position;
+        let state = self.streams.entry(page.serial).or_default();
+
+        let sequence = state.next_sequence.unwrap_or(0);
+        if page.sequence_number != sequence {
+            page.sequence_number = sequence;
+            self.report.sequence_numbers_fixed += 1;
+        }
+        if state.next_sequence.is_none() {
+            page.flags |= FLAG_BOS;
+        } else {
+            page.flags &= !FLAG_BOS;
+        }
+        state.next_sequence = Some(sequence.wrapping_add(1));
+        match last {
+            Some(true) => page.flags |= FLAG_EOS,
+            Some(false) => page.flags &= !FLAG_EOS,
+            None => {}
+        }
+        if page.packets_completed() == 0 {
+            page.granule_position = NO_GRANULE;
+        }
+        if original_flags != page.flags || original_granule != page.granule_position {
+            self.report.flags_fixed += 1;
+        }
+
+        page.update_checksum();
+        page.write_to(&mut self.f_out)?;
+        self.report.pages += 1;
+        Ok(())
+    }
+}
+
+/// Reads a damaged Ogg stream from `f_in` and writes a repaired copy to `f_out`.
+///
+/// The stream is repaired in a single pass, holding back at most one page per logical stream:
+/// the last page read of each stream is only written once it is known whether it ends the stream,
+/// and whether its last packet is complete. Pages of different streams of a multiplexed stream
+/// can come out in a slightly different order than they went in.
+/// # Errors
+/// This function will error if reading from `f_in` or writing to `f_out` fails. Damage to the
+/// stream itself is never an error.
+pub fn repair_from<R: Read, W: Write>(f_in: R, f_out: W) -> Result<RepairReport> {
+    let mut reader = PageReader::new(f_in);
+    let mut repair = Repair {
+        f_out: BufWriter::new(f_out),
+        report: RepairReport::default(),
+        streams: HashMap::new(),
+        bos_done: false,
+        index: 0,
+    };
+
+    while let Some(chunk) = reader.read_chunk()? {
+        match chunk {
+            Chunk::Page { page, .. } => {
+                if page.compute_checksum() != page.checksum {
+                    repair.report.checksums_fixed += 1;
+                }
+                repair.push(page)?;
+            }
+            Chunk::Garbage { length, .. } => repair.report.garbage_bytes_removed += length,
+            Chunk::Truncated { length, .. } => repair.report.truncated_bytes_removed += length,
+        }
+    }
+    repair.flush(|_| Some(true))?;
+    repair.f_out.flush()?;
+    Ok(repair.report)
+}
+
+/// Convenience function for repairing a file in place. See [`repair_from`].
+///
+/// The repaired copy is written to a temporary file in a single pass over the file. It replaces
+/// the original if any repairs were made, and is discarded otherwise.
+/// # Errors
+/// This function will error if the file cannot be read or written.
+#[cfg(feature = "fs")]
+pub fn repair_path<P: AsRef<Path>>(path: P) -> Result<RepairReport> {
+    crate::write::replace_atomically_if(path.as_ref(), |src, dst| {
+        let report = repair_from(BufReader::new(src), dst)?;
+        let changed = !report.is_unchanged();
+        Ok((report, changed))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::OpusStream;
+    use crate::{verify_from, Tag};
+
+    /// Splits a stream into its pages.
+    fn pages(data: &[u8]) -> Vec<Page> {
+        let mut reader = PageReader::new(data);
+        let mut pages = vec![];
+        while let Some(chunk) = reader.read_chunk().unwrap() {
+            if let Chunk::Page { page, .. } = chunk {
+                pages.push(page);
+            }
+        }
+        pages
+    }
+
+    #[test]
+    fn repairs_damaged_stream() {
+        let data = OpusStream::new().comment("TITLE", "kept").build().unwrap();
+        let mut damaged = vec![];
+        let mut pages = pages(&data);
+        // a lost page, a corrupt page and a missing end-of-stream flag
+        pages.remove(4);
+        pages[5].body[0] ^= 0xFF;
+        let last = pages.last_mut().unwrap();
+        last.flags &= !FLAG_EOS;
+        last.update_checksum();
+        for (index, page) in pages.iter().enumerate() {
+            page.write_to(&mut damaged).unwrap();
+            if index == 2 {
+                damaged.extend_from_slice(b"garbage");
+            }
+        }
+        damaged.extend_from_slice(b"OggS\0\0");
+
+        let mut output = vec![];
+        let report = repair_from(&damaged[..], &mut output).unwrap();
+        assert_eq!(report.pages, pages.len() as u64);
+        assert_eq!(report.checksums_fixed, 1);
+        assert_eq!(report.sequence_numbers_fixed, pages.len() as u64 - 4);
+        assert_eq!(report.flags_fixed, 1);
+        assert_eq!(report.garbage_bytes_removed, 7);
+        assert_eq!(report.truncated_bytes_removed, 6);
+        assert!(verify_from(&output[..]).unwrap().is_ok());
+        let tag = Tag::read_from(std::io::Cursor::new(&output)).unwrap();
+        assert_eq!(tag.get_one("TITLE".into()).unwrap(), "kept");
+
+        let mut again = vec![];
+        let report = repair_from(&output[..], &mut again).unwrap();
+        assert!(report.is_unchanged());
+        assert_eq!(again, output);
+    }
+
+    #[test]
+    fn keeps_links_of_chained_stream_apart() {
+        let mut first = pages(&OpusStream::new().serial(1).build().unwrap());
+        // the first link doesn't end properly, so its last page is only known to be its last
+        // once the next link starts
+        let last = first.last_mut().unwrap();
+        last.flags &= !FLAG_EOS;
+        last.update_checksum();
+        let mut input = vec![];
+        for page in &first {
+            page.write_to(&mut input).unwrap();
+        }
+        input.extend_from_slice(&OpusStream::new().serial(2).build().unwrap());
+
+        let mut output = vec![];
+        let report = repair_from(&input[..], &mut output).unwrap();
+        assert_eq!(report.flags_fixed, 1);
+        let serials: Vec<u32> = pages(&output).iter().map(|page| page.serial).collect();
+        let split = serials.iter().position(|&serial| serial == 2).unwrap();
+        assert_eq!(split, first.len());
+        assert!(serials[split..].iter().all(|&serial| serial == 2));
+        assert!(verify_from(&output[..]).unwrap().is_ok());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn leaves_intact_file_untouched() {
+        let data = OpusStream::new().build().unwrap();
+        let dir = std::env::temp_dir().join(format!("opusmeta-repair-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("intact.opus");
+        std::fs::write(&path, &data).unwrap();
+
+        let report = repair_path(&path);
+        let contents = std::fs::read(&path);
+        let entries = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(report.unwrap().is_unchanged());
+        assert_eq!(contents.unwrap(), data);
+        // the temporary file was removed
+        assert_eq!(entries, 1);
+    }
+
+    #[test]
+    fn drops_page_left_empty_by_lost_continuation() {
+        let mut input = vec![];
+        let mut head = Page::from_packet(1, 0, 0, b"OpusHead".to_vec());
+        head[0].flags |= FLAG_BOS;
+        // the packet is split over two pages, and the second one is lost
+        let split = Page::from_packet_in_pages(1, 1, 0, vec![0; 300], 1);
+        let next = Page::from_packet(1, 3, 960, vec![0xFC, 0xFF, 0xFE]);
+        for mut page in [head, vec![split[0].clone()], next].concat() {
+            page.update_checksum();
+            page.write_to(&mut input).unwrap();
+        }
+
+        let mut output = vec![];
+        let report = repair_from(&input[..], &mut output).unwrap();
+        assert_eq!(report.partial_packets_removed, 1);
+        assert_eq!(report.pages, 2);
+        let mut reader = PageReader::new(&output[..]);
+        while let Some(chunk) = reader.read_chunk().unwrap() {
+            let Chunk::Page { page, .. } = chunk else {
+                panic!("unexpected chunk");
+            };
+            assert!(!page.segments.is_empty());
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Checking that a tag survives being written and read back.
+
+use crate::{Error, Result, Tag};
+use std::collections::BTreeSet;
+
+/// Encodes a tag as a comment header, parses it back and compares the result with the tag, as a
+/// cheap check before writing files which must not lose data.
+///
+/// Comments whose key isn't allowed by the spec are the usual culprits: a key containing `=` is
+/// read back as a shorter key, with the rest of it prepended to the value.
+/// # Errors
+/// This function will error with [`Error::RoundTripMismatch`] describing the first difference if
+/// the tag doesn't survive, or for the same reasons as [`Tag::to_packet_data`] if it can't be
+/// encoded at all.
+pub fn verify_roundtrip(tag: &Tag) -> Result<()> {
+    let data = tag.to_packet_data()?;
+    let parsed = Tag::from_packet_data(&data).map_err(|error| {
+        Error::RoundTripMismatch(format!("the comment header can't be read back: {error}"))
+    })?;
+    if parsed.vendor != tag.vendor {
+        return Err(Error::RoundTripMismatch(format!(
+            "the vendor string {:?} would be read back as {:?}",
+            tag.vendor, parsed.vendor
+        )));
+    }
+
+    // check in key order, so that the reported difference doesn't depend on hashing, and report
+    // the comments of the tag which change before those which would appear
+    let expected: BTreeSet<&str> = tag.comments.keys().map(AsRef::as_ref).collect();
+    for key in &expected {
+        let values = &tag.comments[*key];
+        match parsed.comments.get(*key) {
+            Some(found) if found == values => {}
+            Some(found) => {
+                return Err(Error::RoundTripMismatch(format!(
+                    "the values of {key:?} would be read back as {found:?} instead of {values:?}"
+                )))
+            }
+            None => {
+                return Err(Error::RoundTripMismatch(format!(
+                    "the values of {key:?} would be lost: {values:?}"
+                )))
+            }
+        }
+    }
+    let extra: BTreeSet<&str> = parsed.comments.keys().map(AsRef::as_ref).collect();
+    if let Some(key) = extra.difference(&expected).next() {
+        return Err(Error::RoundTripMismatch(format!(
+            "the key {key:?} would appear with the values {:?}",
+            parsed.comments[*key]
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Removing characters which break players and scrobblers from the values of comments.
+
+use crate::Tag;
+use std::borrow::Cow;
+use std::sync::atomic::AtomicU64;
+
+/// The key of the pictures, which are base64 data rather than text and are never sanitized.
+const PICTURE_KEY: &str = "metadata_block_picture";
+
+/// Which characters are removed or replaced in values by [`Tag::sanitize`],
+/// [`Tag::add_one_sanitized`] and [`WriteOptions::sanitize`](crate::WriteOptions::sanitize).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SanitizeOptions {
+    pub(crate) control_characters: bool,
+    pub(crate) line_endings: bool,
+    pub(crate) invisible_characters: bool,
+}
+
+impl Default for SanitizeOptions {
+    fn default() -> Self {
+        Self {
+            control_characters: true,
+            line_endings: true,
+            invisible_characters: false,
+        }
+    }
+}
+
+impl SanitizeOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to remove control characters other than tabs and line breaks, such as NUL or
+    /// escape. Enabled by default.
+    #[must_use]
+    pub const fn control_characters(mut self, remove: bool) -> Self {
+        self.control_characters = remove;
+        self
+    }
+
+    /// Whether to replace Windows (`\r\n`) and old Mac (`\r`) line endings with `\n`. Enabled by
+    /// default.
+    #[must_use]
+    pub const fn line_endings(mut self, normalize: bool) -> Self {
+        self.line_endings = normalize;
+        self
+    }
+
+    /// Whether to remove zero-width characters (such as U+200B ZERO WIDTH SPACE and the byte
+    /// order mark) and bidirectional control characters (such as U+202E RIGHT-TO-LEFT OVERRIDE).
+    /// Disabled by default, since it also removes the zero-width joiners of emoji sequences.
+    #[must_use]
+    pub const fn invisible_characters(mut self, remove: bool) -> Self {
+        self.invisible_characters = remove;
+        self
+    }
+
+    /// Sanitizes a value. Returns it unchanged, without copying it, if there is nothing to
+    /// sanitize.
+    #[must_use]
+    pub fn apply<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if !value.chars().any(|c| self.is_removed(c) || c == '\r') {
+            return Cow::Borrowed(value);
+        }
+        let mut output = String::with_capacity(value.len());
+        let mut chars = value.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\r' && self.line_endings {
+                chars.next_if_eq(&'\n');
+                output.push('\n');
+            } else if !self.is_removed(c) {
+                output.push(c);
+            }
+        }
+        if output == value {
+            Cow::Borrowed(value)
+        } else {
+            Cow::Owned(output)
+        }
+    }
+
+    /// Returns true if the character is removed from values.
+    fn is_removed(self, c: char) -> bool {
+        (self.control_characters && c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+            || (self.invisible_characters && is_invisible(c))
+    }
+
+    /// Returns a sanitized copy of the tag, or None if there is nothing to sanitize.
+    pub(crate) fn apply_to(self, tag: &Tag) -> Option<Tag> {
+        let needs_sanitizing = tag.comments.iter().any(|(key, values)| {
+            key != PICTURE_KEY
+                && values
+                    .iter()
+                    .any(|value| matches!(self.apply(value), Cow::Owned(_)))
+        });
+        if !needs_sanitizing {
+            return None;
+        }
+        let mut sanitized = Tag {
+            vendor: tag.vendor.clone(),
+            comments: tag.comments.clone(),
+            source_header: AtomicU64::new(0),
+            generation: 0,
+            clean_generation: AtomicU64::new(0),
+        };
+        sanitized.sanitize(&self);
+        Some(sanitized)
+    }
+}
+
+/// Returns true for zero-width and bidirectional control characters.
+const fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        // zero width space, non-joiner and joiner, word joiner, byte order mark
+        '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}'
+        // arabic letter mark, left-to-right and right-to-left marks, embeddings, overrides and
+        // isolates
+        | '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+impl Tag {
+    /// Sanitizes the values of all comments, and returns the number of values which changed.
+    /// Pictures are left as they are.
+    pub fn sanitize(&mut self, options: &SanitizeOptions) -> usize {
+        let mut changed = 0;
+        for (key, values) in self.comments_mut() {
+            if key == PICTURE_KEY {
+                continue;
+            }
+            for value in values {
+                if let Cow::Owned(new) = options.apply(value) {
+                    *value = new;
+                    changed += 1;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Add one entry, sanitizing the value first.
+    pub fn add_one_sanitized(&mut self, tag: String, value: &str, options: &SanitizeOptions) {
+        self.add_one(tag, options.apply(value).into_owned());
+    }
+}
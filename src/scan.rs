@@ -0,0 +1,87 @@
+//! Finding the opus files in a directory tree and reading their tags. Requires the `walkdir`
+//! feature.
+//!
+//! Files are recognized by their content rather than their extension: a file is read if it
+//! starts with an Ogg page holding an `OpusHead` packet, whatever its name (`.opus`, `.ogg`,
+//! `.oga`, or none at all). Other files are skipped.
+
+use crate::page::{CAPTURE_PATTERN, FLAG_BOS, HEADER_SIZE};
+use crate::{Error, ReadOptions, Result, Tag};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Walks the directory tree under `root` and reads the tags of every opus file in it. See
+/// [`scan_with`].
+#[must_use]
+pub fn scan<P: AsRef<Path>>(root: P) -> Scan {
+    scan_with(root, &ReadOptions::default())
+}
+
+/// Walks the directory tree under `root` and reads the tags of every opus file in it using the
+/// given [`ReadOptions`].
+///
+/// Entries are visited in file name order, without following symbolic links. Files which can't
+/// be opened or directories which can't be listed are yielded with their error, so that a
+/// single unreadable entry doesn't stop the scan. When scanning a large library,
+/// [`ReadOptions::skip_pictures`] keeps the memory used by the results small.
+#[must_use]
+pub fn scan_with<P: AsRef<Path>>(root: P, options: &ReadOptions) -> Scan {
+    Scan {
+        entries: WalkDir::new(root).sort_by_file_name().into_iter(),
+        options: options.clone(),
+    }
+}
+
+/// An iterator over the opus files of a directory tree, yielding each path with its tags.
+/// Returned by [`scan`] and [`scan_with`].
+pub struct Scan {
+    entries: walkdir::IntoIter,
+    options: ReadOptions,
+}
+
+impl Iterator for Scan {
+    type Item = (PathBuf, Result<Tag>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.entries.next()? {
+                Ok(entry) => entry,
+                Err(error) => {
+                    let path = error.path().map(Path::to_path_buf).unwrap_or_default();
+                    return Some((path, Err(Error::DataError(error.into()))));
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.into_path();
+            match is_opus_file(&path) {
+                Ok(true) => {
+                    let tag = Tag::read_from_path_with(&path, &self.options);
+                    return Some((path, tag));
+                }
+                Ok(false) => {}
+                Err(error) => return Some((path, Err(error.into()))),
+            }
+        }
+    }
+}
+
+/// Returns true if the file starts with the beginning of stream page of an opus stream.
+fn is_opus_file(path: &Path) -> io::Result<bool> {
+    // the page header, its segment table and the start of the `OpusHead` packet
+    let mut start = Vec::with_capacity(HEADER_SIZE + 255 + 8);
+    File::open(path)?
+        .take((HEADER_SIZE + 255 + 8) as u64)
+        .read_to_end(&mut start)?;
+    if start.len() < HEADER_SIZE || !start.starts_with(CAPTURE_PATTERN) || start[5] & FLAG_BOS == 0
+    {
+        return Ok(false);
+    }
+    let body = HEADER_SIZE + usize::from(start[HEADER_SIZE - 1]);
+    Ok(start
+        .get(body..)
+        .is_some_and(|packet| packet.starts_with(b"OpusHead")))
+}
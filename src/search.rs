@@ -0,0 +1,116 @@
+//! Searching the comments of a tag, and replacing text in their values.
+
+use crate::Tag;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::borrow::Cow;
+
+/// The key of the pictures, which are base64 data rather than text and are never searched.
+const PICTURE_KEY: &str = "metadata_block_picture";
+
+/// What [`Tag::find`] and [`Tag::replace_matching`] look for.
+///
+/// A `&str` or `String` converts into a substring pattern, and (with the `regex` feature) a
+/// `regex::Regex` into a regex pattern.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SearchPattern {
+    /// Matches text containing the string, case-sensitively.
+    Substring(String),
+    /// Matches text the regex matches anywhere in. Requires the `regex` feature.
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+}
+
+impl SearchPattern {
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Self::Substring(substring) => text.contains(substring.as_str()),
+            #[cfg(feature = "regex")]
+            Self::Regex(regex) => regex.is_match(text),
+        }
+    }
+
+    fn replace<'a>(&self, text: &'a str, replacement: &str) -> Cow<'a, str> {
+        match self {
+            Self::Substring(substring) if text.contains(substring.as_str()) => {
+                Cow::Owned(text.replace(substring.as_str(), replacement))
+            }
+            Self::Substring(_) => Cow::Borrowed(text),
+            #[cfg(feature = "regex")]
+            Self::Regex(regex) => regex.replace_all(text, replacement),
+        }
+    }
+}
+
+impl From<&str> for SearchPattern {
+    fn from(substring: &str) -> Self {
+        Self::Substring(substring.to_string())
+    }
+}
+
+impl From<String> for SearchPattern {
+    fn from(substring: String) -> Self {
+        Self::Substring(substring)
+    }
+}
+
+#[cfg(feature = "regex")]
+impl From<Regex> for SearchPattern {
+    fn from(regex: Regex) -> Self {
+        Self::Regex(regex)
+    }
+}
+
+impl Tag {
+    /// Returns the comments whose key or value matches a pattern, as (key, value) pairs sorted by
+    /// key. Keys are lowercase, so a pattern with uppercase letters only matches values.
+    /// Pictures are never matched.
+    #[must_use]
+    pub fn find(&self, pattern: impl Into<SearchPattern>) -> Vec<(&str, &str)> {
+        let pattern = pattern.into();
+        let mut found = vec![];
+        for (key, values) in &self.comments {
+            if key == PICTURE_KEY {
+                continue;
+            }
+            let key_matches = pattern.is_match(key);
+            for value in values {
+                if key_matches || pattern.is_match(value) {
+                    found.push((key.as_ref(), value.as_str()));
+                }
+            }
+        }
+        found.sort_by_key(|(key, _)| *key);
+        found
+    }
+
+    /// Replaces every match of a pattern in the values of all comments, such as a misspelled
+    /// name, and returns the number of values which changed. Keys and pictures are left as they
+    /// are.
+    ///
+    /// With a regex pattern, the replacement can refer to capture groups (`$1`, `$name`), as in
+    /// [`Regex::replace_all`](https://docs.rs/regex/latest/regex/struct.Regex.html#method.replace_all).
+    pub fn replace_matching(
+        &mut self,
+        pattern: impl Into<SearchPattern>,
+        replacement: &str,
+    ) -> usize {
+        let pattern = pattern.into();
+        let mut changed = 0;
+        for (key, values) in self.comments_mut() {
+            if key == PICTURE_KEY {
+                continue;
+            }
+            for value in values {
+                if let Cow::Owned(new) = pattern.replace(value, replacement) {
+                    if new != *value {
+                        *value = new;
+                        changed += 1;
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
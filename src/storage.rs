@@ -0,0 +1,188 @@
+//! A minimal storage abstraction for reading and writing tags in places other than local files.
+//!
+//! Implement [`Storage`] for an object store, a database blob or a custom virtual filesystem,
+//! then use [`Tag::read_from_storage`] and [`Tag::write_to_storage`]. Implementations are
+//! provided for `File` (with the `fs` feature, which is enabled by default) and for in-memory
+//! buffers (`Vec<u8>`).
+
+use crate::{Result, Tag, WriteOptions};
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Random-access storage holding an Ogg stream.
+pub trait Storage {
+    /// Returns the size of the stored data in bytes.
+    /// # Errors
+    /// Implementations return an error if the size cannot be determined.
+    fn size(&mut self) -> std::io::Result<u64>;
+
+    /// Reads data starting at `offset` into `buf`, returning the number of bytes read. Reads
+    /// may be short; 0 means `offset` is at or past the end of the data.
+    /// # Errors
+    /// Implementations return an error if the data cannot be read.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize>;
+
+    /// Writes all of `data` starting at `offset`, extending the stored data if needed.
+    /// # Errors
+    /// Implementations return an error if the data cannot be written.
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()>;
+
+    /// Truncates or extends the stored data to `size` bytes.
+    /// # Errors
+    /// Implementations return an error if the size cannot be changed.
+    fn set_size(&mut self, size: u64) -> std::io::Result<()>;
+
+    /// Makes sure written data is stored durably. Does nothing by default.
+    /// # Errors
+    /// Implementations return an error if the data cannot be flushed.
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Storage for File {
+    fn size(&mut self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)
+    }
+
+    fn set_size(&mut self, size: u64) -> std::io::Result<()> {
+        self.set_len(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+impl Storage for Vec<u8> {
+    fn size(&mut self) -> std::io::Result<u64> {
+        Ok(Self::len(self) as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(data) = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.get(offset..))
+        else {
+            return Ok(0);
+        };
+        let count = buf.len().min(data.len());
+        buf[..count].copy_from_slice(&data[..count]);
+        Ok(count)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        let start = usize::try_from(offset).map_err(std::io::Error::other)?;
+        let end = start + data.len();
+        if end > Self::len(self) {
+            self.resize(end, 0);
+        }
+        self[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn set_size(&mut self, size: u64) -> std::io::Result<()> {
+        self.resize(usize::try_from(size).map_err(std::io::Error::other)?, 0);
+        Ok(())
+    }
+}
+
+/// Adapts a [`Storage`] to [`Read`], [`Write`] and [`Seek`], which the reading and writing
+/// functions of this crate work with.
+struct StorageIo<'a, S: ?Sized> {
+    storage: &'a mut S,
+    position: u64,
+}
+
+impl<S: Storage + ?Sized> Read for StorageIo<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.storage.read_at(self.position, buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<S: Storage + ?Sized> Write for StorageIo<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.storage.write_at(self.position, buf)?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.storage.flush()
+    }
+}
+
+impl<S: Storage + ?Sized> Seek for StorageIo<'_, S> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.storage.size()?.checked_add_signed(offset),
+        };
+        self.position = new_position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl Tag {
+    /// Read a `Tag` from a [`Storage`].
+    /// # Errors
+    /// This function will error for the same reasons as [`read_from`](Self::read_from).
+    pub fn read_from_storage<S: Storage + ?Sized>(storage: &mut S) -> Result<Self> {
+        Self::read_from(StorageIo {
+            storage,
+            position: 0,
+        })
+    }
+
+    /// Writes tags to a [`Storage`], truncating it if the new stream is shorter than the old one.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to).
+    pub fn write_to_storage<S: Storage + ?Sized>(&self, storage: &mut S) -> Result<()> {
+        self.write_to_storage_with(storage, &WriteOptions::default())?;
+        Ok(())
+    }
+
+    /// Writes tags to a [`Storage`], using the given [`WriteOptions`]. See
+    /// [`write_to_storage`](Self::write_to_storage).
+    ///
+    /// Returns the number of header pages before and after the write.
+    /// # Errors
+    /// This function will error for the same reasons as [`write_to`](Self::write_to).
+    pub fn write_to_storage_with<S: Storage + ?Sized>(
+        &self,
+        storage: &mut S,
+        options: &WriteOptions,
+    ) -> Result<crate::HeaderPages> {
+        let (length, header_pages, written) = self.write_in_place(
+            StorageIo {
+                storage: &mut *storage,
+                position: 0,
+            },
+            options,
+        )?;
+        storage.set_size(length)?;
+        storage.flush()?;
+        written.commit();
+        Ok(header_pages)
+    }
+}
@@ -0,0 +1,192 @@
+//! A borrowed view of an Opus comment header.
+
+use crate::keys;
+use crate::picture::Picture;
+use crate::{Codec, Error, ReadOptions, Result, Tag};
+use std::io::{Read, Seek};
+
+/// Stores Opus comments borrowed from a comment header packet.
+///
+/// Unlike [`Tag`], a `TagRef` does not allocate a `String` for every key and value (or for every
+/// encoded picture); it only keeps slices into the packet it was parsed from. This makes it
+/// suitable for read-only scanning of large libraries. Keys keep the casing they have in the file,
+/// but lookups are case-insensitive like with [`Tag`].
+///
+/// Use [`read_packet`](Self::read_packet) to get the raw comment header from a file, then
+/// [`from_packet`](Self::from_packet) to parse it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagRef<'a> {
+    vendor: &'a str,
+    comments: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> TagRef<'a> {
+    /// Parses a bare comment header packet, such as an `OpusTags` packet. The codec is detected
+    /// from the magic signature at the start of the packet.
+    /// # Errors
+    /// This function can error if:
+    /// - The packet does not start with the magic signature of a supported [`Codec`]
+    /// - The packet is shorter than mandated by the spec
+    /// - The platform's usize is not at least 32 bits long
+    /// - The spec mandates UTF-8, but the data is invalid unicode
+    /// - A comment line is not in TAG=VALUE format.
+    pub fn from_packet(data: &'a [u8]) -> Result<Self> {
+        let codec = Codec::from_comment_header(data).ok_or(Error::NotOpus)?;
+        Self::from_codec_packet(codec, data)
+    }
+
+    /// Parses the bare comment header packet of a stream of the given codec. The framing bit at
+    /// the end of a Vorbis comment header is not required.
+    /// # Errors
+    /// This function will error for the same reasons as [`from_packet`](Self::from_packet).
+    pub fn from_codec_packet(codec: Codec, data: &'a [u8]) -> Result<Self> {
+        Self::parse(codec, data).map(|(tag, _)| tag)
+    }
+
+    /// Parses the bare comment header packet of a stream of the given codec, like
+    /// [`from_codec_packet`](Self::from_codec_packet). Also returns the number of bytes after the
+    /// last comment, which includes the framing bit of a Vorbis comment header.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(data), fields(size = data.len()), err(level = "debug"))
+    )]
+    pub(crate) fn parse(codec: Codec, data: &'a [u8]) -> Result<(Self, usize)> {
+        let data = codec.strip_prefix(data).ok_or(Error::NotOpus)?;
+        let mut reader = SliceReader { data };
+        let vendor_length = reader.read_length()?;
+        let vendor = str_from_utf8(reader.take(vendor_length)?)?;
+        let comment_count = reader.read_length()?;
+        // don't trust the count for preallocation, every comment is at least 4 bytes long
+        let mut comments = Vec::with_capacity(comment_count.min(reader.data.len() / 4));
+        for _ in 0..comment_count {
+            let comment_length = reader.read_length()?;
+            let comment = str_from_utf8(reader.take(comment_length)?)?;
+            let pair = comment
+                .split_once('=')
+                .ok_or_else(|| Error::MalformedComment(comment.to_string()))?;
+            comments.push(pair);
+        }
+        trace!(comments = comments.len(), "parsed comment header");
+
+        Ok((Self { vendor, comments }, reader.data.len()))
+    }
+
+    /// Reads the raw comment header packet of the first opus stream in a reader, or of the first
+    /// stream of another supported [`Codec`] if there is none, to be parsed with
+    /// [`from_packet`](Self::from_packet).
+    /// # Errors
+    /// This function will error for the same reasons as [`Tag::read_from`], except for those
+    /// related to parsing the comment header itself.
+    pub fn read_packet<R: Read + Seek>(f_in: R) -> Result<Vec<u8>> {
+        crate::read_comment_packet(f_in, &ReadOptions::default()).map(|(_, packet)| packet)
+    }
+
+    /// Gets the vendor string.
+    #[must_use]
+    pub const fn get_vendor(&self) -> &'a str {
+        self.vendor
+    }
+
+    /// Get all entries for a particular key, in the order they appear in the file.
+    pub fn get<'s>(&'s self, tag: &'s str) -> impl Iterator<Item = &'a str> + 's {
+        self.comments
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(tag))
+            .map(|&(_, value)| value)
+    }
+
+    /// Gets the first entry for a particular key, or None if no occurences of the key exist.
+    #[must_use]
+    pub fn get_one(&self, tag: &str) -> Option<&'a str> {
+        self.get(tag).next()
+    }
+
+    /// Returns an iterator over every (key, value) pair, in the order they appear in the file.
+    pub fn comments(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.comments.iter().copied()
+    }
+
+    /// Returns the number of comments.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.comments.len()
+    }
+
+    /// Returns true if there are no comments.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.comments.is_empty()
+    }
+
+    /// Returns an iterator over the base64-encoded pictures, without decoding them.
+    pub fn raw_pictures(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.get("METADATA_BLOCK_PICTURE")
+    }
+
+    /// Returns a Vec of all encoded pictures. This function will skip pictures that are encoded
+    /// improperly.
+    #[must_use]
+    pub fn pictures(&self) -> Vec<Picture> {
+        self.raw_pictures()
+            .filter_map(|data| Picture::from_base64(data).ok())
+            .collect()
+    }
+
+    /// Drops the pictures, so that they are not copied by [`into_owned`](Self::into_owned).
+    /// Returns the number of pictures dropped.
+    pub(crate) fn remove_pictures(&mut self) -> usize {
+        let count = self.comments.len();
+        self.comments
+            .retain(|(key, _)| !key.eq_ignore_ascii_case("METADATA_BLOCK_PICTURE"));
+        count - self.comments.len()
+    }
+
+    /// Copies the borrowed data into an owned [`Tag`].
+    #[must_use]
+    pub fn into_owned(self) -> Tag {
+        let mut tag = Tag::new(self.vendor.to_string(), vec![]);
+        for (key, value) in self.comments {
+            // common keys are not copied
+            tag.add_value(keys::intern(key), value.to_string());
+        }
+        tag
+    }
+}
+
+impl From<TagRef<'_>> for Tag {
+    fn from(tag: TagRef<'_>) -> Self {
+        tag.into_owned()
+    }
+}
+
+struct SliceReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SliceReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn read_length(&mut self) -> Result<usize> {
+        let mut buffer = [0; 4];
+        buffer.copy_from_slice(self.take(4)?);
+        // only panics on platforms where usize < 32 bits
+        Ok(u32::from_le_bytes(buffer).try_into()?)
+    }
+}
+
+/// Like [`std::str::from_utf8`], but returns a `FromUtf8Error` (which contains the offending
+/// bytes) on failure, matching the error returned by [`Tag`]'s functions.
+fn str_from_utf8(bytes: &[u8]) -> Result<&str> {
+    std::str::from_utf8(bytes).map_err(|_| {
+        String::from_utf8(bytes.to_vec())
+            .expect_err("bytes are invalid UTF-8")
+            .into()
+    })
+}
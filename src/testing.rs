@@ -0,0 +1,378 @@
+//! Small Ogg Opus streams synthesized in memory, to test tagging code without committing binary
+//! fixtures. Requires the `testing` feature.
+//!
+//! [`OpusStream`] builds a valid stream with the given vendor string, comments, pictures and
+//! header layout, made of silent audio packets. [`Corruption`] turns it into one of the broken
+//! files found in the wild, to test error handling. [`multiplex`] puts several streams together
+//! in one file.
+
+use crate::page::{Chunk, Page, PageReader, FLAG_BOS, FLAG_EOS};
+use crate::picture::Picture;
+use crate::{Codec, Result, Tag};
+
+/// A way to break the stream built by an [`OpusStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Corruption {
+    /// A byte of the first comment header page is changed, so its checksum doesn't match.
+    HeaderChecksum,
+    /// The stream ends half way through the comment header.
+    TruncatedHeader,
+    /// The comment header is left out, so the audio packets follow the `OpusHead` packet.
+    MissingHeader,
+    /// The comment header starts with `OpusTagz` instead of `OpusTags` (or, for the other codecs
+    /// with a magic signature, with its last byte replaced by `z`).
+    BadSignature,
+    /// The comment count is one more than the number of comments.
+    CommentCountTooLarge,
+    /// A comment without a `=` is added.
+    MalformedComment,
+    /// The vendor string isn't valid UTF-8.
+    InvalidUtf8,
+    /// Bytes which aren't part of any page are inserted between the `OpusHead` page and the
+    /// comment header.
+    Garbage,
+}
+
+/// A builder for an Ogg Opus stream.
+///
+/// Comments are written in the order they were added, with their keys as given. The audio is
+/// made of 20 ms packets of silence, one per page.
+///
+/// Streams of the other supported codecs can be built as well, with [`codec`](Self::codec): they
+/// get the header packets of that codec, but keep the opus audio packets, which is enough for
+/// anything but decoding.
+#[derive(Debug, Clone)]
+pub struct OpusStream {
+    codec: Codec,
+    vendor: String,
+    comments: Vec<(String, String)>,
+    pictures: Vec<Picture>,
+    serial: u32,
+    channels: u8,
+    pre_skip: u16,
+    output_gain: i16,
+    audio_packets: usize,
+    segments_per_page: usize,
+    padding: usize,
+    corruption: Option<Corruption>,
+}
+
+impl Default for OpusStream {
+    fn default() -> Self {
+        Self {
+            codec: Codec::Opus,
+            vendor: "opusmeta testing".to_string(),
+            comments: vec![],
+            pictures: vec![],
+            serial: 1,
+            channels: 2,
+            pre_skip: 312,
+            output_gain: 0,
+            audio_packets: 10,
+            segments_per_page: 255,
+            padding: 0,
+            corruption: None,
+        }
+    }
+}
+
+/// Samples per channel in every audio packet: 20 ms at 48 kHz.
+const SAMPLES_PER_PACKET: u64 = 960;
+
+impl OpusStream {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The codec whose header packets are written. Defaults to Opus.
+    #[must_use]
+    pub const fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// The vendor string. Defaults to `opusmeta testing`.
+    #[must_use]
+    pub fn vendor(mut self, vendor: impl Into<String>) -> Self {
+        self.vendor = vendor.into();
+        self
+    }
+
+    /// Adds a comment.
+    #[must_use]
+    pub fn comment(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.comments.push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds a picture, after the comments.
+    #[must_use]
+    pub fn picture(mut self, picture: Picture) -> Self {
+        self.pictures.push(picture);
+        self
+    }
+
+    /// The serial number of the stream. Defaults to 1.
+    #[must_use]
+    pub const fn serial(mut self, serial: u32) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    /// The number of channels, 1 or 2. Defaults to 2.
+    #[must_use]
+    pub const fn channels(mut self, channels: u8) -> Self {
+        self.channels = if channels == 1 { 1 } else { 2 };
+        self
+    }
+
+    /// The pre-skip, in samples. Defaults to 312.
+    #[must_use]
+    pub const fn pre_skip(mut self, pre_skip: u16) -> Self {
+        self.pre_skip = pre_skip;
+        self
+    }
+
+    /// The output gain, in Q7.8 dB. Defaults to 0.
+    #[must_use]
+    pub const fn output_gain(mut self, gain: i16) -> Self {
+        self.output_gain = gain;
+        self
+    }
+
+    /// The number of audio packets. Defaults to 10, which is 200 ms of audio.
+    #[must_use]
+    pub const fn audio_packets(mut self, count: usize) -> Self {
+        self.audio_packets = count;
+        self
+    }
+
+    /// The largest number of bytes of the comment header on each page, rounded down to a
+    /// multiple of 255 (and to at least 255). A small value spreads even a small comment header
+    /// over several pages. Defaults to 65025, the most a page can hold.
+    #[must_use]
+    pub const fn page_size(mut self, bytes: usize) -> Self {
+        self.segments_per_page = bytes / 255;
+        self
+    }
+
+    /// The number of zero bytes of padding after the comments. Defaults to 0.
+    #[must_use]
+    pub const fn padding(mut self, bytes: usize) -> Self {
+        self.padding = bytes;
+        self
+    }
+
+    /// Breaks the stream in the given way.
+    #[must_use]
+    pub const fn corrupt(mut self, corruption: Corruption) -> Self {
+        self.corruption = Some(corruption);
+        self
+    }
+
+    /// Returns the tag a reader should find in the stream (unless it is corrupt).
+    /// # Errors
+    /// This function will error if a picture can't be encoded.
+    pub fn tag(&self) -> Result<Tag> {
+        let mut tag = Tag::new(self.vendor.clone(), vec![]);
+        for (key, value) in &self.comments {
+            tag.add_one(key.clone(), value.clone());
+        }
+        for picture in &self.pictures {
+            tag.add_one("METADATA_BLOCK_PICTURE".to_string(), picture.to_base64()?);
+        }
+        Ok(tag)
+    }
+
+    /// Builds the stream.
+    /// # Errors
+    /// This function will error if a picture can't be encoded, or if the comment header is too
+    /// big for the opus spec.
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let corruption = self.corruption;
+        let mut pages = Page::from_packet(self.serial, 0, 0, self.head());
+        pages[0].flags |= FLAG_BOS;
+        pages[0].update_checksum();
+        let mut output = vec![];
+        pages[0].write_to(&mut output)?;
+        if corruption == Some(Corruption::Garbage) {
+            output.extend_from_slice(b"not a page");
+        }
+
+        let mut sequence = 1;
+        if corruption != Some(Corruption::MissingHeader) {
+            let pages = Page::from_packet_in_pages(
+                self.serial,
+                sequence,
+                0,
+                self.comment_header()?,
+                self.segments_per_page,
+            );
+            // at most 4 GiB / 255 bytes pages
+            #[allow(clippy::cast_possible_truncation)]
+            let count = pages.len() as u32;
+            sequence += count;
+            for (index, mut page) in pages.into_iter().enumerate() {
+                if index == 0 && corruption == Some(Corruption::HeaderChecksum) {
+                    page.body[0] ^= 0xFF;
+                }
+                let start = output.len();
+                page.write_to(&mut output)?;
+                if corruption == Some(Corruption::TruncatedHeader) {
+                    output.truncate(start + (output.len() - start) / 2);
+                    return Ok(output);
+                }
+            }
+        }
+        if let Some(setup) = self.setup_header() {
+            Page::from_packet(self.serial, sequence, 0, setup)[0].write_to(&mut output)?;
+            sequence += 1;
+        }
+
+        let mut granule = u64::from(self.pre_skip);
+        for index in 0..self.audio_packets {
+            granule += SAMPLES_PER_PACKET;
+            let mut page =
+                Page::from_packet(self.serial, sequence, granule, self.audio_packet()).remove(0);
+            if index + 1 == self.audio_packets {
+                page.flags |= FLAG_EOS;
+                page.update_checksum();
+            }
+            page.write_to(&mut output)?;
+            sequence = sequence.wrapping_add(1);
+        }
+        Ok(output)
+    }
+
+    /// The identification header: the `OpusHead` packet, with channel mapping family 0, or the
+    /// identification header of the other codec, with made up stream parameters.
+    fn head(&self) -> Vec<u8> {
+        match self.codec {
+            Codec::Opus => {
+                let mut head = b"OpusHead".to_vec();
+                head.push(1);
+                head.push(self.channels);
+                head.extend_from_slice(&self.pre_skip.to_le_bytes());
+                head.extend_from_slice(&48_000u32.to_le_bytes());
+                head.extend_from_slice(&self.output_gain.to_le_bytes());
+                head.push(0);
+                head
+            }
+            Codec::Vorbis => {
+                let mut head = b"\x01vorbis".to_vec();
+                head.extend_from_slice(&0u32.to_le_bytes());
+                head.push(self.channels);
+                head.extend_from_slice(&48_000u32.to_le_bytes());
+                // bitrates, then block sizes and the framing bit
+                head.extend_from_slice(&[0; 12]);
+                head.extend_from_slice(&[0xB8, 1]);
+                head
+            }
+            Codec::Speex => {
+                let mut head = b"Speex   1.2.1".to_vec();
+                head.resize(80, 0);
+                head
+            }
+            Codec::Theora => {
+                let mut head = b"\x80theora\x03\x02\x01".to_vec();
+                head.resize(42, 0);
+                head
+            }
+            Codec::Flac => {
+                // mapping version 1.0 with one header packet after this one, then the STREAMINFO
+                // block
+                let mut head = b"\x7fFLAC\x01\x00\x00\x01fLaC\x00\x00\x00\x22".to_vec();
+                head.resize(head.len() + 34, 0);
+                head
+            }
+        }
+    }
+
+    /// The header packet which follows the comment header, for the codecs which have one.
+    fn setup_header(&self) -> Option<Vec<u8>> {
+        match self.codec {
+            Codec::Vorbis => Some(b"\x05vorbis\x00".to_vec()),
+            Codec::Theora => Some(b"\x82theora\x00".to_vec()),
+            _ => None,
+        }
+    }
+
+    /// The comment header packet, such as `OpusTags`, with the corruption applied.
+    fn comment_header(&self) -> Result<Vec<u8>> {
+        let corruption = self.corruption;
+        let mut comments: Vec<Vec<u8>> = self
+            .comments
+            .iter()
+            .map(|(key, value)| format!("{key}={value}").into_bytes())
+            .collect();
+        for picture in &self.pictures {
+            comments.push(format!("METADATA_BLOCK_PICTURE={}", picture.to_base64()?).into_bytes());
+        }
+        if corruption == Some(Corruption::MalformedComment) {
+            comments.push(b"no separator".to_vec());
+        }
+        let mut vendor = self.vendor.clone().into_bytes();
+        if corruption == Some(Corruption::InvalidUtf8) {
+            vendor.push(0xFF);
+        }
+
+        let mut data = vec![];
+        self.codec.write_prefix(&mut data);
+        if corruption == Some(Corruption::BadSignature) {
+            if let Some(last) = self.codec.comment_magic().len().checked_sub(1) {
+                data[last] = b'z';
+            }
+        }
+        data.extend_from_slice(&u32::try_from(vendor.len())?.to_le_bytes());
+        data.extend_from_slice(&vendor);
+        let extra = u32::from(corruption == Some(Corruption::CommentCountTooLarge));
+        data.extend_from_slice(&(u32::try_from(comments.len())? + extra).to_le_bytes());
+        for comment in comments {
+            data.extend_from_slice(&u32::try_from(comment.len())?.to_le_bytes());
+            data.extend_from_slice(&comment);
+        }
+        if self.codec.has_framing_bit() {
+            data.push(1);
+        }
+        data.resize(data.len() + self.padding, 0);
+        self.codec.finish(&mut data)?;
+        Ok(data)
+    }
+
+    /// A 20 ms packet of silence.
+    fn audio_packet(&self) -> Vec<u8> {
+        // TOC byte: configuration 31 (CELT fullband, 20 ms), mono or stereo, one frame
+        let toc = if self.channels == 1 { 0xF8 } else { 0xFC };
+        vec![toc, 0xFF, 0xFE]
+    }
+}
+
+/// Interleaves the pages of several streams into one file.
+///
+/// The streams, such as streams built by [`OpusStream`] with different serial numbers, take turns
+/// one page at a time, so the first page of every stream comes before any other page, as the Ogg
+/// format requires.
+/// # Errors
+/// This function will error if a stream can't be read.
+pub fn multiplex(streams: &[&[u8]]) -> Result<Vec<u8>> {
+    let mut readers: Vec<_> = streams
+        .iter()
+        .map(|stream| PageReader::new(*stream))
+        .collect();
+    let mut output = vec![];
+    let mut written = true;
+    while std::mem::take(&mut written) {
+        for reader in &mut readers {
+            while let Some(chunk) = reader.read_chunk()? {
+                if let Chunk::Page { page, .. } = chunk {
+                    page.write_to(&mut output)?;
+                    written = true;
+                    break;
+                }
+            }
+        }
+    }
+    Ok(output)
+}
@@ -0,0 +1,26 @@
+//! Logging macros which forward to `tracing` with the `tracing` feature, and expand to nothing
+//! without it.
+
+/// Emits a `tracing` event at the warn level.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)*);
+    };
+}
+
+/// Emits a `tracing` event at the debug level.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+/// Emits a `tracing` event at the trace level.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
@@ -0,0 +1,80 @@
+//! Editing the values of a key in place.
+
+use crate::keys::Key;
+use crate::Values;
+use std::collections::hash_map::OccupiedEntry;
+use std::ops::{Deref, DerefMut};
+
+/// The values of a key of a [`Tag`](crate::Tag), borrowed for editing in place. Returned by
+/// [`Tag::get_mut`](crate::Tag::get_mut).
+///
+/// Dereferences to a slice of the values, so they can be read and changed like one. Values can
+/// also be added and removed; the key is removed from the tag when it is dropped without any
+/// value left.
+#[derive(Debug)]
+pub struct ValuesMut<'a> {
+    /// Only None while the handle is being dropped.
+    entry: Option<OccupiedEntry<'a, Key, Values>>,
+}
+
+impl<'a> ValuesMut<'a> {
+    pub(crate) const fn new(entry: OccupiedEntry<'a, Key, Values>) -> Self {
+        Self { entry: Some(entry) }
+    }
+
+    fn values(&self) -> &Values {
+        self.entry.as_ref().expect("entry taken before drop").get()
+    }
+
+    fn values_mut(&mut self) -> &mut Values {
+        self.entry
+            .as_mut()
+            .expect("entry taken before drop")
+            .get_mut()
+    }
+
+    /// Appends a value.
+    pub fn push(&mut self, value: String) {
+        self.values_mut().push(value);
+    }
+
+    /// Removes and returns the value at `index`, shifting the values after it.
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> String {
+        self.values_mut().remove(index)
+    }
+
+    /// Keeps only the values for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(&String) -> bool) {
+        self.values_mut().retain(|value| keep(value));
+    }
+
+    /// Removes every value.
+    pub fn clear(&mut self) {
+        self.values_mut().clear();
+    }
+}
+
+impl Deref for ValuesMut<'_> {
+    type Target = [String];
+
+    fn deref(&self) -> &[String] {
+        self.values()
+    }
+}
+
+impl DerefMut for ValuesMut<'_> {
+    fn deref_mut(&mut self) -> &mut [String] {
+        self.values_mut()
+    }
+}
+
+impl Drop for ValuesMut<'_> {
+    fn drop(&mut self) {
+        // a key without values isn't a comment
+        if let Some(entry) = self.entry.take().filter(|entry| entry.get().is_empty()) {
+            entry.remove();
+        }
+    }
+}
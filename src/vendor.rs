@@ -0,0 +1,65 @@
+//! Parsing the vendor string, which names the program which encoded the file.
+
+use crate::Tag;
+
+/// The encoder and version parsed from a vendor string by [`Tag::vendor_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorInfo {
+    /// The name of the encoder, such as `libopus` or `Lavf`.
+    pub encoder: String,
+    /// The version of the encoder, such as `1.3.1`, or None if the vendor string doesn't have
+    /// one.
+    pub version: Option<String>,
+}
+
+impl VendorInfo {
+    /// Parses a vendor string. Returns None if it is empty.
+    ///
+    /// The version is the first word after the encoder name which starts with a digit (or with
+    /// `v` and a digit), as in `libopus 1.3.1` or `Xiph.Org libVorbis I 20200704 (Reducing
+    /// Environment)`. Otherwise, a first word with the version glued to it, as written by `FFmpeg`
+    /// (`Lavf60.3.100`), is split where the digits start.
+    #[must_use]
+    pub fn parse(vendor: &str) -> Option<Self> {
+        let words: Vec<&str> = vendor.split_whitespace().collect();
+        let first = *words.first()?;
+        if let Some(index) = words.iter().skip(1).position(|word| is_version(word)) {
+            let version = words[index + 1]
+                .trim_start_matches('v')
+                .trim_end_matches([',', ';', ')']);
+            return Some(Self {
+                encoder: words[..=index].join(" "),
+                version: Some(version.to_string()),
+            });
+        }
+        // a version glued to the name, made of digits and dots only
+        if let Some(split) = first.find(|c: char| c.is_ascii_digit()) {
+            let (encoder, version) = first.split_at(split);
+            if !encoder.is_empty() && version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                return Some(Self {
+                    encoder: encoder.to_string(),
+                    version: Some(version.to_string()),
+                });
+            }
+        }
+        Some(Self {
+            encoder: words.join(" "),
+            version: None,
+        })
+    }
+}
+
+/// Returns true if a word of a vendor string looks like a version number.
+fn is_version(word: &str) -> bool {
+    let word = word.strip_prefix('v').unwrap_or(word);
+    word.starts_with(|c: char| c.is_ascii_digit())
+}
+
+impl Tag {
+    /// Parses the vendor string into the encoder name and version. Returns None if the vendor
+    /// string is empty. See [`VendorInfo::parse`].
+    #[must_use]
+    pub fn vendor_info(&self) -> Option<VendorInfo> {
+        VendorInfo::parse(self.get_vendor())
+    }
+}
@@ -0,0 +1,445 @@
+//! Functions and types for checking the integrity of an Ogg Opus file.
+//!
+//! [`verify_path`] walks every page of a file and reports structural problems: bad checksums,
+//! holes in the page sequence, granule positions going backwards, and malformed opus headers.
+//! This can be used to confirm that a retagging operation (or a download) didn't corrupt a file.
+
+use crate::page::{Chunk, Page, PageReader, NO_GRANULE};
+use crate::{Result, Tag};
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::Read;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// A problem found while verifying a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Byte offset of the page (or data) where the problem was found.
+    pub offset: u64,
+    /// Serial number of the logical stream the problem belongs to, if any.
+    pub serial: Option<u32>,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {}", self.offset)?;
+        if let Some(serial) = self.serial {
+            write!(f, ", stream {serial:#010x}")?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+/// The kind of problem described by an [`Issue`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IssueKind {
+    /// The checksum stored in the page header does not match the page contents.
+    ChecksumMismatch { stored: u32, computed: u32 },
+    /// The page sequence number is not one more than the previous page's.
+    SequenceGap { expected: u32, found: u32 },
+    /// The page's granule position is lower than a previous page's.
+    GranuleDecreased { previous: u64, current: u64 },
+    /// The page uses an Ogg stream structure version other than 0.
+    UnsupportedVersion(u8),
+    /// Data was found between pages which isn't part of any page.
+    Garbage { length: u64 },
+    /// The file ends in the middle of a page.
+    TruncatedPage { length: u64 },
+    /// The continuation flag of a page doesn't match whether the previous page ended in the middle
+    /// of a packet.
+    ContinuationMismatch,
+    /// The first page of a logical stream doesn't have the beginning-of-stream flag set.
+    MissingBos,
+    /// The last page of a logical stream doesn't have the end-of-stream flag set.
+    MissingEos,
+    /// A page was found after the end-of-stream page of its logical stream.
+    PageAfterEos,
+    /// The `OpusHead` or `OpusTags` header of an opus stream is malformed. Contains a description
+    /// of the problem.
+    MalformedHeader(String),
+    /// The file doesn't contain any opus stream.
+    NoOpusStream,
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChecksumMismatch { stored, computed } => write!(
+                f,
+                "checksum mismatch (stored {stored:#010x}, computed {computed:#010x})"
+            ),
+            Self::SequenceGap { expected, found } => write!(
+                f,
+                "page sequence number {found} does not follow the previous page (expected {expected})"
+            ),
+            Self::GranuleDecreased { previous, current } => write!(
+                f,
+                "granule position {current} is lower than the previous granule position {previous}"
+            ),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported stream structure version {version}")
+            }
+            Self::Garbage { length } => write!(f, "{length} bytes of data outside of any page"),
+            Self::TruncatedPage { length } => {
+                write!(f, "page truncated after {length} bytes")
+            }
+            Self::ContinuationMismatch => write!(f, "page continuation flag is inconsistent"),
+            Self::MissingBos => write!(f, "first page of the stream is not marked as such"),
+            Self::MissingEos => write!(f, "last page of the stream is not marked as such"),
+            Self::PageAfterEos => write!(f, "page found after the end of the stream"),
+            Self::MalformedHeader(reason) => write!(f, "malformed opus header: {reason}"),
+            Self::NoOpusStream => write!(f, "no opus stream found"),
+        }
+    }
+}
+
+/// The result of verifying a file. See [`verify_path`].
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Number of complete pages in the file.
+    pub pages: u64,
+    /// Serial numbers of every logical stream in the file, in order of appearance.
+    pub streams: Vec<u32>,
+    /// Serial numbers of the opus streams in the file, in order of appearance.
+    pub opus_streams: Vec<u32>,
+    /// Every problem found, in file order.
+    pub issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found.
+    #[must_use]
+    pub const fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct StreamState {
+    next_sequence: u32,
+    last_granule: Option<u64>,
+    last_offset: u64,
+    /// Data of a packet which continues onto the next page.
+    partial: Vec<u8>,
+    packets: u64,
+    is_opus: bool,
+    ended: bool,
+}
+
+/// Verifies the structure of an Ogg Opus stream read from a reader.
+/// # Errors
+/// This function only errors if reading from the reader fails. Problems with the stream itself are
+/// reported in the returned [`VerifyReport`].
+pub fn verify_from<R: Read>(f_in: R) -> Result<VerifyReport> {
+    let mut reader = PageReader::new(f_in);
+    let mut report = VerifyReport::default();
+    let mut streams: HashMap<u32, StreamState> = HashMap::new();
+
+    while let Some(chunk) = reader.read_chunk()? {
+        let (offset, page) = match chunk {
+            Chunk::Page { offset, page } => (offset, page),
+            Chunk::Garbage { offset, length } => {
+                report.push(offset, None, IssueKind::Garbage { length });
+                continue;
+            }
+            Chunk::Truncated { offset, length } => {
+                report.push(offset, None, IssueKind::TruncatedPage { length });
+                continue;
+            }
+        };
+        report.pages += 1;
+        let serial = Some(page.serial);
+
+        if page.version != 0 {
+            report.push(offset, serial, IssueKind::UnsupportedVersion(page.version));
+        }
+        let computed = page.compute_checksum();
+        if computed != page.checksum {
+            let stored = page.checksum;
+            report.push(
+                offset,
+                serial,
+                IssueKind::ChecksumMismatch { stored, computed },
+            );
+        }
+
+        let state = streams.entry(page.serial).or_insert_with(|| {
+            report.streams.push(page.serial);
+            if !page.is_bos() {
+                report.issues.push(Issue {
+                    offset,
+                    serial,
+                    kind: IssueKind::MissingBos,
+                });
+            }
+            StreamState {
+                next_sequence: page.sequence_number,
+                ..StreamState::default()
+            }
+        });
+        state.last_offset = offset;
+
+        if state.ended {
+            report.push(offset, serial, IssueKind::PageAfterEos);
+        }
+        if page.sequence_number != state.next_sequence {
+            let expected = state.next_sequence;
+            let found = page.sequence_number;
+            report.push(offset, serial, IssueKind::SequenceGap { expected, found });
+        }
+        state.next_sequence = page.sequence_number.wrapping_add(1);
+
+        if page.granule_position != NO_GRANULE {
+            if let Some(previous) = state.last_granule {
+                if page.granule_position < previous {
+                    let current = page.granule_position;
+                    report.push(
+                        offset,
+                        serial,
+                        IssueKind::GranuleDecreased { previous, current },
+                    );
+                }
+            }
+            state.last_granule = Some(page.granule_position);
+        }
+
+        if page.is_continued() == state.partial.is_empty() {
+            report.push(offset, serial, IssueKind::ContinuationMismatch);
+            state.partial.clear();
+        }
+
+        for reason in check_packets(state, &page) {
+            report.push(offset, serial, IssueKind::MalformedHeader(reason));
+        }
+        if state.is_opus && !report.opus_streams.contains(&page.serial) {
+            report.opus_streams.push(page.serial);
+        }
+
+        if page.is_eos() {
+            state.ended = true;
+        }
+    }
+
+    for serial in report.streams.clone() {
+        let state = &streams[&serial];
+        if !state.ended {
+            report.push(state.last_offset, Some(serial), IssueKind::MissingEos);
+        }
+        if state.is_opus && state.packets < 2 {
+            let reason = "missing comment header".to_string();
+            report.push(
+                state.last_offset,
+                Some(serial),
+                IssueKind::MalformedHeader(reason),
+            );
+        }
+    }
+    if report.opus_streams.is_empty() {
+        report.push(0, None, IssueKind::NoOpusStream);
+    }
+
+    Ok(report)
+}
+
+/// Convenience function for verifying a file at a path. See [`verify_from`].
+/// # Errors
+/// This function will error if the file cannot be opened, or for the same reasons as
+/// [`verify_from`].
+#[cfg(feature = "fs")]
+pub fn verify_path<P: AsRef<Path>>(path: P) -> Result<VerifyReport> {
+    let file = File::open(path)?;
+    verify_from(BufReader::new(file))
+}
+
+impl VerifyReport {
+    fn push(&mut self, offset: u64, serial: Option<u32>, kind: IssueKind) {
+        self.issues.push(Issue {
+            offset,
+            serial,
+            kind,
+        });
+    }
+}
+
+/// Assembles the packets on a page and checks the opus headers among them. Returns a description
+/// of every problem found.
+fn check_packets(state: &mut StreamState, page: &Page) -> Vec<String> {
+    let mut problems = vec![];
+    let first_packet = state.packets;
+    let fragments = page.fragments();
+    let fragment_count = fragments.len();
+
+    for (index, (fragment, complete)) in fragments.into_iter().enumerate() {
+        state.partial.extend_from_slice(fragment);
+        if !complete {
+            continue;
+        }
+        let packet = std::mem::take(&mut state.partial);
+        state.packets += 1;
+
+        match state.packets {
+            1 if page.is_bos() && packet.starts_with(b"OpusHead") => {
+                state.is_opus = true;
+                if let Err(reason) = check_opus_head(&packet) {
+                    problems.push(reason.to_string());
+                }
+                if fragment_count != 1 {
+                    problems.push("OpusHead is not alone on the first page".to_string());
+                }
+            }
+            2 if state.is_opus => {
+                if !packet.starts_with(b"OpusTags") {
+                    problems.push("second packet is not an OpusTags header".to_string());
+                } else if let Err(err) = Tag::from_packet_data(&packet) {
+                    problems.push(format!("could not parse OpusTags: {err}"));
+                }
+                if index + 1 != fragment_count {
+                    problems.push("audio data shares a page with the comment header".to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let completed_header = state.is_opus && first_packet < 2 && state.packets > first_packet;
+    if completed_header && page.granule_position != 0 {
+        problems.push(format!(
+            "header page has granule position {} instead of 0",
+            page.granule_position
+        ));
+    }
+
+    problems
+}
+
+/// Checks that an `OpusHead` packet is well-formed, according to RFC 7845 section 5.1.
+fn check_opus_head(data: &[u8]) -> std::result::Result<(), &'static str> {
+    if data.len() < 19 {
+        return Err("OpusHead is shorter than 19 bytes");
+    }
+    if data[8] >> 4 != 0 {
+        return Err("unsupported OpusHead major version");
+    }
+    let channels = usize::from(data[9]);
+    if channels == 0 {
+        return Err("OpusHead has a channel count of 0");
+    }
+    match data[18] {
+        0 if channels > 2 => Err("channel mapping family 0 with more than 2 channels"),
+        0 => Ok(()),
+        _ if data.len() < 21 + channels => Err("OpusHead channel mapping table is truncated"),
+        _ if data[19] == 0 => Err("OpusHead has a stream count of 0"),
+        _ if data[20] > data[19] => Err("OpusHead has more coupled streams than streams"),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Corruption, OpusStream};
+    use crate::Codec;
+
+    /// Splits a stream into its pages.
+    fn pages(data: &[u8]) -> Vec<Page> {
+        let mut reader = PageReader::new(data);
+        let mut pages = vec![];
+        while let Some(chunk) = reader.read_chunk().unwrap() {
+            if let Chunk::Page { page, .. } = chunk {
+                pages.push(page);
+            }
+        }
+        pages
+    }
+
+    /// Joins pages back into a stream.
+    fn join(pages: &[Page]) -> Vec<u8> {
+        let mut output = vec![];
+        for page in pages {
+            page.write_to(&mut output).unwrap();
+        }
+        output
+    }
+
+    fn kinds(data: &[u8]) -> Vec<IssueKind> {
+        let report = verify_from(data).unwrap();
+        report.issues.into_iter().map(|issue| issue.kind).collect()
+    }
+
+    #[test]
+    fn accepts_valid_stream() {
+        let data = OpusStream::new().serial(7).build().unwrap();
+        let report = verify_from(&data[..]).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.pages, 12);
+        assert_eq!(report.streams, [7]);
+        assert_eq!(report.opus_streams, [7]);
+    }
+
+    #[test]
+    fn reports_checksum_mismatch() {
+        let data = OpusStream::new()
+            .corrupt(Corruption::HeaderChecksum)
+            .build()
+            .unwrap();
+        let report = verify_from(&data[..]).unwrap();
+        assert_eq!(report.issues.len(), 2, "{:?}", report.issues);
+        let offset = join(&pages(&data)[..1]).len() as u64;
+        let issue = &report.issues[0];
+        assert_eq!((issue.offset, issue.serial), (offset, Some(1)));
+        assert!(matches!(issue.kind, IssueKind::ChecksumMismatch { .. }));
+        // the changed byte is the start of the OpusTags signature
+        assert!(matches!(
+            report.issues[1].kind,
+            IssueKind::MalformedHeader(_)
+        ));
+    }
+
+    #[test]
+    fn reports_sequence_gap_and_missing_eos() {
+        let mut pages = pages(&OpusStream::new().build().unwrap());
+        pages.remove(4);
+        pages.pop();
+        assert_eq!(
+            kinds(&join(&pages)),
+            [
+                IssueKind::SequenceGap {
+                    expected: 4,
+                    found: 5
+                },
+                IssueKind::MissingEos
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_garbage_and_truncated_page() {
+        let mut data = OpusStream::new()
+            .corrupt(Corruption::Garbage)
+            .build()
+            .unwrap();
+        // the last page has a 28 byte header and a 3 byte packet
+        data.truncate(data.len() - 2);
+        assert_eq!(
+            kinds(&data),
+            [
+                IssueKind::Garbage { length: 10 },
+                IssueKind::TruncatedPage { length: 29 },
+                IssueKind::MissingEos
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_file_without_opus_stream() {
+        let data = OpusStream::new().codec(Codec::Vorbis).build().unwrap();
+        assert_eq!(kinds(&data), [IssueKind::NoOpusStream]);
+    }
+}
@@ -0,0 +1,1821 @@
+//! Rewriting the comment headers of a stream.
+
+use crate::options::ProgressCallback;
+use crate::page::{Chunk, Page, PageReader, FLAG_CONTINUED, FLAG_EOS};
+#[cfg(feature = "fs")]
+use crate::WriteStrategy;
+use crate::{
+    Codec, Diagnostic, Error, Pagination, ReadOptions, Result, Tag, TagRef, VendorPolicy,
+    WriteOptions,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "fs")]
+use std::sync::atomic::{self, AtomicU64};
+
+/// The number of pages taken up by the headers of the rewritten streams (the `OpusHead` page and
+/// the comment header pages), before and after a write.
+///
+/// If the numbers differ, the sequence numbers (and so the checksums) of every following page
+/// were rewritten to keep them continuous.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeaderPages {
+    /// Number of header pages in the original stream.
+    pub old: u32,
+    /// Number of header pages in the new stream.
+    pub new: u32,
+}
+
+impl HeaderPages {
+    /// Returns the number of pages added to the stream, which is negative if pages were removed.
+    #[must_use]
+    pub fn delta(&self) -> i64 {
+        i64::from(self.new) - i64::from(self.old)
+    }
+
+    /// Adds the header pages of another stream.
+    const fn add(&mut self, other: Self) {
+        self.old = self.old.saturating_add(other.old);
+        self.new = self.new.saturating_add(other.new);
+    }
+}
+
+/// State of a logical stream in [`copy_streams`].
+enum Stream<'a> {
+    /// A stream whose comment header is being replaced.
+    Header(Header<'a>),
+    /// A stream whose pages are copied through, with their sequence numbers shifted by
+    /// `sequence_delta` to account for the pages added or removed in the header.
+    Body { sequence_delta: u32 },
+}
+
+impl<'a> Stream<'a> {
+    /// Processes a page of this stream, writing whatever is ready to be written. Returns the
+    /// header page counts once the comment header has been replaced, and records it in
+    /// `written`.
+    fn write_page<W: Write>(
+        &mut self,
+        mut page: Page,
+        options: &WriteOptions,
+        mut f_out: W,
+        written: &mut Written<'a>,
+    ) -> Result<Option<HeaderPages>> {
+        match self {
+            Self::Header(header) => {
+                if let Some((pages, header_pages, data_hash)) = header.push(page, options)? {
+                    for page in pages {
+                        page.write_to(&mut f_out)?;
+                    }
+                    written.0.push((header.tag, data_hash));
+                    *self = Self::Body {
+                        sequence_delta: header_pages.new.wrapping_sub(header_pages.old),
+                    };
+                    return Ok(Some(header_pages));
+                }
+            }
+            Self::Body { sequence_delta } => {
+                if *sequence_delta != 0 {
+                    page.sequence_number = page.sequence_number.wrapping_add(*sequence_delta);
+                    page.update_checksum();
+                }
+                page.write_to(&mut f_out)?;
+            }
+        }
+        Ok(None)
+    }
+}
+
+struct Header<'a> {
+    tag: &'a Tag,
+    codec: Codec,
+    /// Sequence number of the next page written for this stream.
+    next_sequence: u32,
+    /// The existing comment header, assembled from the pages read so far.
+    packet: Vec<u8>,
+    /// Whether the pagination of the identification header had to be fixed.
+    fixed: bool,
+}
+
+/// The most padding [`Pagination::PreserveAudio`] adds to keep the number of header pages.
+const MAX_PRESERVE_PADDING: usize = 4 * 1024;
+
+impl Header<'_> {
+    /// Appends part of the existing comment header, taking over the buffer of the first part
+    /// instead of copying it, since most comment headers fit on a single page.
+    fn append(&mut self, body: Vec<u8>) {
+        if self.packet.is_empty() {
+            self.packet = body;
+        } else {
+            self.packet.extend_from_slice(&body);
+        }
+    }
+
+    /// Adds a page holding (part of) the existing comment header. Once the header is complete,
+    /// returns the pages to write in place of every page added, the header page counts, and the
+    /// hash of the new header for [`Written`].
+    fn push(
+        &mut self,
+        mut page: Page,
+        options: &WriteOptions,
+    ) -> Result<Option<(Vec<Page>, HeaderPages, u64)>> {
+        let Some(end) = page.first_packet_end() else {
+            self.append(page.body);
+            return Ok(None);
+        };
+        // packets after the comment header go on a page of their own
+        let mut rest = page.split_off(end);
+        let shared = !rest.segments.is_empty();
+        self.append(std::mem::take(&mut page.body));
+
+        // number of pages the old header took up, not counting the page split off for packets
+        // sharing its last page
+        let old_pages = page
+            .sequence_number
+            .wrapping_add(1)
+            .wrapping_sub(self.next_sequence)
+            .wrapping_sub(u32::from(!rest.segments.is_empty()));
+        let old_pages = usize::try_from(old_pages).ok();
+
+        let mut data = header_packet(self.tag, self.codec, &self.packet, options)?;
+        let length = data.len() + options.padding;
+        self.codec.pad(&mut data, length);
+        if options.pagination == Pagination::PreserveAudio {
+            // every page needs at least one lacing value, so a packet spread over n pages is at
+            // least (n - 1) * 255 bytes long. Pad a shorter header up to that, which never makes
+            // it longer than the old one, unless it takes so much padding that repaginating the
+            // stream is the lesser evil.
+            let minimum = old_pages.map_or(0, |count| count.saturating_sub(1) * 255);
+            if minimum <= self.packet.len()
+                && minimum.saturating_sub(data.len()) <= MAX_PRESERVE_PADDING
+            {
+                self.codec.pad(&mut data, minimum);
+            }
+        }
+        let data_hash = crate::header_hash(&data);
+        // header pages always have a granule position of 0, whatever the input had. If audio
+        // packets shared the last page, they keep its granule position on their own page.
+        let serial = page.serial;
+        let mut pages = match (options.pagination, old_pages) {
+            (Pagination::PreserveAudio, Some(count)) => {
+                Page::from_packet_over(serial, self.next_sequence, 0, data, count)
+            }
+            _ => Err(data),
+        }
+        .unwrap_or_else(|data| Page::from_packet(serial, self.next_sequence, 0, data));
+        debug_assert!(holds_one_packet(&pages));
+        // at most 4 GiB / 64 KiB pages
+        #[allow(clippy::cast_possible_truncation)]
+        let mut next_sequence = self.next_sequence.wrapping_add(pages.len() as u32);
+        if rest.segments.is_empty() {
+            if let Some(last) = pages.last_mut() {
+                last.flags |= rest.flags & FLAG_EOS;
+                last.update_checksum();
+            }
+        } else {
+            rest.sequence_number = next_sequence;
+            rest.update_checksum();
+            pages.push(rest);
+            next_sequence = next_sequence.wrapping_add(1);
+        }
+
+        // the OpusHead page comes right before the first page of the comment header
+        let head_sequence = self.next_sequence.wrapping_sub(1);
+        let header_pages = HeaderPages {
+            old: page
+                .sequence_number
+                .wrapping_add(1)
+                .wrapping_sub(head_sequence),
+            new: next_sequence.wrapping_sub(head_sequence),
+        };
+        if self.fixed || shared {
+            options.report(|| Diagnostic::PaginationFixed { serial });
+        }
+        if header_pages.old != header_pages.new {
+            options.report(|| Diagnostic::PagesRenumbered {
+                serial,
+                old: header_pages.old,
+                new: header_pages.new,
+            });
+        }
+        Ok(Some((pages, header_pages, data_hash)))
+    }
+}
+
+/// The comment headers written for tags, with the tags they were written for.
+///
+/// Writing a header doesn't make its tag clean (see [`Tag::is_dirty`]) by itself, since it may be
+/// written somewhere else than the file the tag was read from, or the write may still fail.
+/// Functions which write back to that file [`commit`](Self::commit) the headers once the write
+/// has succeeded.
+#[derive(Default)]
+#[must_use]
+pub struct Written<'a>(Vec<(&'a Tag, u64)>);
+
+impl<'a> Written<'a> {
+    /// The header `data` written for `tag`.
+    pub fn new(tag: &'a Tag, data: &[u8]) -> Self {
+        Self(vec![(tag, crate::header_hash(data))])
+    }
+
+    /// Records every header as the one its tag was read from, and the tag as clean.
+    pub fn commit(self) {
+        for (tag, data_hash) in self.0 {
+            tag.set_source_hash(data_hash);
+        }
+    }
+}
+
+/// Returns a `tag_for` for [`copy_pages`] which gives `tag` to the stream the functions without a
+/// serial number work on: the first opus stream, or if there is none the first stream of another
+/// supported [`Codec`].
+pub fn preferred_stream<'a>(tag: &'a Tag) -> impl FnMut(u32, Codec) -> Option<&'a Tag> {
+    let mut first = true;
+    move |_, _| std::mem::take(&mut first).then_some(tag)
+}
+
+/// Copies the stream in `f_in` to `f_out`, replacing the comment header of every stream of a
+/// supported codec for which `tag_for` returns a tag. See [`copy_pages`].
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+)]
+pub fn copy_streams<'a, R, W, F>(
+    f_in: R,
+    f_out: W,
+    options: &WriteOptions,
+    tag_for: F,
+) -> Result<HeaderPages>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u32, Codec) -> Option<&'a Tag>,
+{
+    let f_in = ProgressReader::new(f_in, Progress::new(options, None));
+    let mut reader = PageReader::new(f_in);
+    let mut f_out = BufWriter::new(f_out);
+    // the copy doesn't replace the stream the tags were read from
+    let (header_pages, _, _) = copy_pages(&mut reader, &mut f_out, options, tag_for)?;
+    let start = reader.position();
+    let copied = std::io::copy(&mut reader.into_rest(), &mut f_out)?;
+    f_out.flush()?;
+    Progress::new(options, None).finish(start + copied);
+    Ok(header_pages)
+}
+
+/// Copies the stream in `src` to the end of `dst`, like [`copy_streams`]. The part of the stream
+/// which is copied verbatim is copied with [`std::io::copy`], which lets the kernel copy the data
+/// between the files directly (using `copy_file_range` on Linux, which can share the data between
+/// the files on filesystems such as btrfs and XFS) instead of passing it through this process.
+/// Returns the headers written, to be committed once `dst` replaces `src`.
+///
+/// On Linux, an empty `dst` is first made a clone of `src` with the `FICLONE` ioctl where the
+/// filesystem supports it, and the new headers are written over the start of the clone. If they
+/// take up as many bytes as the old ones, the rest of the clone is kept, sharing its data with
+/// `src` without copying any of it.
+#[cfg(feature = "fs")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+)]
+pub fn copy_file<'a, F>(
+    mut src: &File,
+    dst: &mut File,
+    options: &WriteOptions,
+    tag_for: F,
+) -> Result<(HeaderPages, Written<'a>)>
+where
+    F: FnMut(u32, Codec) -> Option<&'a Tag>,
+{
+    let total = src.metadata()?.len();
+    let mut reader = PageReader::new(ProgressReader::new(
+        src,
+        Progress::new(options, Some(total)),
+    ));
+    let cloned = dst.metadata()?.len() == 0 && clone_file(src, dst);
+    let mut f_out = BufWriter::new(&mut *dst);
+    let (header_pages, _, written) = copy_pages(&mut reader, &mut f_out, options, tag_for)?;
+    f_out.flush()?;
+    drop(f_out);
+    let mut progress = Progress::new(options, Some(total));
+    if cloned && dst.stream_position()? == reader.position() {
+        progress.finish(total);
+        return Ok((header_pages, written));
+    }
+
+    // copied in chunks to report progress, which doesn't stop the kernel from copying each chunk
+    let mut done = src.seek(SeekFrom::Start(reader.position()))?;
+    loop {
+        let copied = std::io::copy(&mut src.take(PROGRESS_INTERVAL), dst)?;
+        if copied == 0 {
+            break;
+        }
+        done += copied;
+        progress.update(done);
+    }
+    if cloned {
+        // the end of the clone, which the copy didn't overwrite
+        let end = dst.stream_position()?;
+        dst.set_len(end)?;
+    }
+    progress.finish(done);
+    Ok((header_pages, written))
+}
+
+/// Makes `dst` a clone of `src` sharing its data, with the `FICLONE` ioctl. Returns false if the
+/// filesystem doesn't support it, or the files are on different filesystems.
+#[cfg(all(feature = "fs", target_os = "linux"))]
+fn clone_file(src: &File, dst: &File) -> bool {
+    use std::os::fd::AsRawFd;
+    // SAFETY: both file descriptors stay open for the duration of the call, and FICLONE takes the
+    // source descriptor by value
+    unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) == 0 }
+}
+
+#[cfg(all(feature = "fs", not(target_os = "linux")))]
+const fn clone_file(_src: &File, _dst: &File) -> bool {
+    false
+}
+
+/// Copies pages from `reader` to `f_out`, replacing the comment header of every stream of a
+/// supported [`Codec`] for which `tag_for` returns a tag. `tag_for` is called once per such
+/// stream, with its serial number and codec: for the streams starting the file, once all of their BOS
+/// pages have been read, on the opus streams first and then on the others, so that
+/// [`preferred_stream`] can pick the first opus stream; for the streams of later links of a
+/// chained stream, when their first page is encountered.
+///
+/// Pages other than the comment header pages are copied unchanged, except for their sequence
+/// numbers (and so their checksums) if the new comment header takes up a different number of
+/// pages. Data outside of any page is dropped.
+///
+/// Whatever the layout of the input, the headers of the streams which get new tags are paginated
+/// as required by RFC 7845 section 3 (which the other codecs allow as well): the identification
+/// header is alone on the first page, and the comment header starts on a new page and ends its
+/// last page, so the packets after it start on a page of their own.
+///
+/// Returns once every comment header has been replaced and the pages which follow don't need to
+/// be renumbered, so that the rest of the stream (starting at [`PageReader::position`]) can be
+/// copied verbatim. Returns the header page counts of all rewritten streams together, the
+/// position in the input right after the last rewritten header, and the headers written.
+///
+/// Fails with [`Error::NotOpus`] before anything but the beginning-of-stream pages has been
+/// written if there is no stream of a supported codec.
+fn copy_pages<'a, R, W, F>(
+    reader: &mut PageReader<R>,
+    mut f_out: W,
+    options: &WriteOptions,
+    tag_for: F,
+) -> Result<(HeaderPages, u64, Written<'a>)>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u32, Codec) -> Option<&'a Tag>,
+{
+    let mut copier = PageCopier::new(tag_for);
+    let mut header_end = 0;
+    while !copier.is_done() {
+        let Some(chunk) = reader.read_chunk()? else {
+            let (header_pages, written) = copier.finish()?;
+            return Ok((header_pages, header_end, written));
+        };
+        let page = match chunk {
+            Chunk::Page { page, .. } => page,
+            Chunk::Garbage { offset, length } => {
+                options.report(|| Diagnostic::Garbage { offset, length });
+                continue;
+            }
+            Chunk::Truncated { offset, length } => {
+                options.report(|| Diagnostic::TruncatedPage { offset, length });
+                continue;
+            }
+        };
+        if copier.push(page, options, &mut f_out)? {
+            header_end = reader.position();
+        }
+    }
+    let (header_pages, written) = copier.into_parts();
+    Ok((header_pages, header_end, written))
+}
+
+/// The state of [`copy_pages`], which is fed one page at a time.
+pub struct PageCopier<'a, F> {
+    streams: HashMap<u32, Stream<'a>>,
+    header_pages: HeaderPages,
+    written: Written<'a>,
+    /// Whether a stream of a supported codec has been found.
+    found_stream: bool,
+    /// Whether all BOS pages (which come before any other page) have been read.
+    bos_done: bool,
+    /// The BOS pages read so far, held back until all of them have been read so that `tag_for`
+    /// can be called on the opus streams first.
+    bos_pages: Vec<Page>,
+    tag_for: F,
+}
+
+impl<'a, F: FnMut(u32, Codec) -> Option<&'a Tag>> PageCopier<'a, F> {
+    pub fn new(tag_for: F) -> Self {
+        Self {
+            streams: HashMap::new(),
+            header_pages: HeaderPages::default(),
+            written: Written::default(),
+            found_stream: false,
+            bos_done: false,
+            bos_pages: vec![],
+            tag_for,
+        }
+    }
+
+    /// Returns true once every comment header has been replaced and the pages which follow don't
+    /// need to be renumbered.
+    pub fn is_done(&self) -> bool {
+        let unchanged = self
+            .streams
+            .values()
+            .all(|stream| matches!(stream, Stream::Body { sequence_delta: 0 }));
+        self.found_stream && self.bos_done && unchanged
+    }
+
+    /// Processes the next page of the input, writing whatever is ready to be written. Returns
+    /// true if the page completed a comment header.
+    pub fn push<W: Write>(
+        &mut self,
+        page: Page,
+        options: &WriteOptions,
+        mut f_out: W,
+    ) -> Result<bool> {
+        let mut completed = false;
+        if !self.bos_done {
+            if page.is_bos() {
+                self.bos_pages.push(page);
+                return Ok(false);
+            }
+            self.bos_done = true;
+            completed = self.start_bos_streams(options, &mut f_out)?;
+            if !self.found_stream {
+                return Err(Error::NotOpus);
+            }
+        }
+
+        if let Some(stream) = self.streams.get_mut(&page.serial) {
+            let Some(pages) = stream.write_page(page, options, &mut f_out, &mut self.written)?
+            else {
+                return Ok(completed);
+            };
+            self.header_pages.add(pages);
+            return Ok(true);
+        }
+
+        // first page of a logical stream of a later link of a chained stream
+        let codec = Some(&page)
+            .filter(|page| page.is_bos())
+            .and_then(|page| Codec::from_first_packet(&page.body));
+        let tag = codec.and_then(|codec| (self.tag_for)(page.serial, codec));
+        Ok(self.start_stream(page, codec, tag, options, f_out)? || completed)
+    }
+
+    /// Starts the streams whose BOS pages have been held back, calling `tag_for` on the opus
+    /// streams first, then on the streams of the other codecs, each in the order of the file.
+    /// Returns true if a page completed a comment header.
+    fn start_bos_streams<W: Write>(
+        &mut self,
+        options: &WriteOptions,
+        mut f_out: W,
+    ) -> Result<bool> {
+        let pages = std::mem::take(&mut self.bos_pages);
+        let codecs: Vec<Option<Codec>> = pages
+            .iter()
+            .map(|page| Codec::from_first_packet(&page.body))
+            .collect();
+        let mut order: Vec<usize> = (0..pages.len()).collect();
+        order.sort_by_key(|&index| codecs[index] != Some(Codec::Opus));
+        let mut tags = vec![None; pages.len()];
+        for index in order {
+            if let Some(codec) = codecs[index] {
+                tags[index] = (self.tag_for)(pages[index].serial, codec);
+            }
+        }
+
+        let mut completed = false;
+        for ((page, codec), tag) in pages.into_iter().zip(codecs).zip(tags) {
+            completed |= self.start_stream(page, codec, tag, options, &mut f_out)?;
+        }
+        Ok(completed)
+    }
+
+    /// Processes the first page of a logical stream, which gets `tag` if it is a stream of a
+    /// supported codec. Returns true if the page completed a comment header.
+    fn start_stream<W: Write>(
+        &mut self,
+        mut page: Page,
+        codec: Option<Codec>,
+        tag: Option<&'a Tag>,
+        options: &WriteOptions,
+        mut f_out: W,
+    ) -> Result<bool> {
+        let serial = page.serial;
+        self.found_stream |= codec.is_some();
+        let (Some(tag), Some(codec), Some(end)) = (tag, codec, page.first_packet_end()) else {
+            self.streams
+                .insert(serial, Stream::Body { sequence_delta: 0 });
+            page.write_to(&mut f_out)?;
+            return Ok(false);
+        };
+
+        // the identification header (such as the OpusHead packet) is kept as-is, on a page of its
+        // own
+        let rest = page.split_off(end);
+        let fixed = !rest.segments.is_empty() || page.granule_position != 0;
+        if rest.segments.is_empty() {
+            page.flags |= rest.flags;
+        }
+        page.flags &= !FLAG_CONTINUED;
+        page.granule_position = 0;
+        adjust_gain(&mut page, codec, options)?;
+        page.update_checksum();
+        debug_assert!(holds_one_packet(std::slice::from_ref(&page)));
+        page.write_to(&mut f_out)?;
+        let mut stream = Stream::Header(Header {
+            tag,
+            codec,
+            next_sequence: page.sequence_number.wrapping_add(1),
+            packet: vec![],
+            fixed,
+        });
+        let mut completed = false;
+        if !rest.segments.is_empty() {
+            if let Some(pages) = stream.write_page(rest, options, &mut f_out, &mut self.written)? {
+                self.header_pages.add(pages);
+                completed = true;
+            }
+        }
+        self.streams.insert(serial, stream);
+        Ok(completed)
+    }
+
+    /// Returns the header page counts of the streams rewritten so far, and the headers written.
+    pub fn into_parts(self) -> (HeaderPages, Written<'a>) {
+        (self.header_pages, self.written)
+    }
+
+    /// Checks that the input, which has ended, held every comment header completely. Returns the
+    /// header page counts of all rewritten streams together, and the headers written.
+    pub fn finish(self) -> Result<(HeaderPages, Written<'a>)> {
+        if !self.bos_done {
+            // the input holds nothing but BOS pages, so no comment header at all
+            let supported = self
+                .bos_pages
+                .iter()
+                .any(|page| Codec::from_first_packet(&page.body).is_some());
+            return Err(if supported {
+                Error::MissingPacket
+            } else {
+                Error::NotOpus
+            });
+        }
+        if !self.found_stream {
+            return Err(Error::NotOpus);
+        }
+        if self
+            .streams
+            .values()
+            .any(|stream| matches!(stream, Stream::Header(_)))
+        {
+            return Err(Error::MissingPacket);
+        }
+        Ok(self.into_parts())
+    }
+}
+
+/// Adds the [gain adjustment](WriteOptions::gain_adjustment) to the output gain of the `OpusHead`
+/// packet starting `page`. Returns true if the page changed.
+fn adjust_gain(page: &mut Page, codec: Codec, options: &WriteOptions) -> Result<bool> {
+    let adjustment = options.gain_adjustment;
+    if adjustment == 0 || codec != Codec::Opus || page.body.len() < 19 {
+        return Ok(false);
+    }
+    let output_gain = i16::from_le_bytes([page.body[16], page.body[17]]);
+    let new_gain = output_gain.checked_add(adjustment).ok_or_else(|| {
+        Error::InvalidGain(format!(
+            "output gain {output_gain} + {adjustment} is out of range"
+        ))
+    })?;
+    page.body[16..18].copy_from_slice(&new_gain.to_le_bytes());
+    Ok(true)
+}
+
+/// Returns true if `pages` hold exactly one packet: the first page doesn't continue a previous
+/// packet, and the packet ends at the end of the last page.
+fn holds_one_packet(pages: &[Page]) -> bool {
+    let Some((last, rest)) = pages.split_last() else {
+        return false;
+    };
+    pages.first().is_some_and(|first| !first.is_continued())
+        && rest.iter().all(|page| page.packets_completed() == 0)
+        && last.packets_completed() == 1
+        && !last.ends_with_continued()
+}
+
+/// Encodes the comment header which replaces `old` for `tag`, without padding, and reports the
+/// changes made to fit the [device profile](WriteOptions::device_profile). Values are
+/// [sanitized](WriteOptions::sanitize) first.
+pub fn header_packet(
+    tag: &Tag,
+    codec: Codec,
+    old: &[u8],
+    options: &WriteOptions,
+) -> Result<Vec<u8>> {
+    let (data, changes) = profiled_header_packet(tag, codec, old, options)?;
+    for change in changes {
+        options.report(|| change);
+    }
+    Ok(data)
+}
+
+/// Encodes the comment header like [`header_packet`], returning the changes made to fit the
+/// device profile instead of reporting them, for headers which may not be written.
+fn profiled_header_packet(
+    tag: &Tag,
+    codec: Codec,
+    old: &[u8],
+    options: &WriteOptions,
+) -> Result<(Vec<u8>, Vec<Diagnostic>)> {
+    tag.check_source_header(old, options)?;
+    let vendor = header_vendor(tag, codec, old, options);
+    let sanitized = options.sanitize.and_then(|sanitize| sanitize.apply_to(tag));
+    let tag = sanitized.as_ref().unwrap_or(tag);
+    let (constrained, changes) = match &options.device_profile {
+        Some(profile) => profile.apply(tag, vendor, codec)?,
+        None => (None, vec![]),
+    };
+    let mut data = constrained
+        .as_ref()
+        .unwrap_or(tag)
+        .to_packet_data_with(vendor, codec, options)?;
+    codec.copy_flags(old, &mut data);
+    Ok((data, changes))
+}
+
+/// Picks the vendor string of the comment header which replaces `old`, following
+/// [`WriteOptions::vendor`].
+fn header_vendor<'a>(
+    tag: &'a Tag,
+    codec: Codec,
+    old: &'a [u8],
+    options: &'a WriteOptions,
+) -> &'a str {
+    let old_vendor = TagRef::from_codec_packet(codec, old).map_or("", |old| old.get_vendor());
+    let vendor = match &options.vendor {
+        VendorPolicy::Tag => tag.get_vendor(),
+        VendorPolicy::Keep if old_vendor.is_empty() => tag.get_vendor(),
+        VendorPolicy::Keep => old_vendor,
+        VendorPolicy::Replace(vendor) => return vendor,
+    };
+    let Some(default_vendor) = &options.default_vendor else {
+        return vendor;
+    };
+    // fall back to the existing vendor string, then to the default one
+    [vendor, old_vendor, default_vendor]
+        .into_iter()
+        .find(|vendor| !vendor.is_empty())
+        .unwrap_or_default()
+}
+
+/// Tries to replace the comment header of the [preferred stream](preferred_stream) in `f_in`
+/// with the one for `tag`, without moving anything else in the file. This is only possible if the new header is no longer
+/// than the existing one, and the header pages contain nothing but the header packets. The new
+/// header is padded with zeros to the length of the existing one.
+///
+/// Returns None, without writing anything, if the header can't be patched in place. Otherwise
+/// returns the header page counts, and the header written for `tag`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+)]
+pub fn patch_in_place<'a, F: Read + Write + Seek>(
+    f_in: &mut F,
+    tag: &'a Tag,
+    options: &WriteOptions,
+) -> Result<Option<(HeaderPages, Written<'a>)>> {
+    let Some(patch) = Patch::new(&mut *f_in, tag, options)? else {
+        debug!("the new comment header doesn't fit in the existing one");
+        return Ok(None);
+    };
+    let header_pages = patch.header_pages();
+    let Patch {
+        codec,
+        head,
+        pages,
+        mut data,
+        changes,
+    } = patch;
+    for change in changes {
+        options.report(|| change);
+    }
+
+    // checked before anything is written, as the output gain may overflow
+    let (offset, mut head) = head;
+    let adjusted = adjust_gain(&mut head, codec, options)?;
+
+    // the packet keeps its length, so the lacing values and page boundaries don't change
+    let old_length = pages.iter().map(|(_, page)| page.body.len()).sum();
+    codec.pad(&mut data, old_length);
+    let written = Written::new(tag, &data);
+    let mut remaining = &data[..];
+    for (offset, mut page) in pages {
+        let (body, rest) = remaining.split_at(page.body.len());
+        page.body.copy_from_slice(body);
+        remaining = rest;
+        if rest.is_empty() {
+            page.granule_position = 0;
+        }
+        page.update_checksum();
+        f_in.seek(SeekFrom::Start(offset))?;
+        // one write per page rather than one for the header and one for the body
+        let mut f_out = BufWriter::new(&mut *f_in);
+        page.write_to(&mut f_out)?;
+        f_out.flush()?;
+    }
+    // header pages always have a granule position of 0
+    let fixed = head.granule_position != 0;
+    if fixed {
+        options.report(|| Diagnostic::PaginationFixed {
+            serial: head.serial,
+        });
+        head.granule_position = 0;
+    }
+    if fixed || adjusted {
+        head.update_checksum();
+        f_in.seek(SeekFrom::Start(offset))?;
+        head.write_to(&mut *f_in)?;
+    }
+    f_in.flush()?;
+
+    Ok(Some((header_pages, written)))
+}
+
+/// A new comment header which fits in the pages of the existing one. See [`patch_in_place`].
+struct Patch {
+    codec: Codec,
+    /// The page holding the identification header, such as the `OpusHead` packet.
+    head: PageAt,
+    /// The pages holding the existing comment header.
+    pages: Vec<PageAt>,
+    /// The new comment header, without padding.
+    data: Vec<u8>,
+    /// The changes made to the comments to fit the device profile, reported if the patch is
+    /// written.
+    changes: Vec<Diagnostic>,
+}
+
+impl Patch {
+    /// Reads the header pages of the preferred stream in `f_in` and encodes the new comment
+    /// header. Returns None if the new header doesn't fit in the existing pages.
+    fn new<R: Read + Seek>(mut f_in: R, tag: &Tag, options: &WriteOptions) -> Result<Option<Self>> {
+        f_in.seek(SeekFrom::Start(0))?;
+        let Some((codec, head, pages)) = comment_header_pages(f_in)? else {
+            return Ok(None);
+        };
+        let old: Vec<u8> = pages
+            .iter()
+            .flat_map(|(_, page)| page.body.iter().copied())
+            .collect();
+        let (data, changes) = profiled_header_packet(tag, codec, &old, options)?;
+        if data.len() > old.len() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            codec,
+            head,
+            pages,
+            data,
+            changes,
+        }))
+    }
+
+    /// The header pages, which are the same before and after the patch.
+    const fn header_pages(&self) -> HeaderPages {
+        // at most 4 GiB / 64 KiB pages
+        #[allow(clippy::cast_possible_truncation)]
+        let count = self.pages.len() as u32 + 1;
+        HeaderPages {
+            old: count,
+            new: count,
+        }
+    }
+}
+
+/// The outcome of a write, worked out without writing anything. See [`Tag::plan_write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WritePlan {
+    /// Size of the new comment header packet in bytes, including any
+    /// [padding](WriteOptions::padding), but not the zeros which
+    /// [`Pagination::PreserveAudio`] may add to keep the number of header pages.
+    pub header_size: usize,
+    /// Whether the new comment header fits in the space taken up by the existing one (including
+    /// its padding), so that with [padding](WriteOptions::padding) enabled, only the header pages
+    /// would be overwritten.
+    pub fits_in_place: bool,
+    /// Number of bytes after the headers which would have to be moved, because the headers change
+    /// size.
+    pub bytes_moved: u64,
+    /// Length of the stream before the write.
+    pub old_length: u64,
+    /// Length of the stream after the write.
+    pub new_length: u64,
+    /// Number of header pages before and after the write.
+    pub header_pages: HeaderPages,
+}
+
+impl WritePlan {
+    /// Returns true if the stream would get shorter.
+    #[must_use]
+    pub const fn shrinks(&self) -> bool {
+        self.new_length < self.old_length
+    }
+}
+
+/// Works out what writing `tag` to the preferred stream in `f_in` with
+/// [`Tag::write_to_with`] would do, without writing anything.
+pub fn plan_write<R: Read + Seek>(
+    mut f_in: R,
+    tag: &Tag,
+    options: &WriteOptions,
+) -> Result<WritePlan> {
+    // planning doesn't report progress
+    let options = &WriteOptions {
+        progress: None,
+        ..options.clone()
+    };
+    let old_length = f_in.seek(SeekFrom::End(0))?;
+    let patch = Patch::new(&mut f_in, tag, options)?;
+    if let Some(patch) = patch.as_ref().filter(|_| options.padding > 0) {
+        return Ok(WritePlan {
+            header_size: patch.pages.iter().map(|(_, page)| page.body.len()).sum(),
+            fits_in_place: true,
+            bytes_moved: 0,
+            old_length,
+            new_length: old_length,
+            header_pages: patch.header_pages(),
+        });
+    }
+
+    f_in.seek(SeekFrom::Start(0))?;
+    let mut reader = PageReader::new(&mut f_in);
+    let mut f_out = CountingWriter(0);
+    let (header_pages, header_end, _) =
+        copy_pages(&mut reader, &mut f_out, options, preferred_stream(tag))?;
+    let new_length = f_out.0 + (old_length - reader.position());
+
+    f_in.seek(SeekFrom::Start(0))?;
+    let (codec, old_header) = crate::read_comment_packet(&mut f_in, &ReadOptions::default())?;
+    let header_size = profiled_header_packet(tag, codec, &old_header, options)?
+        .0
+        .len()
+        + options.padding;
+    Ok(WritePlan {
+        header_size,
+        fits_in_place: patch.is_some(),
+        bytes_moved: if new_length == old_length {
+            0
+        } else {
+            old_length - header_end
+        },
+        old_length,
+        new_length,
+        header_pages,
+    })
+}
+
+/// A writer which only counts the bytes written to it.
+struct CountingWriter(u64);
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A page, with its offset in the stream.
+type PageAt = (u64, Page);
+
+/// Finds the codec and identification header page of the first opus stream, or of the first
+/// stream of another supported codec if there is none, and the pages holding its comment header.
+/// Returns None if the header pages are shared with other packets, or if the stream doesn't look
+/// well-formed.
+fn comment_header_pages<R: Read>(f_in: R) -> Result<Option<(Codec, PageAt, Vec<PageAt>)>> {
+    let mut reader = PageReader::new(f_in);
+    let mut head: Option<(Codec, PageAt)> = None;
+    let mut pages = vec![];
+
+    while let Some(chunk) = reader.read_chunk()? {
+        let Chunk::Page { offset, page } = chunk else {
+            return Ok(None);
+        };
+        if page.is_bos() && pages.is_empty() {
+            let chosen = head.as_ref().map(|(codec, _)| *codec);
+            if let Some(codec) =
+                Codec::from_first_packet(&page.body).filter(|codec| codec.is_preferred_to(chosen))
+            {
+                // the identification header must be alone on its page
+                if page.packets_completed() != 1 || page.ends_with_continued() {
+                    return Ok(None);
+                }
+                head = Some((codec, (offset, page)));
+            }
+            continue;
+        }
+        let Some((_, (_, head_page))) = &head else {
+            return Ok(None);
+        };
+        let serial = head_page.serial;
+        if page.serial != serial {
+            continue;
+        }
+
+        let completed = page.packets_completed();
+        let ends_with_continued = page.ends_with_continued();
+        pages.push((offset, page));
+        if completed > 0 {
+            // the comment header must be alone on its last page
+            let alone = completed == 1 && !ends_with_continued;
+            return Ok(head
+                .filter(|_| alone)
+                .map(|(codec, head)| (codec, head, pages)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Rewrites the stream in `f_in` onto itself, like [`copy_streams`]. Returns the length of the new
+/// stream, which can be shorter than the old one, the header page counts, and the headers
+/// written.
+///
+/// Only the difference in size between the old and new headers (plus a few pages) is held in
+/// memory: new data is written as soon as the old data it overwrites has been read. If the new
+/// headers take up exactly as much space as the old ones, the rest of the stream isn't touched.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+)]
+pub fn splice_streams<'a, F, T>(
+    mut f_in: F,
+    options: &WriteOptions,
+    tag_for: T,
+) -> Result<(u64, HeaderPages, Written<'a>)>
+where
+    F: Read + Write + Seek,
+    T: FnMut(u32, Codec) -> Option<&'a Tag>,
+{
+    let total = f_in.seek(SeekFrom::End(0))?;
+    let position = f_in.seek(SeekFrom::Start(0))?;
+    let splice = RefCell::new(Splice {
+        file: f_in,
+        position,
+        read_position: 0,
+        write_position: 0,
+        pending: vec![],
+    });
+    let f_in = ProgressReader::new(SpliceHandle(&splice), Progress::new(options, Some(total)));
+    let mut reader = PageReader::new(f_in);
+    let mut f_out = BufWriter::new(SpliceHandle(&splice));
+    let (header_pages, _, written) = copy_pages(&mut reader, &mut f_out, options, tag_for)?;
+    f_out.flush()?;
+    drop(f_out);
+
+    let output_position = {
+        let splice = splice.borrow();
+        splice.write_position + splice.pending.len() as u64
+    };
+    let in_place = output_position == reader.position();
+    debug!(?header_pages, moved = !in_place, "rewrote the headers");
+    if !in_place {
+        // the rest of the stream has to be moved
+        std::io::copy(&mut reader.into_rest(), &mut SpliceHandle(&splice))?;
+    }
+
+    let mut splice = splice.into_inner();
+    splice.write_pending(true)?;
+    splice.file.flush()?;
+    Progress::new(options, Some(total)).finish(total);
+    if in_place {
+        // the rest of the stream is already where it belongs
+        let length = splice.file.seek(SeekFrom::End(0))?;
+        return Ok((length, header_pages, written));
+    }
+    Ok((splice.write_position, header_pages, written))
+}
+
+/// A file which is read and written at the same time by [`splice_streams`]. Reads continue from
+/// where the last read ended, and written data is held back until the data it overwrites has
+/// been read.
+struct Splice<F> {
+    file: F,
+    /// Current position of `file`, to avoid needless seeks.
+    position: u64,
+    read_position: u64,
+    write_position: u64,
+    /// Written data which hasn't been written to `file` yet.
+    pending: Vec<u8>,
+}
+
+impl<F: Read + Write + Seek> Splice<F> {
+    fn seek_to(&mut self, position: u64) -> std::io::Result<()> {
+        if self.position != position {
+            self.position = self.file.seek(SeekFrom::Start(position))?;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.seek_to(self.read_position)?;
+        let count = self.file.read(buf)?;
+        self.position += count as u64;
+        self.read_position += count as u64;
+        Ok(count)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.write_pending(false)?;
+        Ok(buf.len())
+    }
+
+    /// Writes as much pending data as can be written without overwriting unread data, or all of
+    /// it if `all` is true.
+    fn write_pending(&mut self, all: bool) -> std::io::Result<()> {
+        let count = if all {
+            self.pending.len()
+        } else {
+            let writable = self.read_position.saturating_sub(self.write_position);
+            self.pending
+                .len()
+                .min(usize::try_from(writable).unwrap_or(usize::MAX))
+        };
+        if count == 0 {
+            return Ok(());
+        }
+        self.seek_to(self.write_position)?;
+        self.file.write_all(&self.pending[..count])?;
+        self.pending.drain(..count);
+        self.position += count as u64;
+        self.write_position += count as u64;
+        Ok(())
+    }
+}
+
+/// A shared handle to a [`Splice`], so that it can be used as both the reader and the writer of
+/// [`copy_streams`].
+struct SpliceHandle<'a, F>(&'a RefCell<Splice<F>>);
+
+impl<F: Read + Write + Seek> Read for SpliceHandle<'_, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl<F: Read + Write + Seek> Write for SpliceHandle<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Replaces the first `length` bytes of `f_in` with `head`, moving the rest of the file as
+/// needed. Returns the new length of the file, which the caller has to truncate it to.
+pub fn splice_head<F: Read + Write + Seek>(mut f_in: F, head: &[u8], length: u64) -> Result<u64> {
+    let position = f_in.stream_position()?;
+    let splice = RefCell::new(Splice {
+        file: f_in,
+        position,
+        read_position: length,
+        write_position: 0,
+        pending: vec![],
+    });
+    SpliceHandle(&splice).write_all(head)?;
+    std::io::copy(&mut SpliceHandle(&splice), &mut SpliceHandle(&splice))?;
+
+    let mut splice = splice.into_inner();
+    splice.write_pending(true)?;
+    splice.file.flush()?;
+    Ok(splice.write_position)
+}
+
+/// Rewrites the file at `path` according to the [`WriteStrategy`] of `options`: `in_place` is
+/// given the file opened for reading and writing and returns its new length, and `atomic` is
+/// given the arguments of [`replace_atomically`]. Takes care of the
+/// [backup](WriteOptions::backup) and of [preserving](WriteOptions::preserve_modified) the
+/// modification time.
+#[cfg(feature = "fs")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(?path, strategy = ?options.strategy), err(level = "debug"))
+)]
+pub fn write_path<T, I, A>(path: &Path, options: &WriteOptions, in_place: I, atomic: A) -> Result<T>
+where
+    I: FnOnce(&mut File) -> Result<(u64, T)>,
+    A: FnOnce(&File, &mut File) -> Result<T>,
+{
+    if options.backup {
+        std::fs::copy(path, backup_path(path))?;
+    }
+    let modified = if options.preserve_modified {
+        Some(std::fs::metadata(path)?.modified()?)
+    } else {
+        None
+    };
+
+    let output = match options.strategy {
+        WriteStrategy::InPlace => {
+            let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+            let (length, output) = in_place(&mut file)?;
+            file.set_len(length)?;
+            output
+        }
+        WriteStrategy::Atomic => replace_atomically(path, atomic)?,
+    };
+
+    if let Some(modified) = modified {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)?;
+    }
+    Ok(output)
+}
+
+/// Replaces the file at `path` with the output of `write`, which is given the original file and a
+/// new temporary file in the same directory. Once `write` succeeds, the temporary file gets the
+/// original's metadata (see [`copy_file_metadata`]), is synced to disk, and is renamed over the
+/// original, so the original is never left half-written. The temporary file is removed if
+/// anything fails.
+///
+/// Symlinks are followed, so that the file they point to is replaced rather than the link.
+#[cfg(feature = "fs")]
+pub fn replace_atomically<F, T>(path: &Path, write: F) -> Result<T>
+where
+    F: FnOnce(&File, &mut File) -> Result<T>,
+{
+    replace_atomically_if(path, |src, dst| write(src, dst).map(|value| (value, true)))
+}
+
+/// Replaces the file at `path` with the output of `write` like [`replace_atomically`], unless
+/// `write` returns false along with its value, in which case the temporary file is removed and
+/// the original is left as it is.
+#[cfg(feature = "fs")]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(?path), err(level = "debug"))
+)]
+pub fn replace_atomically_if<F, T>(path: &Path, write: F) -> Result<T>
+where
+    F: FnOnce(&File, &mut File) -> Result<(T, bool)>,
+{
+    let path = &std::fs::canonicalize(path)?;
+    let src = File::open(path)?;
+    let temp_path = temp_path(path);
+    // opened for reading as well, so that `write` can edit what it has copied
+    let mut temp = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)?;
+
+    let result = write(&src, &mut temp).and_then(|(value, replace)| {
+        if replace {
+            copy_file_metadata(&src, &temp)?;
+            temp.sync_all()?;
+            std::fs::rename(&temp_path, path)?;
+        }
+        Ok((value, replace))
+    });
+    let value = match result {
+        Ok((value, true)) => value,
+        Ok((value, false)) => {
+            drop(temp);
+            std::fs::remove_file(&temp_path)?;
+            return Ok(value);
+        }
+        Err(err) => {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err);
+        }
+    };
+
+    // make the rename itself durable. This is best-effort, as not every platform can open or
+    // sync a directory.
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Ok(dir) = File::open(dir.unwrap_or_else(|| Path::new("."))) {
+        let _ = dir.sync_all();
+    }
+    Ok(value)
+}
+
+/// Copies the permissions of `original` onto `file`, along with its ownership where the process
+/// is allowed to change it, and its extended attributes with the `xattr` feature.
+#[cfg(feature = "fs")]
+pub fn copy_file_metadata(original: &File, file: &File) -> Result<()> {
+    let metadata = original.metadata()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+        // changing the owner needs privileges, but the group can be changed to any group the
+        // user is a member of
+        if fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_err() {
+            let _ = fchown(file, None, Some(metadata.gid()));
+        }
+    }
+
+    // attributes which can't be listed or set (for example because the filesystem doesn't
+    // support them, or they need privileges) are skipped
+    #[cfg(all(unix, feature = "xattr"))]
+    if let Ok(names) = xattr::FileExt::list_xattr(original) {
+        for name in names {
+            if let Ok(Some(value)) = xattr::FileExt::get_xattr(original, &name) {
+                let _ = xattr::FileExt::set_xattr(file, &name, &value);
+            }
+        }
+    }
+
+    // set last, since changing the owner can clear the setuid and setgid bits
+    file.set_permissions(metadata.permissions())?;
+    Ok(())
+}
+
+/// Returns the path of the backup of `path`: `name.opus.bak`.
+#[cfg(feature = "fs")]
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+/// Returns a new path for a temporary file next to `path`: `.name.opus.<pid>.<n>.tmp`, where `n`
+/// counts the calls in this process, so that threads writing the same file don't share one.
+#[cfg(feature = "fs")]
+pub fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.{count}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Minimum number of bytes between two calls to a progress callback.
+pub const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// Reports the progress of a write to the callback of [`WriteOptions::progress`], if any.
+pub struct Progress<'a> {
+    callback: Option<&'a ProgressCallback>,
+    total: Option<u64>,
+    /// Number of bytes done at the last report.
+    reported: u64,
+}
+
+impl<'a> Progress<'a> {
+    pub const fn new(options: &'a WriteOptions, total: Option<u64>) -> Self {
+        Self {
+            callback: options.progress.as_ref(),
+            total,
+            reported: 0,
+        }
+    }
+
+    /// Reports that `done` bytes are done, unless the last report was too recent.
+    pub fn update(&mut self, done: u64) {
+        if let Some(callback) = self.callback {
+            if done - self.reported >= PROGRESS_INTERVAL {
+                self.reported = done;
+                (callback.0)(done, self.total);
+            }
+        }
+    }
+
+    /// Reports that the write is complete, after `done` bytes.
+    pub fn finish(&self, done: u64) {
+        if let Some(callback) = self.callback {
+            (callback.0)(self.total.unwrap_or(done), self.total);
+        }
+    }
+}
+
+/// Reports progress for every byte read from a reader.
+struct ProgressReader<'a, R> {
+    inner: R,
+    progress: Progress<'a>,
+    done: u64,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    const fn new(inner: R, progress: Progress<'a>) -> Self {
+        Self {
+            inner,
+            progress,
+            done: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.done += count as u64;
+        self.progress.update(self.done);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::FLAG_BOS;
+    use crate::picture::Picture;
+    use crate::testing::OpusStream;
+
+    /// Splits a stream into its pages, failing on anything which isn't a complete page.
+    fn pages(data: &[u8]) -> Vec<Page> {
+        let mut reader = PageReader::new(data);
+        let mut pages = vec![];
+        while let Some(chunk) = reader.read_chunk().unwrap() {
+            match chunk {
+                Chunk::Page { page, .. } => pages.push(page),
+                chunk => panic!("unexpected {chunk:?}"),
+            }
+        }
+        pages
+    }
+
+    /// Joins pages back into a stream.
+    fn join(pages: &[Page]) -> Vec<u8> {
+        let mut output = vec![];
+        for page in pages {
+            page.write_to(&mut output).unwrap();
+        }
+        output
+    }
+
+    /// Moves the segments of the page after `index` onto it, and renumbers the pages after it.
+    fn merge(pages: &mut Vec<Page>, index: usize) {
+        let next = pages.remove(index + 1);
+        let page = &mut pages[index];
+        page.segments.extend_from_slice(&next.segments);
+        page.body.extend_from_slice(&next.body);
+        page.granule_position = next.granule_position;
+        page.flags |= next.flags & FLAG_EOS;
+        for page in &mut pages[index + 1..] {
+            page.sequence_number -= 1;
+        }
+        for page in pages.iter_mut() {
+            page.update_checksum();
+        }
+    }
+
+    /// Writes `tag` to a copy of `input`, returning the pages of the new stream.
+    fn write(tag: &Tag, input: &[u8], options: &WriteOptions) -> (Vec<Page>, HeaderPages) {
+        let mut output = vec![];
+        let header_pages = copy_streams(input, &mut output, options, |_, _| Some(tag)).unwrap();
+        (pages(&output), header_pages)
+    }
+
+    fn compact() -> WriteOptions {
+        WriteOptions::new().pagination(Pagination::Compact)
+    }
+
+    /// A tag whose comment header is exactly `length` bytes long.
+    fn tag_of_length(length: usize) -> Tag {
+        let mut tag = Tag::new("vendor".to_string(), vec![]);
+        tag.add_one("comment".to_string(), String::new());
+        let value = "x".repeat(length - tag.packet_size().unwrap());
+        tag.remove_entries("comment".to_string());
+        tag.add_one("comment".to_string(), value);
+        tag
+    }
+
+    #[test]
+    fn splits_large_header_over_full_pages() {
+        let input = OpusStream::new().build().unwrap();
+        let tag = tag_of_length(200_000);
+        let (pages, header_pages) = write(&tag, &input, &compact());
+
+        // 200000 bytes take 784 lacing values of 255 and one of 80, which fill 3 pages and part
+        // of a fourth
+        assert_eq!(header_pages, HeaderPages { old: 2, new: 5 });
+        let header = &pages[1..5];
+        for page in &header[..3] {
+            assert_eq!(page.segments, [255; 255]);
+            assert_eq!(page.body.len(), 65025);
+        }
+        assert_eq!(header[3].segments.len(), 20);
+        assert_eq!(header[3].segments.last(), Some(&80));
+        let packet: Vec<u8> = header.iter().flat_map(|page| page.body.clone()).collect();
+        assert_eq!(packet, tag.to_packet_data().unwrap());
+        assert!(holds_one_packet(header));
+    }
+
+    #[test]
+    fn ends_header_with_zero_lacing_value() {
+        let input = OpusStream::new().build().unwrap();
+
+        // a multiple of 255 bytes ends with a lacing value of 0 on the same page
+        let (pages, _) = write(&tag_of_length(255 * 10), &input, &compact());
+        assert_eq!(pages[1].segments, [[255; 10].as_slice(), &[0]].concat());
+        assert!(!pages[2].is_continued());
+
+        // a full page of 255 lacing values needs one more page, holding just the lacing value 0
+        let (pages, header_pages) = write(&tag_of_length(65025), &input, &compact());
+        assert_eq!(header_pages.new, 3);
+        assert_eq!(pages[1].segments, [255; 255]);
+        assert_eq!(pages[2].segments, [0]);
+        assert!(pages[2].body.is_empty());
+        assert!(pages[2].is_continued());
+        assert_eq!(pages[1].granule_position, crate::page::NO_GRANULE);
+        assert_eq!(pages[2].granule_position, 0);
+        assert!(!pages[3].is_continued());
+    }
+
+    #[test]
+    fn sets_page_flags() {
+        let input = OpusStream::new().audio_packets(3).build().unwrap();
+        let (pages, _) = write(&tag_of_length(150_000), &input, &compact());
+
+        assert_eq!(pages.len(), 1 + 3 + 3);
+        assert_eq!(pages[0].flags, FLAG_BOS);
+        assert_eq!(pages[1].flags, 0);
+        assert_eq!(pages[2].flags, FLAG_CONTINUED);
+        assert_eq!(pages[3].flags, FLAG_CONTINUED);
+        assert_eq!(pages[4].flags, 0);
+        assert_eq!(pages[5].flags, 0);
+        assert_eq!(pages[6].flags, FLAG_EOS);
+    }
+
+    #[test]
+    fn moves_packets_off_header_pages() {
+        // the OpusHead packet shares its page with the comment header, which shares its last
+        // page with the first audio packet
+        let original = pages(&OpusStream::new().audio_packets(2).build().unwrap());
+        let mut input = original.clone();
+        merge(&mut input, 1);
+        merge(&mut input, 0);
+        assert_eq!(input.len(), 2);
+        let diagnostics = crate::Diagnostics::new();
+        let options = WriteOptions::new().diagnostics(diagnostics.clone());
+        let tag = Tag::new("vendor".to_string(), vec![]);
+        let (pages, header_pages) = write(&tag, &join(&input), &options);
+
+        assert_eq!(header_pages, HeaderPages { old: 1, new: 3 });
+        assert_eq!(pages.len(), 4);
+        assert!(holds_one_packet(&pages[1..2]));
+        // the OpusHead page and the audio pages are back to how they were, including the granule
+        // position of the first audio packet
+        assert_eq!(pages[0], original[0]);
+        assert_eq!(pages[2..], original[2..]);
+        assert!(diagnostics
+            .take()
+            .contains(&Diagnostic::PaginationFixed { serial: 1 }));
+    }
+
+    #[test]
+    fn renumbers_pages_after_header() {
+        let input = OpusStream::new().serial(7).build().unwrap();
+        let (pages, header_pages) = write(&tag_of_length(100_000), &input, &compact());
+        assert_eq!(header_pages, HeaderPages { old: 2, new: 3 });
+        for (sequence, page) in (0..).zip(&pages) {
+            assert_eq!(page.sequence_number, sequence);
+            assert_eq!(page.serial, 7);
+        }
+
+        // and back again
+        let small = Tag::new("vendor".to_string(), vec![]);
+        let (pages, header_pages) = write(&small, &join(&pages), &compact());
+        assert_eq!(header_pages, HeaderPages { old: 3, new: 2 });
+        for (sequence, page) in (0..).zip(&pages) {
+            assert_eq!(page.sequence_number, sequence);
+        }
+    }
+
+    #[test]
+    fn keeps_page_count_with_preserve_audio() {
+        let input = OpusStream::new().build().unwrap();
+        let (pages, _) = write(&tag_of_length(100_000), &input, &compact());
+        let small = Tag::new("vendor".to_string(), vec![]);
+        let (new_pages, header_pages) = write(&small, &join(&pages), &WriteOptions::new());
+
+        assert_eq!(header_pages, HeaderPages { old: 3, new: 3 });
+        assert!(holds_one_packet(&new_pages[1..3]));
+        // the audio pages are copied byte for byte
+        assert_eq!(new_pages[3..], pages[3..]);
+    }
+
+    #[test]
+    fn repaginates_header_which_shrank_a_lot() {
+        let input = OpusStream::new().build().unwrap();
+        // 24 pages, which would take 23 * 255 bytes of padding
+        let (pages, _) = write(&tag_of_length(1_500_000), &input, &compact());
+        let small = Tag::new("vendor".to_string(), vec![]);
+        let (new_pages, header_pages) = write(&small, &join(&pages), &WriteOptions::new());
+
+        assert_eq!(header_pages, HeaderPages { old: 25, new: 2 });
+        assert!(new_pages[1].body.len() < 1000);
+    }
+
+    /// The output gain of the `OpusHead` page of a stream.
+    fn output_gain(pages: &[Page]) -> i16 {
+        i16::from_le_bytes([pages[0].body[16], pages[0].body[17]])
+    }
+
+    #[test]
+    fn adjusts_output_gain() {
+        let input = OpusStream::new().output_gain(-100).build().unwrap();
+        let tag = Tag::new("vendor".to_string(), vec![]);
+        let options = WriteOptions::new().gain_adjustment(356);
+        let (copied, _) = write(&tag, &input, &options);
+        assert_eq!(output_gain(&copied), 256);
+        assert_eq!(copied[0].checksum, copied[0].compute_checksum());
+
+        // and in place
+        let mut file = std::io::Cursor::new(input);
+        let patched = patch_in_place(&mut file, &tag, &options.padding(1)).unwrap();
+        assert!(patched.is_some());
+        assert_eq!(output_gain(&pages(file.get_ref())), 256);
+    }
+
+    #[test]
+    fn fails_on_output_gain_overflow() {
+        let input = OpusStream::new().output_gain(i16::MAX).build().unwrap();
+        let tag = Tag::new("vendor".to_string(), vec![]);
+        let options = WriteOptions::new().gain_adjustment(1);
+        let result = copy_streams(&input[..], &mut vec![], &options, |_, _| Some(&tag));
+        assert!(matches!(result, Err(Error::InvalidGain(_))));
+
+        let mut file = std::io::Cursor::new(input.clone());
+        let result = patch_in_place(&mut file, &tag, &options.padding(1));
+        assert!(matches!(result, Err(Error::InvalidGain(_))));
+        assert_eq!(file.into_inner(), input);
+    }
+
+    #[test]
+    fn writes_default_vendor_only_when_set() {
+        let input = OpusStream::new().vendor("").build().unwrap();
+        let tag = Tag::new(String::new(), vec![]);
+        let vendor = |options: &WriteOptions| {
+            let (pages, _) = write(&tag, &input, options);
+            let read = Tag::read_from(std::io::Cursor::new(join(&pages))).unwrap();
+            read.get_vendor().to_string()
+        };
+        assert_eq!(vendor(&WriteOptions::new()), "");
+        let options = WriteOptions::new().default_vendor(Some(crate::DEFAULT_VENDOR.into()));
+        assert_eq!(vendor(&options), crate::DEFAULT_VENDOR);
+    }
+
+    #[test]
+    fn writes_valid_checksums() {
+        let input = OpusStream::new().build().unwrap();
+        for length in [100, 65025, 100_000] {
+            let (pages, _) = write(&tag_of_length(length), &input, &compact());
+            for page in &pages {
+                assert_eq!(page.checksum, page.compute_checksum());
+            }
+        }
+    }
+
+    #[test]
+    fn preserves_audio_granule_positions() {
+        let stream = OpusStream::new().pre_skip(3840).audio_packets(5);
+        let input = pages(&stream.build().unwrap());
+        let (output, _) = write(&tag_of_length(100_000), &join(&input), &compact());
+
+        let granules = |pages: &[Page]| -> Vec<u64> {
+            pages.iter().map(|page| page.granule_position).collect()
+        };
+        assert_eq!(granules(&output[..3]), [0, crate::page::NO_GRANULE, 0]);
+        assert_eq!(granules(&output[3..]), granules(&input[2..]));
+        assert_eq!(output[3].granule_position, 3840 + 960);
+    }
+
+    /// Reads the tag of `input`, edits it with `edit`, writes it back in place with each of a
+    /// few options, and checks that reading the result gives the edited tag back, with the audio
+    /// pages unchanged apart from their sequence numbers.
+    fn roundtrip(input: &[Page], edit: impl Fn(&mut Tag)) {
+        let data = join(input);
+        let mut tag = Tag::read_from(std::io::Cursor::new(&data)).unwrap();
+        edit(&mut tag);
+        for options in [
+            WriteOptions::new(),
+            compact(),
+            WriteOptions::new().padding(1024),
+        ] {
+            let mut file = std::io::Cursor::new(data.clone());
+            let (length, header_pages, _) = tag.write_in_place(&mut file, &options).unwrap();
+            let mut output = file.into_inner();
+            output.truncate(usize::try_from(length).unwrap());
+
+            let read = Tag::read_from(std::io::Cursor::new(&output)).unwrap();
+            assert!(read.comments().eq(tag.comments()));
+            let output = pages(&output);
+            let header = usize::try_from(header_pages.new).unwrap();
+            for page in &output[..header] {
+                assert!(page.granule_position == 0 || page.packets_completed() == 0);
+            }
+            let audio: Vec<(u64, &[u8])> = output[header..]
+                .iter()
+                .map(|page| (page.granule_position, page.body.as_slice()))
+                .collect();
+            let old_header = input.len() - audio.len();
+            let expected: Vec<(u64, &[u8])> = input[old_header..]
+                .iter()
+                .map(|page| (page.granule_position, page.body.as_slice()))
+                .collect();
+            assert_eq!(audio, expected);
+        }
+    }
+
+    /// An input whose header pages have non-zero granule positions, as written by some muxers.
+    fn input_with_header_granules(stream: &OpusStream) -> Vec<Page> {
+        let mut input = pages(&stream.build().unwrap());
+        for page in input.iter_mut().filter(|page| page.granule_position == 0) {
+            page.granule_position = 960;
+            page.update_checksum();
+        }
+        input
+    }
+
+    #[test]
+    fn roundtrips_growing_header() {
+        let stream = OpusStream::new().comment("TITLE", "Title").audio_packets(4);
+        roundtrip(&input_with_header_granules(&stream), |tag| {
+            tag.add_one("comment".to_string(), "x".repeat(100_000));
+            tag.add_one("artist".to_string(), "Artist".to_string());
+        });
+    }
+
+    #[test]
+    fn roundtrips_shrinking_header() {
+        let picture = Picture {
+            data: vec![0xAB; 150_000],
+            ..Picture::new()
+        };
+        let stream = OpusStream::new()
+            .comment("TITLE", "Title")
+            .picture(picture)
+            .audio_packets(4);
+        roundtrip(&input_with_header_granules(&stream), |tag| {
+            tag.remove_entries("metadata_block_picture".to_string());
+            tag.add_one("artist".to_string(), "Artist".to_string());
+        });
+    }
+
+    /// A file which records the largest single write to it.
+    struct LargestWrite {
+        file: std::io::Cursor<Vec<u8>>,
+        largest: usize,
+    }
+
+    impl Read for LargestWrite {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.file.read(buf)
+        }
+    }
+
+    impl Write for LargestWrite {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.largest = self.largest.max(buf.len());
+            self.file.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl Seek for LargestWrite {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    #[test]
+    fn splices_headers_without_buffering_the_stream() {
+        let input = OpusStream::new()
+            .comment("TITLE", "x".repeat(5000))
+            .audio_packets(10_000)
+            .build()
+            .unwrap();
+        let grown = tag_of_length(20_000);
+        let shrunk = tag_of_length(100);
+        for tag in [&grown, &shrunk] {
+            let mut expected = vec![];
+            copy_streams(&input[..], &mut expected, &compact(), |_, _| Some(tag)).unwrap();
+            assert_ne!(expected.len(), input.len());
+
+            let mut file = LargestWrite {
+                file: std::io::Cursor::new(input.clone()),
+                largest: 0,
+            };
+            let (length, _, _) = splice_streams(&mut file, &compact(), |_, _| Some(tag)).unwrap();
+            let mut output = file.file.into_inner();
+            output.truncate(usize::try_from(length).unwrap());
+            assert_eq!(output, expected);
+            // only the difference in size and a few pages are held back, not the 300 KB stream
+            assert!(
+                file.largest < 50_000,
+                "wrote {} bytes at once",
+                file.largest
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn gives_each_write_its_own_temp_path() {
+        let path = Path::new("dir/song.opus");
+        let paths = std::thread::scope(|scope| {
+            let threads = [(); 4].map(|()| scope.spawn(|| temp_path(path)));
+            threads.map(|thread| thread.join().unwrap())
+        });
+        for (i, temp) in paths.iter().enumerate() {
+            assert_eq!(temp.parent(), path.parent());
+            assert!(!paths[..i].contains(temp));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn copies_file_like_copy_streams() {
+        let dir = std::env::temp_dir().join(format!("opusmeta-copy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = OpusStream::new()
+            .comment("TITLE", "title")
+            .audio_packets(1000)
+            .build()
+            .unwrap();
+        let src_path = dir.join("src.opus");
+        std::fs::write(&src_path, &input).unwrap();
+
+        // a header of the same length, which leaves the rest of the file where it was, then a
+        // shorter and a longer one
+        let mut outputs = vec![];
+        for title in ["eltit", "", "a longer title"] {
+            let mut tag = Tag::read_from(std::io::Cursor::new(&input)).unwrap();
+            tag.remove_entries("title".to_string());
+            tag.add_one("title".to_string(), title.to_string());
+            let dst_path = dir.join(format!("{}.opus", title.len()));
+            let src = File::open(&src_path).unwrap();
+            let mut dst = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&dst_path)
+                .unwrap();
+            let copied = copy_file(&src, &mut dst, &WriteOptions::new(), |_, _| Some(&tag))
+                .map(|(header_pages, _)| header_pages);
+            let mut expected = vec![];
+            let header_pages =
+                copy_streams(&input[..], &mut expected, &WriteOptions::new(), |_, _| {
+                    Some(&tag)
+                });
+            outputs.push((copied.unwrap(), std::fs::read(&dst_path).unwrap()));
+            outputs.push((header_pages.unwrap(), expected));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        for pair in outputs.chunks(2) {
+            assert_eq!(pair[0].0, pair[1].0);
+            assert!(pair[0].1 == pair[1].1);
+        }
+        assert_eq!(outputs[0].1.len(), input.len());
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "fs"))]
+    fn replaces_symlink_target() {
+        let dir = std::env::temp_dir().join(format!("opusmeta-symlink-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target.opus");
+        let link = dir.join("link.opus");
+        std::fs::write(&target, b"old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        replace_atomically(&link, |_, temp| Ok(temp.write_all(b"new")?)).unwrap();
+        let is_symlink = std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink();
+        let contents = std::fs::read(&target).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(is_symlink);
+        assert_eq!(contents, b"new");
+    }
+}